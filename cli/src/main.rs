@@ -1,15 +1,18 @@
 mod opts;
 
 use std::{
-    io::{Read, Write},
-    os::unix::net::UnixStream,
+    fs,
+    io::{self, Read, Write},
+    net::Shutdown,
     path::PathBuf,
+    process,
+    time::Duration,
 };
 
 use clap::Parser;
 use serde::Serialize;
 use serde_json::to_string;
-use wpaperd_ipc::{socket_path, IpcError, IpcMessage, IpcResponse};
+use wpaperd_ipc::{connect, socket_path, Candidates, IpcError, IpcMessage, IpcResponse, Status};
 
 use crate::opts::{Opts, SubCmd};
 
@@ -25,8 +28,11 @@ fn main() {
     let args = Opts::parse();
 
     let mut json_resp = false;
+    // Sent right after the message
+    let mut pixels = None;
 
-    let mut conn = UnixStream::connect(socket_path().unwrap()).unwrap();
+    let socket_path = args.socket.unwrap_or_else(|| socket_path().unwrap());
+    let mut conn = connect(&socket_path).unwrap();
     let msg = match args.subcmd {
         SubCmd::GetWallpaper { monitor } => IpcMessage::CurrentWallpaper {
             monitor: unquote(monitor),
@@ -35,12 +41,34 @@ fn main() {
             json_resp = json;
             IpcMessage::AllWallpapers
         }
-        SubCmd::NextWallpaper { monitors } => IpcMessage::NextWallpaper {
+        SubCmd::NextWallpaper {
+            transition: Some(transition),
+            transition_time,
+            monitors,
+        } => IpcMessage::NextWithTransition {
+            monitors: monitors.into_iter().map(unquote).collect(),
+            transition,
+            transition_time,
+        },
+        SubCmd::NextWallpaper {
+            transition: None,
+            monitors,
+            ..
+        } => IpcMessage::NextWallpaper {
             monitors: monitors.into_iter().map(unquote).collect(),
         },
         SubCmd::PreviousWallpaper { monitors } => IpcMessage::PreviousWallpaper {
             monitors: monitors.into_iter().map(unquote).collect(),
         },
+        SubCmd::PreviewTransition { image, monitors } => IpcMessage::PreviewTransition {
+            monitors: monitors.into_iter().map(unquote).collect(),
+            // The daemon runs in another directory
+            image: image.map(|image| {
+                std::env::current_dir()
+                    .map(|dir| dir.join(&image))
+                    .unwrap_or(image)
+            }),
+        },
         SubCmd::ReloadWallpaper { monitors } => IpcMessage::ReloadWallpaper {
             monitors: monitors.into_iter().map(unquote).collect(),
         },
@@ -53,8 +81,120 @@ fn main() {
         SubCmd::TogglePauseWallpaper { monitors } => IpcMessage::TogglePauseWallpaper {
             monitors: monitors.into_iter().map(unquote).collect(),
         },
+        SubCmd::DisableWallpaper { color, monitors } => IpcMessage::DisableWallpaper {
+            monitors: monitors.into_iter().map(unquote).collect(),
+            color,
+        },
+        SubCmd::EnableWallpaper { monitors } => IpcMessage::EnableWallpaper {
+            monitors: monitors.into_iter().map(unquote).collect(),
+        },
+        SubCmd::SessionLocked => IpcMessage::SessionLock { locked: true },
+        SubCmd::SessionUnlocked => IpcMessage::SessionLock { locked: false },
+        SubCmd::ToggleMode { monitors } => IpcMessage::ToggleMode {
+            monitors: monitors.into_iter().map(unquote).collect(),
+        },
+        SubCmd::Sorting { set, monitors } => IpcMessage::Sorting {
+            monitors: monitors.into_iter().map(unquote).collect(),
+            sorting: set,
+        },
+        SubCmd::Offset { set, by, monitors } => IpcMessage::Offset {
+            monitors: monitors.into_iter().map(unquote).collect(),
+            offset: set,
+            delta: by,
+        },
+        SubCmd::AddFavorite { monitors } => IpcMessage::AddFavorite {
+            monitors: monitors.into_iter().map(unquote).collect(),
+        },
+        SubCmd::ToggleFavorites { monitors } => IpcMessage::ToggleFavorites {
+            monitors: monitors.into_iter().map(unquote).collect(),
+        },
+        SubCmd::Duration {
+            set,
+            expire,
+            reset,
+            monitors,
+        } => IpcMessage::Duration {
+            monitors: monitors.into_iter().map(unquote).collect(),
+            duration: set,
+            expire,
+            reset,
+        },
+        SubCmd::LogFilter { filter, reset } => IpcMessage::LogFilter { filter, reset },
+        SubCmd::Metrics {
+            reset,
+            json,
+            monitors,
+        } => {
+            json_resp = json;
+            IpcMessage::Metrics {
+                monitors: monitors.into_iter().map(unquote).collect(),
+                reset,
+            }
+        }
+        SubCmd::Status { json, monitors } => {
+            json_resp = json;
+            IpcMessage::Status {
+                monitors: monitors.into_iter().map(unquote).collect(),
+                watch: false,
+            }
+        }
+        SubCmd::Candidates {
+            limit,
+            json,
+            monitors,
+        } => {
+            json_resp = json;
+            IpcMessage::Candidates {
+                monitors: monitors.into_iter().map(unquote).collect(),
+                limit: (limit != 0).then_some(limit),
+            }
+        }
+        SubCmd::FlushCache => IpcMessage::FlushCache,
+        SubCmd::Profile { name, reset } => IpcMessage::Profile {
+            profile: name,
+            reset,
+        },
+        SubCmd::SetPixels {
+            width,
+            height,
+            file,
+            monitors,
+        } => {
+            let data = match &file {
+                Some(file) => fs::read(file),
+                None => {
+                    let mut data = Vec::new();
+                    io::stdin().read_to_end(&mut data).map(|_| data)
+                }
+            };
+            pixels = Some(data.unwrap_or_else(|err| {
+                eprintln!("the pixels could not be read: {err}");
+                process::exit(1);
+            }));
+            IpcMessage::SetPixels {
+                monitors: monitors.into_iter().map(unquote).collect(),
+                width,
+                height,
+            }
+        }
+        SubCmd::Screenshot { monitor, path } => IpcMessage::Screenshot {
+            monitor: unquote(monitor),
+            // The daemon runs in another directory
+            path: std::env::current_dir()
+                .map(|dir| dir.join(&path))
+                .unwrap_or(path),
+        },
+        SubCmd::Capabilities { json } => {
+            json_resp = json;
+            IpcMessage::Capabilities
+        }
     };
     conn.write_all(&serde_json::to_vec(&msg).unwrap()).unwrap();
+    if let Some(pixels) = pixels {
+        // The daemon reads the pixels until the end of the stream
+        conn.write_all(&pixels).unwrap();
+        conn.shutdown(Shutdown::Write).unwrap();
+    }
     let mut buf = String::new();
     conn.read_to_string(&mut buf).unwrap();
     let res: Result<IpcResponse, IpcError> =
@@ -86,17 +226,253 @@ fn main() {
                     }
                 }
             }
+            IpcResponse::BackgroundModes { entries } | IpcResponse::Sortings { entries } => {
+                for (monitor, value) in entries {
+                    println!("{monitor}: {value}");
+                }
+            }
+            IpcResponse::Offsets { entries } => {
+                for (monitor, offset) in entries {
+                    println!("{monitor}: {offset:.3}");
+                }
+            }
+            IpcResponse::FavoritesAdded { entries } => {
+                for (monitor, path, added) in entries {
+                    if added {
+                        println!("{monitor}: {}", path.to_string_lossy());
+                    } else {
+                        println!("{monitor}: {} (already a favorite)", path.to_string_lossy());
+                    }
+                }
+            }
+            IpcResponse::FavoritesModes { entries } => {
+                for (monitor, favorites_only) in entries {
+                    println!("{monitor}: {}", if favorites_only { "on" } else { "off" });
+                }
+            }
+            IpcResponse::Durations { entries } => {
+                for (monitor, duration) in entries {
+                    match duration {
+                        Some(duration) => {
+                            println!("{monitor}: {}", humantime::format_duration(duration))
+                        }
+                        None => println!("{monitor}: none"),
+                    }
+                }
+            }
+            IpcResponse::Metrics { entries } => {
+                if json_resp {
+                    #[derive(Serialize)]
+                    struct Item {
+                        display: String,
+                        decode_time_ms: Option<f64>,
+                        draw_time_ms: Option<f64>,
+                        transition_frames: u32,
+                        frame_requests: u32,
+                        idle_frame_requests: u32,
+                        idle_draws: u32,
+                    }
+                    let val = entries
+                        .into_iter()
+                        .map(|(name, metrics)| Item {
+                            display: name,
+                            decode_time_ms: metrics.decode_time.map(|d| d.as_secs_f64() * 1000.0),
+                            draw_time_ms: metrics.draw_time.map(|d| d.as_secs_f64() * 1000.0),
+                            transition_frames: metrics.transition_frames,
+                            frame_requests: metrics.frame_requests,
+                            idle_frame_requests: metrics.idle_frame_requests,
+                            idle_draws: metrics.idle_draws,
+                        })
+                        .collect::<Vec<_>>();
+                    println!(
+                        "{}",
+                        serde_json::to_string(&val).expect("json encoding to work")
+                    );
+                } else {
+                    let format = |time: Option<Duration>| {
+                        time.map_or_else(|| "-".to_string(), |time| format!("{time:.2?}"))
+                    };
+                    for (monitor, metrics) in entries {
+                        println!(
+                            "{monitor}: decode {}, draw {}, transition frames {}, frame requests {} ({} idle), idle draws {}",
+                            format(metrics.decode_time),
+                            format(metrics.draw_time),
+                            metrics.transition_frames,
+                            metrics.frame_requests,
+                            metrics.idle_frame_requests,
+                            metrics.idle_draws
+                        );
+                    }
+                }
+            }
+            IpcResponse::Status { entries } => {
+                if json_resp {
+                    #[derive(Serialize)]
+                    struct Item {
+                        display: String,
+                        #[serde(flatten)]
+                        status: Status,
+                    }
+                    let val = entries
+                        .into_iter()
+                        .map(|(name, status)| Item {
+                            display: name,
+                            status,
+                        })
+                        .collect::<Vec<_>>();
+                    println!(
+                        "{}",
+                        serde_json::to_string(&val).expect("json encoding to work")
+                    );
+                } else {
+                    for (monitor, status) in entries {
+                        let position = status
+                            .position
+                            .map(|(x, y)| format!(", position {x},{y}"))
+                            .unwrap_or_default();
+                        let profile = status
+                            .profile
+                            .map(|profile| format!(", profile {profile}"))
+                            .unwrap_or_default();
+                        println!(
+                            "{monitor}: {}, mode {}, sorting {}, swap interval {}{position}{profile}{}",
+                            status.path.to_string_lossy(),
+                            status.mode,
+                            status.sorting,
+                            status.swap_interval,
+                            if status.paused { ", paused" } else { "" }
+                        );
+                    }
+                }
+            }
+            IpcResponse::Candidates { entries } => {
+                if json_resp {
+                    #[derive(Serialize)]
+                    struct Item {
+                        display: String,
+                        #[serde(flatten)]
+                        candidates: Candidates,
+                    }
+                    let val = entries
+                        .into_iter()
+                        .map(|(name, candidates)| Item {
+                            display: name,
+                            candidates,
+                        })
+                        .collect::<Vec<_>>();
+                    println!(
+                        "{}",
+                        serde_json::to_string(&val).expect("json encoding to work")
+                    );
+                } else {
+                    for (monitor, candidates) in entries {
+                        let current = candidates
+                            .current
+                            .map_or_else(|| "none".to_string(), |index| index.to_string());
+                        println!(
+                            "{monitor}: {} wallpapers, current index {current}",
+                            candidates.total
+                        );
+                        for (index, path) in candidates.paths.iter().enumerate() {
+                            let marker = if candidates.current == Some(index) {
+                                "*"
+                            } else {
+                                " "
+                            };
+                            println!("{marker} {index}: {}", path.to_string_lossy());
+                        }
+                        let hidden = candidates.total - candidates.paths.len();
+                        if hidden > 0 {
+                            println!("  ... and {hidden} more");
+                        }
+                    }
+                }
+            }
+            IpcResponse::CacheFlushed { images, dimensions } => {
+                println!("evicted {images} decoded images and {dimensions} image dimensions")
+            }
+            IpcResponse::Profile { profile } => match profile {
+                Some(profile) => println!("{profile}"),
+                None => println!("configuration file"),
+            },
+            IpcResponse::Capabilities { version, commands } => {
+                if json_resp {
+                    #[derive(Serialize)]
+                    struct Capabilities {
+                        version: u32,
+                        commands: Vec<String>,
+                    }
+                    println!(
+                        "{}",
+                        serde_json::to_string(&Capabilities { version, commands })
+                            .expect("json encoding to work")
+                    );
+                } else {
+                    println!("version: {version}");
+                    for command in commands {
+                        println!("{command}");
+                    }
+                }
+            }
             IpcResponse::Ok => (),
         },
         Err(err) => match err {
             IpcError::MonitorNotFound { monitor } => {
                 eprintln!("monitor {monitor} could not be found")
             }
+            IpcError::InvalidSorting { sorting } => eprintln!("sorting {sorting} is not valid"),
+            IpcError::InvalidLogFilter { filter, error } => {
+                eprintln!("log filter {filter} is not valid: {error}")
+            }
+            IpcError::GroupedSorting { monitor } => {
+                eprintln!("monitor {monitor} is in a group, its sorting can't be changed")
+            }
+            IpcError::InvalidOffset { offset } => eprintln!(
+                "offset {offset} is not valid, use a number from 0 to 1 or top, left, center, bottom or right"
+            ),
+            IpcError::NoFavorites { monitor } => {
+                eprintln!("monitor {monitor} has no favorites file, set it with favorites")
+            }
+            IpcError::GroupedFavorites { monitor } => {
+                eprintln!("monitor {monitor} is in a group, it can't pick only its favorites")
+            }
+            IpcError::FavoritesErrors(errors) => {
+                for (monitor, err) in errors {
+                    eprintln!("the favorite of monitor {monitor} could not be added: {err}")
+                }
+            }
+            IpcError::InvalidColor { color } => {
+                eprintln!("color {color} is not valid, use the #RRGGBB or #RRGGBBAA format")
+            }
+            IpcError::InvalidTransition { transition, error } => {
+                eprintln!("transition {transition} is not valid: {error}")
+            }
+            IpcError::InvalidProfile { profile, error } => {
+                eprintln!("profile {profile} could not be switched to: {error}")
+            }
+            IpcError::InvalidPixelsSize { width, height } => {
+                eprintln!("the size {width}x{height} of the pixels is not valid")
+            }
+            IpcError::PixelsMismatch { expected, received } => {
+                if received > expected {
+                    eprintln!("more than the expected {expected} bytes of pixels have been sent")
+                } else {
+                    eprintln!("{received} bytes of pixels have been sent, {expected} were expected")
+                }
+            }
             IpcError::DrawErrors(errors) => {
                 for (monitor, err) in errors {
                     eprintln!("Wallpaper could not be drawn for monitor {monitor}: {err}")
                 }
             }
+            IpcError::PreviewErrors(errors) => {
+                for (monitor, err) in errors {
+                    eprintln!("the transition of monitor {monitor} could not be previewed: {err}")
+                }
+            }
+            IpcError::ScreenshotFailed { monitor, error } => {
+                eprintln!("the screenshot of monitor {monitor} could not be taken: {error}")
+            }
         },
     }
 }
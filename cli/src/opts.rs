@@ -1,8 +1,16 @@
+use std::{path::PathBuf, time::Duration};
+
 use clap::Parser;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Opts {
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Socket of the daemon, prefixed by @ for an abstract socket (WPAPERD_SOCKET or XDG_RUNTIME_DIR/wpaperd-$WAYLAND_DISPLAY.sock by default)"
+    )]
+    pub socket: Option<PathBuf>,
     #[clap(subcommand)]
     pub subcmd: SubCmd,
 }
@@ -10,22 +18,218 @@ pub struct Opts {
 #[derive(clap::Subcommand)]
 pub enum SubCmd {
     #[clap(visible_alias = "get")]
-    GetWallpaper { monitor: String },
+    GetWallpaper {
+        monitor: String,
+    },
     #[clap(visible_alias = "get-all")]
     AllWallpapers {
         #[clap(short, long)]
         json: bool,
     },
     #[clap(visible_alias = "next")]
-    NextWallpaper { monitors: Vec<String> },
+    NextWallpaper {
+        #[clap(
+            short,
+            long,
+            help = "Transition for this change only, e.g. iris, none or '{ iris = { contract = true } }'"
+        )]
+        transition: Option<String>,
+        #[clap(
+            long,
+            value_name = "MS",
+            requires = "transition",
+            help = "Milliseconds the transition runs for, its default time otherwise"
+        )]
+        transition_time: Option<u32>,
+        monitors: Vec<String>,
+    },
     #[clap(visible_alias = "previous")]
-    PreviousWallpaper { monitors: Vec<String> },
+    PreviousWallpaper {
+        monitors: Vec<String>,
+    },
+    /// Play the transition of the displays without changing their wallpaper, and wait for it
+    #[clap(visible_alias = "preview")]
+    PreviewTransition {
+        #[clap(
+            short,
+            long,
+            help = "Transition to this image, relative to the current directory, instead of the current wallpaper"
+        )]
+        image: Option<PathBuf>,
+        monitors: Vec<String>,
+    },
     #[clap(visible_alias = "reload")]
-    ReloadWallpaper { monitors: Vec<String> },
+    ReloadWallpaper {
+        monitors: Vec<String>,
+    },
     #[clap(visible_alias = "pause")]
-    PauseWallpaper { monitors: Vec<String> },
+    PauseWallpaper {
+        monitors: Vec<String>,
+    },
     #[clap(visible_alias = "resume")]
-    ResumeWallpaper { monitors: Vec<String> },
+    ResumeWallpaper {
+        monitors: Vec<String>,
+    },
     #[clap(visible_alias = "toggle-pause")]
-    TogglePauseWallpaper { monitors: Vec<String> },
+    TogglePauseWallpaper {
+        monitors: Vec<String>,
+    },
+    /// Stop drawing on the displays and pause them, until they are enabled again
+    #[clap(visible_alias = "disable")]
+    DisableWallpaper {
+        #[clap(
+            short,
+            long,
+            help = "Fill the displays with this color, in the #RRGGBB or #RRGGBBAA format"
+        )]
+        color: Option<String>,
+        monitors: Vec<String>,
+    },
+    /// Draw the current wallpaper again on the disabled displays
+    #[clap(visible_alias = "enable")]
+    EnableWallpaper {
+        monitors: Vec<String>,
+    },
+    /// Stop drawing and pause all the displays while the session is locked
+    SessionLocked,
+    /// Draw again after the session has been unlocked
+    SessionUnlocked,
+    ToggleMode {
+        monitors: Vec<String>,
+    },
+    /// Print the sorting of the displays, or change it until the configuration is reloaded
+    Sorting {
+        #[clap(
+            short,
+            long,
+            value_parser = ["random", "ascending", "descending", "random-start", "shuffle"]
+        )]
+        set: Option<String>,
+        monitors: Vec<String>,
+    },
+    /// Print the offset of the displays, or change it until the configuration is reloaded
+    Offset {
+        #[clap(
+            short,
+            long,
+            help = "Set the offset, from 0 to 1 or the side to align the wallpaper to (top, left, center, bottom or right)"
+        )]
+        set: Option<String>,
+        #[clap(
+            short,
+            long,
+            allow_hyphen_values = true,
+            conflicts_with = "set",
+            help = "Move the wallpaper from its current offset by this amount, e.g. -0.05"
+        )]
+        by: Option<f32>,
+        monitors: Vec<String>,
+    },
+    /// Append the current wallpaper of the displays to their favorites file
+    AddFavorite {
+        monitors: Vec<String>,
+    },
+    /// Pick the wallpapers randomly from the favorites only, or go back to the configured ones
+    ToggleFavorites {
+        monitors: Vec<String>,
+    },
+    /// Print the duration of the displays, or replace it until the configuration is reloaded
+    Duration {
+        #[clap(short, long, value_parser = humantime::parse_duration)]
+        set: Option<Duration>,
+        #[clap(
+            short,
+            long,
+            requires = "set",
+            value_parser = humantime::parse_duration,
+            help = "Restore the configured duration after this amount of time"
+        )]
+        expire: Option<Duration>,
+        #[clap(
+            short,
+            long,
+            conflicts_with = "set",
+            help = "Restore the configured duration"
+        )]
+        reset: bool,
+        monitors: Vec<String>,
+    },
+    /// Change the log filter of the daemon until it is restarted, e.g. `debug`
+    LogFilter {
+        #[clap(required_unless_present = "reset")]
+        filter: Option<String>,
+        #[clap(
+            short,
+            long,
+            conflicts_with = "filter",
+            help = "Restore the log filter used at startup"
+        )]
+        reset: bool,
+    },
+    /// Print the decode and draw timings of the displays
+    Metrics {
+        #[clap(short, long, help = "Reset the metrics after printing them")]
+        reset: bool,
+        #[clap(short, long)]
+        json: bool,
+        monitors: Vec<String>,
+    },
+    /// Print the wallpaper, the background mode, the sorting and whether the displays are paused
+    Status {
+        #[clap(short, long)]
+        json: bool,
+        monitors: Vec<String>,
+    },
+    /// Print the wallpapers the displays pick from, to debug the paths and the filters
+    Candidates {
+        #[clap(
+            short,
+            long,
+            default_value_t = 50,
+            help = "Print at most this number of wallpapers per display, 0 to print all of them"
+        )]
+        limit: usize,
+        #[clap(short, long)]
+        json: bool,
+        monitors: Vec<String>,
+    },
+    /// Drop the cached images and dimensions, to read the images edited in place again
+    FlushCache,
+    /// Print the active profile, or switch all the displays to the profile `profiles/NAME.toml`
+    /// next to the configuration file
+    Profile {
+        name: Option<String>,
+        #[clap(
+            short,
+            long,
+            conflicts_with = "name",
+            help = "Go back to the configuration file"
+        )]
+        reset: bool,
+    },
+    /// Show raw RGBA pixels on the displays, 4 bytes per pixel row by row from the top left
+    SetPixels {
+        #[clap(long, help = "Width of the image in pixels")]
+        width: u32,
+        #[clap(long, help = "Height of the image in pixels")]
+        height: u32,
+        #[clap(
+            short,
+            long,
+            value_name = "PATH",
+            help = "Read the pixels from this file instead of the standard input"
+        )]
+        file: Option<PathBuf>,
+        monitors: Vec<String>,
+    },
+    /// Write what is shown on a display to a PNG image, e.g. to attach it to a bug report
+    Screenshot {
+        monitor: String,
+        #[clap(help = "Path of the PNG image, relative to the current directory")]
+        path: PathBuf,
+    },
+    Capabilities {
+        #[clap(short, long)]
+        json: bool,
+    },
 }
@@ -0,0 +1,70 @@
+//! Pause the automatic wallpaper sequence while the system is running on battery, for the
+//! displays that have `pause-on-battery` set. The power supplies are polled from sysfs.
+
+use std::{fs, path::Path, time::Duration};
+
+use color_eyre::{eyre::anyhow, Result};
+use smithay_client_toolkit::reexports::calloop::{
+    timer::{TimeoutAction, Timer},
+    LoopHandle,
+};
+
+use crate::wpaperd::Wpaperd;
+
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+/// How often the power supplies are checked
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+fn read_attribute(supply: &Path, attribute: &str) -> Option<String> {
+    fs::read_to_string(supply.join(attribute))
+        .ok()
+        .map(|value| value.trim().to_string())
+}
+
+/// Return true when a battery is discharging and no external power supply is online
+pub fn on_battery() -> bool {
+    let Ok(entries) = fs::read_dir(POWER_SUPPLY_DIR) else {
+        return false;
+    };
+    let mut discharging = false;
+    for entry in entries.flatten() {
+        let supply = entry.path();
+        // Skip the batteries of mice, keyboards and other peripherals
+        if read_attribute(&supply, "scope").as_deref() == Some("Device") {
+            continue;
+        }
+        match read_attribute(&supply, "type").as_deref() {
+            Some("Battery") => {
+                discharging |= read_attribute(&supply, "status").as_deref() == Some("Discharging");
+            }
+            // A mains adapter or a USB supply is plugged in
+            Some(_) if read_attribute(&supply, "online").as_deref() == Some("1") => return false,
+            _ => {}
+        }
+    }
+    discharging
+}
+
+/// Check the power supplies periodically and update the surfaces
+pub fn insert_battery_timer(handle: &LoopHandle<Wpaperd>) -> Result<()> {
+    handle
+        .insert_source(
+            Timer::immediate(),
+            |_deadline, _: &mut (), wpaperd: &mut Wpaperd| {
+                // Don't read sysfs when nobody needs it
+                if wpaperd
+                    .surfaces
+                    .iter()
+                    .any(|surface| surface.wallpaper_info.pause_on_battery)
+                {
+                    let on_battery = on_battery();
+                    for surface in &mut wpaperd.surfaces {
+                        surface.set_on_battery(on_battery);
+                    }
+                }
+                TimeoutAction::ToDuration(POLL_INTERVAL)
+            },
+        )
+        .map_err(|err| anyhow!("inserting the battery timer in the event loop: {err}"))?;
+    Ok(())
+}
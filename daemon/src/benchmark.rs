@@ -0,0 +1,384 @@
+//! Play a transition back and forth between two images on a display and print how long the
+//! frames took, to compare the transitions and the GPUs. The frames are drawn by the same
+//! renderer as the wallpapers, one after the other without the timers of the daemon, and the
+//! vertical sync limits them to the refresh rate of the display.
+
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use color_eyre::{
+    eyre::{bail, ensure, Context, ContextCompat},
+    Result, Section,
+};
+use image::DynamicImage;
+use smithay_client_toolkit::{
+    compositor::{CompositorHandler, CompositorState},
+    delegate_compositor, delegate_layer, delegate_output, delegate_registry,
+    output::{OutputHandler, OutputState},
+    reexports::client::{
+        globals::registry_queue_init,
+        protocol::{
+            wl_output::{Transform, WlOutput},
+            wl_surface::WlSurface,
+        },
+        Connection, EventQueue, Proxy, QueueHandle,
+    },
+    registry::{ProvidesRegistryState, RegistryState},
+    registry_handlers,
+    shell::wlr_layer::{
+        Anchor, Layer, LayerShell, LayerShellHandler, LayerSurface, LayerSurfaceConfigure,
+    },
+};
+
+use crate::{
+    config::parse_transition,
+    display_info::DisplayInfo,
+    image_loader::{decode_file, ImageStats},
+    init_egl_display,
+    render::{EglContext, Renderer},
+    wallpaper_info::{BackgroundMode, TextureFilter},
+};
+
+const DEFAULT_TRANSITION: &str = "fade";
+const DEFAULT_ITERATIONS: u32 = 10;
+/// Refresh rate used when the compositor doesn't report it, in mHz
+const DEFAULT_REFRESH_RATE: i32 = 60_000;
+
+struct Benchmark {
+    registry_state: RegistryState,
+    output_state: OutputState,
+    /// Last configuration of the layer surface, taken once it has been applied
+    configure: Option<LayerSurfaceConfigure>,
+    closed: bool,
+    /// Time of the frames drawn while the transitions are running
+    frames: Vec<Duration>,
+}
+
+struct Summary {
+    frames: Vec<Duration>,
+    /// Time between two refreshes of the display
+    refresh_interval: Duration,
+}
+
+impl Summary {
+    /// Number of refreshes of the display that have shown the same frame again
+    fn dropped(&self) -> u32 {
+        self.frames
+            .iter()
+            .map(|frame| {
+                let refreshes = frame.as_secs_f64() / self.refresh_interval.as_secs_f64();
+                (refreshes.round() as u32).saturating_sub(1)
+            })
+            .sum()
+    }
+
+    fn print(&self) {
+        let Some(max) = self.frames.iter().max() else {
+            println!("frames: 0");
+            return;
+        };
+        let average = self.frames.iter().sum::<Duration>() / self.frames.len() as u32;
+        println!("frames: {}, {} dropped", self.frames.len(), self.dropped());
+        println!(
+            "frame time: {:.2} ms average, {:.2} ms max",
+            average.as_secs_f64() * 1000.0,
+            max.as_secs_f64() * 1000.0
+        );
+    }
+}
+
+/// Decode an image like the image loader does, following its EXIF orientation
+fn load_image(path: &Path) -> Result<DynamicImage> {
+    decode_file(path, true)
+        .map(|image| image.into_rgba8().into())
+        .with_context(|| format!("unable to decode the image {path:?}"))
+}
+
+/// Connect to the compositor, play `transition` `iterations` times on the display `output`
+/// and print the frame times
+pub fn benchmark(
+    images: &[PathBuf],
+    output: Option<&str>,
+    transition: Option<&str>,
+    transition_time: Option<u32>,
+    iterations: Option<u32>,
+) -> Result<()> {
+    let transition_name = transition.unwrap_or(DEFAULT_TRANSITION);
+    let transition = parse_transition(transition_name)?;
+    let transition_time = transition_time.unwrap_or_else(|| transition.default_transition_time());
+    ensure!(
+        transition_time > 0,
+        "the transition must last more than 0 milliseconds to be measured"
+    );
+    let iterations = iterations.unwrap_or(DEFAULT_ITERATIONS);
+    let images = images
+        .iter()
+        .map(PathBuf::as_path)
+        .map(load_image)
+        .collect::<Result<Vec<_>>>()?;
+
+    let conn = Connection::connect_to_env()
+        .context("connecting to wayland")
+        .suggestion("Are you running a wayland compositor?")?;
+    let native_display = conn.display().id().as_ptr() as *mut std::ffi::c_void;
+    let egl_display = init_egl_display(native_display, None)
+        .suggestion("The benchmark measures the OpenGL ES renderer, which needs EGL")?;
+
+    let (globals, mut event_queue) =
+        registry_queue_init(&conn).context("initializing the wayland registry queue")?;
+    let qh = event_queue.handle();
+    let compositor_state = CompositorState::bind(&globals, &qh)?;
+    let layer_state = LayerShell::bind(&globals, &qh)?;
+    let mut benchmark = Benchmark {
+        registry_state: RegistryState::new(&globals),
+        output_state: OutputState::new(&globals, &qh),
+        configure: None,
+        closed: false,
+        frames: Vec::new(),
+    };
+    // The first roundtrip receives the outputs, the second one their xdg-output information
+    for _ in 0..2 {
+        event_queue
+            .roundtrip(&mut benchmark)
+            .context("receiving the outputs from the compositor")?;
+    }
+
+    let (wl_output, info) = benchmark
+        .output_state
+        .outputs()
+        .filter_map(|wl_output| {
+            let info = benchmark.output_state.info(&wl_output)?;
+            Some((wl_output, info))
+        })
+        .find(|(_, info)| output.is_none() || info.name.as_deref() == output)
+        .with_context(|| match output {
+            Some(output) => format!("display {output:?} not found"),
+            None => "no display found".to_string(),
+        })?;
+    let refresh_rate = info
+        .modes
+        .iter()
+        .find(|mode| mode.current)
+        .map(|mode| mode.refresh_rate)
+        .filter(|&refresh_rate| refresh_rate > 0)
+        .unwrap_or(DEFAULT_REFRESH_RATE);
+
+    let wl_surface = compositor_state.create_surface(&qh);
+    wl_surface.set_buffer_scale(info.scale_factor);
+    wl_surface.set_buffer_transform(info.transform);
+    let transform = info.transform;
+    let display_info = Rc::new(RefCell::new(DisplayInfo::new(info)));
+    let layer = layer_state.create_layer_surface(
+        &qh,
+        wl_surface.clone(),
+        Layer::Background,
+        Some("wpaperd-benchmark"),
+        Some(&wl_output),
+    );
+    layer.set_anchor(Anchor::all());
+    layer.set_exclusive_zone(-1);
+    wl_surface.commit();
+
+    let configure = loop {
+        if let Some(configure) = benchmark.configure.take() {
+            break configure;
+        }
+        if benchmark.closed {
+            bail!("the compositor has closed the surface before configuring it");
+        }
+        event_queue
+            .blocking_dispatch(&mut benchmark)
+            .context("waiting for the compositor to configure the surface")?;
+    };
+    display_info.borrow_mut().change_size(configure);
+    let (width, height) = {
+        let info = display_info.borrow();
+        (info.adjusted_width(), info.adjusted_height())
+    };
+
+    let mut egl_context = EglContext::new(egl_display, &wl_surface)?;
+    egl_context.resize(&wl_surface, width, height)?;
+    egl_context.make_current()?;
+    // Dropped frames only make sense when following the refresh rate
+    egl_context.set_swap_interval(1)?;
+    let mut renderer = unsafe {
+        Renderer::new(
+            images[0].clone(),
+            display_info.clone(),
+            transition_time,
+            transition,
+            transform,
+        )?
+    };
+    renderer.resize()?;
+
+    println!(
+        "display: {}, {width}x{height} at {:.3} Hz",
+        display_info.borrow().name,
+        refresh_rate as f64 / 1000.0
+    );
+    println!("transition: {transition_name}, {transition_time} ms, {iterations} times");
+
+    let result = run(
+        &mut benchmark,
+        &mut event_queue,
+        &egl_context,
+        &mut renderer,
+        &images,
+        transition_time,
+        iterations,
+    );
+    // Print what has been measured even if the benchmark has been interrupted
+    Summary {
+        frames: benchmark.frames,
+        refresh_interval: Duration::from_secs_f64(1000.0 / refresh_rate as f64),
+    }
+    .print();
+
+    // The textures and the program belong to the context
+    drop(renderer);
+    egl_context.destroy();
+    drop(layer);
+    result
+}
+
+/// Play the transitions, alternating the images, and record the time of their frames
+fn run(
+    benchmark: &mut Benchmark,
+    event_queue: &mut EventQueue<Benchmark>,
+    egl_context: &EglContext,
+    renderer: &mut Renderer,
+    images: &[DynamicImage],
+    transition_time: u32,
+    iterations: u32,
+) -> Result<()> {
+    let started = Instant::now();
+    for iteration in 0..iterations {
+        let image = images[(iteration as usize + 1) % images.len()].clone();
+        renderer.load_wallpaper(
+            image,
+            ImageStats::default(),
+            BackgroundMode::default(),
+            None,
+            TextureFilter::Linear,
+        )?;
+        // The upload of the image is not measured, the transition starts from the next frame
+        renderer.start_transition_after_upload(transition_time)?;
+        let mut last_frame = None;
+        while renderer.transition_running() {
+            event_queue
+                .dispatch_pending(benchmark)
+                .context("dispatching the wayland events")?;
+            if benchmark.closed {
+                bail!("the compositor has closed the surface");
+            }
+
+            let time = started.elapsed().as_millis() as u32;
+            if !renderer.update_transition_status(time) {
+                renderer.transition_finished();
+            }
+            unsafe { renderer.draw()? }
+            renderer.clear_after_draw()?;
+            egl_context.swap_buffers()?;
+
+            let now = Instant::now();
+            if let Some(last_frame) = last_frame.replace(now) {
+                benchmark.frames.push(now - last_frame);
+            }
+        }
+    }
+    Ok(())
+}
+
+impl CompositorHandler for Benchmark {
+    fn scale_factor_changed(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _surface: &WlSurface,
+        _new_factor: i32,
+    ) {
+    }
+
+    fn frame(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _surface: &WlSurface,
+        _time: u32,
+    ) {
+    }
+
+    fn transform_changed(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _surface: &WlSurface,
+        _new_transform: Transform,
+    ) {
+    }
+
+    fn surface_enter(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _surface: &WlSurface,
+        _output: &WlOutput,
+    ) {
+    }
+
+    fn surface_leave(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _surface: &WlSurface,
+        _output: &WlOutput,
+    ) {
+    }
+}
+
+impl OutputHandler for Benchmark {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.output_state
+    }
+
+    fn new_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {}
+
+    fn update_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {}
+
+    fn output_destroyed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {
+    }
+}
+
+impl LayerShellHandler for Benchmark {
+    fn closed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _layer: &LayerSurface) {
+        self.closed = true;
+    }
+
+    fn configure(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _layer: &LayerSurface,
+        configure: LayerSurfaceConfigure,
+        _serial: u32,
+    ) {
+        self.configure = Some(configure);
+    }
+}
+
+delegate_compositor!(Benchmark);
+delegate_output!(Benchmark);
+delegate_registry!(Benchmark);
+delegate_layer!(Benchmark);
+
+impl ProvidesRegistryState for Benchmark {
+    fn registry(&mut self) -> &mut RegistryState {
+        &mut self.registry_state
+    }
+    registry_handlers![OutputState];
+}
@@ -0,0 +1,195 @@
+//! Pick the wallpapers from one of the `conditions` by the output of the `condition` command,
+//! e.g. to show rainy wallpapers while it's raining. The command runs in a thread each time the
+//! timer changes the wallpaper and its first line selects the wallpapers of the next change.
+//! When it fails, doesn't finish in time or prints an unknown name, `path` is used.
+
+use std::{
+    io::Read,
+    os::unix::process::CommandExt,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use color_eyre::{
+    eyre::{anyhow, bail, ensure, Context},
+    Result,
+};
+use log::{error, warn};
+use nix::{
+    sys::signal::{killpg, Signal},
+    unistd::Pid,
+};
+use smithay_client_toolkit::reexports::{
+    calloop::{
+        channel::{self, Sender},
+        LoopHandle,
+    },
+    client::QueueHandle,
+};
+
+use crate::{wallpaper_info::WallpaperInfo, wpaperd::Wpaperd};
+
+pub const DEFAULT_CONDITION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often the thread checks whether the command has finished
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Output of the condition command of a display, None when it's not usable
+struct ConditionResult {
+    name: String,
+    condition: Option<String>,
+}
+
+/// Run the condition commands outside of the event loop, the results are sent back to it
+#[derive(Clone)]
+pub struct ConditionRunner {
+    sender: Sender<ConditionResult>,
+}
+
+impl ConditionRunner {
+    pub fn new(event_loop_handle: LoopHandle<Wpaperd>, qh: QueueHandle<Wpaperd>) -> Result<Self> {
+        let (sender, results) = channel::channel();
+        let handle = event_loop_handle.clone();
+        event_loop_handle
+            .insert_source(results, move |event, _, wpaperd| {
+                let channel::Event::Msg(ConditionResult { name, condition }) = event else {
+                    return;
+                };
+                let Some(surface) = wpaperd
+                    .surfaces
+                    .iter_mut()
+                    .find(|surface| surface.name() == name)
+                else {
+                    return;
+                };
+                if !surface.set_condition(condition) {
+                    return;
+                }
+                // Go through the configuration again, to go back to `path`
+                let res = wpaperd.config.get_output(&surface.info());
+                match res {
+                    Ok(wallpaper_info) => surface.update_condition(&handle, &qh, wallpaper_info),
+                    Err(err) => warn!("Configuration error for display {name}: {err:?}"),
+                }
+            })
+            .map_err(|e| anyhow!("inserting the condition listener in the event loop: {e}"))?;
+
+        Ok(Self { sender })
+    }
+
+    /// Run `command` in a new thread, killing it after `timeout`
+    pub fn run(&self, name: String, command: String, timeout: Duration) {
+        let sender = self.sender.clone();
+        std::thread::spawn(move || {
+            let condition = run_command(&command, timeout)
+                .map_err(|err| warn!("{name}: {err:?}, using path"))
+                .ok()
+                .flatten();
+            // The event loop has stopped
+            let _ = sender.send(ConditionResult { name, condition });
+        });
+    }
+}
+
+/// Show the wallpapers of `condition` in place of `path`, if it has any
+pub fn apply_condition(wallpaper_info: &mut WallpaperInfo, condition: Option<&str>) {
+    let Some(path) = condition.and_then(|condition| wallpaper_info.conditions.get(condition))
+    else {
+        return;
+    };
+    wallpaper_info.path = path.clone();
+    wallpaper_info.pool = Vec::new();
+    wallpaper_info.fallback = Vec::new();
+    wallpaper_info.command = None;
+}
+
+/// Run the command in a shell and return the first line it prints, None if it's empty
+fn run_command(command: &str, timeout: Duration) -> Result<Option<String>> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        // Its own group, so that the processes started by the shell are killed with it
+        .process_group(0)
+        .spawn()
+        .with_context(|| format!("running condition {command:?}"))?;
+    let group = Pid::from_raw(child.id() as i32);
+    // Read while the command runs, it would block once the pipe is full otherwise
+    let mut stdout = child
+        .stdout
+        .take()
+        .expect("the output of the condition is piped");
+    let reader = std::thread::spawn(move || {
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).map(|_| output)
+    });
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child
+            .try_wait()
+            .with_context(|| format!("waiting for condition {command:?}"))?
+        {
+            break status;
+        }
+        if started.elapsed() >= timeout {
+            if let Err(err) = killpg(group, Signal::SIGKILL) {
+                error!("unable to kill condition {command:?}: {err}");
+            }
+            if let Err(err) = child.wait() {
+                error!("unable to wait for condition {command:?}: {err}");
+            }
+            bail!("condition {command:?} did not finish in {timeout:?}");
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    };
+    // The processes left in the background would keep the output open
+    let _ = killpg(group, Signal::SIGKILL);
+    ensure!(status.success(), "condition {command:?} failed ({status})");
+
+    let output = reader
+        .join()
+        .expect("the thread reading the condition doesn't panic")
+        .with_context(|| format!("reading the output of condition {command:?}"))?;
+    Ok(String::from_utf8_lossy(&output)
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_string))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_command() {
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            run_command("printf '\\n  rain \\nsnow\\n'", timeout).unwrap(),
+            Some("rain".to_string())
+        );
+        assert_eq!(run_command("true", timeout).unwrap(), None);
+        assert!(run_command("echo rain; false", timeout).is_err());
+    }
+
+    #[test]
+    fn test_run_command_large_output() {
+        // More than the capacity of a pipe
+        let output = run_command(
+            "echo rain; head -c 1000000 /dev/zero",
+            Duration::from_secs(5),
+        );
+        assert_eq!(output.unwrap(), Some("rain".to_string()));
+    }
+
+    #[test]
+    fn test_run_command_timeout() {
+        // The sleep started by the shell keeps the output open until it is killed
+        let started = Instant::now();
+        assert!(run_command("sleep 10; echo rain", Duration::from_millis(200)).is_err());
+        assert!(run_command("sleep 10 & echo rain", Duration::from_millis(200)).is_ok());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fs,
     path::{Path, PathBuf},
     sync::{
@@ -12,18 +12,31 @@ use std::{
 use color_eyre::{
     eyre::{anyhow, ensure, Context},
     owo_colors::OwoColorize,
-    Result, Section,
+    Report, Result, Section,
 };
 use dirs::home_dir;
 use hotwatch::{Event, Hotwatch};
 use log::{error, warn};
-use serde::Deserialize;
-use smithay_client_toolkit::reexports::calloop::ping::Ping;
+use serde::{de::value::StrDeserializer, Deserialize};
+use smithay_client_toolkit::{
+    reexports::{calloop::ping::Ping, client::protocol::wl_output::Transform},
+    shell::wlr_layer::{Anchor, Layer},
+};
 
 use crate::{
+    condition::DEFAULT_CONDITION_TIMEOUT,
+    display_info::DisplayInfo,
+    do_not_disturb::TimeWindow,
+    filelist_cache::ListCommand,
+    http::{is_url, path_exists},
     image_picker::ImagePicker,
-    render::Transition,
-    wallpaper_info::{BackgroundMode, Sorting, WallpaperInfo},
+    night_light::NightLight,
+    render::{Backdrop, Border, ColorAdjustment, Transition},
+    wallpaper_info::{
+        BackgroundMode, DownscaleFilter, LayerPlacement, Sorting, TextureFilter, WallpaperInfo,
+        DEFAULT_MAX_UPSCALE,
+    },
+    weekdays::{Weekday, WeekdayPin},
 };
 
 #[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
@@ -33,6 +46,9 @@ pub enum SerializedSorting {
     Random,
     Ascending,
     Descending,
+    RandomStart,
+    Daily,
+    Shuffle,
 }
 
 impl From<Sorting> for SerializedSorting {
@@ -40,17 +56,129 @@ impl From<Sorting> for SerializedSorting {
         match s {
             Sorting::Ascending => SerializedSorting::Ascending,
             Sorting::Descending => SerializedSorting::Descending,
+            Sorting::RandomStart => SerializedSorting::RandomStart,
+            Sorting::Daily => SerializedSorting::Daily,
+            Sorting::Shuffle => SerializedSorting::Shuffle,
             Sorting::Random => SerializedSorting::Random,
             _ => unreachable!(),
         }
     }
 }
 
+/// Value of the `path` attribute
+#[derive(Deserialize, PartialEq, Debug, Clone)]
+#[serde(untagged)]
+pub enum SerializedPath {
+    Single(PathBuf),
+    /// Multiple directories and files combined in a single list of wallpapers
+    Pool(Vec<PathBuf>),
+    /// Directories and files tried in order, until one of them has any wallpaper
+    Fallback {
+        fallback: Vec<PathBuf>,
+    },
+}
+
+/// Directory or images of a day of the `weekdays` attribute
+#[derive(Deserialize, PartialEq, Debug, Clone)]
+#[serde(untagged)]
+pub enum SerializedWeekdayPaths {
+    Single(PathBuf),
+    List(Vec<PathBuf>),
+}
+
+/// Edge of the display listed in the `anchor` attribute
+#[derive(Deserialize, PartialEq, Eq, Debug, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum Edge {
+    Top,
+    Right,
+    Bottom,
+    Left,
+}
+
+impl Edge {
+    const ALL: [Edge; 4] = [Edge::Top, Edge::Right, Edge::Bottom, Edge::Left];
+
+    fn anchor(self) -> Anchor {
+        match self {
+            Edge::Top => Anchor::TOP,
+            Edge::Right => Anchor::RIGHT,
+            Edge::Bottom => Anchor::BOTTOM,
+            Edge::Left => Anchor::LEFT,
+        }
+    }
+}
+
+/// Layer of the compositor the wallpaper is drawn on
+#[derive(Deserialize, PartialEq, Eq, Debug, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum SerializedLayer {
+    Background,
+    Bottom,
+}
+
+impl From<SerializedLayer> for Layer {
+    fn from(layer: SerializedLayer) -> Self {
+        match layer {
+            SerializedLayer::Background => Layer::Background,
+            SerializedLayer::Bottom => Layer::Bottom,
+        }
+    }
+}
+
+/// Value of the `margin` attribute, the same for all the edges or one for each of them
+#[derive(Deserialize, PartialEq, Debug, Copy, Clone)]
+#[serde(untagged)]
+pub enum SerializedMargin {
+    All(i32),
+    /// Top, right, bottom and left
+    Sides([i32; 4]),
+}
+
+/// Value of the `backdrop` attribute, a color or the colors at the top and at the bottom of a
+/// gradient
+#[derive(Deserialize, PartialEq, Debug, Clone)]
+#[serde(untagged)]
+pub enum SerializedBackdrop {
+    Color(String),
+    Gradient(Vec<String>),
+}
+
+/// Value of the `offset` attribute, either a number or the side to align the wallpaper to
+#[derive(Deserialize, PartialEq, Debug, Copy, Clone)]
+#[serde(untagged)]
+pub enum SerializedOffset {
+    Value(f32),
+    Alignment(Alignment),
+}
+
+#[derive(Deserialize, PartialEq, Eq, Debug, Copy, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub enum Alignment {
+    Top,
+    Left,
+    Center,
+    Bottom,
+    Right,
+}
+
+impl SerializedOffset {
+    /// Only one side of the image is ever cropped or letterboxed, so top and left are the same
+    pub fn value(self) -> f32 {
+        match self {
+            SerializedOffset::Value(offset) => offset,
+            SerializedOffset::Alignment(Alignment::Top | Alignment::Left) => 0.0,
+            SerializedOffset::Alignment(Alignment::Center) => 0.5,
+            SerializedOffset::Alignment(Alignment::Bottom | Alignment::Right) => 1.0,
+        }
+    }
+}
+
 #[derive(Default, Deserialize, PartialEq, Debug, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct SerializedWallpaperInfo {
     #[serde(default, deserialize_with = "tilde_expansion_deserialize")]
-    pub path: Option<PathBuf>,
+    pub path: Option<SerializedPath>,
     #[serde(default, with = "humantime_serde")]
     pub duration: Option<Duration>,
     #[serde(rename = "apply-shadow")]
@@ -59,8 +187,15 @@ pub struct SerializedWallpaperInfo {
     pub mode: Option<BackgroundMode>,
     #[serde(rename = "queue-size")]
     pub queue_size: Option<usize>,
+    /// Number of the most recent images not picked again by the random sorting, `queue-size`
+    /// by default
+    #[serde(rename = "no-repeat")]
+    pub no_repeat: Option<usize>,
     #[serde(rename = "transition-time")]
     pub transition_time: Option<u32>,
+    /// Milliseconds to wait after loading a wallpaper before starting the transition
+    #[serde(rename = "transition-delay")]
+    pub transition_delay: Option<u32>,
 
     /// Determines if we should show the transition between black and first
     /// wallpaper. `Some(false)` means we instantly cut to the first wallpaper,
@@ -69,23 +204,257 @@ pub struct SerializedWallpaperInfo {
     /// See [crate::wallpaper_info::WallpaperInfo]
     #[serde(rename = "initial-transition")]
     pub initial_transition: Option<bool>,
+    /// Milliseconds to wait after the display has been configured before the first draw
+    #[serde(rename = "startup-delay")]
+    pub startup_delay: Option<u32>,
+    /// Determines if we should show the transition when reloading the configuration changes
+    /// the path. `Some(false)` means we instantly cut to the new wallpaper.
+    #[serde(rename = "reload-transition")]
+    pub reload_transition: Option<bool>,
+    /// Either a table describing the transition or `"none"` to swap the wallpapers instantly
+    #[serde(default, deserialize_with = "transition_deserialize")]
     pub transition: Option<Transition>,
+    /// List of transitions to pick from each time the wallpaper changes, replacing `transition`
+    #[serde(default, deserialize_with = "transitions_deserialize")]
+    pub transitions: Option<Vec<Transition>>,
+    /// Pick the transitions at random instead of in the order they have been listed
+    #[serde(rename = "random-transition")]
+    pub random_transition: Option<bool>,
+    /// Transition of the changes requested with wpaperctl, replacing `transition` for them
+    #[serde(
+        default,
+        rename = "manual-transition",
+        deserialize_with = "transition_deserialize"
+    )]
+    pub manual_transition: Option<Transition>,
+    #[serde(rename = "manual-transition-time")]
+    pub manual_transition_time: Option<u32>,
 
     /// Determine the offset for the wallpaper to be drawn into the screen
-    /// Must be from 0.0 to 1.0, by default is 0.0 in tile mode and 0.5 in all the others
+    /// Must be from 0.0 to 1.0, or one of top, left, center, bottom and right.
+    /// By default is 0.0 in tile mode and 0.5 in all the others
     ///
     /// See [crate::wallpaper_info::WallpaperInfo]
-    pub offset: Option<f32>,
+    pub offset: Option<SerializedOffset>,
+
+    /// Rotate the wallpaper counter-clockwise by 0, 90, 180 or 270 degrees, on top of the
+    /// transform of the display
+    pub rotate: Option<u16>,
+    /// Mirror the wallpaper horizontally, before rotating it
+    pub flip: Option<bool>,
 
     /// Assign these displays to a group that shows the same wallpaper
     pub group: Option<u8>,
+    /// Name of the display to copy the wallpaper and the transitions from, only read from the
+    /// display section
+    pub mirror: Option<String>,
+    /// Move the wallpaper of the day by this amount of images
+    #[serde(rename = "daily-offset")]
+    pub daily_offset: Option<usize>,
+    /// Directories and images only shown on some days of the week, listed for each day
+    pub weekdays: Option<BTreeMap<Weekday, SerializedWeekdayPaths>>,
+    /// Directory or image shown in place of `path` on each workspace, by name
+    pub workspaces: Option<BTreeMap<String, PathBuf>>,
+    /// Command printing the name of one of `conditions`, run each time the timer changes the
+    /// wallpaper
+    pub condition: Option<String>,
+    /// Stop waiting for the condition command after this amount of time
+    #[serde(default, rename = "condition-timeout", with = "humantime_serde")]
+    pub condition_timeout: Option<Duration>,
+    /// Directory or image shown in place of `path` for each output of `condition`
+    pub conditions: Option<BTreeMap<String, PathBuf>>,
+
+    /// Multiply the colors of the wallpaper, 1.0 by default
+    pub brightness: Option<f32>,
+    /// Increase or decrease the distance of the colors from the middle gray, 1.0 by default
+    pub contrast: Option<f32>,
+    /// Gamma correction applied to the wallpaper, 1.0 by default
+    pub gamma: Option<f32>,
+    /// Darken the corners of the wallpaper by this amount, from 0.0 (disabled) to 1.0
+    #[serde(rename = "vignette-strength")]
+    pub vignette_strength: Option<f32>,
+    /// Distance from the center where the vignette starts, from 0.0 to 1.0 (the corners)
+    #[serde(rename = "vignette-radius")]
+    pub vignette_radius: Option<f32>,
+
+    /// Multiply the brightness by this value when a fullscreen window covers the display
+    #[serde(rename = "fullscreen-brightness")]
+    pub fullscreen_brightness: Option<f32>,
+
+    /// Color temperature in Kelvin applied to the wallpaper during the night, e.g. 4000
+    #[serde(rename = "night-temperature")]
+    pub night_temperature: Option<u32>,
+    /// Time when the colors are back to normal, `07:00` by default
+    pub sunrise: Option<String>,
+    /// Time when the colors start to warm up, `19:00` by default
+    pub sunset: Option<String>,
+    /// Time needed to change the color temperature, 1 hour by default
+    #[serde(default, rename = "night-transition", with = "humantime_serde")]
+    pub night_transition: Option<Duration>,
+
+    /// Pause the automatic wallpaper sequence after the user has been idle for this long and
+    /// change the wallpaper when the user is back
+    #[serde(default, rename = "idle-timeout", with = "humantime_serde")]
+    pub idle_timeout: Option<Duration>,
+
+    /// Pause the automatic wallpaper sequence while the system is running on battery
+    #[serde(rename = "pause-on-battery")]
+    pub pause_on_battery: Option<bool>,
+
+    /// Pause the automatic wallpaper sequence while the pointer is on the display
+    #[serde(rename = "pause-on-hover")]
+    pub pause_on_hover: Option<bool>,
+
+    /// Times of the day when the wallpaper is not changed automatically, in the HH:MM-HH:MM
+    /// format
+    #[serde(rename = "do-not-disturb")]
+    pub do_not_disturb: Option<Vec<String>>,
+
+    /// Maximum number of frames drawn each second during transitions, 0 means uncapped
+    #[serde(rename = "max-fps")]
+    pub max_fps: Option<u32>,
+
+    /// Number of frames to wait for before posting a new frame, 0 to post it immediately and 1
+    /// (the default) to wait for the vertical sync
+    #[serde(rename = "swap-interval")]
+    pub swap_interval: Option<u32>,
+
+    /// Only update the region that changed from the previous wallpaper during transitions
+    #[serde(rename = "partial-transition")]
+    pub partial_transition: Option<bool>,
+    /// Wait for the wallpaper to be uploaded to the GPU before starting the transition
+    #[serde(rename = "transition-wait-upload")]
+    pub transition_wait_upload: Option<bool>,
+    /// Dither the frames of the transitions to hide the banding of the fades
+    #[serde(rename = "transition-dither")]
+    pub transition_dither: Option<bool>,
+    /// Start the transitions requested with wpaperctl from what is currently on the display
+    #[serde(rename = "transition-capture")]
+    pub transition_capture: Option<bool>,
+
+    /// Filter used when scaling the wallpaper, `linear` by default
+    #[serde(rename = "texture-filter")]
+    pub texture_filter: Option<TextureFilter>,
+
+    /// Downscale the images bigger than this factor of the display size when loading them
+    #[serde(rename = "max-image-scale")]
+    pub max_image_scale: Option<f32>,
+    /// Enlarge the images at most by this factor in `contain` mode, 1.5 by default
+    #[serde(rename = "max-upscale")]
+    pub max_upscale: Option<f32>,
+    /// Filter used to downscale the images, `triangle` by default
+    #[serde(rename = "downscale-filter")]
+    pub downscale_filter: Option<DownscaleFilter>,
+
+    /// Rotate the images as stored in their EXIF metadata, true by default
+    #[serde(rename = "exif-orientation")]
+    pub exif_orientation: Option<bool>,
+
+    /// Crop the images around their most detailed region in `center` mode, false by default
+    #[serde(rename = "auto-offset")]
+    pub auto_offset: Option<bool>,
+
+    /// Pan the wallpaper back and forth within this fraction of the image, from 0.0 to 1.0
+    #[serde(rename = "pan-range")]
+    pub pan_range: Option<f32>,
+
+    /// Time needed to pan from one end of the range to the other and back
+    #[serde(default, rename = "pan-duration", with = "humantime_serde")]
+    pub pan_duration: Option<Duration>,
+
+    /// Move the wallpaper with the pointer within this fraction of the image, from 0.0 to 1.0
+    #[serde(rename = "cursor-parallax")]
+    pub cursor_parallax: Option<f32>,
+
+    /// Command printing the list of wallpapers to pick from, one path per line.
+    /// It replaces `path`.
+    pub command: Option<String>,
+
+    /// Run the command again after this amount of time
+    #[serde(default, rename = "command-interval", with = "humantime_serde")]
+    pub command_interval: Option<Duration>,
+
+    /// File with the weights of the images picked by the random sorting, see [`crate::ranking`]
+    pub ranking: Option<PathBuf>,
+
+    /// File with the images marked with `wpaperctl add-favorite`, see [`crate::favorites`]
+    pub favorites: Option<PathBuf>,
+
+    /// Fetch the wallpapers given as HTTP(S) URLs, false by default
+    #[serde(rename = "allow-network")]
+    pub allow_network: Option<bool>,
+
+    /// Only pick the images with an aspect ratio close to the display one
+    #[serde(rename = "match-aspect-ratio")]
+    pub match_aspect_ratio: Option<bool>,
+    /// How much the aspect ratio of the images can differ, as a fraction of the display one
+    #[serde(rename = "aspect-ratio-tolerance")]
+    pub aspect_ratio_tolerance: Option<f32>,
+
+    /// Skip the images smaller than this resolution, in the WIDTHxHEIGHT format
+    #[serde(rename = "min-resolution")]
+    pub min_resolution: Option<String>,
+
+    /// Width of the border drawn around the wallpaper, in logical pixels
+    #[serde(rename = "border-width")]
+    pub border_width: Option<u32>,
+    /// Color of the border, in the #RRGGBB or #RRGGBBAA format
+    #[serde(rename = "border-color")]
+    pub border_color: Option<String>,
+
+    /// Color or gradient shown through the transparent pixels of the images
+    pub backdrop: Option<SerializedBackdrop>,
+
+    /// Edges of the display the wallpaper is attached to, all of them by default
+    pub anchor: Option<Vec<Edge>>,
+    /// Distance of the wallpaper from the anchored edges, in logical pixels
+    pub margin: Option<SerializedMargin>,
+    /// Size of the wallpaper in the WIDTHxHEIGHT format, in logical pixels, required along the
+    /// sides that are not anchored to both edges
+    #[serde(rename = "layer-size")]
+    pub layer_size: Option<String>,
+    /// Layer the wallpaper is drawn on, `background` by default
+    pub layer: Option<SerializedLayer>,
 }
 
 impl SerializedWallpaperInfo {
+    /// Return how long `transition` runs: the time of the display section, then the one of
+    /// default, which applies to every display that doesn't set its own, then the time of the
+    /// transition itself
+    fn transition_time(&self, default: &Self, transition: &Transition) -> u32 {
+        self.transition_time
+            .or(default.transition_time)
+            .unwrap_or_else(|| transition.default_transition_time())
+    }
+
     pub fn apply_and_validate(&self, default: &Self) -> Result<WallpaperInfo> {
+        if self.path.is_some() && self.command.is_some() {
+            return Err(anyhow!(
+                "attributes {} and {} cannot be both set",
+                "path".bold().italic().blue(),
+                "command".bold().italic().blue(),
+            ))
+            .with_suggestion(|| "remove one of them from the display section");
+        }
+        // The attribute set in the display section has precedence over the one in default
+        let command = match (&self.command, &default.command) {
+            (None, Some(_)) if self.path.is_some() => None,
+            (Some(command), _) | (None, Some(command)) => Some(command.clone()),
+            (None, None) => None,
+        };
+        let command_interval = match (&self.command_interval, &default.command_interval) {
+            (Some(interval), _) | (None, Some(interval)) if command.is_some() => Some(*interval),
+            _ => None,
+        };
+
         let mut path_inherited = false;
+        let empty_path = SerializedPath::Single(PathBuf::new());
         let path = match (&self.path, &default.path) {
-            (Some(path), None) | (Some(path), Some(_))=> path,
+            // The list of wallpapers comes from the command
+            _ if command.is_some() => &empty_path,
+            // The wallpapers come from the mirrored display
+            (None, None) if self.mirror.is_some() => &empty_path,
+            (Some(path), None) | (Some(path), Some(_)) => path,
             (None, Some(path)) => {
                 path_inherited = true;
                 path
@@ -102,10 +471,46 @@ impl SerializedWallpaperInfo {
                     )
                 });
             }
-        }
-        .to_path_buf();
-        // Ensure that a path exists
-        if !path.exists() {
+        };
+        // Like a command, a pool or a fallback chain provide the wallpapers in place of the path
+        let (path, pool, fallback) = match path {
+            SerializedPath::Single(path) => (path.to_path_buf(), Vec::new(), Vec::new()),
+            SerializedPath::Pool(paths) | SerializedPath::Fallback { fallback: paths }
+                if paths.is_empty() =>
+            {
+                return Err(anyhow!(
+                    "attribute {} cannot be an empty list",
+                    "path".bold().italic().blue(),
+                ))
+                .with_suggestion(|| "list the directories and files to pick the wallpapers from");
+            }
+            SerializedPath::Pool(paths) if paths.len() == 1 => {
+                (paths[0].clone(), Vec::new(), Vec::new())
+            }
+            SerializedPath::Pool(paths) => (PathBuf::new(), paths.clone(), Vec::new()),
+            SerializedPath::Fallback { fallback } => (PathBuf::new(), Vec::new(), fallback.clone()),
+        };
+        // A mirror without wallpapers of its own ignores the attributes inherited from default
+        let mirror_only = self.mirror.is_some()
+            && command.is_none()
+            && path.as_os_str().is_empty()
+            && pool.is_empty()
+            && fallback.is_empty();
+        // Ensure that all the paths exist, a fallback chain only needs one of them
+        let paths_to_check = match (&command, pool.is_empty()) {
+            (Some(_), _) => &[][..],
+            (None, true) if mirror_only => &[][..],
+            (None, true) if !fallback.is_empty() => {
+                if fallback.iter().any(|path| path_exists(path)) {
+                    &[][..]
+                } else {
+                    &fallback[..]
+                }
+            }
+            (None, true) => std::slice::from_ref(&path),
+            (None, false) => &pool[..],
+        };
+        if let Some(path) = paths_to_check.iter().find(|path| !path_exists(path)) {
             return Err(anyhow!(
                 "path {} for attribute {}{} does not exist",
                 path.to_string_lossy().italic().yellow(),
@@ -127,15 +532,65 @@ impl SerializedWallpaperInfo {
             });
         }
 
+        let ranking = self
+            .ranking
+            .as_ref()
+            .or(default.ranking.as_ref())
+            .map(|ranking| expand_tilde(ranking));
+
+        let favorites = self
+            .favorites
+            .as_ref()
+            .or(default.favorites.as_ref())
+            .map(|favorites| expand_tilde(favorites));
+
+        let allow_network = match (&self.allow_network, &default.allow_network) {
+            (Some(allow_network), _) | (None, Some(allow_network)) => *allow_network,
+            (None, None) => false,
+        };
+        if let Some(url) = std::iter::once(&path)
+            .chain(&pool)
+            .chain(&fallback)
+            .find(|path| is_url(path))
+        {
+            if !allow_network {
+                return Err(anyhow!(
+                    "path {} for attribute {} is a URL but network access is not allowed",
+                    url.to_string_lossy().italic().yellow(),
+                    "path".bold().italic().blue(),
+                ))
+                .with_suggestion(|| {
+                    format!(
+                        "set attribute {} to true to fetch the wallpapers from the network",
+                        "allow-network".bold().italic().blue(),
+                    )
+                });
+            }
+            if !cfg!(feature = "http") {
+                return Err(anyhow!(
+                    "path {} for attribute {} is a URL but wpaperd has been built without the http feature",
+                    url.to_string_lossy().italic().yellow(),
+                    "path".bold().italic().blue(),
+                ))
+                .with_suggestion(|| "build wpaperd with `--features http`");
+            }
+        }
+
+        // A URL is a single image like a file, but it can be fetched again periodically
+        let is_single_image = path.is_file() || is_url(&path);
         let duration = match (&self.duration, &default.duration) {
             // duration is inherited from default, but this section set path to a file, ignore
             // duration
-            (None, Some(_)) if path.is_file() && !path_inherited => None,
+            (None, Some(_)) if (is_single_image && !path_inherited) || mirror_only => None,
             (Some(duration), _) | (None, Some(duration)) => Some(*duration),
             (None, None) => None,
         };
-        // duration can only be set when path is a directory
-        if duration.is_some() && !path.is_dir() {
+        // A command, a pool and a fallback chain work like a directory, they provide multiple
+        // wallpapers
+        let is_list =
+            path.is_dir() || command.is_some() || !pool.is_empty() || !fallback.is_empty();
+        // duration can only be set when path is a directory or a URL
+        if duration.is_some() && !is_list && !is_url(&path) {
             // Do no use bail! to add suggestion
             return Err(anyhow!(
                 "Attribute {} is set to a file and attribute {} is also set.",
@@ -152,19 +607,19 @@ impl SerializedWallpaperInfo {
         }
 
         let sorting = match (&self.sorting, &default.sorting) {
-            (None, Some(_)) if path.is_file() && !path_inherited => None,
+            (None, Some(_)) if (is_single_image && !path_inherited) || mirror_only => None,
             (Some(sorting), _) | (None, Some(sorting)) => Some(*sorting),
             (None, None) => None,
         };
 
         let group = match (&self.group, &default.group) {
-            (None, Some(_)) if path.is_file() && !path_inherited => None,
+            (None, Some(_)) if (is_single_image && !path_inherited) || mirror_only => None,
             (Some(sorting), _) | (None, Some(sorting)) => Some(*sorting),
             (None, None) => None,
         };
 
         // sorting and group can only be set when path is a directory
-        if (sorting.is_some() || group.is_some()) && !path.is_dir() {
+        if (sorting.is_some() || group.is_some()) && !is_list {
             // Do no use bail! to add suggestion
             return Err(anyhow!(
                 "{} cannot be set when {} is a directory",
@@ -201,21 +656,133 @@ impl SerializedWallpaperInfo {
         } else {
             sorting
         };
-        let sorting = sorting.map(|sorting| {
-            if let Some(group) = group {
-                match sorting {
-                    SerializedSorting::Random => Sorting::GroupedRandom { group },
-                    SerializedSorting::Ascending => todo!(),
-                    SerializedSorting::Descending => todo!(),
+        let grouped_sorting_error = |name: &str| {
+            anyhow!(
+                "sorting {} cannot be used with {}",
+                name.bold().italic().blue(),
+                "group".bold().italic().blue()
+            )
+        };
+        let sorting = sorting
+            .map(|sorting| {
+                Ok(if let Some(group) = group {
+                    match sorting {
+                        SerializedSorting::Random => Sorting::GroupedRandom { group },
+                        SerializedSorting::Ascending => todo!(),
+                        SerializedSorting::Descending => todo!(),
+                        SerializedSorting::RandomStart => {
+                            return Err(grouped_sorting_error("random-start"))
+                        }
+                        SerializedSorting::Daily => return Err(grouped_sorting_error("daily")),
+                        SerializedSorting::Shuffle => return Err(grouped_sorting_error("shuffle")),
+                    }
+                } else {
+                    match sorting {
+                        SerializedSorting::Random => Sorting::Random,
+                        SerializedSorting::Ascending => Sorting::Ascending,
+                        SerializedSorting::Descending => Sorting::Descending,
+                        SerializedSorting::RandomStart => Sorting::RandomStart,
+                        SerializedSorting::Daily => Sorting::Daily,
+                        SerializedSorting::Shuffle => Sorting::Shuffle,
+                    }
+                })
+            })
+            .transpose()?;
+
+        let daily_offset = match (&self.daily_offset, &default.daily_offset) {
+            (Some(daily_offset), _) | (None, Some(daily_offset)) => *daily_offset,
+            (None, None) => 0,
+        };
+        let mut weekdays: Vec<WeekdayPin> = Vec::new();
+        if let Some(days) = self.weekdays.as_ref().or(default.weekdays.as_ref()) {
+            for (day, paths) in days {
+                let paths = match paths {
+                    SerializedWeekdayPaths::Single(path) => std::slice::from_ref(path),
+                    SerializedWeekdayPaths::List(paths) => &paths[..],
+                };
+                for path in paths.iter().map(|path| expand_tilde(path)) {
+                    if !path.exists() {
+                        return Err(anyhow!(
+                            "path {} for attribute {} does not exist",
+                            path.to_string_lossy().italic().yellow(),
+                            "weekdays".bold().italic().blue(),
+                        ))
+                        .with_suggestion(|| {
+                            "pin existing directories and images to the days of the week"
+                        });
+                    }
+                    match weekdays.iter_mut().find(|pin| pin.path == path) {
+                        Some(pin) => pin.add(*day),
+                        None => {
+                            let mut pin = WeekdayPin::new(path);
+                            pin.add(*day);
+                            weekdays.push(pin);
+                        }
+                    }
                 }
-            } else {
-                match sorting {
-                    SerializedSorting::Random => Sorting::Random,
-                    SerializedSorting::Ascending => Sorting::Ascending,
-                    SerializedSorting::Descending => Sorting::Descending,
+            }
+        }
+
+        let mut workspaces = BTreeMap::new();
+        if let Some(paths) = self.workspaces.as_ref().or(default.workspaces.as_ref()) {
+            for (workspace, path) in paths {
+                let path = expand_tilde(path);
+                if !path.exists() {
+                    return Err(anyhow!(
+                        "path {} for attribute {} does not exist",
+                        path.to_string_lossy().italic().yellow(),
+                        "workspaces".bold().italic().blue(),
+                    ))
+                    .with_suggestion(|| {
+                        format!(
+                            "set an existing directory or image for workspace {}",
+                            workspace.italic().yellow()
+                        )
+                    });
                 }
+                workspaces.insert(workspace.clone(), path);
             }
-        });
+        }
+
+        let condition = self
+            .condition
+            .as_ref()
+            .or(default.condition.as_ref())
+            .cloned();
+        let condition_timeout = match (&self.condition_timeout, &default.condition_timeout) {
+            (Some(timeout), _) | (None, Some(timeout)) => *timeout,
+            (None, None) => DEFAULT_CONDITION_TIMEOUT,
+        };
+        let mut conditions = BTreeMap::new();
+        if let Some(paths) = self.conditions.as_ref().or(default.conditions.as_ref()) {
+            for (name, path) in paths {
+                let path = expand_tilde(path);
+                if !path.exists() {
+                    return Err(anyhow!(
+                        "path {} for attribute {} does not exist",
+                        path.to_string_lossy().italic().yellow(),
+                        "conditions".bold().italic().blue(),
+                    ))
+                    .with_suggestion(|| {
+                        format!(
+                            "set an existing directory or image for condition {}",
+                            name.italic().yellow()
+                        )
+                    });
+                }
+                conditions.insert(name.clone(), path);
+            }
+        }
+        if condition.is_none() != conditions.is_empty() {
+            return Err(anyhow!(
+                "attributes {} and {} must be set together",
+                "condition".bold().italic().blue(),
+                "conditions".bold().italic().blue(),
+            ))
+            .with_suggestion(|| {
+                "set the command and the wallpapers shown for each of the names it prints"
+            });
+        }
 
         let mode = match (&self.mode, &default.mode) {
             (Some(mode), _) | (None, Some(mode)) => *mode,
@@ -225,37 +792,561 @@ impl SerializedWallpaperInfo {
             (Some(size), _) | (None, Some(size)) => *size,
             (None, None) => ImagePicker::DEFAULT_DRAWN_IMAGES_QUEUE_SIZE,
         };
+        let no_repeat_size = self
+            .no_repeat
+            .or(default.no_repeat)
+            .unwrap_or(drawn_images_queue_size);
         let initial_transition = match (&self.initial_transition, &default.initial_transition) {
             (Some(initial_transition), _) | (None, Some(initial_transition)) => *initial_transition,
             (None, None) => true,
         };
+        let startup_delay = match (&self.startup_delay, &default.startup_delay) {
+            (Some(startup_delay), _) | (None, Some(startup_delay)) => *startup_delay,
+            (None, None) => 0,
+        };
+        let reload_transition = match (&self.reload_transition, &default.reload_transition) {
+            (Some(reload_transition), _) | (None, Some(reload_transition)) => *reload_transition,
+            (None, None) => true,
+        };
 
         let transition = match (&self.transition, &default.transition) {
             (Some(transition), _) | (None, Some(transition)) => transition.clone(),
-            (None, None) => Transition::Fade {},
+            (None, None) => Transition::Fade {
+                through_average: None,
+            },
         };
 
-        let transition_time = match (&self.transition_time, &default.transition_time) {
-            (Some(transition_time), _) | (None, Some(transition_time)) => *transition_time,
-            (None, None) => transition.default_transition_time(),
+        let transition_time = self.transition_time(default, &transition);
+        if self.transition.is_some() && self.transitions.is_some() {
+            return Err(anyhow!(
+                "attributes {} and {} cannot be set at the same time",
+                "transition".bold().italic().blue(),
+                "transitions".bold().italic().blue()
+            )
+            .with_suggestion(|| {
+                format!(
+                    "Add the {} to the {} list",
+                    "transition".bold().italic().blue(),
+                    "transitions".bold().italic().blue()
+                )
+            }));
+        }
+        // The transitions set in the section replace the transition of the default one
+        let transitions = match (&self.transition, &self.transitions, &default.transitions) {
+            (None, Some(transitions), _) | (None, None, Some(transitions)) => transitions.clone(),
+            _ => Vec::new(),
+        };
+        if self.transitions.as_ref().is_some_and(Vec::is_empty) {
+            return Err(anyhow!(
+                "attribute {} cannot be empty",
+                "transitions".bold().italic().blue()
+            )
+            .with_suggestion(|| {
+                format!(
+                    "Remove {} or add at least one transition to it",
+                    "transitions".bold().italic().blue()
+                )
+            }));
+        }
+        // Each transition runs for its own default time unless transition-time is set
+        let transitions = transitions
+            .into_iter()
+            .map(|transition| {
+                let time = self.transition_time(default, &transition);
+                (transition, time)
+            })
+            .collect();
+        let manual_transition_time = self
+            .manual_transition_time
+            .or(default.manual_transition_time);
+        let manual_transition = match (&self.manual_transition, &default.manual_transition) {
+            (Some(transition), _) | (None, Some(transition)) => Some((
+                transition.clone(),
+                manual_transition_time.unwrap_or_else(|| transition.default_transition_time()),
+            )),
+            (None, None) if manual_transition_time.is_some() => {
+                return Err(anyhow!(
+                    "attribute {} requires {}",
+                    "manual-transition-time".bold().italic().blue(),
+                    "manual-transition".bold().italic().blue()
+                )
+                .with_suggestion(|| {
+                    format!(
+                        "Set {} too, e.g. {}",
+                        "manual-transition".bold().italic().blue(),
+                        "manual-transition.fade = {}".bold().italic().blue()
+                    )
+                }));
+            }
+            (None, None) => None,
+        };
+        let random_transition = match (&self.random_transition, &default.random_transition) {
+            (Some(random_transition), _) | (None, Some(random_transition)) => *random_transition,
+            (None, None) => true,
+        };
+        let transition_delay = match (&self.transition_delay, &default.transition_delay) {
+            (Some(transition_delay), _) | (None, Some(transition_delay)) => *transition_delay,
+            (None, None) => 0,
         };
 
         let offset = match (&self.offset, &default.offset) {
-            (Some(offset), _) | (None, Some(offset)) => Some(*offset),
+            (Some(offset), _) | (None, Some(offset)) => Some(offset.value()),
             (None, None) => None,
         };
 
+        let rotate = match (&self.rotate, &default.rotate) {
+            (Some(rotate), _) | (None, Some(rotate)) => *rotate,
+            (None, None) => 0,
+        };
+        let flip = match (&self.flip, &default.flip) {
+            (Some(flip), _) | (None, Some(flip)) => *flip,
+            (None, None) => false,
+        };
+        let transform = match (rotate, flip) {
+            (0, false) => Transform::Normal,
+            (90, false) => Transform::_90,
+            (180, false) => Transform::_180,
+            (270, false) => Transform::_270,
+            (0, true) => Transform::Flipped,
+            (90, true) => Transform::Flipped90,
+            (180, true) => Transform::Flipped180,
+            (270, true) => Transform::Flipped270,
+            _ => {
+                return Err(anyhow!(
+                    "attribute {} must be 0, 90, 180 or 270",
+                    "rotate".bold().italic().blue()
+                ))
+            }
+        };
+
+        let brightness = match (&self.brightness, &default.brightness) {
+            (Some(brightness), _) | (None, Some(brightness)) => *brightness,
+            (None, None) => 1.0,
+        };
+        let contrast = match (&self.contrast, &default.contrast) {
+            (Some(contrast), _) | (None, Some(contrast)) => *contrast,
+            (None, None) => 1.0,
+        };
+        let gamma = match (&self.gamma, &default.gamma) {
+            (Some(gamma), _) | (None, Some(gamma)) => *gamma,
+            (None, None) => 1.0,
+        };
+        let fullscreen_brightness =
+            match (&self.fullscreen_brightness, &default.fullscreen_brightness) {
+                (Some(brightness), _) | (None, Some(brightness)) => Some(*brightness),
+                (None, None) => None,
+            };
+        if let Some((attribute, _)) = [
+            ("brightness", brightness),
+            ("contrast", contrast),
+            (
+                "fullscreen-brightness",
+                fullscreen_brightness.unwrap_or_default(),
+            ),
+        ]
+        .into_iter()
+        .find(|(_, value)| *value < 0.0)
+        {
+            return Err(anyhow!(
+                "attribute {} cannot be negative",
+                attribute.bold().italic().blue()
+            ));
+        }
+        // gamma is used as divisor in the shader
+        if gamma <= 0.0 {
+            return Err(anyhow!(
+                "attribute {} must be greater than 0",
+                "gamma".bold().italic().blue()
+            ));
+        }
+        let vignette_strength = match (&self.vignette_strength, &default.vignette_strength) {
+            (Some(strength), _) | (None, Some(strength)) => *strength,
+            (None, None) => 0.0,
+        };
+        let vignette_radius = match (&self.vignette_radius, &default.vignette_radius) {
+            (Some(radius), _) | (None, Some(radius)) => *radius,
+            (None, None) => 0.5,
+        };
+        if let Some((attribute, _)) = [
+            ("vignette-strength", vignette_strength),
+            ("vignette-radius", vignette_radius),
+        ]
+        .into_iter()
+        .find(|(_, value)| !(0.0..=1.0).contains(value))
+        {
+            return Err(anyhow!(
+                "attribute {} must be between 0.0 and 1.0",
+                attribute.bold().italic().blue()
+            ));
+        }
+        // The darkening starts at the radius and ends in the corners
+        if vignette_radius == 1.0 && vignette_strength > 0.0 {
+            return Err(anyhow!(
+                "attribute {} must be lower than 1.0",
+                "vignette-radius".bold().italic().blue()
+            ))
+            .with_suggestion(|| "the vignette needs some room before the corners");
+        }
+
+        let night_temperature = match (&self.night_temperature, &default.night_temperature) {
+            (Some(temperature), _) | (None, Some(temperature)) => Some(*temperature),
+            (None, None) => None,
+        };
+        let sunrise = match (&self.sunrise, &default.sunrise) {
+            (Some(sunrise), _) | (None, Some(sunrise)) => parse_time_of_day("sunrise", sunrise)?,
+            (None, None) => NightLight::DEFAULT_SUNRISE,
+        };
+        let sunset = match (&self.sunset, &default.sunset) {
+            (Some(sunset), _) | (None, Some(sunset)) => parse_time_of_day("sunset", sunset)?,
+            (None, None) => NightLight::DEFAULT_SUNSET,
+        };
+        let night_transition = match (&self.night_transition, &default.night_transition) {
+            (Some(night_transition), _) | (None, Some(night_transition)) => *night_transition,
+            (None, None) => NightLight::DEFAULT_TRANSITION,
+        };
+        if let Some(temperature) = night_temperature {
+            if !(1000..=6500).contains(&temperature) {
+                return Err(anyhow!(
+                    "attribute {} must be between 1000 and 6500",
+                    "night-temperature".bold().italic().blue()
+                ));
+            }
+        }
+        let night_light = night_temperature.map(|temperature| NightLight {
+            temperature,
+            sunrise,
+            sunset,
+            transition: night_transition,
+        });
+
+        let idle_timeout = match (&self.idle_timeout, &default.idle_timeout) {
+            (Some(idle_timeout), _) | (None, Some(idle_timeout)) => Some(*idle_timeout),
+            (None, None) => None,
+        };
+        let pause_on_battery = match (&self.pause_on_battery, &default.pause_on_battery) {
+            (Some(pause_on_battery), _) | (None, Some(pause_on_battery)) => *pause_on_battery,
+            (None, None) => false,
+        };
+        let pause_on_hover = match (&self.pause_on_hover, &default.pause_on_hover) {
+            (Some(pause_on_hover), _) | (None, Some(pause_on_hover)) => *pause_on_hover,
+            (None, None) => false,
+        };
+        let do_not_disturb = match (&self.do_not_disturb, &default.do_not_disturb) {
+            (Some(windows), _) | (None, Some(windows)) => windows
+                .iter()
+                .map(|window| parse_time_window("do-not-disturb", window))
+                .collect::<Result<_>>()?,
+            (None, None) => Vec::new(),
+        };
+
+        let max_fps = match (&self.max_fps, &default.max_fps) {
+            (Some(max_fps), _) | (None, Some(max_fps)) => *max_fps,
+            (None, None) => 0,
+        };
+
+        let swap_interval = match (&self.swap_interval, &default.swap_interval) {
+            (Some(swap_interval), _) | (None, Some(swap_interval)) => *swap_interval,
+            (None, None) => 1,
+        };
+        let swap_interval = i32::try_from(swap_interval).map_err(|_| {
+            anyhow!(
+                "attribute {} is too big",
+                "swap-interval".bold().italic().blue()
+            )
+        })?;
+
+        let partial_transition = match (&self.partial_transition, &default.partial_transition) {
+            (Some(partial_transition), _) | (None, Some(partial_transition)) => *partial_transition,
+            (None, None) => false,
+        };
+        let transition_wait_upload = match (
+            &self.transition_wait_upload,
+            &default.transition_wait_upload,
+        ) {
+            (Some(wait_upload), _) | (None, Some(wait_upload)) => *wait_upload,
+            (None, None) => false,
+        };
+        let transition_dither = match (&self.transition_dither, &default.transition_dither) {
+            (Some(dither), _) | (None, Some(dither)) => *dither,
+            (None, None) => false,
+        };
+        let transition_capture = match (&self.transition_capture, &default.transition_capture) {
+            (Some(capture), _) | (None, Some(capture)) => *capture,
+            (None, None) => false,
+        };
+
+        let texture_filter = match (&self.texture_filter, &default.texture_filter) {
+            (Some(texture_filter), _) | (None, Some(texture_filter)) => *texture_filter,
+            (None, None) => TextureFilter::default(),
+        };
+
+        let max_image_scale = match (&self.max_image_scale, &default.max_image_scale) {
+            (Some(max_image_scale), _) | (None, Some(max_image_scale)) => Some(*max_image_scale),
+            (None, None) => None,
+        };
+        if max_image_scale.is_some_and(|max_image_scale| !(1.0..).contains(&max_image_scale)) {
+            return Err(anyhow!(
+                "attribute {} must be at least 1.0",
+                "max-image-scale".bold().italic().blue()
+            ))
+            .with_suggestion(|| "smaller values would make the wallpaper blurry");
+        }
+        let max_upscale = match (&self.max_upscale, &default.max_upscale) {
+            (Some(max_upscale), _) | (None, Some(max_upscale)) => *max_upscale,
+            (None, None) => DEFAULT_MAX_UPSCALE,
+        };
+        if !(1.0..).contains(&max_upscale) {
+            return Err(anyhow!(
+                "attribute {} must be at least 1.0",
+                "max-upscale".bold().italic().blue()
+            ))
+            .with_suggestion(|| "1.0 shows the small images at their own size");
+        }
+        let downscale_filter = match (&self.downscale_filter, &default.downscale_filter) {
+            (Some(downscale_filter), _) | (None, Some(downscale_filter)) => *downscale_filter,
+            (None, None) => DownscaleFilter::default(),
+        };
+        let exif_orientation = match (&self.exif_orientation, &default.exif_orientation) {
+            (Some(exif_orientation), _) | (None, Some(exif_orientation)) => *exif_orientation,
+            (None, None) => true,
+        };
+        let auto_offset = match (&self.auto_offset, &default.auto_offset) {
+            (Some(auto_offset), _) | (None, Some(auto_offset)) => *auto_offset,
+            (None, None) => false,
+        };
+
+        let pan_range = match (&self.pan_range, &default.pan_range) {
+            (Some(pan_range), _) | (None, Some(pan_range)) => *pan_range,
+            (None, None) => 0.0,
+        };
+        if !(0.0..=1.0).contains(&pan_range) {
+            return Err(anyhow!(
+                "attribute {} must be between 0.0 and 1.0",
+                "pan-range".bold().italic().blue()
+            ));
+        }
+        let cursor_parallax = match (&self.cursor_parallax, &default.cursor_parallax) {
+            (Some(cursor_parallax), _) | (None, Some(cursor_parallax)) => *cursor_parallax,
+            (None, None) => 0.0,
+        };
+        if !(0.0..=1.0).contains(&cursor_parallax) {
+            return Err(anyhow!(
+                "attribute {} must be between 0.0 and 1.0",
+                "cursor-parallax".bold().italic().blue()
+            ));
+        }
+        let match_aspect_ratio = match (&self.match_aspect_ratio, &default.match_aspect_ratio) {
+            (Some(match_aspect_ratio), _) | (None, Some(match_aspect_ratio)) => *match_aspect_ratio,
+            (None, None) => false,
+        };
+        let aspect_ratio_tolerance = match (
+            &self.aspect_ratio_tolerance,
+            &default.aspect_ratio_tolerance,
+        ) {
+            (Some(tolerance), _) | (None, Some(tolerance)) => *tolerance,
+            (None, None) => WallpaperInfo::DEFAULT_ASPECT_RATIO_TOLERANCE,
+        };
+        if aspect_ratio_tolerance < 0.0 {
+            return Err(anyhow!(
+                "attribute {} cannot be negative",
+                "aspect-ratio-tolerance".bold().italic().blue()
+            ));
+        }
+
+        let min_resolution = match (&self.min_resolution, &default.min_resolution) {
+            (Some(min_resolution), _) | (None, Some(min_resolution)) => {
+                Some(parse_resolution("min-resolution", min_resolution)?)
+            }
+            (None, None) => None,
+        };
+
+        let border_width = match (&self.border_width, &default.border_width) {
+            (Some(border_width), _) | (None, Some(border_width)) => *border_width,
+            (None, None) => Border::default().width,
+        };
+        let border_color = match (&self.border_color, &default.border_color) {
+            (Some(border_color), _) | (None, Some(border_color)) => {
+                parse_color("border-color", border_color)?
+            }
+            (None, None) => Border::default().color,
+        };
+        let backdrop = match self.backdrop.as_ref().or(default.backdrop.as_ref()) {
+            Some(SerializedBackdrop::Color(color)) => {
+                let color = parse_color("backdrop", color)?;
+                Backdrop {
+                    top: color,
+                    bottom: color,
+                }
+            }
+            Some(SerializedBackdrop::Gradient(colors)) => match &colors[..] {
+                [top, bottom] => Backdrop {
+                    top: parse_color("backdrop", top)?,
+                    bottom: parse_color("backdrop", bottom)?,
+                },
+                _ => {
+                    return Err(anyhow!(
+                        "attribute {} must be a color or a list of 2 colors",
+                        "backdrop".bold().italic().blue()
+                    ))
+                    .with_suggestion(|| {
+                        "list the color at the top of the wallpaper and the one at the bottom"
+                    })
+                }
+            },
+            None => Backdrop::default(),
+        };
+
+        let anchor = match self.anchor.as_ref().or(default.anchor.as_ref()) {
+            Some(edges) => edges
+                .iter()
+                .fold(Anchor::empty(), |anchor, edge| anchor | edge.anchor()),
+            None => LayerPlacement::default().anchor,
+        };
+        let margin = match (&self.margin, &default.margin) {
+            (Some(margin), _) | (None, Some(margin)) => match *margin {
+                // Only the anchored edges have a margin
+                SerializedMargin::All(margin) => Edge::ALL.map(|edge| {
+                    if anchor.contains(edge.anchor()) {
+                        margin
+                    } else {
+                        0
+                    }
+                }),
+                SerializedMargin::Sides(margin) => margin,
+            },
+            (None, None) => [0; 4],
+        };
+        let layer = match (&self.layer, &default.layer) {
+            (Some(layer), _) | (None, Some(layer)) => (*layer).into(),
+            (None, None) => Layer::Background,
+        };
+        let layer_size = match (&self.layer_size, &default.layer_size) {
+            (Some(layer_size), _) | (None, Some(layer_size)) => {
+                parse_resolution("layer-size", layer_size)?
+            }
+            (None, None) => (0, 0),
+        };
+        // The compositor stretches the surface between the anchors along the sides without a size
+        for (name, size, edges, edge_names) in [
+            (
+                "width",
+                layer_size.0,
+                Anchor::LEFT | Anchor::RIGHT,
+                "left and right",
+            ),
+            (
+                "height",
+                layer_size.1,
+                Anchor::TOP | Anchor::BOTTOM,
+                "top and bottom",
+            ),
+        ] {
+            if size == 0 && !anchor.contains(edges) {
+                return Err(anyhow!(
+                    "attribute {} must set a {name} when {} doesn't have both {edge_names}",
+                    "layer-size".bold().italic().blue(),
+                    "anchor".bold().italic().blue(),
+                ))
+                .with_suggestion(|| "list both edges in anchor, or set the size of the wallpaper");
+            }
+        }
+        for (edge, margin) in Edge::ALL.into_iter().zip(margin) {
+            if margin != 0 && !anchor.contains(edge.anchor()) {
+                return Err(anyhow!(
+                    "attribute {} is set for the edge {}, which is not in {}",
+                    "margin".bold().italic().blue(),
+                    format!("{edge:?}").to_lowercase().italic().yellow(),
+                    "anchor".bold().italic().blue(),
+                ))
+                .with_suggestion(|| "the margins only move the wallpaper from the anchored edges");
+            }
+        }
+
+        let pan_duration = match (&self.pan_duration, &default.pan_duration) {
+            (Some(pan_duration), _) | (None, Some(pan_duration)) => *pan_duration,
+            (None, None) => WallpaperInfo::DEFAULT_PAN_DURATION,
+        };
+        if pan_duration.is_zero() {
+            return Err(anyhow!(
+                "attribute {} cannot be zero",
+                "pan-duration".bold().italic().blue()
+            ));
+        }
+
         Ok(WallpaperInfo {
             path,
+            pool,
+            fallback,
             duration,
             apply_shadow: false,
             sorting,
+            daily_offset,
+            weekdays,
+            workspaces,
+            condition,
+            condition_timeout,
+            conditions,
             mode,
             drawn_images_queue_size,
+            no_repeat_size,
             transition_time,
+            transition_delay,
             initial_transition,
+            startup_delay,
+            reload_transition,
             transition,
+            transitions,
+            random_transition,
+            manual_transition,
             offset,
+            transform,
+            color_adjustment: ColorAdjustment {
+                brightness,
+                contrast,
+                gamma,
+                vignette_strength,
+                vignette_radius,
+            },
+            fullscreen_brightness,
+            night_light,
+            idle_timeout,
+            pause_on_battery,
+            pause_on_hover,
+            do_not_disturb,
+            max_fps,
+            swap_interval,
+            partial_transition,
+            transition_wait_upload,
+            transition_dither,
+            transition_capture,
+            texture_filter,
+            max_image_scale,
+            max_upscale,
+            downscale_filter,
+            exif_orientation,
+            auto_offset,
+            pan_range,
+            cursor_parallax,
+            pan_duration,
+            command,
+            command_interval,
+            ranking,
+            favorites,
+            allow_network,
+            mirror: self.mirror.clone(),
+            aspect_ratio_tolerance: match_aspect_ratio.then_some(aspect_ratio_tolerance),
+            min_resolution,
+            border: Border {
+                width: border_width,
+                color: border_color,
+            },
+            backdrop,
+            layer_placement: LayerPlacement {
+                anchor,
+                margin,
+                size: layer_size,
+            },
+            layer,
         })
     }
 }
@@ -268,16 +1359,54 @@ pub struct Config {
     default: SerializedWallpaperInfo,
     #[serde(skip)]
     any: SerializedWallpaperInfo,
+    /// File read, either the configuration of the user or a profile
     #[serde(skip)]
     pub path: PathBuf,
+    /// Configuration of the user, the profiles are read from the `profiles` directory next to it
+    #[serde(skip)]
+    pub main_path: PathBuf,
+    /// Name of the profile replacing the configuration of the user
+    #[serde(skip)]
+    pub profile: Option<String>,
+    /// Errors of the sections that have been ignored
+    #[serde(skip)]
+    invalid_sections: Vec<Report>,
+    /// System configuration files read before `path`, from the least important one
+    #[serde(skip)]
+    pub system_paths: Vec<PathBuf>,
+    /// Files included by the configuration, directly or by other included files
+    #[serde(skip)]
+    pub includes: Vec<PathBuf>,
     #[serde(skip)]
     pub reloaded: Option<Arc<AtomicBool>>,
 }
 
 impl Config {
-    pub fn new_from_path(path: &Path) -> Result<Self> {
-        ensure!(path.exists(), "File {path:?} does not exists");
-        let mut config: Self = toml::from_str(&fs::read_to_string(path)?)?;
+    /// Read the system configuration files in `system_paths`, then the one of the user in
+    /// `path`. The attributes of each file override the ones of the previous files, the user
+    /// configuration can be missing when there is a system one.
+    pub fn new_from_paths(system_paths: &[PathBuf], path: &Path) -> Result<Self> {
+        ensure!(
+            path.exists() || !system_paths.is_empty(),
+            "File {path:?} does not exists"
+        );
+        let mut includes = Vec::new();
+        let mut table = toml::Table::new();
+        for system_path in system_paths {
+            let system_table = read_with_includes(system_path, &mut Vec::new(), &mut includes)
+                .with_context(|| {
+                    format!("while reading the system configuration {system_path:?}")
+                })?;
+            merge_tables(&mut table, system_table);
+        }
+        if path.exists() {
+            merge_tables(
+                &mut table,
+                read_with_includes(path, &mut Vec::new(), &mut includes)?,
+            );
+        }
+        let mut config: Self = toml::Value::Table(table).try_into()?;
+        config.includes = includes;
         config
             .data
             .get("default")
@@ -288,6 +1417,7 @@ impl Config {
             .get("any")
             .unwrap_or(&SerializedWallpaperInfo::default())
             .clone_into(&mut config.any);
+        let mut invalid_sections = Vec::new();
         config.data.retain(|name, info| {
             // The default configuration does not follow these rules
             // We still need the default configuration here because the path needs to be cached
@@ -302,104 +1432,227 @@ impl Config {
                     Err(err) => {
                         // We do not want to exit when error occurs, print it and go forward
                         warn!("{err:?}");
+                        invalid_sections.push(err);
                         false
                     }
                 }
             }
         });
+        config.invalid_sections = invalid_sections;
 
-        let groups = config
-            .data
-            .iter()
-            .filter_map(|(name, info)| {
-                // It's a bit overkill to call this function again, but the displays are on average 1 or 2
-                // and the code is simple enough that it wouldn't make a difference with 10 either
-                info.apply_and_validate(&config.default)
-                    .map(|res| (name, res))
-                    .ok()
-            })
-            .collect::<Vec<_>>();
-
-        // Check if all the groups share the same path
-        // This check is only useful when there is more than one display
-        // We display a warning related to the config issue, but we are not fixing anything here
-        // The path that will be used is probably not what the user expect, but proving an "expected"
-        // behaviour for a configuration issue seems overkill
-        if groups.len() > 1 {
-            // We want to skip displays for which we already displayed the warning
-            let mut errored_list = Vec::new();
-            for i in 0..groups.len() {
-                let x = groups.get(i).unwrap();
-                for j in 1..groups.len() {
-                    if errored_list.contains(&j) {
-                        continue;
-                    }
-                    let y = groups.get(j).unwrap();
-                    if x.1.sorting == y.1.sorting && x.1.path != y.1.path {
-                        warn!(
-                            "Displays {} and {} are assigned to group {} but have different paths",
-                            x.0,
-                            y.0,
-                            match x.1.sorting.unwrap() {
-                                Sorting::GroupedRandom { group } => group,
-                                _ => unreachable!(),
-                            }
-                        );
-                        errored_list.push(j);
-                    }
+        config.path = path.to_path_buf();
+        config.main_path = path.to_path_buf();
+        config.system_paths = system_paths.to_vec();
+        Ok(config)
+    }
+
+    /// Read the profile `profile`, i.e. `profiles/<profile>.toml` next to the configuration of
+    /// the user, or the configuration of the user itself when it's None. Unlike a reload, any
+    /// invalid section is an error, so that a broken profile is never applied in part.
+    pub fn load_profile(&self, profile: Option<&str>) -> Result<Self> {
+        let path = match profile {
+            Some(profile) => {
+                ensure!(
+                    !profile.is_empty() && !profile.starts_with('.') && !profile.contains('/'),
+                    "the profile name {} is not valid",
+                    profile.italic().yellow()
+                );
+                let path = self
+                    .main_path
+                    .parent()
+                    .unwrap_or(Path::new("."))
+                    .join("profiles")
+                    .join(format!("{profile}.toml"));
+                if !path.exists() {
+                    return Err(anyhow!(
+                        "the profile file {} does not exist",
+                        path.to_string_lossy().italic().yellow()
+                    ))
+                    .suggestion("Create it with the same format as the configuration file");
                 }
+                path
             }
+            None => self.main_path.clone(),
+        };
+        let mut config = Config::new_from_paths(&self.system_paths, &path)
+            .with_context(|| format!("while reading {}", path.to_string_lossy()))?;
+        if let Some(err) = config.invalid_sections.pop() {
+            return Err(err);
         }
-
-        config.path = path.to_path_buf();
+        config.main_path = self.main_path.clone();
+        config.profile = profile.map(str::to_string);
+        config.reloaded = self.reloaded.clone();
         Ok(config)
     }
 
-    pub fn get_output_by_name(&self, name: &str) -> Result<WallpaperInfo> {
-        self.data
-            .get(name)
+    /// Return the configuration of a display, from the section named after its serial, its name
+    /// or its description, in this order, and `any` otherwise
+    pub fn get_output(&self, info: &DisplayInfo) -> Result<WallpaperInfo> {
+        info.serial
+            .as_deref()
+            .and_then(|serial| self.data.get(serial))
+            .or_else(|| self.data.get(&info.name))
+            .or_else(|| {
+                self.data
+                    .get(&info.description)
+                    .filter(|_| !info.description.is_empty())
+            })
             .unwrap_or(&self.any)
             .apply_and_validate(&self.default)
+            .and_then(|wallpaper_info| {
+                if let Some(logical_size) = info.logical_size {
+                    check_layer_placement(wallpaper_info.layer_placement, logical_size)?;
+                }
+                Ok(wallpaper_info)
+            })
+    }
+
+    /// Return the files to reload the configuration on
+    fn watched_paths(&self) -> impl Iterator<Item = &PathBuf> {
+        // The user configuration is not watched when it does not exist yet
+        let user_path =
+            Some(&self.path).filter(|path| path.exists() || self.system_paths.is_empty());
+        self.system_paths
+            .iter()
+            .chain(user_path)
+            .chain(&self.includes)
     }
 
     pub fn listen_to_changes(&self, hotwatch: &mut Hotwatch, ping: Ping) -> Result<()> {
-        let reloaded = self.reloaded.as_ref().unwrap().clone();
-        hotwatch
-            .watch(&self.path, move |event: Event| {
-                if let hotwatch::EventKind::Modify(_) = event.kind {
-                    reloaded.store(true, Ordering::Relaxed);
-                    ping.ping();
-                }
-            })
-            .with_context(|| format!("watching file {:?}", &self.path))?;
+        for path in self.watched_paths() {
+            let reloaded = self.reloaded.as_ref().unwrap().clone();
+            let ping = ping.clone();
+            hotwatch
+                .watch(path, move |event: Event| {
+                    if let hotwatch::EventKind::Modify(_) = event.kind {
+                        reloaded.store(true, Ordering::Relaxed);
+                        ping.ping();
+                    }
+                })
+                .with_context(|| format!("watching file {path:?}"))?;
+        }
         Ok(())
     }
 
+    /// Stop watching the files of this configuration, before replacing it with a profile
+    pub fn stop_listening(&self, hotwatch: &mut Hotwatch) {
+        for path in self.watched_paths() {
+            if let Err(err) = hotwatch.unwatch(path) {
+                warn!("unwatching file {path:?}: {err:?}");
+            }
+        }
+    }
+
+    /// Return all the paths to cache, including the ones in the pools and fallback chains
     pub fn paths(&self) -> Vec<PathBuf> {
         let mut paths: Vec<_> = self
             .data
             .values()
-            .filter_map(|info| info.path.as_ref().map(|p| p.to_path_buf()))
+            .flat_map(|info| match &info.path {
+                Some(SerializedPath::Single(path)) => std::slice::from_ref(path),
+                Some(SerializedPath::Pool(paths))
+                | Some(SerializedPath::Fallback { fallback: paths }) => &paths[..],
+                None => &[],
+            })
+            .cloned()
+            // The workspaces replace the path of the display
+            .chain(
+                self.data
+                    .values()
+                    .flat_map(|info| info.workspaces.iter().flatten())
+                    .map(|(_, path)| expand_tilde(path)),
+            )
+            // And so do the conditions
+            .chain(
+                self.data
+                    .values()
+                    .flat_map(|info| info.conditions.iter().flatten())
+                    .map(|(_, path)| expand_tilde(path)),
+            )
             .collect();
         paths.sort_unstable();
         paths.dedup();
         paths
     }
 
+    /// Return the pools combining multiple paths
+    pub fn pools(&self) -> Vec<Vec<PathBuf>> {
+        let mut pools: Vec<_> = self
+            .data
+            .values()
+            .filter_map(|info| match &info.path {
+                Some(SerializedPath::Pool(paths)) if paths.len() > 1 => Some(paths.clone()),
+                _ => None,
+            })
+            .collect();
+        pools.sort_unstable();
+        pools.dedup();
+        pools
+    }
+
+    /// Return the fallback chains, in the order they are tried
+    pub fn fallbacks(&self) -> Vec<Vec<PathBuf>> {
+        let mut fallbacks: Vec<_> = self
+            .data
+            .values()
+            .filter_map(|info| match &info.path {
+                Some(SerializedPath::Fallback { fallback }) => Some(fallback.clone()),
+                _ => None,
+            })
+            .collect();
+        fallbacks.sort_unstable();
+        fallbacks.dedup();
+        fallbacks
+    }
+
+    /// Return the favorites files of all the displays
+    pub fn favorites(&self) -> Vec<PathBuf> {
+        let mut favorites: Vec<_> = self
+            .data
+            .values()
+            .filter_map(|info| info.favorites.as_deref().map(expand_tilde))
+            .collect();
+        favorites.sort_unstable();
+        favorites.dedup();
+        favorites
+    }
+
+    /// Return the commands listing the wallpapers, with the interval they should be run with
+    pub fn commands(&self) -> Vec<ListCommand> {
+        let mut commands: Vec<_> = self
+            .data
+            .values()
+            .filter_map(|info| {
+                info.command
+                    .as_ref()
+                    .map(|command| (command.to_string(), info.command_interval))
+            })
+            .collect();
+        commands.sort_unstable();
+        // A command used by multiple displays with the same interval is only run once
+        commands.dedup();
+        commands
+    }
+
     /// Return true if the struct changed
     pub fn update(&mut self) -> bool {
         // When the config file has been written into
-        let new_config = Config::new_from_path(&self.path).with_context(|| {
-            format!(
-                "updating configuration from file {}",
-                self.path.to_string_lossy()
-            )
-        });
+        let new_config =
+            Config::new_from_paths(&self.system_paths, &self.path).with_context(|| {
+                format!(
+                    "updating configuration from file {}",
+                    self.path.to_string_lossy()
+                )
+            });
         match new_config {
             Ok(new_config) if new_config != *self => {
                 let reloaded = self.reloaded.as_ref().unwrap().clone();
+                let main_path = std::mem::take(&mut self.main_path);
+                let profile = self.profile.take();
                 *self = new_config;
                 self.reloaded = Some(reloaded);
+                self.main_path = main_path;
+                self.profile = profile;
                 true
             }
             Ok(_) => {
@@ -420,15 +1673,411 @@ impl PartialEq for Config {
     }
 }
 
-pub fn tilde_expansion_deserialize<'de, D>(deserializer: D) -> Result<Option<PathBuf>, D::Error>
+/// Read a configuration file, replacing its `include` attribute with the content of the
+/// files listed. The sections of the including file are merged over the included ones.
+/// `stack` contains the files that are being read, to detect circular includes.
+fn read_with_includes(
+    path: &Path,
+    stack: &mut Vec<PathBuf>,
+    includes: &mut Vec<PathBuf>,
+) -> Result<toml::Table> {
+    let canonical_path = path
+        .canonicalize()
+        .with_context(|| format!("File {path:?} does not exists"))?;
+    if stack.contains(&canonical_path) {
+        return Err(anyhow!(
+            "file {} includes itself",
+            path.to_string_lossy().italic().yellow()
+        ))
+        .with_suggestion(|| {
+            format!(
+                "remove the circular reference from the {} attributes",
+                "include".bold().italic().blue()
+            )
+        });
+    }
+
+    let mut table: toml::Table = toml::from_str(
+        &fs::read_to_string(path).with_context(|| format!("reading file {path:?}"))?,
+    )
+    .with_context(|| format!("parsing file {path:?}"))?;
+    let mut merged = toml::Table::new();
+    if let Some(value) = table.remove("include") {
+        let files: Vec<String> = value.try_into().map_err(|_| {
+            anyhow!(
+                "attribute {} must be a list of paths",
+                "include".bold().italic().blue()
+            )
+        })?;
+        stack.push(canonical_path);
+        for file in files {
+            let file = expand_tilde(Path::new(&file));
+            // Relative paths are relative to the including file
+            let file = path.parent().unwrap_or(Path::new(".")).join(file);
+            let included = read_with_includes(&file, stack, includes)
+                .with_context(|| format!("while including file {file:?}"))?;
+            merge_tables(&mut merged, included);
+            includes.push(file);
+        }
+        stack.pop();
+    }
+    merge_tables(&mut merged, table);
+
+    Ok(merged)
+}
+
+/// Merge the sections of `other` into `base`, the attributes of `other` have precedence
+fn merge_tables(base: &mut toml::Table, other: toml::Table) {
+    for (name, value) in other {
+        match (base.get_mut(&name), value) {
+            (Some(toml::Value::Table(section)), toml::Value::Table(other_section)) => {
+                section.extend(other_section);
+            }
+            (_, value) => {
+                base.insert(name, value);
+            }
+        }
+    }
+}
+
+/// Parse a time of the day in the `HH:MM` format, returning the seconds from midnight
+fn parse_time_of_day(attribute: &str, value: &str) -> Result<u32> {
+    value
+        .split_once(':')
+        .and_then(|(hours, minutes)| {
+            Some((hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?))
+        })
+        .filter(|(hours, minutes)| *hours < 24 && *minutes < 60)
+        .map(|(hours, minutes)| hours * 60 * 60 + minutes * 60)
+        .ok_or_else(|| {
+            anyhow!(
+                "attribute {} must be a time in the HH:MM format, e.g. 19:30",
+                attribute.bold().italic().blue()
+            )
+        })
+}
+
+fn parse_time_window(attribute: &str, value: &str) -> Result<TimeWindow> {
+    let (start, end) = value.split_once('-').ok_or_else(|| {
+        anyhow!(
+            "attribute {} must contain time windows in the HH:MM-HH:MM format, e.g. 09:00-17:30",
+            attribute.bold().italic().blue()
+        )
+    })?;
+    Ok(TimeWindow {
+        start: parse_time_of_day(attribute, start.trim())?,
+        end: parse_time_of_day(attribute, end.trim())?,
+    })
+}
+
+fn parse_resolution(attribute: &str, value: &str) -> Result<(u32, u32)> {
+    value
+        .split_once('x')
+        .and_then(|(width, height)| Some((width.parse::<u32>().ok()?, height.parse::<u32>().ok()?)))
+        .ok_or_else(|| {
+            anyhow!(
+                "attribute {} must be a resolution in the WIDTHxHEIGHT format, e.g. 1920x1080",
+                attribute.bold().italic().blue()
+            )
+        })
+}
+
+/// Check that the wallpaper placed with `margin` and `layer-size` has room on a display of
+/// `logical_size`
+fn check_layer_placement(placement: LayerPlacement, logical_size: (i32, i32)) -> Result<()> {
+    let [top, right, bottom, left] = placement.margin;
+    for (name, margins, size, length) in [
+        ("width", left + right, placement.size.0, logical_size.0),
+        ("height", top + bottom, placement.size.1, logical_size.1),
+    ] {
+        // Without a size, the wallpaper is stretched between the margins
+        let fits = if size == 0 {
+            margins < length
+        } else {
+            margins as i64 + size as i64 <= length as i64
+        };
+        if !fits {
+            return Err(anyhow!(
+                "attribute {} leaves no room for the wallpaper in the {name} of the display, \
+                 which is {length}",
+                "margin".bold().italic().blue(),
+            ))
+            .with_suggestion(|| "make the margins and layer-size smaller than the display");
+        }
+    }
+    Ok(())
+}
+
+/// Parse a color in the `#RRGGBB` or `#RRGGBBAA` format
+pub fn parse_color(attribute: &str, value: &str) -> Result<[f32; 4]> {
+    let hex = value
+        .strip_prefix('#')
+        .filter(|hex| (hex.len() == 6 || hex.len() == 8) && hex.is_ascii());
+    let components: Option<Vec<f32>> = hex.and_then(|hex| {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+            .map(|component| component.map(|component| component as f32 / 255.0))
+            .collect()
+    });
+    match components.as_deref() {
+        Some(&[red, green, blue]) => Ok([red, green, blue, 1.0]),
+        Some(&[red, green, blue, alpha]) => Ok([red, green, blue, alpha]),
+        _ => Err(anyhow!(
+            "attribute {} must be a color in the #RRGGBB or #RRGGBBAA format, e.g. #1e1e2e",
+            attribute.bold().italic().blue()
+        )),
+    }
+}
+
+/// Parse an offset written like the value of the `offset` attribute, a number or the side to
+/// align the image to, e.g. `top`
+pub fn parse_offset(offset: &str) -> Result<f32> {
+    let offset = match offset.parse() {
+        Ok(offset) => SerializedOffset::Value(offset),
+        Err(_) => {
+            let deserializer = StrDeserializer::<serde::de::value::Error>::new(offset);
+            SerializedOffset::Alignment(Alignment::deserialize(deserializer)?)
+        }
+    };
+    Ok(offset.value())
+}
+
+/// Parse a transition written like the value of the `transition` attribute, e.g.
+/// `{ iris = { contract = true } }` or `"none"`. A bare name, e.g. `fade`, stands for the
+/// transition with its default settings.
+pub fn parse_transition(value: &str) -> Result<Transition> {
+    #[derive(Deserialize)]
+    struct Value {
+        #[serde(deserialize_with = "transition_deserialize")]
+        transition: Option<Transition>,
+    }
+    let value = value.trim();
+    let document = match value {
+        "none" => "transition = \"none\"".to_string(),
+        _ if value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') => {
+            format!("transition.{value} = {{}}")
+        }
+        _ => format!("transition = {value}"),
+    };
+    let parsed: Value = toml::from_str(&document)
+        .with_context(|| format!("{} is not a valid transition", value.italic().yellow()))?;
+    Ok(parsed.transition.expect("the transition to be set"))
+}
+
+fn expand_tilde(path: &Path) -> PathBuf {
+    path.strip_prefix("~")
+        .map_or(path.to_path_buf(), |p| home_dir().unwrap().join(p))
+}
+
+pub fn tilde_expansion_deserialize<'de, D>(
+    deserializer: D,
+) -> Result<Option<SerializedPath>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let path = match SerializedPath::deserialize(deserializer)? {
+        SerializedPath::Single(path) => SerializedPath::Single(expand_tilde(&path)),
+        SerializedPath::Pool(paths) => {
+            SerializedPath::Pool(paths.into_iter().map(|path| expand_tilde(&path)).collect())
+        }
+        SerializedPath::Fallback { fallback } => SerializedPath::Fallback {
+            fallback: fallback
+                .into_iter()
+                .map(|path| expand_tilde(&path))
+                .collect(),
+        },
+    };
+
+    Ok(Some(path))
+}
+
+/// Accept `transition = "none"` on top of the tables describing the transitions
+pub fn transition_deserialize<'de, D>(deserializer: D) -> Result<Option<Transition>, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
-    let path = String::deserialize(deserializer)?;
-    let path = Path::new(&path);
+    struct TransitionVisitor;
+
+    impl<'de> serde::de::Visitor<'de> for TransitionVisitor {
+        type Value = Transition;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("\"none\" or a transition")
+        }
+
+        fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+        where
+            E: serde::de::Error,
+        {
+            match value {
+                "none" => Ok(Transition::None {}),
+                _ => Err(E::invalid_value(serde::de::Unexpected::Str(value), &self)),
+            }
+        }
 
-    Ok(Some(
-        path.strip_prefix("~")
-            .map_or(path.to_path_buf(), |p| home_dir().unwrap().join(p)),
-    ))
+        fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+        where
+            A: serde::de::MapAccess<'de>,
+        {
+            Transition::deserialize(serde::de::value::MapAccessDeserializer::new(map))
+        }
+    }
+
+    deserializer.deserialize_any(TransitionVisitor).map(Some)
+}
+
+/// Deserialize a list of transitions, each of them like `transition`
+pub fn transitions_deserialize<'de, D>(deserializer: D) -> Result<Option<Vec<Transition>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct Item(#[serde(deserialize_with = "transition_deserialize")] Option<Transition>);
+
+    Vec::<Item>::deserialize(deserializer)
+        .map(|items| Some(items.into_iter().filter_map(|Item(t)| t).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn display(name: &str) -> DisplayInfo {
+        DisplayInfo {
+            name: name.to_string(),
+            description: String::new(),
+            serial: None,
+            width: 0,
+            height: 0,
+            scale: 1,
+            fractional_scale: None,
+            transform: Transform::Normal,
+            position: None,
+            logical_size: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_transition() {
+        assert_eq!(parse_transition("none").unwrap(), Transition::None {});
+        assert_eq!(
+            parse_transition(" fade ").unwrap(),
+            Transition::Fade {
+                through_average: None
+            }
+        );
+        assert_eq!(
+            parse_transition("{ iris = { contract = true } }").unwrap(),
+            Transition::Iris {
+                center: None,
+                smoothness: None,
+                contract: Some(true),
+            }
+        );
+
+        // Unknown transitions and settings, and values of the wrong type
+        for value in [
+            "",
+            "unknown",
+            "fade()",
+            "{ iris = { size = 2 } }",
+            "{ iris = { contract = 1 } }",
+        ] {
+            assert!(parse_transition(value).is_err(), "{value} was parsed");
+        }
+    }
+
+    #[test]
+    fn test_layer_placement() {
+        let placement = |attributes: &str| {
+            toml::from_str::<SerializedWallpaperInfo>(&format!(
+                "path = {:?}\n{attributes}",
+                std::env::temp_dir()
+            ))
+            .map_err(Report::from)
+            .and_then(|info| info.apply_and_validate(&SerializedWallpaperInfo::default()))
+            .map(|wallpaper_info| wallpaper_info.layer_placement)
+        };
+
+        assert_eq!(placement("").unwrap(), LayerPlacement::default());
+        // The margin of all the edges only applies to the anchored ones
+        let top_left =
+            placement("anchor = [\"top\", \"left\"]\nmargin = 10\nlayer-size = \"800x600\"")
+                .unwrap();
+        assert_eq!(top_left.anchor, Anchor::TOP | Anchor::LEFT);
+        assert_eq!(top_left.margin, [10, 0, 0, 10]);
+        assert_eq!(top_left.size, (800, 600));
+        let below_bar = placement("margin = [32, 0, 0, 0]").unwrap();
+        assert_eq!(below_bar.margin, [32, 0, 0, 0]);
+        // Stretched between the left and right edges
+        let bottom =
+            placement("anchor = [\"bottom\", \"left\", \"right\"]\nlayer-size = \"0x200\"")
+                .unwrap();
+        assert_eq!(bottom.size, (0, 200));
+
+        // The sides not anchored to both edges need a size
+        assert!(placement("anchor = [\"top\"]").is_err());
+        assert!(placement("anchor = [\"top\", \"bottom\"]\nlayer-size = \"0x200\"").is_err());
+        // Only the anchored edges can have a margin
+        assert!(
+            placement("anchor = [\"top\", \"left\", \"right\"]\nlayer-size = \"0x200\"\nmargin = [0, 0, 10, 0]")
+                .is_err()
+        );
+        assert!(placement("anchor = [\"middle\"]").is_err());
+    }
+
+    #[test]
+    fn test_check_layer_placement() {
+        let display = (1920, 1080);
+        let placement = |margin, size| LayerPlacement {
+            margin,
+            size,
+            ..LayerPlacement::default()
+        };
+        assert!(check_layer_placement(placement([0; 4], (0, 0)), display).is_ok());
+        assert!(check_layer_placement(placement([32, 0, 0, 0], (0, 0)), display).is_ok());
+        assert!(check_layer_placement(placement([0, 0, 80, 0], (0, 1000)), display).is_ok());
+
+        // Margins taking the whole display, or larger than it
+        assert!(check_layer_placement(placement([540, 0, 540, 0], (0, 0)), display).is_err());
+        assert!(check_layer_placement(placement([0, 2000, 0, 0], (0, 0)), display).is_err());
+        // The size doesn't fit between the margins
+        assert!(check_layer_placement(placement([0, 0, 0, 200], (1800, 0)), display).is_err());
+    }
+
+    #[test]
+    fn test_reload_global_transition_time() {
+        let dir = std::env::temp_dir().join(format!("wpaperd-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        let write_config = |transition_time: u32| {
+            fs::write(
+                &path,
+                format!(
+                    "[default]\npath = {dir:?}\ntransition-time = {transition_time}\n\n\
+                     [DP-1]\ntransition-time = 2000\n\n\
+                     [DP-2]\ntransition.iris = {{}}\n"
+                ),
+            )
+            .unwrap()
+        };
+        let transition_times = |config: &Config| {
+            ["DP-1", "DP-2", "DP-3"]
+                .map(|name| config.get_output(&display(name)).unwrap().transition_time)
+        };
+
+        write_config(500);
+        let mut config = Config::new_from_paths(&[], &path).unwrap();
+        config.reloaded = Some(Arc::new(AtomicBool::new(false)));
+        // DP-2 changes the transition but not its time, DP-3 only has the default section
+        assert_eq!(transition_times(&config), [2000, 500, 500]);
+
+        // Only the displays that don't set their own time follow the new global one
+        write_config(800);
+        assert!(config.update());
+        assert_eq!(transition_times(&config), [2000, 800, 800]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
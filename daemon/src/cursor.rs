@@ -0,0 +1,214 @@
+//! Follow the pointer on the displays with `cursor-parallax` or `pause-on-hover`. Their surfaces
+//! accept the pointer input, so wpaperd has to set the cursor image itself, which is only
+//! possible through the cursor-shape protocol; both are ignored when the compositor doesn't
+//! support it.
+
+use log::warn;
+use smithay_client_toolkit::compositor::{CompositorState, Region};
+use smithay_client_toolkit::reexports::client::{
+    globals::GlobalList,
+    protocol::{
+        wl_pointer::{self, WlPointer},
+        wl_seat::{self, Capability, WlSeat},
+        wl_surface::WlSurface,
+    },
+    Connection, Dispatch, Proxy, QueueHandle, WEnum,
+};
+use smithay_client_toolkit::reexports::protocols::wp::cursor_shape::v1::client::{
+    wp_cursor_shape_device_v1::{self, Shape, WpCursorShapeDeviceV1},
+    wp_cursor_shape_manager_v1::{self, WpCursorShapeManagerV1},
+};
+
+use crate::{surface::Surface, wpaperd::Wpaperd};
+
+/// User data of the seat bound to receive the pointer, the idle notifications use their own
+pub struct CursorSeat;
+
+pub struct CursorState {
+    shape_manager: Option<WpCursorShapeManagerV1>,
+    seat: Option<WlSeat>,
+    pointer: Option<(WlPointer, WpCursorShapeDeviceV1)>,
+    /// Surface the pointer is on
+    focus: Option<WlSurface>,
+    /// Names of the displays accepting the pointer input
+    following: Vec<String>,
+    /// The lack of support has already been reported
+    warned: bool,
+}
+
+impl CursorState {
+    pub fn bind(globals: &GlobalList, qh: &QueueHandle<Wpaperd>) -> Self {
+        Self {
+            shape_manager: globals.bind(qh, 1..=1, ()).ok(),
+            seat: globals.bind(qh, 1..=5, CursorSeat).ok(),
+            pointer: None,
+            focus: None,
+            following: Vec::new(),
+            warned: false,
+        }
+    }
+
+    /// Let the pointer input through on the displays with `cursor-parallax` or `pause-on-hover`,
+    /// and stop it on the others so that the compositor shows its own cursor there
+    pub fn update(&mut self, surfaces: &[Surface], compositor_state: &CompositorState) {
+        self.following
+            .retain(|name| surfaces.iter().any(|surface| surface.name() == *name));
+        let supported = self.shape_manager.is_some() && self.seat.is_some();
+
+        for surface in surfaces {
+            let name = surface.name();
+            let wants_pointer = surface.wants_pointer();
+            if wants_pointer && !supported {
+                if !self.warned {
+                    warn!(
+                        "{name}: cursor-parallax and pause-on-hover are not supported by the compositor, ignoring them"
+                    );
+                    self.warned = true;
+                }
+                continue;
+            }
+            let following = self.following.contains(&name);
+            if wants_pointer == following {
+                continue;
+            }
+            if wants_pointer {
+                // The whole surface
+                surface.wl_surface().set_input_region(None);
+                self.following.push(name);
+            } else {
+                match Region::new(compositor_state) {
+                    Ok(region) => {
+                        surface
+                            .wl_surface()
+                            .set_input_region(Some(region.wl_region()));
+                        region.wl_region().destroy();
+                    }
+                    Err(err) => {
+                        warn!("{name}: could not create region: {err:?}");
+                        continue;
+                    }
+                }
+                self.following.retain(|following| *following != name);
+            }
+            surface.wl_surface().commit();
+        }
+    }
+}
+
+impl Dispatch<WlSeat, CursorSeat> for Wpaperd {
+    fn event(
+        state: &mut Self,
+        seat: &WlSeat,
+        event: wl_seat::Event,
+        _data: &CursorSeat,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let wl_seat::Event::Capabilities {
+            capabilities: WEnum::Value(capabilities),
+        } = event
+        else {
+            return;
+        };
+        let cursor_state = &mut state.cursor_state;
+        let has_pointer = capabilities.contains(Capability::Pointer);
+        if has_pointer && cursor_state.pointer.is_none() {
+            if let Some(shape_manager) = &cursor_state.shape_manager {
+                let pointer = seat.get_pointer(qh, ());
+                let shape_device = shape_manager.get_pointer(&pointer, qh, ());
+                cursor_state.pointer = Some((pointer, shape_device));
+            }
+        } else if !has_pointer {
+            if let Some((pointer, shape_device)) = cursor_state.pointer.take() {
+                shape_device.destroy();
+                if pointer.version() >= 3 {
+                    pointer.release();
+                }
+            }
+            // The pointer has been unplugged while on a display
+            if let Some(focus) = cursor_state.focus.take() {
+                if let Some(surface) = state
+                    .surfaces
+                    .iter_mut()
+                    .find(|surface| *surface.wl_surface() == focus)
+                {
+                    surface.set_pointer_position(None, qh);
+                }
+            }
+        }
+    }
+}
+
+impl Dispatch<WlPointer, ()> for Wpaperd {
+    fn event(
+        state: &mut Self,
+        _pointer: &WlPointer,
+        event: wl_pointer::Event,
+        _data: &(),
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let cursor_state = &mut state.cursor_state;
+        let (wl_surface, position) = match event {
+            wl_pointer::Event::Enter {
+                serial,
+                surface,
+                surface_x,
+                surface_y,
+            } => {
+                // Keep showing the default cursor over the wallpaper
+                if let Some((_, shape_device)) = &cursor_state.pointer {
+                    shape_device.set_shape(serial, Shape::Default);
+                }
+                cursor_state.focus = Some(surface.clone());
+                (surface, Some((surface_x, surface_y)))
+            }
+            wl_pointer::Event::Motion {
+                surface_x,
+                surface_y,
+                ..
+            } => match &cursor_state.focus {
+                Some(focus) => (focus.clone(), Some((surface_x, surface_y))),
+                None => return,
+            },
+            wl_pointer::Event::Leave { surface, .. } => {
+                cursor_state.focus = None;
+                (surface, None)
+            }
+            _ => return,
+        };
+        if let Some(surface) = state
+            .surfaces
+            .iter_mut()
+            .find(|surface| *surface.wl_surface() == wl_surface)
+        {
+            surface.set_pointer_position(position, qh);
+        }
+    }
+}
+
+impl Dispatch<WpCursorShapeManagerV1, ()> for Wpaperd {
+    fn event(
+        _state: &mut Self,
+        _manager: &WpCursorShapeManagerV1,
+        _event: wp_cursor_shape_manager_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // The manager doesn't have any event
+    }
+}
+
+impl Dispatch<WpCursorShapeDeviceV1, ()> for Wpaperd {
+    fn event(
+        _state: &mut Self,
+        _device: &WpCursorShapeDeviceV1,
+        _event: wp_cursor_shape_device_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // The device doesn't have any event
+    }
+}
@@ -0,0 +1,37 @@
+//! Pick the wallpaper of the day. The local date is hashed into an index of the sorted list of
+//! wallpapers, so that every machine with the same wallpapers shows the same one on a given day.
+
+use std::time::Duration;
+
+use crate::night_light::local_seconds_of_day;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Return the number of days since the epoch, in local time
+pub fn local_day() -> i64 {
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&now, &mut tm).is_null() {
+            return now.div_euclid(SECONDS_PER_DAY as i64);
+        }
+        (now + tm.tm_gmtoff).div_euclid(SECONDS_PER_DAY as i64)
+    }
+}
+
+/// Return the time left until the next local midnight. A second is added so that the timer
+/// never fires while it is still the previous day.
+pub fn until_midnight() -> Duration {
+    Duration::from_secs(SECONDS_PER_DAY - local_seconds_of_day() as u64 + 1)
+}
+
+/// Return the index of the wallpaper of `day`, moved forward by `offset` so that the displays
+/// can show different wallpapers
+pub fn daily_index(day: i64, offset: usize, files_len: usize) -> usize {
+    // splitmix64, the standard hasher is not guaranteed to be the same across Rust versions
+    let mut hash = (day as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^= hash >> 31;
+    ((hash % files_len as u64) as usize + offset % files_len) % files_len
+}
@@ -6,20 +6,61 @@ use smithay_client_toolkit::{
 #[derive(Debug)]
 pub struct DisplayInfo {
     pub name: String,
+    pub description: String,
+    /// Serial number of the monitor, if the compositor exposes it
+    pub serial: Option<String>,
     pub width: i32,
     pub height: i32,
     pub scale: i32,
+    /// Preferred scale of the surface in 120ths, sent through the fractional-scale protocol.
+    /// It replaces `scale` once received.
+    pub fractional_scale: Option<u32>,
     pub transform: Transform,
+    /// Logical position of the display in the compositor space, None until the compositor
+    /// has sent it
+    pub position: Option<(i32, i32)>,
+    /// Logical size of the display, the layer surface can be smaller than it. None until the
+    /// compositor has sent it.
+    pub logical_size: Option<(i32, i32)>,
+}
+
+/// Return the serial number of the monitor. There is no event for it, but compositors build the
+/// description from the make, the model and the serial read from the EDID, followed by the
+/// connector, e.g. `Dell Inc. DELL U2720Q 8FXYZ23 (DP-3)`.
+pub fn serial(info: &OutputInfo) -> Option<String> {
+    let description = info.description.as_deref()?;
+    if info.model.trim().is_empty() {
+        // The whole description would be taken as the serial
+        return None;
+    }
+    let description = match &info.name {
+        Some(name) => description
+            .strip_suffix(&format!("({name})"))
+            .unwrap_or(description),
+        None => description,
+    };
+    let serial = description
+        .trim()
+        .strip_prefix(info.make.trim())?
+        .trim_start()
+        .strip_prefix(info.model.trim())?
+        .trim();
+    (!serial.is_empty() && !serial.eq_ignore_ascii_case("unknown")).then(|| serial.to_string())
 }
 
 impl DisplayInfo {
     pub fn new(info: OutputInfo) -> Self {
         Self {
+            serial: serial(&info),
             name: info.name.unwrap_or_default(),
+            description: info.description.unwrap_or_default(),
             width: 0,
             height: 0,
             scale: info.scale_factor,
+            fractional_scale: None,
             transform: info.transform,
+            position: info.logical_position,
+            logical_size: info.logical_size,
         }
     }
 
@@ -27,10 +68,10 @@ impl DisplayInfo {
     pub fn adjusted_width(&self) -> i32 {
         match self.transform {
             Transform::Normal | Transform::_180 | Transform::Flipped | Transform::Flipped180 => {
-                self.width * self.scale
+                self.scaled_width()
             }
             Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270 => {
-                self.height * self.scale
+                self.scaled_height()
             }
             _ => unreachable!(),
         }
@@ -40,23 +81,33 @@ impl DisplayInfo {
     pub fn adjusted_height(&self) -> i32 {
         match self.transform {
             Transform::Normal | Transform::_180 | Transform::Flipped | Transform::Flipped180 => {
-                self.height * self.scale
+                self.scaled_height()
             }
             Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270 => {
-                self.width * self.scale
+                self.scaled_width()
             }
             _ => unreachable!(),
         }
     }
 
+    /// Convert `length` from logical pixels to the pixels of the buffers
+    #[inline]
+    pub fn scaled(&self, length: i32) -> i32 {
+        match self.fractional_scale {
+            // Rounded half away from zero, like the compositor does
+            Some(scale) => ((length as i64 * scale as i64 + 60) / 120) as i32,
+            None => length * self.scale,
+        }
+    }
+
     #[inline]
     pub fn scaled_width(&self) -> i32 {
-        self.width * self.scale
+        self.scaled(self.width)
     }
 
     #[inline]
     pub fn scaled_height(&self) -> i32 {
-        self.height * self.scale
+        self.scaled(self.height)
     }
 
     #[inline]
@@ -85,6 +136,24 @@ impl DisplayInfo {
         }
     }
 
+    pub fn change_position(&mut self, position: Option<(i32, i32)>) -> bool {
+        if self.position != position {
+            self.position = position;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn change_logical_size(&mut self, logical_size: Option<(i32, i32)>) -> bool {
+        if self.logical_size != logical_size {
+            self.logical_size = logical_size;
+            true
+        } else {
+            false
+        }
+    }
+
     pub fn change_scale_factor(&mut self, scale_factor: i32) -> bool {
         if self.scale != scale_factor {
             self.scale = scale_factor;
@@ -93,4 +162,13 @@ impl DisplayInfo {
             false
         }
     }
+
+    pub fn change_fractional_scale(&mut self, scale: u32) -> bool {
+        if self.fractional_scale != Some(scale) {
+            self.fractional_scale = Some(scale);
+            true
+        } else {
+            false
+        }
+    }
 }
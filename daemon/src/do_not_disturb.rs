@@ -0,0 +1,57 @@
+//! Suppress the automatic wallpaper changes during the `do-not-disturb` time windows, e.g.
+//! during work hours. The displays are paused while the local time is inside a window, so the
+//! time left before the next change is kept and the schedule continues from there afterwards.
+
+use std::time::Duration;
+
+use color_eyre::{eyre::anyhow, Result};
+use smithay_client_toolkit::reexports::calloop::{
+    timer::{TimeoutAction, Timer},
+    LoopHandle,
+};
+
+use crate::{night_light::local_seconds_of_day, wpaperd::Wpaperd};
+
+/// How often the local time is checked
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Time of the day when the wallpaper is not changed automatically
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeWindow {
+    /// Seconds from midnight when the window starts
+    pub start: u32,
+    /// Seconds from midnight when the window ends, it can be before `start` to span midnight
+    pub end: u32,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, seconds_of_day: u32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&seconds_of_day)
+        } else {
+            seconds_of_day >= self.start || seconds_of_day < self.end
+        }
+    }
+}
+
+/// Check the local time periodically and update the surfaces
+pub fn insert_do_not_disturb_timer(handle: &LoopHandle<Wpaperd>) -> Result<()> {
+    handle
+        .insert_source(
+            Timer::immediate(),
+            |_deadline, _: &mut (), wpaperd: &mut Wpaperd| {
+                let now = local_seconds_of_day();
+                for surface in &mut wpaperd.surfaces {
+                    let disturb = surface
+                        .wallpaper_info
+                        .do_not_disturb
+                        .iter()
+                        .any(|window| window.contains(now));
+                    surface.set_do_not_disturb(disturb);
+                }
+                TimeoutAction::ToDuration(CHECK_INTERVAL)
+            },
+        )
+        .map_err(|err| anyhow!("inserting the do not disturb timer in the event loop: {err}"))?;
+    Ok(())
+}
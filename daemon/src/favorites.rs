@@ -0,0 +1,275 @@
+//! Images marked as favorites with `wpaperctl add-favorite`, stored in the `favorites` file.
+//! Each line has the path of an image, empty lines and lines starting with `#` are ignored and
+//! the relative paths are relative to the directory of the file. The file can be edited while
+//! _wpaperd_ is running, `wpaperctl toggle-favorites` then picks the wallpapers only from it.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+
+use color_eyre::{eyre::Context, Result};
+use hotwatch::Hotwatch;
+use log::{error, info, warn};
+use smithay_client_toolkit::reexports::calloop::ping::Ping;
+
+use crate::http::is_url;
+
+#[derive(Debug)]
+pub struct Favorites {
+    path: PathBuf,
+    /// Modification time of the file when it was last read
+    modified: Option<SystemTime>,
+    /// Sorted, like the lists of the filelist cache
+    images: Arc<Vec<PathBuf>>,
+    /// The file could not be read the last time, so that the error is only logged once
+    failed: bool,
+}
+
+impl Favorites {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            modified: None,
+            images: Arc::new(Vec::new()),
+            failed: false,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn images(&self) -> Arc<Vec<PathBuf>> {
+        self.images.clone()
+    }
+
+    pub fn contains(&self, image: &Path) -> bool {
+        self.images
+            .binary_search_by(|path| path.as_path().cmp(image))
+            .is_ok()
+    }
+
+    /// Read the file again if it has been modified since the last time. A missing file has no
+    /// favorites, while the previous ones are kept when it can't be read.
+    pub fn refresh(&mut self, name: &str) {
+        let modified = match fs::metadata(&self.path).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified,
+            // Nothing has been added yet
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                self.images = Arc::new(Vec::new());
+                self.modified = None;
+                self.failed = false;
+                return;
+            }
+            Err(err) => {
+                if !std::mem::replace(&mut self.failed, true) {
+                    warn!(
+                        "{name}: unable to read the favorites {:?}: {err}",
+                        self.path
+                    );
+                }
+                return;
+            }
+        };
+        if self.modified == Some(modified) {
+            return;
+        }
+        match fs::read_to_string(&self.path)
+            .with_context(|| format!("unable to read the favorites {:?}", self.path))
+        {
+            Ok(content) => {
+                let images = parse(&content, self.path.parent().unwrap_or(Path::new("")));
+                info!(
+                    "{name}: read {} images from the favorites {:?}",
+                    images.len(),
+                    self.path
+                );
+                self.images = Arc::new(images);
+                self.modified = Some(modified);
+                self.failed = false;
+            }
+            Err(err) => {
+                if !std::mem::replace(&mut self.failed, true) {
+                    warn!("{name}: {err:?}");
+                }
+            }
+        }
+    }
+
+    /// Append `image` to the file, creating it if needed. Return false if it was already there.
+    pub fn add(&mut self, image: &Path, name: &str) -> Result<bool> {
+        self.refresh(name);
+        if self.contains(image) {
+            return Ok(false);
+        }
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("unable to create the directory {dir:?}"))?;
+        }
+        // Don't join the new path to the last line if it has no newline
+        let missing_newline = match fs::read(&self.path) {
+            Ok(content) => content.last().is_some_and(|byte| *byte != b'\n'),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => false,
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("unable to read the favorites {:?}", self.path))
+            }
+        };
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| {
+                if missing_newline {
+                    writeln!(file)?;
+                }
+                writeln!(file, "{}", image.display())
+            })
+            .with_context(|| format!("unable to write the favorites {:?}", self.path))?;
+        self.refresh(name);
+        Ok(true)
+    }
+}
+
+/// Return the sorted images listed in `content`
+fn parse(content: &str, dir: &Path) -> Vec<PathBuf> {
+    let mut images: Vec<PathBuf> = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(Path::new)
+        .map(|path| {
+            if is_url(path) {
+                path.to_path_buf()
+            } else {
+                dir.join(path)
+            }
+        })
+        .collect();
+    images.sort_unstable();
+    images.dedup();
+    images
+}
+
+/// Watch the favorites files, so that the images removed from them are replaced right away
+pub struct FavoritesWatcher {
+    paths: Vec<PathBuf>,
+    /// Set by the hotwatch callbacks, the event loop is woken up by the ping
+    changed: Arc<AtomicBool>,
+    ping: Ping,
+}
+
+impl FavoritesWatcher {
+    pub fn new(ping: Ping) -> Self {
+        Self {
+            paths: Vec::new(),
+            changed: Arc::new(AtomicBool::new(false)),
+            ping,
+        }
+    }
+
+    /// Watch the files in `paths` again and stop watching the other ones. The files that don't
+    /// exist yet are skipped, call it again once they have been created.
+    pub fn update(&mut self, paths: Vec<PathBuf>, hotwatch: &mut Hotwatch) {
+        for path in self.paths.drain(..) {
+            // The editors that replace the file remove its watch, which fails to be unwatched
+            let _ = hotwatch.unwatch(&path);
+        }
+        for path in paths {
+            if !path.is_file() {
+                continue;
+            }
+            let changed = self.changed.clone();
+            let ping = self.ping.clone();
+            match hotwatch
+                .watch(&path, move |event| match event.kind {
+                    hotwatch::EventKind::Create(_)
+                    | hotwatch::EventKind::Remove(_)
+                    | hotwatch::EventKind::Modify(_) => {
+                        changed.store(true, Ordering::Release);
+                        ping.ping();
+                    }
+                    _ => {}
+                })
+                .with_context(|| format!("hotwatch watch error on path {path:?}"))
+            {
+                Ok(()) => self.paths.push(path),
+                Err(err) => error!("{err:?}"),
+            }
+        }
+    }
+
+    /// Return true if any of the files has changed since the last call
+    pub fn take_changed(&self) -> bool {
+        self.changed.swap(false, Ordering::AcqRel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let content = "# Sunsets\n\
+                       b.jpg\n\
+                       \n\
+                       \x20 /home/user/a.png \n\
+                       https://example.com/c.jpg\n\
+                       sub/d.jpg\n\
+                       b.jpg\n";
+        assert_eq!(
+            parse(content, Path::new("/favorites")),
+            [
+                PathBuf::from("/favorites/b.jpg"),
+                PathBuf::from("/favorites/sub/d.jpg"),
+                PathBuf::from("/home/user/a.png"),
+                PathBuf::from("https://example.com/c.jpg"),
+            ]
+        );
+        assert!(parse("\n# nothing yet\n", Path::new("/favorites")).is_empty());
+    }
+
+    #[test]
+    fn test_add() {
+        let dir = std::env::temp_dir().join(format!("wpaperd-favorites-{}", std::process::id()));
+        let path = dir.join("sub").join("favorites");
+        let mut favorites = Favorites::new(path.clone());
+        let image = Path::new("/home/user/a.png");
+
+        // The missing file has no favorites, it's created with its directory
+        favorites.refresh("DP-1");
+        assert!(favorites.images().is_empty());
+        assert!(favorites.add(image, "DP-1").unwrap());
+        assert!(favorites.contains(image));
+        assert!(!favorites.add(image, "DP-1").unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "/home/user/a.png\n");
+
+        // The line edited by hand without a newline isn't joined to the new one
+        fs::write(&path, "/home/user/a.png\nb.jpg").unwrap();
+        // Read from scratch, the file might have the same modification time as before
+        let mut favorites = Favorites::new(path.clone());
+        assert!(!favorites.add(image, "DP-1").unwrap());
+        assert!(favorites.contains(&path.parent().unwrap().join("b.jpg")));
+        assert!(favorites
+            .add(Path::new("/home/user/c.png"), "DP-1")
+            .unwrap());
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "/home/user/a.png\nb.jpg\n/home/user/c.png\n"
+        );
+
+        // Removing the file removes the favorites
+        fs::remove_file(&path).unwrap();
+        favorites.refresh("DP-1");
+        assert!(favorites.images().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
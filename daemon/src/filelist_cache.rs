@@ -1,18 +1,24 @@
 use std::{
     path::{Path, PathBuf},
+    process::{Command, Stdio},
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
+    thread::JoinHandle,
+    time::Duration,
 };
 
-use color_eyre::eyre::{anyhow, Context, Result};
+use color_eyre::eyre::{anyhow, ensure, Context, Result};
 use hotwatch::Hotwatch;
 use log::error;
-use smithay_client_toolkit::reexports::calloop::{self, ping::Ping, LoopHandle};
+use smithay_client_toolkit::reexports::{
+    calloop::{self, ping::Ping, LoopHandle},
+    client::QueueHandle,
+};
 use walkdir::WalkDir;
 
-use crate::wpaperd::Wpaperd;
+use crate::{http::is_url, jxl, wpaperd::Wpaperd};
 
 #[derive(Debug)]
 struct Filelist {
@@ -38,13 +44,7 @@ impl Filelist {
                 .sort_by_file_name()
                 .into_iter()
                 .filter_map(|e| e.ok())
-                .filter(|e| {
-                    if let Some(guess) = new_mime_guess::from_path(e.path()).first() {
-                        guess.type_() == "image"
-                    } else {
-                        false
-                    }
-                })
+                .filter(|e| is_wallpaper(e.path()))
                 .map(|e| e.path().to_path_buf())
                 .collect(),
         );
@@ -52,24 +52,294 @@ impl Filelist {
     }
 }
 
+/// Command printing the wallpapers, with the interval it is run again after. The same command
+/// with different intervals is run separately for each of them
+pub type ListCommand = (String, Option<Duration>);
+
+/// List of wallpapers printed by a command, one path per line
+#[derive(Debug)]
+struct CommandFilelist {
+    command: ListCommand,
+    filelist: Arc<Vec<PathBuf>>,
+    outdated: Arc<AtomicBool>,
+    /// Stop the thread that periodically refreshes the list
+    removed: Arc<AtomicBool>,
+    /// Thread running the command, it pings the event loop once it's done
+    thread_handle: Option<JoinHandle<Result<Vec<PathBuf>>>>,
+    /// Set by the thread right before pinging, it can be joined even if it's still exiting
+    finished: Arc<AtomicBool>,
+    /// True once the command has finished running at least once
+    listed: bool,
+    event_loop_ping: Ping,
+}
+
+impl CommandFilelist {
+    fn new(command: ListCommand, event_loop_ping: Ping) -> Self {
+        let interval = command.1;
+        let mut res = Self {
+            command,
+            filelist: Arc::new(Vec::new()),
+            outdated: Arc::new(AtomicBool::new(true)),
+            removed: Arc::new(AtomicBool::new(false)),
+            thread_handle: None,
+            finished: Arc::new(AtomicBool::new(false)),
+            listed: false,
+            event_loop_ping: event_loop_ping.clone(),
+        };
+        res.populate();
+
+        if let Some(interval) = interval {
+            let outdated = res.outdated.clone();
+            let removed = res.removed.clone();
+            // Same as the hotwatch callbacks, mark the list as outdated and let the event loop
+            // run the command again
+            std::thread::spawn(move || loop {
+                std::thread::sleep(interval);
+                if removed.load(Ordering::Acquire) {
+                    break;
+                }
+                outdated.store(true, Ordering::Release);
+                event_loop_ping.ping();
+            });
+        }
+
+        res
+    }
+
+    /// Run the command in a thread, so that the event loop isn't blocked until it's done.
+    /// When it's already running, it's run again after it has finished.
+    fn populate(&mut self) {
+        if self.thread_handle.is_some() {
+            return;
+        }
+        self.outdated.store(false, Ordering::Relaxed);
+        self.finished.store(false, Ordering::Relaxed);
+        let command = self.command.0.clone();
+        let finished = self.finished.clone();
+        let ping = self.event_loop_ping.clone();
+        self.thread_handle = Some(std::thread::spawn(move || {
+            let res = run_command(&command);
+            finished.store(true, Ordering::Release);
+            ping.ping();
+            res
+        }));
+    }
+
+    /// Replace the list with the one printed by the command, once it has finished. Return true
+    /// the first time it finishes and each time the list changes.
+    fn update(&mut self) -> bool {
+        let Some(handle) = self.thread_handle.take() else {
+            return false;
+        };
+        if !handle.is_finished() && !self.finished.load(Ordering::Acquire) {
+            self.thread_handle = Some(handle);
+            return false;
+        }
+        let first = !std::mem::replace(&mut self.listed, true);
+        match handle.join() {
+            Ok(Ok(filelist)) if *self.filelist != filelist => {
+                self.filelist = Arc::new(filelist);
+                true
+            }
+            Ok(Ok(_)) => first,
+            // Keep using the previous list
+            Ok(Err(err)) => {
+                error!("{err:?}");
+                first
+            }
+            Err(err) => {
+                error!(
+                    "the thread running command {:?} panicked: {err:?}",
+                    self.command.0
+                );
+                first
+            }
+        }
+    }
+}
+
+impl Drop for CommandFilelist {
+    fn drop(&mut self) {
+        self.removed.store(true, Ordering::Release);
+    }
+}
+
+/// Wallpapers of multiple directories and files combined in a single list
+#[derive(Debug)]
+struct PoolFilelist {
+    paths: Vec<PathBuf>,
+    filelist: Arc<Vec<PathBuf>>,
+}
+
+impl PoolFilelist {
+    fn new(paths: Vec<PathBuf>, cache: &[Filelist]) -> Self {
+        let mut res = Self {
+            paths,
+            filelist: Arc::new(Vec::new()),
+        };
+        res.populate(cache);
+        res
+    }
+
+    /// Combine the lists of the directories, they must be populated first
+    fn populate(&mut self, cache: &[Filelist]) {
+        let mut filelist: Vec<PathBuf> = self
+            .paths
+            .iter()
+            .flat_map(|path| {
+                let dir = cache.iter().find(|filelist| &filelist.path == path);
+                match dir {
+                    Some(dir) => dir.filelist.to_vec(),
+                    // Files and URLs are added as they are, directories that don't exist are
+                    // skipped
+                    None if path.is_file() || is_url(path) => vec![path.to_path_buf()],
+                    None => Vec::new(),
+                }
+            })
+            // The same image could be reached from different paths, e.g. nested directories
+            // or symlinks
+            .filter_map(|path| {
+                if is_url(&path) {
+                    Some(path)
+                } else {
+                    path.canonicalize().ok()
+                }
+            })
+            .collect();
+        // The sorting and the lookup of the current image expect a sorted list
+        filelist.sort_unstable();
+        filelist.dedup();
+        self.filelist = Arc::new(filelist);
+    }
+}
+
+/// Wallpapers of the first path of a fallback chain that provides any
+#[derive(Debug)]
+struct FallbackFilelist {
+    paths: Vec<PathBuf>,
+    filelist: Arc<Vec<PathBuf>>,
+    /// Index of the path in use, the length of `paths` when none of them has any wallpaper
+    level: usize,
+}
+
+impl FallbackFilelist {
+    fn new(paths: Vec<PathBuf>, cache: &[Filelist]) -> Self {
+        let mut res = Self {
+            paths,
+            filelist: Arc::new(Vec::new()),
+            level: 0,
+        };
+        res.populate(cache);
+        res
+    }
+
+    /// Return the wallpapers of a path of the chain, None if it's not available
+    fn path_filelist(path: &Path, cache: &[Filelist]) -> Option<Arc<Vec<PathBuf>>> {
+        match cache.iter().find(|filelist| filelist.path == path) {
+            Some(dir) => Some(dir.filelist.clone()),
+            None if (path.is_file() && is_wallpaper(path)) || is_url(path) => {
+                Some(Arc::new(vec![path.to_path_buf()]))
+            }
+            // The directory is not available, e.g. an unmounted network share
+            None => None,
+        }
+    }
+
+    /// Use the first path that has any wallpaper, the directories must be populated first
+    fn populate(&mut self, cache: &[Filelist]) {
+        for (level, path) in self.paths.iter().enumerate() {
+            let Some(filelist) = Self::path_filelist(path, cache) else {
+                continue;
+            };
+            if !filelist.is_empty() {
+                self.filelist = filelist;
+                self.level = level;
+                return;
+            }
+        }
+        self.filelist = Arc::new(Vec::new());
+        self.level = self.paths.len();
+    }
+}
+
+/// Run the command in a shell and collect the wallpapers it prints
+fn run_command(command: &str) -> Result<Vec<PathBuf>> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("running command {command:?}"))?;
+    ensure!(
+        output.status.success(),
+        "command {command:?} failed ({}), keeping the previous list of wallpapers",
+        output.status
+    );
+
+    let mut filelist: Vec<PathBuf> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        // The URLs of a dashboard don't always end with the extension of the image
+        .filter(|path| is_url(path) || is_wallpaper(path))
+        .collect();
+    // The sorting and the lookup of the current image expect a sorted list
+    filelist.sort_unstable();
+    filelist.dedup();
+    Ok(filelist)
+}
+
+fn is_wallpaper(path: &Path) -> bool {
+    // Only the image crate formats are supported without the decoder
+    if jxl::has_jxl_extension(path) {
+        return cfg!(feature = "jxl");
+    }
+    if let Some(guess) = new_mime_guess::from_path(path).first() {
+        guess.type_() == "image" || (cfg!(feature = "video") && guess.type_() == "video")
+    } else {
+        false
+    }
+}
+
 pub struct FilelistCache {
     cache: Vec<Filelist>,
+    commands: Vec<CommandFilelist>,
+    pools: Vec<PoolFilelist>,
+    fallbacks: Vec<FallbackFilelist>,
 }
 
 impl FilelistCache {
     pub fn new(
         paths: Vec<PathBuf>,
+        commands: Vec<ListCommand>,
+        pools: Vec<Vec<PathBuf>>,
+        fallbacks: Vec<Vec<PathBuf>>,
         hotwatch: &mut Hotwatch,
         event_loop_handle: LoopHandle<Wpaperd>,
+        qh: QueueHandle<Wpaperd>,
     ) -> Result<(Ping, Self)> {
         let (ping, ping_source) =
             calloop::ping::make_ping().context("Unable to create a calloop::ping::Ping")?;
 
-        let mut filelist_cache = Self { cache: Vec::new() };
+        let mut filelist_cache = Self {
+            cache: Vec::new(),
+            commands: Vec::new(),
+            pools: Vec::new(),
+            fallbacks: Vec::new(),
+        };
         filelist_cache.update_paths(paths, hotwatch, ping.clone());
+        filelist_cache.update_commands(commands, ping.clone());
+        filelist_cache.update_pools(pools);
+        filelist_cache.update_fallbacks(fallbacks);
         event_loop_handle
             .insert_source(ping_source, move |_, _, wpaperd| {
+                let listed = wpaperd.filelist_cache.borrow_mut().take_command_lists();
                 wpaperd.filelist_cache.borrow_mut().update_cache();
+                // The displays waiting for the first list of a command can show a wallpaper
+                for surface in &mut wpaperd.surfaces {
+                    surface.command_listed(&listed, &qh);
+                }
             })
             .map_err(|e| anyhow!("inserting the filelist event listener in the event loop: {e}"))?;
 
@@ -86,6 +356,176 @@ impl FilelistCache {
             .clone()
     }
 
+    /// Return the wallpapers printed by a command passed to [`FilelistCache::update_commands`],
+    /// empty until it has finished running
+    pub fn get_command(&self, command: &ListCommand) -> Arc<Vec<PathBuf>> {
+        self.get_command_filelist(command).filelist.clone()
+    }
+
+    /// Return true once the command has finished running, i.e. its list is known
+    pub fn is_listed(&self, command: &ListCommand) -> bool {
+        self.get_command_filelist(command).listed
+    }
+
+    fn get_command_filelist(&self, command: &ListCommand) -> &CommandFilelist {
+        self.commands
+            .iter()
+            .find(|filelist| &filelist.command == command)
+            .expect("command passed to Filelist::get_command has been cached")
+    }
+
+    /// Take the lists printed by the commands that have finished running, return the commands
+    /// that printed their first list or a different one
+    fn take_command_lists(&mut self) -> Vec<ListCommand> {
+        self.commands
+            .iter_mut()
+            .filter_map(|filelist| filelist.update().then(|| filelist.command.clone()))
+            .collect()
+    }
+
+    /// Return the wallpapers of a pool passed to [`FilelistCache::update_pools`]
+    pub fn get_pool(&self, paths: &[PathBuf]) -> Arc<Vec<PathBuf>> {
+        self.pools
+            .iter()
+            .find(|filelist| filelist.paths == paths)
+            .expect("pool passed to Filelist::get_pool has been cached")
+            .filelist
+            .clone()
+    }
+
+    fn get_fallback_filelist(&self, paths: &[PathBuf]) -> &FallbackFilelist {
+        self.fallbacks
+            .iter()
+            .find(|filelist| filelist.paths == paths)
+            .expect("fallback passed to Filelist::get_fallback has been cached")
+    }
+
+    /// Return the wallpapers of the first path of a fallback chain passed to
+    /// [`FilelistCache::update_fallbacks`] that has any
+    pub fn get_fallback(&self, paths: &[PathBuf]) -> Arc<Vec<PathBuf>> {
+        self.get_fallback_filelist(paths).filelist.clone()
+    }
+
+    /// Return the wallpapers of the first path of a fallback chain that has any once the ones
+    /// matching `skip` are removed, e.g. the URLs that could not be fetched
+    pub fn get_fallback_skipping(
+        &self,
+        paths: &[PathBuf],
+        skip: impl Fn(&Path) -> bool,
+    ) -> Arc<Vec<PathBuf>> {
+        paths
+            .iter()
+            .filter_map(|path| FallbackFilelist::path_filelist(path, &self.cache))
+            .map(|filelist| -> Vec<PathBuf> {
+                filelist
+                    .iter()
+                    .filter(|file| !skip(file))
+                    .cloned()
+                    .collect()
+            })
+            .find(|filelist| !filelist.is_empty())
+            .map(Arc::new)
+            .unwrap_or_default()
+    }
+
+    /// Return the index of the path of the fallback chain in use
+    pub fn fallback_level(&self, paths: &[PathBuf]) -> usize {
+        self.get_fallback_filelist(paths).level
+    }
+
+    /// Return the list of wallpapers from the command, the pool or the fallback chain, if any,
+    /// or from the directory
+    pub fn get_list(
+        &self,
+        path: &Path,
+        command: Option<&ListCommand>,
+        pool: &[PathBuf],
+        fallback: &[PathBuf],
+    ) -> Arc<Vec<PathBuf>> {
+        match command {
+            Some(command) => self.get_command(command),
+            None if !pool.is_empty() => self.get_pool(pool),
+            None if !fallback.is_empty() => self.get_fallback(fallback),
+            None => self.get(path),
+        }
+    }
+
+    /// Read again the directories of a list of wallpapers. Commands are not run again,
+    /// they are refreshed by their interval.
+    pub fn refresh(
+        &mut self,
+        path: &Path,
+        command: Option<&ListCommand>,
+        pool: &[PathBuf],
+        fallback: &[PathBuf],
+    ) {
+        if command.is_some() {
+            return;
+        }
+        for filelist in &self.cache {
+            if filelist.path == path
+                || pool.contains(&filelist.path)
+                || fallback.contains(&filelist.path)
+            {
+                filelist.outdated.store(true, Ordering::Release);
+            }
+        }
+        self.update_cache();
+    }
+
+    /// Combine the paths of the new pools and remove the old ones.
+    /// The paths must have been passed to [`FilelistCache::update_paths`] first.
+    pub fn update_pools(&mut self, pools: Vec<Vec<PathBuf>>) {
+        self.pools
+            .retain(|filelist| pools.iter().any(|paths| &filelist.paths == paths));
+        for paths in pools {
+            if !self.pools.iter().any(|filelist| filelist.paths == paths) {
+                self.pools.push(PoolFilelist::new(paths, &self.cache));
+            }
+        }
+    }
+
+    /// Add the new fallback chains and remove the old ones.
+    /// The paths must have been passed to [`FilelistCache::update_paths`] first.
+    pub fn update_fallbacks(&mut self, fallbacks: Vec<Vec<PathBuf>>) {
+        self.fallbacks
+            .retain(|filelist| fallbacks.iter().any(|paths| &filelist.paths == paths));
+        for paths in fallbacks {
+            if !self
+                .fallbacks
+                .iter()
+                .any(|filelist| filelist.paths == paths)
+            {
+                self.fallbacks
+                    .push(FallbackFilelist::new(paths, &self.cache));
+            }
+        }
+    }
+
+    /// Start running the new commands and stop the ones that have been removed.
+    /// The commands that are kept are run again, so that reloading the configuration
+    /// refreshes their list.
+    pub fn update_commands(&mut self, commands: Vec<ListCommand>, event_loop_ping: Ping) {
+        self.commands
+            .retain(|filelist| commands.contains(&filelist.command));
+        for filelist in &self.commands {
+            filelist.outdated.store(true, Ordering::Release);
+        }
+
+        for command in commands {
+            if !self
+                .commands
+                .iter()
+                .any(|filelist| filelist.command == command)
+            {
+                self.commands
+                    .push(CommandFilelist::new(command, event_loop_ping.clone()));
+            }
+        }
+
+        self.update_cache();
+    }
+
     /// paths must be sorted
     pub fn update_paths(
         &mut self,
@@ -95,7 +535,8 @@ impl FilelistCache {
     ) {
         self.cache.retain(|filelist| {
             let path_exists = filelist.path.exists();
-            if paths.contains(&filelist.path) && path_exists {
+            // Keep the paths that are temporarily unavailable, e.g. unmounted network shares
+            if paths.contains(&filelist.path) {
                 true
             } else {
                 // Stop watching paths that have been removed
@@ -148,7 +589,23 @@ impl FilelistCache {
     }
 
     pub fn update_cache(&mut self) {
+        let mut updated = false;
         for filelist in &mut self.cache {
+            if filelist.outdated.load(std::sync::atomic::Ordering::Relaxed) {
+                filelist.populate();
+                updated = true;
+            }
+        }
+        // Any of the directories could be part of a pool
+        if updated {
+            for pool in &mut self.pools {
+                pool.populate(&self.cache);
+            }
+            for fallback in &mut self.fallbacks {
+                fallback.populate(&self.cache);
+            }
+        }
+        for filelist in &mut self.commands {
             if filelist.outdated.load(std::sync::atomic::Ordering::Relaxed) {
                 filelist.populate();
             }
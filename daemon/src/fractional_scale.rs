@@ -0,0 +1,131 @@
+//! Draw the wallpapers at the exact scale of the displays scaled by a fraction, e.g. 1.25 or
+//! 1.5. The scale of a wl_surface can only be an integer, so without these protocols the
+//! buffers are drawn at the next integer scale and the compositor shrinks them, which blurs the
+//! wallpaper. The fractional-scale protocol sends the preferred scale of each surface in 120ths:
+//! its buffers are drawn at the size multiplied by that scale, with a buffer scale of 1, and the
+//! viewporter protocol maps them back to the logical size of the surface.
+
+use smithay_client_toolkit::reexports::client::{
+    globals::GlobalList, protocol::wl_surface::WlSurface, Connection, Dispatch, QueueHandle,
+};
+use smithay_client_toolkit::reexports::protocols::wp::{
+    fractional_scale::v1::client::{
+        wp_fractional_scale_manager_v1::{self, WpFractionalScaleManagerV1},
+        wp_fractional_scale_v1::{self, WpFractionalScaleV1},
+    },
+    viewporter::client::{
+        wp_viewport::{self, WpViewport},
+        wp_viewporter::{self, WpViewporter},
+    },
+};
+
+use crate::wpaperd::Wpaperd;
+
+/// Both protocols are needed, the fractional scale can't be applied without a viewport
+pub struct FractionalScaleManager {
+    manager: WpFractionalScaleManagerV1,
+    viewporter: WpViewporter,
+}
+
+impl FractionalScaleManager {
+    /// Return None when the compositor doesn't support either protocol, the surfaces then use
+    /// the integer scale of their display
+    pub fn bind(globals: &GlobalList, qh: &QueueHandle<Wpaperd>) -> Option<Self> {
+        let manager = globals.bind(qh, 1..=1, ()).ok()?;
+        let viewporter = globals.bind(qh, 1..=1, ()).ok()?;
+        Some(Self {
+            manager,
+            viewporter,
+        })
+    }
+
+    pub fn create(&self, wl_surface: &WlSurface, qh: &QueueHandle<Wpaperd>) -> FractionalScale {
+        FractionalScale {
+            fractional_scale: self
+                .manager
+                .get_fractional_scale(wl_surface, qh, wl_surface.clone()),
+            viewport: self.viewporter.get_viewport(wl_surface, qh, ()),
+        }
+    }
+}
+
+/// Objects of a surface, destroyed with it
+pub struct FractionalScale {
+    fractional_scale: WpFractionalScaleV1,
+    viewport: WpViewport,
+}
+
+impl FractionalScale {
+    /// Show the buffers on `width` x `height` logical pixels, whatever their size
+    pub fn set_size(&self, width: i32, height: i32) {
+        self.viewport.set_destination(width, height);
+    }
+}
+
+impl Drop for FractionalScale {
+    fn drop(&mut self) {
+        self.fractional_scale.destroy();
+        self.viewport.destroy();
+    }
+}
+
+impl Dispatch<WpFractionalScaleManagerV1, ()> for Wpaperd {
+    fn event(
+        _state: &mut Self,
+        _manager: &WpFractionalScaleManagerV1,
+        _event: wp_fractional_scale_manager_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // The manager doesn't have any event
+    }
+}
+
+impl Dispatch<WpViewporter, ()> for Wpaperd {
+    fn event(
+        _state: &mut Self,
+        _viewporter: &WpViewporter,
+        _event: wp_viewporter::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // The viewporter doesn't have any event
+    }
+}
+
+impl Dispatch<WpViewport, ()> for Wpaperd {
+    fn event(
+        _state: &mut Self,
+        _viewport: &WpViewport,
+        _event: wp_viewport::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // The viewport doesn't have any event
+    }
+}
+
+impl Dispatch<WpFractionalScaleV1, WlSurface> for Wpaperd {
+    fn event(
+        state: &mut Self,
+        _fractional_scale: &WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        wl_surface: &WlSurface,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let wp_fractional_scale_v1::Event::PreferredScale { scale } = event else {
+            return;
+        };
+        if let Some(surface) = state
+            .surfaces
+            .iter_mut()
+            .find(|surface| surface.wl_surface() == wl_surface)
+        {
+            surface.change_fractional_scale(scale, qh);
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! Fetch the wallpapers given as HTTP(S) URLs. The responses are stored in the cache directory
+//! together with their validators, so that an image that did not change is not downloaded
+//! again and the images are still shown after a restart. Network access is only allowed when
+//! `allow-network` is set.
+
+use std::path::Path;
+
+/// Return true if the path is an `http://` or `https://` URL instead of a file
+pub fn is_url(path: &Path) -> bool {
+    let Some(path) = path.to_str() else {
+        return false;
+    };
+    ["http://", "https://"].iter().any(|scheme| {
+        path.get(..scheme.len())
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(scheme))
+    })
+}
+
+/// Return true if the file exists or if it's a URL, which can't be checked without fetching it
+pub fn path_exists(path: &Path) -> bool {
+    is_url(path) || path.exists()
+}
+
+/// Download the images and keep them in a cache directory
+#[derive(Clone)]
+pub struct HttpCache {
+    #[cfg(feature = "http")]
+    dir: std::path::PathBuf,
+    #[cfg(feature = "http")]
+    agent: ureq::Agent,
+}
+
+#[cfg(feature = "http")]
+mod fetch {
+    use std::{
+        fs,
+        io::Read,
+        path::{Path, PathBuf},
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    use color_eyre::{
+        eyre::{anyhow, bail, Context},
+        Result,
+    };
+    use log::{debug, warn};
+    use serde::{Deserialize, Serialize};
+
+    use super::HttpCache;
+
+    /// Maximum time to connect and download an image
+    const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+    /// Bigger responses are refused, they are unlikely to be wallpapers
+    const MAX_IMAGE_SIZE: u64 = 64 * 1024 * 1024;
+
+    /// Validators and freshness of a cached response
+    #[derive(Default, Serialize, Deserialize)]
+    struct CacheEntry {
+        etag: Option<String>,
+        last_modified: Option<String>,
+        /// Seconds since the epoch until which the cached image can be used without asking
+        /// the server
+        expires: Option<u64>,
+    }
+
+    /// Caching directives of the `Cache-Control` header that wpaperd cares about
+    #[derive(Default)]
+    struct CacheControl {
+        no_store: bool,
+        no_cache: bool,
+        max_age: Option<u64>,
+    }
+
+    impl CacheControl {
+        fn parse(header: Option<&str>) -> Self {
+            let mut cache_control = Self::default();
+            for directive in header.unwrap_or_default().split(',') {
+                let directive = directive.trim().to_ascii_lowercase();
+                match directive.split_once('=') {
+                    Some(("max-age", value)) => {
+                        cache_control.max_age = value.trim_matches('"').parse().ok()
+                    }
+                    None if directive == "no-store" => cache_control.no_store = true,
+                    None if directive == "no-cache" => cache_control.no_cache = true,
+                    _ => {}
+                }
+            }
+            cache_control
+        }
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+
+    /// FNV-1a, the cached files must keep the same name across Rust versions
+    fn hash(url: &str) -> u64 {
+        url.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+
+    /// Write the file atomically, another thread could be fetching the same image
+    fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(format!(".{:08x}.tmp", rand::random::<u32>()));
+        fs::write(&tmp, contents).with_context(|| format!("writing {tmp:?}"))?;
+        fs::rename(&tmp, path).with_context(|| format!("renaming {tmp:?} to {path:?}"))
+    }
+
+    impl HttpCache {
+        pub fn new(dir: PathBuf) -> Self {
+            Self {
+                dir,
+                agent: ureq::AgentBuilder::new().timeout(FETCH_TIMEOUT).build(),
+            }
+        }
+
+        fn paths(&self, url: &str) -> (PathBuf, PathBuf) {
+            let name = format!("{:016x}", hash(url));
+            (self.dir.join(&name), self.dir.join(format!("{name}.json")))
+        }
+
+        /// Return the content of the image at `url`, from the cache if it's still fresh or
+        /// if the server answers that it has not changed
+        pub fn fetch(&self, url: &Path) -> Result<Vec<u8>> {
+            let url = url.to_string_lossy();
+            let (image_path, entry_path) = self.paths(&url);
+            let cached = fs::read(&image_path).ok();
+            let entry: CacheEntry = cached
+                .as_ref()
+                .and_then(|_| fs::read_to_string(&entry_path).ok())
+                .and_then(|entry| serde_json::from_str(&entry).ok())
+                .unwrap_or_default();
+
+            if let Some(cached) = &cached {
+                if entry.expires.is_some_and(|expires| expires > now()) {
+                    debug!("using the cached image of {url}");
+                    return Ok(cached.clone());
+                }
+            }
+
+            let mut request = self.agent.get(&url);
+            if cached.is_some() {
+                if let Some(etag) = &entry.etag {
+                    request = request.set("If-None-Match", etag);
+                }
+                if let Some(last_modified) = &entry.last_modified {
+                    request = request.set("If-Modified-Since", last_modified);
+                }
+            }
+            let response = match request.call() {
+                Ok(response) => response,
+                Err(ureq::Error::Status(status, response)) => {
+                    bail!("fetching {url}: {status} {}", response.status_text())
+                }
+                Err(err) => return Err(anyhow!(err).wrap_err(format!("fetching {url}"))),
+            };
+
+            let cache_control = CacheControl::parse(response.header("Cache-Control"));
+            let new_entry = CacheEntry {
+                // A 304 response doesn't have to repeat the validators
+                etag: response.header("ETag").map(str::to_string).or(entry.etag),
+                last_modified: response
+                    .header("Last-Modified")
+                    .map(str::to_string)
+                    .or(entry.last_modified),
+                expires: cache_control
+                    .max_age
+                    .filter(|_| !cache_control.no_cache)
+                    .map(|max_age| now().saturating_add(max_age)),
+            };
+
+            let image = match (response.status(), cached) {
+                (304, Some(cached)) => {
+                    debug!("the image of {url} has not changed");
+                    cached
+                }
+                (304, None) => bail!("fetching {url}: unexpected 304 Not Modified response"),
+                _ => {
+                    let mut image = Vec::new();
+                    response
+                        .into_reader()
+                        .take(MAX_IMAGE_SIZE + 1)
+                        .read_to_end(&mut image)
+                        .with_context(|| format!("downloading {url}"))?;
+                    if image.len() as u64 > MAX_IMAGE_SIZE {
+                        bail!("fetching {url}: the image is bigger than {MAX_IMAGE_SIZE} bytes");
+                    }
+                    image
+                }
+            };
+
+            if cache_control.no_store {
+                let _ = fs::remove_file(&image_path);
+                let _ = fs::remove_file(&entry_path);
+                return Ok(image);
+            }
+            // The image can still be shown when the cache can't be written
+            let res = fs::create_dir_all(&self.dir)
+                .with_context(|| format!("creating the cache directory {:?}", self.dir))
+                .and_then(|_| write_atomic(&image_path, &image))
+                .and_then(|_| {
+                    write_atomic(
+                        &entry_path,
+                        serde_json::to_string(&new_entry)
+                            .expect("json encoding to work")
+                            .as_bytes(),
+                    )
+                });
+            if let Err(err) = res {
+                warn!("caching the image of {url}: {err:?}");
+            }
+            Ok(image)
+        }
+    }
+}
+
+#[cfg(not(feature = "http"))]
+mod no_fetch {
+    use std::path::{Path, PathBuf};
+
+    use color_eyre::{eyre::anyhow, Result};
+
+    use super::HttpCache;
+
+    impl HttpCache {
+        pub fn new(_dir: PathBuf) -> Self {
+            Self {}
+        }
+
+        pub fn fetch(&self, url: &Path) -> Result<Vec<u8>> {
+            Err(anyhow!(
+                "cannot fetch {url:?}, wpaperd has been built without the http feature"
+            ))
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Pause the automatic wallpaper sequence while the user is idle, using the ext-idle-notify
+//! protocol, and change the wallpaper when the user is back.
+
+use std::time::Duration;
+
+use smithay_client_toolkit::reexports::client::{
+    globals::GlobalList,
+    protocol::wl_seat::{self, WlSeat},
+    Connection, Dispatch, QueueHandle,
+};
+use smithay_client_toolkit::reexports::protocols::ext::idle_notify::v1::client::{
+    ext_idle_notification_v1::{self, ExtIdleNotificationV1},
+    ext_idle_notifier_v1::{self, ExtIdleNotifierV1},
+};
+
+use crate::{surface::Surface, wpaperd::Wpaperd};
+
+struct IdleNotification {
+    surface_name: String,
+    timeout: Duration,
+    notification: ExtIdleNotificationV1,
+}
+
+impl Drop for IdleNotification {
+    fn drop(&mut self) {
+        self.notification.destroy();
+    }
+}
+
+pub struct IdleState {
+    notifier: Option<ExtIdleNotifierV1>,
+    seat: Option<WlSeat>,
+    notifications: Vec<IdleNotification>,
+}
+
+impl IdleState {
+    /// The protocol is optional, when the compositor doesn't support it the
+    /// `idle-timeout` configuration is ignored
+    pub fn bind(globals: &GlobalList, qh: &QueueHandle<Wpaperd>) -> Self {
+        Self {
+            notifier: globals.bind(qh, 1..=1, ()).ok(),
+            seat: globals.bind(qh, 1..=1, ()).ok(),
+            notifications: Vec::new(),
+        }
+    }
+
+    /// Create or remove the idle notifications so that they match the `idle-timeout`
+    /// of each surface
+    pub fn update(&mut self, surfaces: &[Surface], qh: &QueueHandle<Wpaperd>) {
+        self.notifications.retain(|notification| {
+            surfaces.iter().any(|surface| {
+                surface.name() == notification.surface_name
+                    && surface.wallpaper_info.idle_timeout == Some(notification.timeout)
+            })
+        });
+
+        let (Some(notifier), Some(seat)) = (&self.notifier, &self.seat) else {
+            return;
+        };
+        for surface in surfaces {
+            let Some(timeout) = surface.wallpaper_info.idle_timeout else {
+                continue;
+            };
+            let name = surface.name();
+            if self
+                .notifications
+                .iter()
+                .any(|notification| notification.surface_name == name)
+            {
+                continue;
+            }
+            let notification = notifier.get_idle_notification(
+                timeout.as_millis().try_into().unwrap_or(u32::MAX),
+                seat,
+                qh,
+                name.clone(),
+            );
+            self.notifications.push(IdleNotification {
+                surface_name: name,
+                timeout,
+                notification,
+            });
+        }
+    }
+}
+
+impl Dispatch<ExtIdleNotifierV1, ()> for Wpaperd {
+    fn event(
+        _state: &mut Self,
+        _notifier: &ExtIdleNotifierV1,
+        _event: ext_idle_notifier_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // The notifier doesn't have any event
+    }
+}
+
+impl Dispatch<WlSeat, ()> for Wpaperd {
+    fn event(
+        _state: &mut Self,
+        _seat: &WlSeat,
+        _event: wl_seat::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // The seat is only needed to create the idle notifications
+    }
+}
+
+impl Dispatch<ExtIdleNotificationV1, String> for Wpaperd {
+    fn event(
+        state: &mut Self,
+        _notification: &ExtIdleNotificationV1,
+        event: ext_idle_notification_v1::Event,
+        surface_name: &String,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let Some(surface) = state.surface_from_name(surface_name) else {
+            return;
+        };
+        match event {
+            ext_idle_notification_v1::Event::Idled => surface.set_idle(true, qh),
+            ext_idle_notification_v1::Event::Resumed => surface.set_idle(false, qh),
+            _ => {}
+        }
+    }
+}
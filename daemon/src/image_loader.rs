@@ -1,48 +1,488 @@
-use std::{collections::HashMap, path::PathBuf, thread::JoinHandle};
+use std::{
+    collections::{HashMap, HashSet},
+    io::{BufRead, Cursor, Seek},
+    path::{Path, PathBuf},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
 
-use image::{open, RgbaImage};
+use color_eyre::{eyre::anyhow, Result};
+use image::{
+    imageops::{self, FilterType},
+    metadata::Orientation,
+    DynamicImage, ImageDecoder, ImageReader, ImageResult, RgbaImage,
+};
 use log::warn;
+use smithay_client_toolkit::reexports::{
+    calloop::{
+        channel::{self, Sender},
+        LoopHandle,
+    },
+    client::QueueHandle,
+};
+
+#[cfg(feature = "jxl")]
+use crate::jxl;
+use crate::{
+    http::{is_url, HttpCache},
+    wallpaper_info::DownscaleFilter,
+    wpaperd::Wpaperd,
+};
+
+/// Time to wait before fetching again an image that could not be fetched, doubled after each
+/// failure
+const FETCH_RETRY_INITIAL_DELAY: Duration = Duration::from_secs(10);
+const FETCH_RETRY_MAX_DELAY: Duration = Duration::from_secs(600);
+
+/// Maximum size requested for a decoded image. Bigger images are downscaled until they
+/// cover this size, so that they can still fill the display in any mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Downscale {
+    pub width: u32,
+    pub height: u32,
+    pub filter: DownscaleFilter,
+}
+
+impl From<DownscaleFilter> for FilterType {
+    fn from(filter: DownscaleFilter) -> Self {
+        match filter {
+            DownscaleFilter::Nearest => FilterType::Nearest,
+            DownscaleFilter::Triangle => FilterType::Triangle,
+            DownscaleFilter::CatmullRom => FilterType::CatmullRom,
+            DownscaleFilter::Gaussian => FilterType::Gaussian,
+            DownscaleFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// Options changing how an image is decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LoadOptions {
+    pub max_size: Option<Downscale>,
+    /// Rotate and flip the image as stored in its EXIF metadata
+    pub exif_orientation: bool,
+    /// Find the most detailed region of the image, see [`focus`]
+    pub auto_offset: bool,
+    /// Compute the average color of the image, see [`average_color`]
+    pub average_color: bool,
+    /// Fetch the images given as URLs
+    pub allow_network: bool,
+}
+
+/// Return true if the orientation swaps the width and the height
+fn is_rotated(orientation: Orientation) -> bool {
+    matches!(
+        orientation,
+        Orientation::Rotate90
+            | Orientation::Rotate270
+            | Orientation::Rotate90FlipH
+            | Orientation::Rotate270FlipH
+    )
+}
+
+/// Size of an image, read from its header
+#[derive(Debug, Clone, Copy)]
+pub struct Dimensions {
+    pub width: u32,
+    pub height: u32,
+    /// The EXIF orientation rotates the image by 90 or 270 degrees
+    pub rotated: bool,
+}
+
+impl Dimensions {
+    /// Return the width and height, swapped if the image is rotated and `exif_orientation` is
+    /// followed
+    pub fn oriented(&self, exif_orientation: bool) -> (u32, u32) {
+        if self.rotated && exif_orientation {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        }
+    }
+}
+
+/// Size of an image as known by the [`ImageLoader`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageSize {
+    Known(u32, u32),
+    /// The header could not be read, e.g. for videos and URLs
+    Unknown,
+    /// The header is being read in the background, the displays are notified when it's done
+    Pending,
+}
+
+/// Dimensions read by a probing thread, with the generation of the cache they were read for
+type ProbeResults = (u64, Vec<(PathBuf, Option<Dimensions>)>);
+
+/// Read the dimensions of an image without decoding it
+pub fn probe(path: &Path) -> Result<Dimensions> {
+    // jxl-oxide already returns the dimensions of the oriented image
+    #[cfg(feature = "jxl")]
+    if jxl::has_jxl_extension(path) {
+        let (width, height) = jxl::dimensions(path)?;
+        return Ok(Dimensions {
+            width,
+            height,
+            rotated: false,
+        });
+    }
+    let mut decoder = ImageReader::open(path)?.into_decoder()?;
+    let rotated = decoder.orientation().is_ok_and(is_rotated);
+    let (width, height) = decoder.dimensions();
+    Ok(Dimensions {
+        width,
+        height,
+        rotated,
+    })
+}
+
+fn decode<R: BufRead + Seek>(
+    reader: ImageReader<R>,
+    exif_orientation: bool,
+) -> ImageResult<DynamicImage> {
+    let mut decoder = reader.into_decoder()?;
+    // A broken EXIF block shouldn't prevent the image from being shown
+    let orientation = if exif_orientation {
+        decoder.orientation().unwrap_or(Orientation::NoTransforms)
+    } else {
+        Orientation::NoTransforms
+    };
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+/// Decode an image fetched from the network, guessing its format from its content
+fn decode_bytes(content: Vec<u8>, exif_orientation: bool) -> Result<DynamicImage> {
+    #[cfg(feature = "jxl")]
+    if jxl::is_jxl(&content) {
+        return jxl::decode(Cursor::new(content));
+    }
+    Ok(decode(
+        ImageReader::new(Cursor::new(content)).with_guessed_format()?,
+        exif_orientation,
+    )?)
+}
+
+/// Decode an image from disk, guessing its format from its extension
+pub fn decode_file(path: &Path, exif_orientation: bool) -> Result<DynamicImage> {
+    #[cfg(feature = "jxl")]
+    if jxl::has_jxl_extension(path) {
+        return jxl::decode(std::io::BufReader::new(std::fs::File::open(path)?));
+    }
+    Ok(decode(ImageReader::open(path)?, exif_orientation)?)
+}
+
+/// Shrink the image to the smallest size covering the requested one, keeping its aspect ratio
+fn downscale(image: DynamicImage, max_size: Downscale) -> DynamicImage {
+    let (width, height) = (image.width(), image.height());
+    let scale = (max_size.width as f32 / width as f32).max(max_size.height as f32 / height as f32);
+    if scale >= 1.0 {
+        return image;
+    }
+    let new_width = ((width as f32 * scale).round() as u32).max(1);
+    let new_height = ((height as f32 * scale).round() as u32).max(1);
+    image.resize_exact(new_width, new_height, max_size.filter.into())
+}
+
+/// Size of the thumbnail whose edges are measured by [`focus`]
+const FOCUS_THUMBNAIL_SIZE: u32 = 64;
+
+/// Return the point of the image the eye is drawn to, from `[0.0, 0.0]` (top left) to
+/// `[1.0, 1.0]` (bottom right). It's the centroid of the edges of a small thumbnail, weighted
+/// by their strength, so that the subjects stand out from flat backgrounds like the sky.
+fn focus(image: &RgbaImage) -> [f32; 2] {
+    let thumbnail = imageops::thumbnail(image, FOCUS_THUMBNAIL_SIZE, FOCUS_THUMBNAIL_SIZE);
+    let (width, height) = thumbnail.dimensions();
+    let luma = |x: u32, y: u32| {
+        let [r, g, b, _] = thumbnail.get_pixel(x, y).0;
+        0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32
+    };
+    let (mut total, mut sum_x, mut sum_y) = (0.0, 0.0, 0.0);
+    for y in 0..height.saturating_sub(1) {
+        for x in 0..width.saturating_sub(1) {
+            let value = luma(x, y);
+            let edge = (luma(x + 1, y) - value).abs() + (luma(x, y + 1) - value).abs();
+            total += edge;
+            sum_x += edge * (x as f32 + 0.5);
+            sum_y += edge * (y as f32 + 0.5);
+        }
+    }
+    if total == 0.0 {
+        return [0.5, 0.5];
+    }
+    [sum_x / total / width as f32, sum_y / total / height as f32]
+}
+
+/// Return the average of the colors of the image, from 0.0 to 1.0
+pub fn average_color(image: &RgbaImage) -> [f32; 3] {
+    let mut sum = [0u64; 3];
+    for pixel in image.pixels() {
+        for (sum, channel) in sum.iter_mut().zip(pixel.0) {
+            *sum += channel as u64;
+        }
+    }
+    let count = (image.width() as u64 * image.height() as u64).max(1) as f32 * 255.0;
+    sum.map(|sum| sum as f32 / count)
+}
+
+/// Properties of an image computed while decoding it, when requested by the [`LoadOptions`]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ImageStats {
+    /// Point of the image to keep visible when it is cropped
+    pub focus: Option<[f32; 2]>,
+    pub average_color: Option<[f32; 3]>,
+}
+
+/// An image ready to be uploaded
+#[derive(Clone)]
+pub struct DecodedImage {
+    pub data: RgbaImage,
+    /// Time spent by the thread to decode the image
+    pub decode_time: Duration,
+    pub stats: ImageStats,
+}
 
 struct Image {
-    data: Option<RgbaImage>,
-    thread_handle: Option<JoinHandle<Option<RgbaImage>>>,
+    decoded: Option<DecodedImage>,
+    thread_handle: Option<JoinHandle<Option<DecodedImage>>>,
+    /// The thread has notified that it's done, it can be joined even if it's still exiting
+    finished: bool,
     requesters: Vec<String>,
 }
 
+/// Notify the event loop when dropped, i.e. when the thread has decoded the image, failed or
+/// panicked
+struct FinishedNotifier {
+    sender: Sender<(PathBuf, LoadOptions)>,
+    key: Option<(PathBuf, LoadOptions)>,
+}
+
+impl Drop for FinishedNotifier {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            // The event loop is gone when wpaperd is exiting
+            let _ = self.sender.send(key);
+        }
+    }
+}
+
+/// An image that could not be fetched and when to try again
+struct FetchRetry {
+    at: Instant,
+    delay: Duration,
+}
+
 pub enum ImageLoaderStatus {
-    Loaded(RgbaImage),
+    Loaded(DecodedImage),
     Waiting,
     Error,
 }
 
 pub struct ImageLoader {
-    images: HashMap<PathBuf, Image>,
+    /// The same image is decoded once for each set of options
+    images: HashMap<(PathBuf, LoadOptions), Image>,
+    /// Dimensions of the images probed so far, None if they couldn't be read
+    dimensions: HashMap<PathBuf, Option<Dimensions>>,
+    /// Images whose dimensions have been requested and are not known yet
+    probing: HashSet<PathBuf>,
+    /// Images requested since the last probing thread has been started
+    to_probe: Vec<PathBuf>,
+    /// Incremented when the dimensions are flushed, to discard the results of older threads
+    probe_generation: u64,
+    probe_sender: Sender<ProbeResults>,
+    http_cache: HttpCache,
+    /// The URLs whose last fetch failed
+    unreachable: HashMap<PathBuf, FetchRetry>,
+    /// Wake up the event loop when a thread is done
+    finished_sender: Sender<(PathBuf, LoadOptions)>,
 }
 
 impl ImageLoader {
-    pub fn new() -> Self {
-        Self {
+    /// Create the loader and listen to its threads in the event loop. The displays waiting
+    /// for an image are drawn again when it's ready, instead of polling on each frame.
+    pub fn new(
+        http_cache: HttpCache,
+        event_loop_handle: LoopHandle<Wpaperd>,
+        qh: QueueHandle<Wpaperd>,
+    ) -> Result<Self> {
+        let qh_probe = qh.clone();
+        let (finished_sender, finished_channel) = channel::channel();
+        event_loop_handle
+            .insert_source(finished_channel, move |event, _, wpaperd| {
+                let channel::Event::Msg(key) = event else {
+                    return;
+                };
+                wpaperd.image_loader.borrow_mut().finished(&key);
+                // The displays that have not been drawn yet are handled by the main loop
+                for surface in &mut wpaperd.surfaces {
+                    if surface.has_been_drawn() && surface.is_loading(&key.0) {
+                        surface.queue_draw(&qh);
+                    }
+                    if surface.is_loading_preview(&key.0) {
+                        surface.load_preview(&qh);
+                    }
+                }
+            })
+            .map_err(|e| anyhow!("inserting the image loader listener in the event loop: {e}"))?;
+
+        let (probe_sender, probe_channel) = channel::channel();
+        event_loop_handle
+            .insert_source(probe_channel, move |event, _, wpaperd| {
+                let channel::Event::Msg((generation, results)) = event else {
+                    return;
+                };
+                if !wpaperd
+                    .image_loader
+                    .borrow_mut()
+                    .probed(generation, results)
+                {
+                    return;
+                }
+                for surface in &mut wpaperd.surfaces {
+                    surface.images_probed(&qh_probe);
+                }
+            })
+            .map_err(|e| anyhow!("inserting the image probe listener in the event loop: {e}"))?;
+
+        Ok(Self {
             images: HashMap::new(),
+            dimensions: HashMap::new(),
+            probing: HashSet::new(),
+            to_probe: Vec::new(),
+            probe_generation: 0,
+            probe_sender,
+            http_cache,
+            unreachable: HashMap::new(),
+            finished_sender,
+        })
+    }
+
+    /// Mark the thread decoding the image as done
+    fn finished(&mut self, key: &(PathBuf, LoadOptions)) {
+        if let Some(image) = self.images.get_mut(key) {
+            image.finished = true;
         }
     }
 
-    pub fn background_load(&mut self, path: PathBuf, requester_name: String) -> ImageLoaderStatus {
-        if let Some(image) = self.images.get_mut(&path) {
+    /// Return true if the image is a URL that could not be fetched recently, it should not be
+    /// picked until it's time to try again
+    pub fn is_unreachable(&self, path: &Path) -> bool {
+        !self.unreachable.is_empty()
+            && self
+                .unreachable
+                .get(path)
+                .is_some_and(|retry| retry.at > Instant::now())
+    }
+
+    /// Keep track of the URLs that could not be fetched, waiting longer after each failure
+    fn update_unreachable(&mut self, path: &Path, failed: bool) {
+        if !is_url(path) {
+            return;
+        }
+        if !failed {
+            self.unreachable.remove(path);
+            return;
+        }
+        let delay = self
+            .unreachable
+            .get(path)
+            .map_or(FETCH_RETRY_INITIAL_DELAY, |retry| {
+                (retry.delay * 2).min(FETCH_RETRY_MAX_DELAY)
+            });
+        warn!("fetching {path:?} again in {}s", delay.as_secs());
+        self.unreachable.insert(
+            path.to_path_buf(),
+            FetchRetry {
+                at: Instant::now() + delay,
+                delay,
+            },
+        );
+    }
+
+    /// Return the width and height of an image, only reading its header. The EXIF orientation
+    /// is taken into account when `exif_orientation` is set.
+    /// The headers are read in the background by [`Self::start_probing`] and cached, as they
+    /// are requested each time the list of images changes.
+    pub fn image_size(&mut self, path: &Path, exif_orientation: bool) -> ImageSize {
+        match self.dimensions.get(path) {
+            Some(Some(dimensions)) => {
+                let (width, height) = dimensions.oriented(exif_orientation);
+                ImageSize::Known(width, height)
+            }
+            Some(None) => ImageSize::Unknown,
+            // The URLs would have to be fetched first
+            None if is_url(path) => ImageSize::Unknown,
+            None => {
+                if self.probing.insert(path.to_path_buf()) {
+                    self.to_probe.push(path.to_path_buf());
+                }
+                ImageSize::Pending
+            }
+        }
+    }
+
+    /// Read the headers of the images requested by [`Self::image_size`] in a thread, the
+    /// displays are notified when all of them are known
+    pub fn start_probing(&mut self) {
+        if self.to_probe.is_empty() {
+            return;
+        }
+        let paths = std::mem::take(&mut self.to_probe);
+        let sender = self.probe_sender.clone();
+        let generation = self.probe_generation;
+        std::thread::spawn(move || {
+            let results = paths
+                .into_iter()
+                .map(|path| {
+                    let dimensions = probe(&path).ok();
+                    (path, dimensions)
+                })
+                .collect();
+            // The event loop is gone when wpaperd is exiting
+            let _ = sender.send((generation, results));
+        });
+    }
+
+    /// Store the dimensions read by a probing thread, return true once all the requested
+    /// dimensions are known. The results are discarded if they have been flushed meanwhile.
+    fn probed(&mut self, generation: u64, results: Vec<(PathBuf, Option<Dimensions>)>) -> bool {
+        if generation != self.probe_generation {
+            return false;
+        }
+        for (path, dimensions) in results {
+            self.probing.remove(&path);
+            self.dimensions.insert(path, dimensions);
+        }
+        self.probing.is_empty()
+    }
+
+    pub fn background_load(
+        &mut self,
+        path: PathBuf,
+        requester_name: String,
+        options: LoadOptions,
+    ) -> ImageLoaderStatus {
+        let key = (path, options);
+        if let Some(image) = self.images.get_mut(&key) {
             if let Some(handle) = image.thread_handle.take() {
-                if handle.is_finished() {
+                if handle.is_finished() || image.finished {
                     match handle.join() {
                         Ok(thread_result) => match thread_result {
-                            Some(image_data) => {
-                                image.data = Some(image_data);
+                            Some(decoded) => {
+                                image.decoded = Some(decoded);
+                                self.update_unreachable(&key.0, false);
                             }
                             None => {
-                                self.images.remove(&path);
+                                self.images.remove(&key);
+                                self.update_unreachable(&key.0, true);
                                 return ImageLoaderStatus::Error;
                             }
                         },
                         Err(err) => {
                             warn!("{err:?}");
-                            self.images.remove(&path);
+                            self.images.remove(&key);
+                            self.update_unreachable(&key.0, true);
                             return ImageLoaderStatus::Error;
                         }
                     }
@@ -53,14 +493,15 @@ impl ImageLoader {
                     return ImageLoaderStatus::Waiting;
                 }
             }
-            if let Some(data) = &image.data {
+            let image = self.images.get_mut(&key).expect("image to be loading");
+            if let Some(decoded) = &image.decoded {
                 // If the requesters is only one and it's the same as the current
                 if image.requesters.len() == 1
                     && image.requesters.first().unwrap() == &requester_name
                 {
                     // Just send it up and remove it from the map
-                    let image = self.images.remove(&path);
-                    ImageLoaderStatus::Loaded(image.unwrap().data.unwrap())
+                    let image = self.images.remove(&key).unwrap();
+                    ImageLoaderStatus::Loaded(image.decoded.unwrap())
                 } else {
                     // otherwise this image has been requested by multiple surfaces
                     let requesters = &mut image.requesters;
@@ -68,7 +509,7 @@ impl ImageLoader {
                     {
                         requesters.remove(index);
                     }
-                    ImageLoaderStatus::Loaded(data.clone())
+                    ImageLoaderStatus::Loaded(decoded.clone())
                 }
             } else {
                 // The decoded image is not ready yet
@@ -76,24 +517,78 @@ impl ImageLoader {
             }
         } else {
             // Start loading a new image
-            let path_clone = path.clone();
-            let handle = std::thread::spawn(|| match open(path_clone) {
-                Ok(image) => Some(image.into_rgba8()),
-                Err(err) => {
-                    warn!("{err:?}");
-                    None
+            let path_clone = key.0.clone();
+            let http_cache = self.http_cache.clone();
+            let notifier = FinishedNotifier {
+                sender: self.finished_sender.clone(),
+                key: Some(key.clone()),
+            };
+            let handle = std::thread::spawn(move || {
+                let _notifier = notifier;
+                let start = Instant::now();
+                let res = if is_url(&path_clone) {
+                    if options.allow_network {
+                        http_cache
+                            .fetch(&path_clone)
+                            .and_then(|content| decode_bytes(content, options.exif_orientation))
+                    } else {
+                        Err(anyhow!(
+                            "cannot fetch {path_clone:?}, allow-network is not set"
+                        ))
+                    }
+                } else {
+                    decode_file(&path_clone, options.exif_orientation)
+                };
+                match res {
+                    Ok(image) => {
+                        let image = match options.max_size {
+                            Some(max_size) => downscale(image, max_size),
+                            None => image,
+                        };
+                        let data = image.into_rgba8();
+                        let stats = ImageStats {
+                            focus: options.auto_offset.then(|| focus(&data)),
+                            average_color: options.average_color.then(|| average_color(&data)),
+                        };
+                        Some(DecodedImage {
+                            data,
+                            decode_time: start.elapsed(),
+                            stats,
+                        })
+                    }
+                    Err(err) => {
+                        warn!("{err:?}");
+                        None
+                    }
                 }
             });
             let image = Image {
                 requesters: vec![requester_name],
                 thread_handle: Some(handle),
-                data: None,
+                finished: false,
+                decoded: None,
             };
-            self.images.insert(path, image);
+            self.images.insert(key, image);
             ImageLoaderStatus::Waiting
         }
     }
 
+    /// Forget the decoded images and the probed dimensions, so that the images are read again
+    /// from disk the next time they are requested. The images still being decoded are dropped
+    /// as well, their requesters will start decoding them again. The URLs that could not be
+    /// fetched can be picked again right away.
+    /// Return the number of decoded images and dimensions evicted.
+    pub fn flush(&mut self) -> (usize, usize) {
+        let evicted = (self.images.len(), self.dimensions.len());
+        self.images.clear();
+        self.dimensions.clear();
+        self.probing.clear();
+        self.to_probe.clear();
+        self.probe_generation += 1;
+        self.unreachable.clear();
+        evicted
+    }
+
     /// Check that there are no threads waiting on zero requesters
     #[cfg(debug_assertions)]
     pub fn check_lingering_threads(&mut self) {
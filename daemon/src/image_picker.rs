@@ -1,36 +1,60 @@
 use std::{
     cell::RefCell,
-    collections::VecDeque,
+    collections::{HashMap, HashSet, VecDeque},
+    fs,
     path::{Path, PathBuf},
     rc::Rc,
-    time::Instant,
+    sync::Arc,
+    time::{Instant, SystemTime},
 };
 
-use log::warn;
+use color_eyre::{
+    eyre::{bail, ContextCompat},
+    Result,
+};
+use log::{info, warn};
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    seq::SliceRandom,
+};
 use smithay_client_toolkit::reexports::client::{protocol::wl_surface::WlSurface, QueueHandle};
 
 use crate::{
-    filelist_cache::FilelistCache,
-    wallpaper_groups::{WallpaperGroup, WallpaperGroups},
+    daily::{daily_index, local_day},
+    favorites::Favorites,
+    filelist_cache::{FilelistCache, ListCommand},
+    http::{is_url, path_exists},
+    image_loader::{ImageLoader, ImageSize},
+    ranking::Ranking,
+    wallpaper_groups::{GroupChange, GroupPool, WallpaperGroup, WallpaperGroups},
     wallpaper_info::{Sorting, WallpaperInfo},
+    weekdays::{is_shown_on, Weekday, WeekdayPin},
     wpaperd::Wpaperd,
 };
 
+/// Images drawn with the random sorting. The history is walked through by previous and next,
+/// while the most recent images, up to `no_repeat`, are not picked again. The latter are kept
+/// apart so that the two sizes are independent.
 #[derive(Debug)]
 pub struct Queue {
     buffer: VecDeque<PathBuf>,
     current: usize,
     tail: usize,
     size: usize,
+    /// Images drawn most recently, the last one is the newest
+    recent: VecDeque<PathBuf>,
+    no_repeat: usize,
 }
 
 impl Queue {
-    pub fn with_capacity(size: usize) -> Self {
+    pub fn new(size: usize, no_repeat: usize) -> Self {
         Self {
             buffer: VecDeque::with_capacity(size),
             current: 0,
             tail: size - 1,
             size,
+            recent: VecDeque::with_capacity(no_repeat),
+            no_repeat,
         }
     }
 
@@ -70,8 +94,9 @@ impl Queue {
         self.buffer.len() == self.size
     }
 
+    /// Return true if the image has been drawn too recently to be picked again
     fn contains(&self, p: &PathBuf) -> bool {
-        self.buffer.contains(p)
+        self.recent.contains(p)
     }
 
     fn set_current_to(&mut self, p: &Path) {
@@ -86,6 +111,14 @@ impl Queue {
             return;
         };
 
+        self.recent.retain(|recent| *recent != p);
+        if self.no_repeat > 0 {
+            if self.recent.len() == self.no_repeat {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(p.clone());
+        }
+
         if self.is_full() {
             self.buffer.pop_front();
             self.buffer.push_back(p);
@@ -117,6 +150,120 @@ impl Queue {
             self.buffer = new_buf;
         }
     }
+
+    fn set_no_repeat(&mut self, no_repeat: usize) {
+        while self.recent.len() > no_repeat {
+            self.recent.pop_front();
+        }
+        self.no_repeat = no_repeat;
+    }
+}
+
+/// Every image is shown once in a random order before any of them is repeated
+#[derive(Debug, Default)]
+struct Shuffle {
+    /// Images left in the current round, the next one is the last. None until the first round
+    /// has started, which skips the images already in `shown`
+    remaining: Option<Vec<PathBuf>>,
+    /// Images shown during the current round, so that previous can go back through them
+    shown: Vec<PathBuf>,
+    /// Position of the current image in `shown`
+    position: usize,
+    /// Image picked by next or previous, returned again until it's shown
+    loading: Option<PathBuf>,
+}
+
+impl Shuffle {
+    /// Start a shuffle in which `current` has already been shown
+    fn starting_from(current: &Path) -> Self {
+        Self {
+            remaining: None,
+            shown: vec![current.to_path_buf()],
+            position: 0,
+            loading: None,
+        }
+    }
+
+    /// Add the images that have appeared to the remaining ones, at random positions, and
+    /// forget the images that have been removed
+    fn sync(&mut self, files: &[PathBuf]) {
+        let Some(remaining) = &mut self.remaining else {
+            return;
+        };
+        let files_set: HashSet<&PathBuf> = files.iter().collect();
+        remaining.retain(|file| files_set.contains(file));
+        let current = self.shown.get(self.position).cloned();
+        self.shown.retain(|file| files_set.contains(file));
+        self.position = current
+            .and_then(|current| self.shown.iter().position(|file| *file == current))
+            .unwrap_or(self.shown.len().saturating_sub(1));
+
+        if remaining.len() + self.shown.len() == files.len() {
+            return;
+        }
+        let known: HashSet<&PathBuf> = remaining.iter().chain(self.shown.iter()).collect();
+        let added: Vec<PathBuf> = files
+            .iter()
+            .filter(|file| !known.contains(file))
+            .cloned()
+            .collect();
+        for file in added {
+            let index = rand::random::<usize>() % (remaining.len() + 1);
+            remaining.insert(index, file);
+        }
+    }
+
+    /// Return the next image, going forward in the images already shown first
+    fn next(&mut self, current: &Path, files: &[PathBuf]) -> PathBuf {
+        self.sync(files);
+        if self.position + 1 < self.shown.len() {
+            self.position += 1;
+            let next = self.shown[self.position].clone();
+            self.loading = Some(next.clone());
+            return next;
+        }
+        let remaining = match &mut self.remaining {
+            Some(remaining) if !remaining.is_empty() => remaining,
+            remaining => {
+                let first_round = remaining.is_none();
+                let mut files = files.to_vec();
+                if first_round {
+                    files.retain(|file| !self.shown.contains(file));
+                } else {
+                    self.shown.clear();
+                }
+                files.shuffle(&mut rand::thread_rng());
+                // Don't show the current image twice in a row when starting a new round
+                let last = files.len().saturating_sub(1);
+                if last > 0 && files[last] == current {
+                    files.swap(0, last);
+                }
+                if files.is_empty() {
+                    // Every image has been shown while waiting for the first round
+                    files = self.shown.drain(..).collect();
+                }
+                remaining.insert(files)
+            }
+        };
+        let next = remaining.pop().expect("the list of files not to be empty");
+        self.shown.push(next.clone());
+        self.position = self.shown.len() - 1;
+        self.loading = Some(next.clone());
+        next
+    }
+
+    /// Return the image shown before the current one in this round, the current one if it
+    /// is the first
+    fn previous(&mut self, current: &Path) -> PathBuf {
+        if self.position > 0 && self.position < self.shown.len() {
+            self.position -= 1;
+            let previous = self.shown[self.position].clone();
+            self.loading = Some(previous.clone());
+            previous
+        } else {
+            current.to_path_buf()
+        }
+    }
 }
 
 enum ImagePickerAction {
@@ -125,6 +272,7 @@ enum ImagePickerAction {
 }
 
 struct GroupedRandom {
+    id: u8,
     surface: WlSurface,
     group: Rc<RefCell<WallpaperGroup>>,
 }
@@ -140,6 +288,19 @@ enum ImagePickerSorting {
     GroupedRandom(GroupedRandom),
     Ascending(usize),
     Descending(usize),
+    /// Ascending order from a random image, `start` is where the current run began
+    RandomStart {
+        index: usize,
+        start: Option<usize>,
+        /// Random image picked to begin the next run, returned again until it's shown
+        loading_start: Option<(usize, PathBuf)>,
+    },
+    /// Image of the day, `day` is the local day it has been picked for
+    Daily {
+        index: usize,
+        day: Option<i64>,
+    },
+    Shuffle(Shuffle),
 }
 
 impl ImagePickerSorting {
@@ -150,29 +311,45 @@ impl ImagePickerSorting {
         filelist_cache: Rc<RefCell<FilelistCache>>,
     ) -> Self {
         match wallpaper_info.sorting {
-            None | Some(Sorting::Random) => {
-                Self::new_random(wallpaper_info.drawn_images_queue_size)
-            }
+            None | Some(Sorting::Random) => Self::new_random(
+                wallpaper_info.drawn_images_queue_size,
+                wallpaper_info.no_repeat_size,
+            ),
             Some(Sorting::GroupedRandom { group }) => Self::new_grouped_random(
                 groups,
                 group,
+                GroupPool {
+                    path: wallpaper_info.path.clone(),
+                    pool: wallpaper_info.pool.clone(),
+                    fallback: wallpaper_info.fallback.clone(),
+                    command: wallpaper_info.list_command(),
+                },
                 wl_surface,
                 wallpaper_info.drawn_images_queue_size,
+                wallpaper_info.no_repeat_size,
             ),
             Some(Sorting::Ascending) => {
                 let files_len = filelist_cache
                     .clone()
                     .borrow()
-                    .get(&wallpaper_info.path)
+                    .get_list(
+                        &wallpaper_info.path,
+                        wallpaper_info.list_command().as_ref(),
+                        &wallpaper_info.pool,
+                        &wallpaper_info.fallback,
+                    )
                     .len();
                 Self::new_ascending(files_len)
             }
             Some(Sorting::Descending) => Self::new_descending(),
+            Some(Sorting::RandomStart) => Self::new_random_start(),
+            Some(Sorting::Daily) => Self::new_daily(),
+            Some(Sorting::Shuffle) => Self::Shuffle(Shuffle::default()),
         }
     }
 
-    fn new_random(queue_size: usize) -> Self {
-        Self::Random(Queue::with_capacity(queue_size))
+    fn new_random(queue_size: usize, no_repeat_size: usize) -> Self {
+        Self::Random(Queue::new(queue_size, no_repeat_size))
     }
 
     fn new_descending() -> ImagePickerSorting {
@@ -180,64 +357,337 @@ impl ImagePickerSorting {
     }
 
     fn new_ascending(files_len: usize) -> ImagePickerSorting {
-        Self::Ascending(files_len - 1)
+        Self::Ascending(files_len.saturating_sub(1))
+    }
+
+    fn new_random_start() -> ImagePickerSorting {
+        Self::RandomStart {
+            index: 0,
+            start: None,
+            loading_start: None,
+        }
+    }
+
+    /// Return the random sequence followed by the display
+    fn random_sequence(&self) -> RandomSequence {
+        match self {
+            Self::Random(_) => RandomSequence::Own,
+            Self::GroupedRandom(group) => RandomSequence::Group(group.id),
+            Self::Ascending(_)
+            | Self::Descending(_)
+            | Self::RandomStart { .. }
+            | Self::Daily { .. }
+            | Self::Shuffle(_) => RandomSequence::None,
+        }
+    }
+
+    fn new_daily() -> ImagePickerSorting {
+        Self::Daily {
+            index: 0,
+            day: None,
+        }
     }
 
     fn new_grouped_random(
         groups: Rc<RefCell<WallpaperGroups>>,
         group: u8,
+        pool: GroupPool,
         wl_surface: &WlSurface,
         queue_size: usize,
+        no_repeat_size: usize,
     ) -> Self {
         Self::GroupedRandom(GroupedRandom {
+            id: group,
             surface: wl_surface.clone(),
-            group: groups
-                .borrow_mut()
-                .get_or_insert(group, wl_surface, queue_size),
+            group: groups.borrow_mut().get_or_insert(
+                group,
+                pool,
+                wl_surface,
+                queue_size,
+                no_repeat_size,
+            ),
         })
     }
 }
 
+/// The random sequence followed by a display.
+///
+/// Each display without a group walks through the wallpapers on its own, with its own queue of
+/// drawn images, so that next and previous on a display never affect the others; they only
+/// share the random number generator, and the images shown elsewhere with
+/// `--unique-wallpapers`. The displays in a group with the same wallpapers share a single queue
+/// and the current image of the group instead, so that they always move in lockstep. The
+/// displays of the group picking from other wallpapers have their own queue and image, and
+/// follow the changes of the group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RandomSequence {
+    /// The sorting is not random
+    None,
+    Own,
+    Group(u8),
+}
+
+/// How the random sequence of a display changes when its sorting is updated
+#[derive(Debug, PartialEq, Eq)]
+enum RandomUpdate {
+    /// Keep the current sequence
+    Keep,
+    /// Start a new sequence from the current image, so that previous still goes back to it
+    Continue,
+    /// Start a new sequence, the current image is not one of the wallpapers anymore
+    Restart,
+    /// Share the sequence of the group, dropping the current one
+    Join(u8),
+}
+
+/// Decide how the random sequence changes, None if the new sorting is not random.
+/// Joining a group always replaces the sequence of the display with the group one, even if the
+/// group has just been created, while leaving a group continues from the image shown by the
+/// group.
+fn random_update(
+    current: RandomSequence,
+    new_sorting: Sorting,
+    path_changed: bool,
+) -> Option<RandomUpdate> {
+    Some(match (current, new_sorting) {
+        (RandomSequence::Group(id), Sorting::GroupedRandom { group })
+            if id == group && !path_changed =>
+        {
+            RandomUpdate::Keep
+        }
+        (_, Sorting::GroupedRandom { group }) => RandomUpdate::Join(group),
+        (_, Sorting::Random) if path_changed => RandomUpdate::Restart,
+        (RandomSequence::Own, Sorting::Random) => RandomUpdate::Keep,
+        (RandomSequence::None | RandomSequence::Group(_), Sorting::Random) => {
+            RandomUpdate::Continue
+        }
+        (
+            _,
+            Sorting::Ascending
+            | Sorting::Descending
+            | Sorting::RandomStart
+            | Sorting::Daily
+            | Sorting::Shuffle,
+        ) => return None,
+    })
+}
+
+/// Images left once some are skipped from a list, returned again while the same ones are skipped
+/// from the same list so that [`FilteredFilelist`] can tell that it hasn't changed
+struct SkippedFilelist {
+    unfiltered: Arc<Vec<PathBuf>>,
+    /// Indices of the images skipped
+    skipped: Vec<usize>,
+    filtered: Arc<Vec<PathBuf>>,
+}
+
+impl SkippedFilelist {
+    /// Remove the images whose index is in `skipped`, reusing the list of the last call if the
+    /// same ones have been skipped
+    fn skip(
+        cache: &mut Option<Self>,
+        files: Arc<Vec<PathBuf>>,
+        skipped: Vec<usize>,
+    ) -> Arc<Vec<PathBuf>> {
+        if skipped.is_empty() {
+            return files;
+        }
+        if let Some(cache) = cache {
+            if Arc::ptr_eq(&cache.unfiltered, &files) && cache.skipped == skipped {
+                return cache.filtered.clone();
+            }
+        }
+        let filtered: Arc<Vec<PathBuf>> = Arc::new(
+            files
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| skipped.binary_search(index).is_err())
+                .map(|(_, file)| file.clone())
+                .collect(),
+        );
+        *cache = Some(Self {
+            unfiltered: files,
+            skipped,
+            filtered: filtered.clone(),
+        });
+        filtered
+    }
+
+    /// Return the list of the last call if it has the same images, e.g. when it comes from a
+    /// fallback chain
+    fn reuse(cache: &mut Option<Self>, files: Arc<Vec<PathBuf>>) -> Arc<Vec<PathBuf>> {
+        match cache {
+            Some(cache) if cache.filtered == files => cache.filtered.clone(),
+            _ => {
+                *cache = Some(Self {
+                    unfiltered: files.clone(),
+                    skipped: Vec::new(),
+                    filtered: files.clone(),
+                });
+                files
+            }
+        }
+    }
+}
+
+/// Return the indices of the images matching `skip`
+fn skipped_indices(files: &[PathBuf], skip: impl Fn(&Path) -> bool) -> Vec<usize> {
+    files
+        .iter()
+        .enumerate()
+        .filter(|(_, file)| skip(file))
+        .map(|(index, _)| index)
+        .collect()
+}
+
+/// Images of a list returned by the cache that match the aspect ratio and the minimum resolution
+struct FilteredFilelist {
+    unfiltered: Arc<Vec<PathBuf>>,
+    filtered: Arc<Vec<PathBuf>>,
+}
+
 pub struct ImagePicker {
+    /// Name of the display, added to the log messages
+    name: String,
     current_img: PathBuf,
     pub image_changed_instant: Instant,
     action: Option<ImagePickerAction>,
     sorting: ImagePickerSorting,
     filelist_cache: Rc<RefCell<FilelistCache>>,
+    /// Pick the wallpapers printed by this command instead of the ones in the directory
+    command: Option<ListCommand>,
+    /// Pick the wallpapers from all these paths instead of the directory
+    pool: Vec<PathBuf>,
+    /// Pick the wallpapers from the first of these paths that has any
+    fallback: Vec<PathBuf>,
+    /// Index of the fallback path in use the last time the list was read
+    fallback_level: usize,
+    /// Only pick images whose aspect ratio differs from the display one by this fraction
+    aspect_ratio_tolerance: Option<f32>,
+    /// Aspect ratio of the display, None until the surface has been configured
+    display_ratio: Option<f32>,
+    /// Skip the images smaller than this width and height
+    min_resolution: Option<(u32, u32)>,
+    /// Images already reported as too small, so that they are only logged once
+    skipped_small_images: HashSet<PathBuf>,
+    /// Images that could not be decoded with their modification time, skipped until they are
+    /// changed or the cache is flushed
+    failed_images: HashMap<PathBuf, Option<SystemTime>>,
+    /// Swap the dimensions of the images rotated by their EXIF orientation
+    exif_orientation: bool,
+    /// Move the wallpaper of the day by this amount of images
+    daily_offset: usize,
+    /// Directories and images only shown on some days of the week
+    weekdays: Vec<WeekdayPin>,
+    /// Weights of the images picked by the random sorting
+    ranking: Option<Ranking>,
+    /// Images marked as favorites
+    favorites: Option<Favorites>,
+    /// Pick the wallpapers only from the favorites, set with `wpaperctl toggle-favorites`
+    favorites_only: bool,
+    image_loader: Rc<RefCell<ImageLoader>>,
+    /// Left by each of the filters skipping images, see [`SkippedFilelist`]
+    skipped_unreachable: Option<SkippedFilelist>,
+    skipped_failed: Option<SkippedFilelist>,
+    skipped_weekdays: Option<SkippedFilelist>,
+    /// Filtered from the last list of files returned by the cache
+    filtered_filelist: Option<FilteredFilelist>,
+    /// Some images of the filtered list were skipped because their dimensions are being read
+    probing: bool,
     reload: bool,
+    /// True when the list of wallpapers was empty the last time it was read
+    unavailable: bool,
+    wl_surface: WlSurface,
+    /// Shared with the other displays, to know which images they are showing
+    wallpaper_groups: Rc<RefCell<WallpaperGroups>>,
+}
+
+impl Drop for ImagePicker {
+    fn drop(&mut self) {
+        self.wallpaper_groups
+            .borrow_mut()
+            .remove_shown(&self.wl_surface);
+    }
 }
 
 impl ImagePicker {
     pub const DEFAULT_DRAWN_IMAGES_QUEUE_SIZE: usize = 10;
     pub fn new(
+        name: String,
         wallpaper_info: &WallpaperInfo,
         wl_surface: &WlSurface,
         filelist_cache: Rc<RefCell<FilelistCache>>,
         groups: Rc<RefCell<WallpaperGroups>>,
+        image_loader: Rc<RefCell<ImageLoader>>,
     ) -> Self {
         Self {
+            name,
             current_img: PathBuf::from(""),
             image_changed_instant: Instant::now(),
             action: Some(ImagePickerAction::Next),
             sorting: ImagePickerSorting::new(
                 wallpaper_info,
                 wl_surface,
-                groups,
+                groups.clone(),
                 filelist_cache.clone(),
             ),
             filelist_cache,
+            command: wallpaper_info.list_command(),
+            pool: wallpaper_info.pool.clone(),
+            fallback: wallpaper_info.fallback.clone(),
+            fallback_level: 0,
+            aspect_ratio_tolerance: wallpaper_info.aspect_ratio_tolerance,
+            display_ratio: None,
+            min_resolution: wallpaper_info.min_resolution,
+            skipped_small_images: HashSet::new(),
+            failed_images: HashMap::new(),
+            exif_orientation: wallpaper_info.exif_orientation,
+            daily_offset: wallpaper_info.daily_offset,
+            weekdays: wallpaper_info.weekdays.clone(),
+            ranking: wallpaper_info.ranking.clone().map(Ranking::new),
+            favorites: wallpaper_info.favorites.clone().map(Favorites::new),
+            favorites_only: false,
+            image_loader,
+            skipped_unreachable: None,
+            skipped_failed: None,
+            skipped_weekdays: None,
+            filtered_filelist: None,
+            probing: false,
             reload: false,
+            unavailable: false,
+            wl_surface: wl_surface.clone(),
+            wallpaper_groups: groups,
         }
     }
 
     /// Get the next image based on the sorting method
     fn get_image_path(&mut self, files: &[PathBuf], qh: &QueueHandle<Wpaperd>) -> (usize, PathBuf) {
+        let shown = self
+            .wallpaper_groups
+            .borrow()
+            .shown_elsewhere(&self.wl_surface);
+        let ranking = &mut self.ranking;
         match (&self.action, &mut self.sorting) {
+            (None, ImagePickerSorting::Daily { index, day }) => {
+                let today = local_day();
+                if *day == Some(today) && path_exists(&self.current_img) {
+                    (*index, self.current_img.to_path_buf())
+                } else {
+                    // A new day has started, or the image of the day has not been picked yet
+                    *day = Some(today);
+                    let index = daily_index(today, self.daily_offset, files.len());
+                    (index, files[index].to_path_buf())
+                }
+            }
             (
                 None,
                 ImagePickerSorting::Ascending(current_index)
-                | ImagePickerSorting::Descending(current_index),
-            ) if self.current_img.exists() => (*current_index, self.current_img.to_path_buf()),
+                | ImagePickerSorting::Descending(current_index)
+                | ImagePickerSorting::RandomStart {
+                    index: current_index,
+                    ..
+                },
+            ) if path_exists(&self.current_img) => (*current_index, self.current_img.to_path_buf()),
             (_, ImagePickerSorting::GroupedRandom(group))
                 if group.group.borrow().loading_image.is_some() =>
             {
@@ -245,6 +695,37 @@ impl ImagePicker {
                 let (index, loading_image) = group.loading_image.as_ref().unwrap();
                 (*index, loading_image.to_path_buf())
             }
+            // The timers of the other pools expire at the same time, the change they made is
+            // followed instead of changing the whole group once more
+            (None | Some(ImagePickerAction::Next), ImagePickerSorting::GroupedRandom(group))
+                if group.group.borrow().change.is_some() =>
+            {
+                // Another pool of the group has changed its wallpaper, change this one too
+                let mut group = group.group.borrow_mut();
+                let current_image = group.current_image.clone();
+                let (index, path) = match group.change.take().unwrap() {
+                    GroupChange::Next => {
+                        let (index, path) = next_random_image(
+                            &current_image,
+                            &mut group.queue,
+                            files,
+                            &shown,
+                            refreshed(ranking, &self.name),
+                        );
+                        if group.queue.has_reached_end() || group.queue.buffer.get(index).is_none()
+                        {
+                            group.queue.push(path.clone());
+                        }
+                        (index, path)
+                    }
+                    GroupChange::Previous => {
+                        get_previous_image_for_random(&current_image, &mut group.queue)
+                    }
+                };
+                group.loading_image = Some((index, path.to_path_buf()));
+                group.queue_all_surfaces(qh);
+                (index, path)
+            }
             (_, ImagePickerSorting::GroupedRandom(group))
                 if group.group.borrow().current_image != self.current_img =>
             {
@@ -252,21 +733,63 @@ impl ImagePicker {
                 (group.index, group.current_image.clone())
             }
             (None, ImagePickerSorting::Random(_) | ImagePickerSorting::GroupedRandom(_))
-                if self.current_img.exists() =>
+                if path_exists(&self.current_img) =>
             {
                 (0, self.current_img.to_path_buf())
             }
+            (None, ImagePickerSorting::Shuffle(_)) if path_exists(&self.current_img) => (
+                index_of(files, &self.current_img),
+                self.current_img.to_path_buf(),
+            ),
+            // The image has been picked but not shown yet, e.g. it's being decoded
+            (
+                _,
+                ImagePickerSorting::Shuffle(Shuffle {
+                    loading: Some(path),
+                    ..
+                }),
+            ) if files.contains(path) => (index_of(files, path), path.to_path_buf()),
+            (None | Some(ImagePickerAction::Next), ImagePickerSorting::Shuffle(shuffle)) => {
+                let path = shuffle.next(&self.current_img, files);
+                (index_of(files, &path), path)
+            }
+            (Some(ImagePickerAction::Previous), ImagePickerSorting::Shuffle(shuffle)) => {
+                let path = shuffle.previous(&self.current_img);
+                (index_of(files, &path), path)
+            }
             (None | Some(ImagePickerAction::Next), ImagePickerSorting::Random(queue)) => {
-                next_random_image(&self.current_img, queue, files)
+                next_random_image(
+                    &self.current_img,
+                    queue,
+                    files,
+                    &shown,
+                    refreshed(ranking, &self.name),
+                )
             }
-            (None | Some(ImagePickerAction::Next), ImagePickerSorting::GroupedRandom(group)) => {
-                let mut group = group.group.borrow_mut();
+            (None | Some(ImagePickerAction::Next), ImagePickerSorting::GroupedRandom(grouped)) => {
+                let mut group = grouped.group.borrow_mut();
                 if self.current_img == group.current_image {
                     // start loading a new image
-                    let (index, path) =
-                        next_random_image(&self.current_img, &mut group.queue, files);
+                    let (index, path) = next_random_image(
+                        &self.current_img,
+                        &mut group.queue,
+                        files,
+                        &shown,
+                        refreshed(ranking, &self.name),
+                    );
                     group.loading_image = Some((index, path.to_path_buf()));
+                    group.change = None;
                     group.queue_all_surfaces(qh);
+                    drop(group);
+                    // The first image of the display is not a change of the group
+                    if self.action.is_some() {
+                        self.wallpaper_groups.borrow().change_other_pools(
+                            grouped.id,
+                            &grouped.group,
+                            GroupChange::Next,
+                            qh,
+                        );
+                    }
                     (index, path)
                 } else {
                     (group.index, group.current_image.clone())
@@ -275,13 +798,21 @@ impl ImagePicker {
             (Some(ImagePickerAction::Previous), ImagePickerSorting::Random(queue)) => {
                 get_previous_image_for_random(&self.current_img, queue)
             }
-            (Some(ImagePickerAction::Previous), ImagePickerSorting::GroupedRandom(group)) => {
-                let mut group = group.group.borrow_mut();
+            (Some(ImagePickerAction::Previous), ImagePickerSorting::GroupedRandom(grouped)) => {
+                let mut group = grouped.group.borrow_mut();
                 let queue = &mut group.queue;
                 let (index, path) = get_previous_image_for_random(&self.current_img, queue);
                 if path != group.current_image {
                     group.loading_image = Some((index, path.to_path_buf()));
+                    group.change = None;
                     group.queue_all_surfaces(qh);
+                    drop(group);
+                    self.wallpaper_groups.borrow().change_other_pools(
+                        grouped.id,
+                        &grouped.group,
+                        GroupChange::Previous,
+                        qh,
+                    );
                 }
                 (index, path)
             }
@@ -289,7 +820,18 @@ impl ImagePicker {
                 None | Some(ImagePickerAction::Next),
                 ImagePickerSorting::Descending(current_index),
             )
-            | (Some(ImagePickerAction::Previous), ImagePickerSorting::Ascending(current_index)) => {
+            | (Some(ImagePickerAction::Previous), ImagePickerSorting::Ascending(current_index))
+            | (
+                Some(ImagePickerAction::Previous),
+                ImagePickerSorting::RandomStart {
+                    index: current_index,
+                    ..
+                }
+                | ImagePickerSorting::Daily {
+                    index: current_index,
+                    ..
+                },
+            ) => {
                 let index = if files.get(*current_index) == Some(&self.current_img) {
                     *current_index
                 } else {
@@ -320,6 +862,13 @@ impl ImagePicker {
             | (
                 None | Some(ImagePickerAction::Next),
                 ImagePickerSorting::Ascending(current_index),
+            )
+            | (
+                Some(ImagePickerAction::Next),
+                ImagePickerSorting::Daily {
+                    index: current_index,
+                    ..
+                },
             ) => {
                 let index = if files.get(*current_index) == Some(&self.current_img) {
                     *current_index
@@ -332,6 +881,42 @@ impl ImagePicker {
                 let index = (index + 1) % files.len();
                 (index, files[index].to_path_buf())
             }
+            (
+                None | Some(ImagePickerAction::Next),
+                ImagePickerSorting::RandomStart {
+                    loading_start: Some((index, path)),
+                    ..
+                },
+            ) if files.get(*index) == Some(path) => (*index, path.to_path_buf()),
+            (
+                None | Some(ImagePickerAction::Next),
+                ImagePickerSorting::RandomStart {
+                    index,
+                    start,
+                    loading_start,
+                },
+            ) => {
+                let current = if files.get(*index) == Some(&self.current_img) {
+                    Some(*index)
+                } else {
+                    files.binary_search(&self.current_img).ok()
+                };
+                let next = current.map(|current| (current + 1) % files.len());
+                match (next, *start) {
+                    // Continue in order until we are back where this run started
+                    (Some(next), Some(start)) if next != start && start < files.len() => {
+                        (next, files[next].to_path_buf())
+                    }
+                    // The list has been exhausted (or the current image is gone),
+                    // start again from a random image
+                    // The run begins once the image is shown, picking again returns it
+                    _ => {
+                        let next = random_start_index(files.len(), current);
+                        *loading_start = Some((next, files[next].to_path_buf()));
+                        (next, files[next].to_path_buf())
+                    }
+                }
+            }
         }
     }
 
@@ -340,21 +925,96 @@ impl ImagePicker {
         path: &Path,
         qh: &QueueHandle<Wpaperd>,
     ) -> Option<(PathBuf, usize)> {
-        if path.is_dir() {
-            let files = self.filelist_cache.borrow().get(path);
+        if path.is_dir()
+            || self.command.is_some()
+            || !self.pool.is_empty()
+            || !self.fallback.is_empty()
+            || self.favorites_only
+        {
+            // We don't know which images match the display yet
+            if self.aspect_ratio_tolerance.is_some() && self.display_ratio.is_none() {
+                return None;
+            }
+            let files = self.filelist(path);
+            self.check_fallback_level();
 
-            // There are no images, forcefully break out of the loop
+            // There are no images, forcefully break out of the loop and keep the current
+            // wallpaper until the list is checked again
             if files.is_empty() {
-                warn!("Directory {path:?} does not contain any valid image files.");
+                // The command is still running, the display is drawn once it has finished
+                if !self.favorites_only
+                    && self
+                        .command
+                        .as_ref()
+                        .is_some_and(|command| !self.filelist_cache.borrow().is_listed(command))
+                {
+                    return None;
+                }
+                // The display is drawn once the dimensions of the images have been read
+                if self.probing {
+                    return None;
+                }
+                if self.unavailable {
+                    return None;
+                }
+                self.unavailable = true;
+                match &self.command {
+                    _ if self.favorites_only => warn!(
+                        "{}: favorites {:?} do not contain any valid image files.",
+                        self.name,
+                        self.favorites
+                            .as_ref()
+                            .map_or(Path::new(""), Favorites::path)
+                    ),
+                    Some((command, _)) => {
+                        warn!(
+                            "{}: command {command:?} did not print any valid image file.",
+                            self.name
+                        )
+                    }
+                    None if !self.pool.is_empty() => {
+                        warn!(
+                            "{}: paths {:?} do not contain any valid image files.",
+                            self.name, self.pool
+                        )
+                    }
+                    None if !self.fallback.is_empty() => {
+                        warn!(
+                            "{}: none of the paths {:?} contain any valid image files.",
+                            self.name, self.fallback
+                        )
+                    }
+                    None => warn!(
+                        "{}: directory {path:?} does not contain any valid image files.",
+                        self.name
+                    ),
+                }
                 None
             } else {
+                self.unavailable = false;
                 let (index, img_path) = self.get_image_path(&files, qh);
                 if img_path == self.current_img && !self.reload {
                     None
                 } else {
+                    // Reserve the image, so that the other displays don't pick it while loading
+                    self.wallpaper_groups
+                        .borrow_mut()
+                        .set_shown(&self.wl_surface, &img_path);
                     Some((img_path, index))
                 }
             }
+        } else if is_url(path) {
+            // Keep the current image until it's time to fetch the URL again
+            if self.image_loader.borrow().is_unreachable(path) {
+                self.unavailable = true;
+                return None;
+            }
+            self.unavailable = false;
+            if path == self.current_img && !self.reload && self.action.is_none() {
+                None
+            } else {
+                Some((path.to_path_buf(), 0))
+            }
         } else if path == self.current_img && !self.reload {
             None
         } else {
@@ -371,6 +1031,23 @@ impl ImagePicker {
                 }
             }
             (None | Some(ImagePickerAction::Previous), ImagePickerSorting::Random { .. }) => {}
+            // The shuffle has already moved to the image when picking it
+            (_, ImagePickerSorting::Shuffle(shuffle)) => shuffle.loading = None,
+            (
+                _,
+                ImagePickerSorting::RandomStart {
+                    index: current_index,
+                    start,
+                    loading_start,
+                },
+            ) => {
+                if let Some((start_index, start_path)) = loading_start.take() {
+                    if start_path == img_path {
+                        *start = Some(start_index);
+                    }
+                }
+                *current_index = index;
+            }
             (
                 None | Some(ImagePickerAction::Previous),
                 ImagePickerSorting::GroupedRandom(group),
@@ -383,7 +1060,11 @@ impl ImagePicker {
             (
                 _,
                 ImagePickerSorting::Ascending(current_index)
-                | ImagePickerSorting::Descending(current_index),
+                | ImagePickerSorting::Descending(current_index)
+                | ImagePickerSorting::Daily {
+                    index: current_index,
+                    ..
+                },
             ) => *current_index = index,
             (Some(ImagePickerAction::Next), ImagePickerSorting::GroupedRandom(group)) => {
                 let mut group = group.group.borrow_mut();
@@ -397,7 +1078,64 @@ impl ImagePicker {
             }
         }
 
+        self.wallpaper_groups
+            .borrow_mut()
+            .set_shown(&self.wl_surface, &img_path);
         self.current_img = img_path;
+        self.image_changed_instant = Instant::now();
+    }
+
+    /// Resume from the image that was drawn before wpaperd was restarted.
+    /// If the image does not exist anymore, the next one will be picked instead.
+    pub fn restore(&mut self, img_path: PathBuf, index: usize) {
+        let exists = path_exists(&img_path);
+        match &mut self.sorting {
+            ImagePickerSorting::Ascending(current_index)
+            | ImagePickerSorting::Descending(current_index)
+            | ImagePickerSorting::Daily {
+                index: current_index,
+                ..
+            } => {
+                // Even when the image has been removed, the index tells where to start from
+                *current_index = index;
+                self.current_img.clone_from(&img_path);
+            }
+            ImagePickerSorting::RandomStart {
+                index: current_index,
+                start,
+                ..
+            } => {
+                // Continue the run from the restored image instead of picking a new start
+                *current_index = index;
+                *start = Some(index);
+                self.current_img.clone_from(&img_path);
+            }
+            ImagePickerSorting::Random(queue) if exists => {
+                queue.push(img_path.clone());
+            }
+            ImagePickerSorting::Random(_) => {}
+            ImagePickerSorting::Shuffle(shuffle) if exists => {
+                *shuffle = Shuffle::starting_from(&img_path);
+            }
+            ImagePickerSorting::Shuffle(_) => {}
+            ImagePickerSorting::GroupedRandom(group) => {
+                let mut group = group.group.borrow_mut();
+                // Another display in the same group has already restored its image
+                if !exists || !group.current_image.as_os_str().is_empty() {
+                    return;
+                }
+                group.queue.push(img_path.clone());
+                group.current_image.clone_from(&img_path);
+                group.index = index;
+            }
+        }
+
+        if exists {
+            // Keep the restored image and load it without any transition
+            self.current_img = img_path;
+            self.action = None;
+            self.reload = true;
+        }
     }
 
     /// Update wallpaper by going down 1 index through the cached image paths
@@ -412,10 +1150,342 @@ impl ImagePicker {
         self.get_image_from_path(path, qh);
     }
 
+    /// Return true if there were no wallpapers to pick from, e.g. because the directory
+    /// is on a network share that has been unmounted
+    #[inline]
+    pub fn is_unavailable(&self) -> bool {
+        self.unavailable
+    }
+
+    /// Return the index of the path of the fallback chain in use, 0 if there is no chain
+    pub fn fallback_level(&self) -> usize {
+        if self.fallback.is_empty() {
+            0
+        } else {
+            self.filelist_cache.borrow().fallback_level(&self.fallback)
+        }
+    }
+
+    /// Return true if the wallpapers are picked from a fallback path, because the paths
+    /// before it don't have any wallpaper
+    #[inline]
+    pub fn is_using_fallback(&self) -> bool {
+        let level = self.fallback_level();
+        level > 0 && level < self.fallback.len()
+    }
+
+    /// Log when the fallback chain switches to another path
+    fn check_fallback_level(&mut self) {
+        let level = self.fallback_level();
+        if level == self.fallback_level || level >= self.fallback.len() {
+            return;
+        }
+        self.fallback_level = level;
+        if level == 0 {
+            info!("{}: using {:?} again", self.name, self.fallback[0]);
+        } else {
+            warn!(
+                "{}: {:?} does not contain any valid image files, falling back to {:?}",
+                self.name,
+                self.fallback[level - 1],
+                self.fallback[level]
+            );
+        }
+    }
+
+    /// Read the directories again, without waiting for them to notify a change
+    pub fn refresh_filelist(&self, path: &Path) {
+        self.filelist_cache.borrow_mut().refresh(
+            path,
+            self.command.as_ref(),
+            &self.pool,
+            &self.fallback,
+        );
+    }
+
     pub fn current_image(&self) -> PathBuf {
         self.current_img.clone()
     }
 
+    /// Return the images the next wallpaper is picked from and the index of the current one
+    pub fn candidates(&mut self, path: &Path) -> (Arc<Vec<PathBuf>>, Option<usize>) {
+        let files = self.filelist(path);
+        let current = files.iter().position(|file| file == &self.current_img);
+        (files, current)
+    }
+
+    /// Return the images to pick from, skipping the ones that don't match the aspect ratio
+    /// of the display or that are smaller than the minimum resolution, if requested
+    fn filelist(&mut self, path: &Path) -> Arc<Vec<PathBuf>> {
+        let files = match &self.favorites {
+            // Read again when the file changes, see ImagePicker::refresh_favorites
+            Some(favorites) if self.favorites_only => favorites.images(),
+            _ => self.filelist_cache.borrow().get_list(
+                path,
+                self.command.as_ref(),
+                &self.pool,
+                &self.fallback,
+            ),
+        };
+        let files = self.skip_unreachable(files);
+        let files = skip_failed(&mut self.skipped_failed, files, &self.failed_images);
+        let files = skip_other_weekdays(
+            &mut self.skipped_weekdays,
+            files,
+            &self.weekdays,
+            Weekday::today(),
+        );
+        let aspect_ratio = self.aspect_ratio_tolerance.zip(self.display_ratio);
+        if aspect_ratio.is_none() && self.min_resolution.is_none() {
+            self.probing = false;
+            return files;
+        }
+        match &self.filtered_filelist {
+            // The list of files has not changed
+            Some(FilteredFilelist {
+                unfiltered,
+                filtered,
+            }) if Arc::ptr_eq(unfiltered, &files) => return filtered.clone(),
+            _ => {}
+        }
+
+        let mut image_loader = self.image_loader.borrow_mut();
+        let mut probing = false;
+        let filtered: Vec<PathBuf> = files
+            .iter()
+            .filter(|file| {
+                let (width, height) = match image_loader.image_size(file, self.exif_orientation) {
+                    ImageSize::Known(width, height) => (width, height),
+                    // Keep the files we can't probe, e.g. videos
+                    ImageSize::Unknown => return true,
+                    // Filtered again when the dimensions are known
+                    ImageSize::Pending => {
+                        probing = true;
+                        return false;
+                    }
+                };
+                if let Some((min_width, min_height)) = self.min_resolution {
+                    if is_smaller((width, height), (min_width, min_height)) {
+                        if self.skipped_small_images.insert(file.to_path_buf()) {
+                            warn!(
+                                "{}: skipping {file:?}, it is smaller than {min_width}x{min_height}",
+                                self.name
+                            );
+                        }
+                        return false;
+                    }
+                }
+                match aspect_ratio {
+                    Some((tolerance, display_ratio)) if height != 0 => {
+                        let ratio = width as f32 / height as f32;
+                        (ratio / display_ratio - 1.0).abs() <= tolerance
+                    }
+                    _ => true,
+                }
+            })
+            .cloned()
+            .collect();
+        image_loader.start_probing();
+        drop(image_loader);
+        self.probing = probing;
+        let filtered = if filtered.is_empty() && !files.is_empty() && !probing {
+            warn!(
+                "{}: no image matches the display, ignoring the aspect ratio and minimum resolution",
+                self.name
+            );
+            files.clone()
+        } else {
+            Arc::new(filtered)
+        };
+        self.filtered_filelist = Some(FilteredFilelist {
+            unfiltered: files,
+            filtered: filtered.clone(),
+        });
+        filtered
+    }
+
+    /// The dimensions of some images have been read, apply the filters again
+    pub fn images_probed(&mut self) {
+        if self.probing {
+            self.filtered_filelist = None;
+        }
+    }
+
+    /// Return true if the filters are waiting for the dimensions of some images
+    #[inline]
+    pub fn is_probing(&self) -> bool {
+        self.probing
+    }
+
+    /// Remove the URLs that could not be fetched recently, a fallback chain moves to the next
+    /// path if none of the images of the current one are left
+    fn skip_unreachable(&mut self, files: Arc<Vec<PathBuf>>) -> Arc<Vec<PathBuf>> {
+        let image_loader = self.image_loader.borrow();
+        let skipped = skipped_indices(&files, |file| image_loader.is_unreachable(file));
+        if !skipped.is_empty() && !self.fallback.is_empty() && !self.favorites_only {
+            let files = self
+                .filelist_cache
+                .borrow()
+                .get_fallback_skipping(&self.fallback, |file| image_loader.is_unreachable(file));
+            return SkippedFilelist::reuse(&mut self.skipped_unreachable, files);
+        }
+        SkippedFilelist::skip(&mut self.skipped_unreachable, files, skipped)
+    }
+
+    /// Update the aspect ratio of the display, the images will be filtered again
+    pub fn set_display_ratio(&mut self, display_ratio: f32) {
+        if self.display_ratio != Some(display_ratio) {
+            self.display_ratio = Some(display_ratio);
+            self.filtered_filelist = None;
+        }
+    }
+
+    pub fn update_aspect_ratio_tolerance(&mut self, aspect_ratio_tolerance: Option<f32>) {
+        if self.aspect_ratio_tolerance != aspect_ratio_tolerance {
+            self.aspect_ratio_tolerance = aspect_ratio_tolerance;
+            self.filtered_filelist = None;
+        }
+    }
+
+    pub fn update_min_resolution(&mut self, min_resolution: Option<(u32, u32)>) {
+        if self.min_resolution != min_resolution {
+            self.min_resolution = min_resolution;
+            self.filtered_filelist = None;
+            // Report the images that are still too small for the new resolution
+            self.skipped_small_images.clear();
+        }
+    }
+
+    /// Apply the filters again and retry the images that could not be decoded, e.g. after the
+    /// dimensions of the images have been flushed
+    pub fn reset_filters(&mut self) {
+        self.filtered_filelist = None;
+        self.failed_images.clear();
+        self.skipped_small_images.clear();
+    }
+
+    /// Skip the image that could not be decoded, so that the next one is picked instead of
+    /// trying the same image again. The URLs are already skipped while they are unreachable.
+    pub fn failed_image(&mut self, img_path: &Path) {
+        if !is_url(img_path) {
+            self.failed_images
+                .insert(img_path.to_path_buf(), modified(img_path));
+        }
+        // The other displays of the group would keep loading it
+        if let ImagePickerSorting::GroupedRandom(group) = &self.sorting {
+            let mut group = group.group.borrow_mut();
+            if group
+                .loading_image
+                .as_ref()
+                .is_some_and(|(_, path)| path == img_path)
+            {
+                group.loading_image = None;
+            }
+        }
+    }
+
+    pub fn update_exif_orientation(&mut self, exif_orientation: bool) {
+        if self.exif_orientation != exif_orientation {
+            self.exif_orientation = exif_orientation;
+            self.filtered_filelist = None;
+        }
+    }
+
+    pub fn update_daily_offset(&mut self, daily_offset: usize) {
+        if self.daily_offset != daily_offset {
+            self.daily_offset = daily_offset;
+            // Pick the wallpaper of the day again
+            if let ImagePickerSorting::Daily { day, .. } = &mut self.sorting {
+                *day = None;
+            }
+        }
+    }
+
+    pub fn update_weekdays(&mut self, weekdays: Vec<WeekdayPin>) {
+        if self.weekdays != weekdays {
+            self.weekdays = weekdays;
+            // Pick the wallpaper of the day again
+            if let ImagePickerSorting::Daily { day, .. } = &mut self.sorting {
+                *day = None;
+            }
+        }
+    }
+
+    /// Read the weights of the images from a different file, or pick them uniformly if `None`
+    pub fn update_ranking(&mut self, ranking: Option<PathBuf>) {
+        if self.ranking.as_ref().map(Ranking::path) != ranking.as_deref() {
+            self.ranking = ranking.map(Ranking::new);
+        }
+    }
+
+    /// Read the favorites from a different file, the favorites mode is left when it's `None`
+    pub fn update_favorites(&mut self, favorites: Option<PathBuf>) {
+        if self.favorites.as_ref().map(Favorites::path) != favorites.as_deref() {
+            self.favorites = favorites.map(Favorites::new);
+            self.favorites_only &= self.favorites.is_some();
+        }
+    }
+
+    pub fn has_favorites(&self) -> bool {
+        self.favorites.is_some()
+    }
+
+    #[inline]
+    pub fn favorites_only(&self) -> bool {
+        self.favorites_only
+    }
+
+    /// Pick the wallpapers only from the favorites, read again from their file, or from the
+    /// configured ones again
+    pub fn set_favorites_only(&mut self, favorites_only: bool) {
+        self.favorites_only = favorites_only && self.favorites.is_some();
+        if let Some(favorites) = self.favorites.as_mut().filter(|_| self.favorites_only) {
+            favorites.refresh(&self.name);
+        }
+    }
+
+    /// Append the current image to the favorites, return false if it was already one of them
+    pub fn add_favorite(&mut self) -> Result<bool> {
+        let favorites = self
+            .favorites
+            .as_mut()
+            .context("no favorites file has been configured")?;
+        if self.current_img.as_os_str().is_empty() {
+            bail!("no wallpaper has been shown yet");
+        }
+        favorites.add(&self.current_img, &self.name)
+    }
+
+    /// Read the favorites again, return true if the current image is not one of them anymore
+    /// while picking only from them
+    pub fn refresh_favorites(&mut self) -> bool {
+        match &mut self.favorites {
+            Some(favorites) if self.favorites_only => {
+                favorites.refresh(&self.name);
+                !favorites.contains(&self.current_img)
+            }
+            _ => false,
+        }
+    }
+
+    /// Pick the wallpapers from a different command, or from the directory if `None`
+    pub fn update_command(&mut self, command: Option<ListCommand>) {
+        self.command = command;
+    }
+
+    /// Pick the wallpapers from a different pool, or from the directory if empty
+    pub fn update_pool(&mut self, pool: Vec<PathBuf>) {
+        self.pool = pool;
+    }
+
+    /// Pick the wallpapers from a different fallback chain, or from the directory if empty
+    pub fn update_fallback(&mut self, fallback: Vec<PathBuf>) {
+        if self.fallback != fallback {
+            self.fallback = fallback;
+            self.fallback_level = 0;
+        }
+    }
+
     /// Return true if the path changed
     pub fn update_sorting(
         &mut self,
@@ -423,31 +1493,74 @@ impl ImagePicker {
         path: &Path,
         path_changed: bool,
         drawn_images_queue_size: usize,
+        no_repeat_size: usize,
     ) {
         if let Some(new_sorting) = new_sorting {
+            if let Some(update) =
+                random_update(self.sorting.random_sequence(), new_sorting, path_changed)
+            {
+                self.update_random_sequence(update, path, drawn_images_queue_size, no_repeat_size);
+                return;
+            }
             match (&mut self.sorting, new_sorting) {
                 (_, Sorting::Ascending) if path_changed => {
-                    self.sorting = ImagePickerSorting::new_ascending(
-                        self.filelist_cache.borrow().get(path).len(),
-                    );
+                    self.sorting = ImagePickerSorting::new_ascending(self.filelist(path).len());
                 }
                 (_, Sorting::Descending) if path_changed => {
                     self.sorting = ImagePickerSorting::new_descending();
                 }
+                (_, Sorting::RandomStart) if path_changed => {
+                    self.sorting = ImagePickerSorting::new_random_start();
+                }
+                (ImagePickerSorting::Daily { .. }, Sorting::Daily) => {}
+                (_, Sorting::Daily) => {
+                    self.sorting = ImagePickerSorting::new_daily();
+                }
+                (ImagePickerSorting::Shuffle(_), Sorting::Shuffle) if !path_changed => {}
+                (_, Sorting::Shuffle) => {
+                    self.sorting = ImagePickerSorting::Shuffle(self.new_shuffle());
+                }
+                (
+                    ImagePickerSorting::RandomStart { index, .. }
+                    | ImagePickerSorting::Daily { index, .. },
+                    Sorting::Ascending,
+                ) => self.sorting = ImagePickerSorting::Ascending(*index),
+                (
+                    ImagePickerSorting::RandomStart { index, .. }
+                    | ImagePickerSorting::Daily { index, .. },
+                    Sorting::Descending,
+                ) => self.sorting = ImagePickerSorting::Descending(*index),
+                (ImagePickerSorting::RandomStart { .. }, Sorting::RandomStart) => {}
+                (_, Sorting::RandomStart) => {
+                    // Continue from the current image, the next run will start randomly
+                    let index = self.filelist(path).binary_search(&self.current_img).ok();
+                    self.sorting = ImagePickerSorting::RandomStart {
+                        index: index.unwrap_or_default(),
+                        start: index,
+                        loading_start: None,
+                    };
+                }
                 (ImagePickerSorting::Descending(current_index), Sorting::Ascending) => {
                     self.sorting = ImagePickerSorting::Ascending(*current_index)
                 }
                 (ImagePickerSorting::Ascending(current_index), Sorting::Descending) => {
                     self.sorting = ImagePickerSorting::Descending(*current_index)
                 }
-                (ImagePickerSorting::Random { .. }, Sorting::Ascending | Sorting::Descending) => {
-                    let files = self.filelist_cache.borrow().get(path);
+                (
+                    ImagePickerSorting::Random { .. } | ImagePickerSorting::Shuffle(_),
+                    Sorting::Ascending | Sorting::Descending,
+                ) => {
+                    let files = self.filelist(path);
                     let index = match files.binary_search(&self.current_img) {
                         Ok(index) => Some(index),
                         Err(_) => None,
                     };
                     self.sorting = match new_sorting {
-                        Sorting::Random | Sorting::GroupedRandom { .. } => unreachable!(),
+                        Sorting::Random
+                        | Sorting::GroupedRandom { .. }
+                        | Sorting::RandomStart
+                        | Sorting::Daily
+                        | Sorting::Shuffle => unreachable!(),
                         Sorting::Ascending => match index {
                             Some(index) => ImagePickerSorting::Ascending(index),
                             None => ImagePickerSorting::new_ascending(files.len()),
@@ -458,52 +1571,152 @@ impl ImagePicker {
                         },
                     };
                 }
-                (
-                    ImagePickerSorting::Descending(_) | ImagePickerSorting::Ascending(_),
-                    Sorting::Random,
-                ) if path_changed => {
-                    // If the path was changed, use a new random sorting
-                    self.sorting = ImagePickerSorting::new_random(drawn_images_queue_size);
-                }
-                // The path has changed, use a new random sorting, otherwise we reuse the current
-                // drawn_images
-                (_, Sorting::Random) if path_changed => {
-                    self.sorting = ImagePickerSorting::new_random(drawn_images_queue_size);
-                }
-                (
-                    ImagePickerSorting::Descending(_) | ImagePickerSorting::Ascending(_),
-                    Sorting::Random,
-                ) => {
-                    // if the path was not changed, use the current image as the first image of
-                    // the drawn_images
-                    let mut queue = Queue::with_capacity(drawn_images_queue_size);
-                    queue.push(self.current_image());
-                    self.sorting = ImagePickerSorting::Random(queue);
-                }
                 // No need to update the sorting if it's the same
                 (_, _) => {}
             }
         } else {
-            self.sorting = ImagePickerSorting::new_random(drawn_images_queue_size);
+            self.sorting = ImagePickerSorting::new_random(drawn_images_queue_size, no_repeat_size);
+        }
+    }
+
+    fn update_random_sequence(
+        &mut self,
+        update: RandomUpdate,
+        path: &Path,
+        drawn_images_queue_size: usize,
+        no_repeat_size: usize,
+    ) {
+        match update {
+            RandomUpdate::Keep => {}
+            RandomUpdate::Continue => {
+                // Use the current image as the first image of the drawn images
+                let mut queue = Queue::new(drawn_images_queue_size, no_repeat_size);
+                queue.push(self.current_image());
+                self.sorting = ImagePickerSorting::Random(queue);
+            }
+            RandomUpdate::Restart => {
+                self.sorting =
+                    ImagePickerSorting::new_random(drawn_images_queue_size, no_repeat_size);
+            }
+            RandomUpdate::Join(group) => {
+                // Dropping the previous sorting leaves the previous group, if any
+                self.sorting = ImagePickerSorting::new_grouped_random(
+                    self.wallpaper_groups.clone(),
+                    group,
+                    GroupPool {
+                        path: path.to_path_buf(),
+                        pool: self.pool.clone(),
+                        fallback: self.fallback.clone(),
+                        command: self.command.clone(),
+                    },
+                    &self.wl_surface,
+                    drawn_images_queue_size,
+                    no_repeat_size,
+                );
+                let ImagePickerSorting::GroupedRandom(grouped) = &self.sorting else {
+                    unreachable!()
+                };
+                let mut group = grouped.group.borrow_mut();
+                // A new group starts from the image of the first display joining it, the
+                // others show the image of the group from the next draw
+                if group.current_image.as_os_str().is_empty() && path_exists(&self.current_img) {
+                    group.queue.push(self.current_img.clone());
+                    group.current_image.clone_from(&self.current_img);
+                }
+            }
         }
     }
 
-    pub fn update_queue_size(&mut self, drawn_images_queue_size: usize) {
+    /// Switch to another sorting without waiting for a configuration reload, keeping the
+    /// current image. Random starts a new random sequence, while the ordered sortings continue
+    /// from the index of the current image. Setting random-start again reshuffles its start.
+    pub fn set_sorting(
+        &mut self,
+        sorting: Sorting,
+        path: &Path,
+        drawn_images_queue_size: usize,
+        no_repeat_size: usize,
+    ) {
+        let index = match sorting {
+            Sorting::Random | Sorting::GroupedRandom { .. } => {
+                let mut queue = Queue::new(drawn_images_queue_size, no_repeat_size);
+                // Keep the current image in the queue, so that previous still works
+                queue.push(self.current_image());
+                self.sorting = ImagePickerSorting::Random(queue);
+                return;
+            }
+            Sorting::Daily => {
+                self.sorting = ImagePickerSorting::new_daily();
+                return;
+            }
+            Sorting::Shuffle => {
+                self.sorting = ImagePickerSorting::Shuffle(self.new_shuffle());
+                return;
+            }
+            Sorting::Ascending | Sorting::Descending | Sorting::RandomStart => {
+                self.filelist(path).binary_search(&self.current_img).ok()
+            }
+        };
+        self.sorting = match (sorting, index) {
+            (Sorting::Ascending, Some(index)) => ImagePickerSorting::Ascending(index),
+            (Sorting::Ascending, None) => {
+                ImagePickerSorting::new_ascending(self.filelist(path).len())
+            }
+            (Sorting::Descending, Some(index)) => ImagePickerSorting::Descending(index),
+            (Sorting::Descending, None) => ImagePickerSorting::new_descending(),
+            // Without a start, the next image begins a new run from a random one
+            (Sorting::RandomStart, index) => ImagePickerSorting::RandomStart {
+                index: index.unwrap_or_default(),
+                start: None,
+                loading_start: None,
+            },
+            (
+                Sorting::Random | Sorting::GroupedRandom { .. } | Sorting::Daily | Sorting::Shuffle,
+                _,
+            ) => unreachable!(),
+        };
+    }
+
+    pub fn update_queue_size(&mut self, drawn_images_queue_size: usize, no_repeat_size: usize) {
         match &mut self.sorting {
             ImagePickerSorting::Random(queue) => {
                 queue.resize(drawn_images_queue_size);
+                queue.set_no_repeat(no_repeat_size);
             }
-            ImagePickerSorting::Ascending(_) | ImagePickerSorting::Descending(_) => {}
+            // The ordered sortings don't use the queue
+            ImagePickerSorting::Ascending(_)
+            | ImagePickerSorting::Descending(_)
+            | ImagePickerSorting::RandomStart { .. }
+            | ImagePickerSorting::Daily { .. }
+            | ImagePickerSorting::Shuffle(_) => {}
             ImagePickerSorting::GroupedRandom(group) => {
-                group
-                    .group
-                    .borrow_mut()
-                    .queue
-                    .resize(drawn_images_queue_size);
+                let queue = &mut group.group.borrow_mut().queue;
+                queue.resize(drawn_images_queue_size);
+                queue.set_no_repeat(no_repeat_size);
             }
         }
     }
 
+    /// Start a new shuffle, in which the current image counts as shown
+    fn new_shuffle(&self) -> Shuffle {
+        if path_exists(&self.current_img) {
+            Shuffle::starting_from(&self.current_img)
+        } else {
+            Shuffle::default()
+        }
+    }
+
+    /// Continue from the history of `previous`, the picker of the same display before its
+    /// surface was created again, and load its current image
+    pub fn take_history(&mut self, previous: &mut ImagePicker) {
+        std::mem::swap(&mut self.sorting, &mut previous.sorting);
+        std::mem::swap(&mut self.current_img, &mut previous.current_img);
+        self.image_changed_instant = previous.image_changed_instant;
+        self.set_favorites_only(previous.favorites_only);
+        self.action = None;
+        self.reload = true;
+    }
+
     #[inline]
     pub fn reload(&mut self) {
         self.reload = true;
@@ -520,14 +1733,96 @@ impl ImagePicker {
     }
 }
 
+/// Return true if the image is narrower or shorter than the minimum resolution
+fn is_smaller((width, height): (u32, u32), (min_width, min_height): (u32, u32)) -> bool {
+    width < min_width || height < min_height
+}
+
+/// Return the modification time of the file, None if it can't be read
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Return true if the image could not be decoded and hasn't been changed since
+fn has_failed(file: &Path, failed: &HashMap<PathBuf, Option<SystemTime>>) -> bool {
+    failed
+        .get(file)
+        .is_some_and(|failed_modified| *failed_modified == modified(file))
+}
+
+/// Remove the images that could not be decoded, unless none would be left, in which case they
+/// are all tried again
+fn skip_failed(
+    cache: &mut Option<SkippedFilelist>,
+    files: Arc<Vec<PathBuf>>,
+    failed: &HashMap<PathBuf, Option<SystemTime>>,
+) -> Arc<Vec<PathBuf>> {
+    if failed.is_empty() {
+        return files;
+    }
+    let skipped = skipped_indices(&files, |file| has_failed(file, failed));
+    if skipped.len() == files.len() {
+        return files;
+    }
+    SkippedFilelist::skip(cache, files, skipped)
+}
+
+/// Remove the images pinned to other days of the week than `today`
+fn skip_other_weekdays(
+    cache: &mut Option<SkippedFilelist>,
+    files: Arc<Vec<PathBuf>>,
+    pins: &[WeekdayPin],
+    today: Weekday,
+) -> Arc<Vec<PathBuf>> {
+    if pins.is_empty() {
+        return files;
+    }
+    let skipped = skipped_indices(&files, |file| !is_shown_on(file, pins, today));
+    SkippedFilelist::skip(cache, files, skipped)
+}
+
+/// Return the index of `path` in the list, 0 if it is not part of it
+fn index_of(files: &[PathBuf], path: &Path) -> usize {
+    files
+        .iter()
+        .position(|file| file == path)
+        .unwrap_or_default()
+}
+
+/// Pick the first image of a random-start run, avoiding the image that ended the previous one
+fn random_start_index(files_len: usize, current: Option<usize>) -> usize {
+    if files_len == 1 {
+        return 0;
+    }
+    loop {
+        let index = rand::random::<usize>() % files_len;
+        if Some(index) != current {
+            break index;
+        }
+    }
+}
+
+/// Return the ranking once it has been read again, the changes of the file apply from the next
+/// image picked
+fn refreshed<'a>(ranking: &'a mut Option<Ranking>, name: &str) -> Option<&'a Ranking> {
+    let ranking = ranking.as_mut()?;
+    ranking.refresh(name);
+    Some(ranking)
+}
+
+/// Pick a random image, skipping the ones in `shown` unless there are not enough images
 fn next_random_image(
     current_image: &Path,
     queue: &mut Queue,
     files: &[PathBuf],
+    shown: &HashSet<PathBuf>,
+    ranking: Option<&Ranking>,
 ) -> (usize, PathBuf) {
     // Use the next images in the queue, if any
     while let Some((next, index)) = queue.next() {
-        if next.exists() {
+        if path_exists(next) {
             return (index, next.to_path_buf());
         }
     }
@@ -536,28 +1831,62 @@ fn next_random_image(
         return (0, files[0].to_path_buf());
     }
 
+    // There are less images than displays, allow showing the same image on multiple of them
+    let no_shown = HashSet::new();
+    let shown = if files
+        .iter()
+        .any(|file| file != current_image && !shown.contains(file))
+    {
+        shown
+    } else {
+        &no_shown
+    };
+
+    // The images are picked according to their weight, uniformly when they are all 0
+    let weights = ranking
+        .and_then(|ranking| WeightedIndex::new(files.iter().map(|file| ranking.weight(file))).ok());
+    let mut rng = rand::thread_rng();
+
     // Otherwise pick a new random image that has not been drawn before
     // Try 5 times, then get a random image. We do this because it might happen
     // that the queue is bigger than the amount of available wallpapers
     let mut tries = 5;
     loop {
-        let index = rand::random::<usize>() % files.len();
+        let index = match &weights {
+            Some(weights) => weights.sample(&mut rng),
+            None => rand::random::<usize>() % files.len(),
+        };
         // search for an image that has not been drawn yet
         // fail after 5 tries
-        if !queue.contains(&files[index]) {
+        if files[index] != current_image
+            && !queue.contains(&files[index])
+            && !shown.contains(&files[index])
+        {
             break (index, files[index].to_path_buf());
         }
 
         // We have already tried a bunch of times
-        // We still need a new image, get the first one that is different than
+        // We still need a new image, get any one that is different than
         // the current one. We also know that there is more than one image
         if tries == 0 {
-            break loop {
-                let index = rand::random::<usize>() % files.len();
-                if files[index] != current_image {
-                    break (index, files[index].to_path_buf());
-                }
+            let candidates: Vec<usize> = (0..files.len())
+                .filter(|index| files[*index] != current_image && !shown.contains(&files[*index]))
+                .collect();
+            // The images with a weight of 0 are only picked when there are no others
+            let weighted: Vec<usize> = candidates
+                .iter()
+                .copied()
+                .filter(|index| {
+                    ranking.map_or(true, |ranking| ranking.weight(&files[*index]) > 0.0)
+                })
+                .collect();
+            let candidates = if weighted.is_empty() {
+                candidates
+            } else {
+                weighted
             };
+            let index = candidates.choose(&mut rng).copied().unwrap_or_default();
+            break (index, files[index].to_path_buf());
         }
 
         tries -= 1;
@@ -566,7 +1895,7 @@ fn next_random_image(
 
 fn get_previous_image_for_random(current_image: &Path, queue: &mut Queue) -> (usize, PathBuf) {
     while let Some((prev, index)) = queue.previous() {
-        if prev.exists() {
+        if path_exists(prev) {
             return (index, prev.to_path_buf());
         }
     }
@@ -579,11 +1908,62 @@ fn get_previous_image_for_random(current_image: &Path, queue: &mut Queue) -> (us
 #[cfg(test)]
 mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use std::{os::unix::net::UnixStream, time::Duration};
+
+    use hotwatch::Hotwatch;
+    use image::RgbaImage;
+    use smithay_client_toolkit::reexports::{
+        calloop::EventLoop,
+        client::{Connection, Proxy},
+    };
+
     use super::*;
+    use crate::{
+        http::HttpCache,
+        image_loader::{ImageLoaderStatus, LoadOptions},
+    };
+
+    /// Create a picker for a display that is not connected to any compositor, with the caches
+    /// of the daemon
+    fn test_picker(
+        wallpaper_info: &WallpaperInfo,
+        groups: &Rc<RefCell<WallpaperGroups>>,
+    ) -> (ImagePicker, QueueHandle<Wpaperd>) {
+        let (socket, _) = UnixStream::pair().unwrap();
+        let connection = Connection::from_socket(socket).unwrap();
+        let qh = connection.new_event_queue::<Wpaperd>().handle();
+        let event_loop = EventLoop::<Wpaperd>::try_new().unwrap();
+        let (_, filelist_cache) = FilelistCache::new(
+            vec![wallpaper_info.path.clone()],
+            vec![],
+            vec![],
+            vec![],
+            &mut Hotwatch::new().unwrap(),
+            event_loop.handle(),
+            qh.clone(),
+        )
+        .unwrap();
+        let image_loader = ImageLoader::new(
+            HttpCache::new(std::env::temp_dir()),
+            event_loop.handle(),
+            qh.clone(),
+        )
+        .unwrap();
+        let wl_surface = WlSurface::inert(connection.backend().downgrade());
+        let picker = ImagePicker::new(
+            "test".to_string(),
+            wallpaper_info,
+            &wl_surface,
+            Rc::new(RefCell::new(filelist_cache)),
+            groups.clone(),
+            Rc::new(RefCell::new(image_loader)),
+        );
+        (picker, qh)
+    }
 
     #[test]
     fn test_push() {
-        let mut queue = Queue::with_capacity(2);
+        let mut queue = Queue::new(2, 2);
         queue.push(PathBuf::from("mypath"));
         queue.push(PathBuf::from("mypath2"));
         assert_eq!(Path::new("mypath2"), queue.current());
@@ -595,7 +1975,7 @@ mod tests {
 
     #[test]
     fn test_resize() {
-        let mut queue = Queue::with_capacity(5);
+        let mut queue = Queue::new(5, 5);
         queue.push(PathBuf::from("mypath"));
         queue.push(PathBuf::from("mypath2"));
         queue.push(PathBuf::from("mypath3"));
@@ -612,9 +1992,359 @@ mod tests {
         assert_eq!(Some((Path::new("mypath5"), 1)), queue.next());
     }
 
+    #[test]
+    fn test_no_repeat() {
+        // Long history, short no-repeat window
+        let mut queue = Queue::new(5, 1);
+        queue.push(PathBuf::from("mypath"));
+        queue.push(PathBuf::from("mypath2"));
+        assert!(!queue.contains(&PathBuf::from("mypath")));
+        assert!(queue.contains(&PathBuf::from("mypath2")));
+        assert_eq!(Some((Path::new("mypath"), 0)), queue.previous());
+
+        // Short history, long no-repeat window
+        let mut queue = Queue::new(1, 3);
+        queue.push(PathBuf::from("mypath"));
+        queue.push(PathBuf::from("mypath2"));
+        assert!(queue.contains(&PathBuf::from("mypath")));
+        assert_eq!(None, queue.previous());
+
+        queue.set_no_repeat(1);
+        assert!(!queue.contains(&PathBuf::from("mypath")));
+        assert!(queue.contains(&PathBuf::from("mypath2")));
+    }
+
+    #[test]
+    fn test_next_random_image_skips_shown() {
+        let files = vec![
+            PathBuf::from("mypath"),
+            PathBuf::from("mypath2"),
+            PathBuf::from("mypath3"),
+        ];
+        let shown = HashSet::from([PathBuf::from("mypath3")]);
+        for _ in 0..20 {
+            let mut queue = Queue::new(2, 2);
+            let (index, path) =
+                next_random_image(Path::new("mypath"), &mut queue, &files, &shown, None);
+            assert_eq!(files[index], path);
+            assert_ne!(Path::new("mypath3"), path);
+        }
+
+        // All the other images are shown, a shown image has to be picked
+        let shown = HashSet::from([PathBuf::from("mypath2"), PathBuf::from("mypath3")]);
+        let mut queue = Queue::new(2, 2);
+        let (_, path) = next_random_image(Path::new("mypath"), &mut queue, &files, &shown, None);
+        assert_ne!(Path::new("mypath"), path);
+    }
+
+    #[test]
+    fn test_next_random_image_ranking() {
+        let files = vec![
+            PathBuf::from("mypath"),
+            PathBuf::from("mypath2"),
+            PathBuf::from("mypath3"),
+        ];
+        let ranking = Ranking::with_weights(HashMap::from([
+            (PathBuf::from("mypath"), 0.0),
+            (PathBuf::from("mypath3"), 0.0),
+        ]));
+        for _ in 0..20 {
+            let mut queue = Queue::new(2, 2);
+            let (_, path) = next_random_image(
+                Path::new("mypath"),
+                &mut queue,
+                &files,
+                &HashSet::new(),
+                Some(&ranking),
+            );
+            assert_eq!(Path::new("mypath2"), path);
+        }
+
+        // The only other image with a weight has been drawn recently, it's still picked
+        // instead of the one with a weight of 0
+        let ranking = Ranking::with_weights(HashMap::from([(PathBuf::from("mypath2"), 0.0)]));
+        for _ in 0..20 {
+            let mut queue = Queue::new(2, 2);
+            queue.push(PathBuf::from("mypath3"));
+            let (_, path) = next_random_image(
+                Path::new("mypath"),
+                &mut queue,
+                &files,
+                &HashSet::new(),
+                Some(&ranking),
+            );
+            assert_eq!(Path::new("mypath3"), path);
+        }
+    }
+
+    #[test]
+    fn test_is_smaller() {
+        assert!(!is_smaller((1920, 1080), (1920, 1080)));
+        assert!(!is_smaller((3840, 2160), (1920, 1080)));
+        // Both dimensions have to be big enough, a panorama is still too short
+        assert!(is_smaller((5000, 1000), (1920, 1080)));
+        assert!(is_smaller((1000, 5000), (1920, 1080)));
+        // The orientation is applied by the loader, a portrait image doesn't fit a landscape one
+        assert!(is_smaller((1080, 1920), (1920, 1080)));
+    }
+
+    #[test]
+    fn test_skip_failed() {
+        let files = Arc::new(vec![
+            PathBuf::from("mypath"),
+            PathBuf::from("mypath2"),
+            PathBuf::from("mypath3"),
+        ]);
+        let unchanged = skip_failed(&mut None, files.clone(), &HashMap::new());
+        assert!(Arc::ptr_eq(&files, &unchanged));
+
+        // The image failed to decode during the transition to mypath, the next one is mypath3
+        let failed = HashMap::from([(PathBuf::from("mypath2"), None)]);
+        let mut cache = None;
+        let skipped = skip_failed(&mut cache, files.clone(), &failed);
+        assert_eq!(
+            vec![PathBuf::from("mypath"), PathBuf::from("mypath3")],
+            *skipped
+        );
+        // The same list is returned while the same images are skipped
+        assert!(Arc::ptr_eq(
+            &skipped,
+            &skip_failed(&mut cache, files.clone(), &failed)
+        ));
+        let other_files = Arc::new(files.to_vec());
+        assert!(!Arc::ptr_eq(
+            &skipped,
+            &skip_failed(&mut cache, other_files, &failed)
+        ));
+        let current = skipped.binary_search(&PathBuf::from("mypath")).unwrap();
+        assert_eq!(Path::new("mypath3"), skipped[(current + 1) % skipped.len()]);
+
+        // Every image has failed, try them again instead of showing nothing
+        let failed = HashMap::from_iter(files.iter().map(|file| (file.clone(), None)));
+        assert!(Arc::ptr_eq(
+            &files,
+            &skip_failed(&mut None, files.clone(), &failed)
+        ));
+
+        // The image has been changed since it failed, e.g. it was still being written
+        let failed = HashMap::from([(PathBuf::from("mypath2"), Some(SystemTime::UNIX_EPOCH))]);
+        assert!(Arc::ptr_eq(
+            &files,
+            &skip_failed(&mut None, files.clone(), &failed)
+        ));
+    }
+
+    #[test]
+    fn test_decode_error_during_transition() {
+        let dir = std::env::temp_dir().join(format!("wpaperd-failed-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let images: Vec<PathBuf> = ["a.png", "b.png", "c.png"]
+            .iter()
+            .map(|name| dir.join(name))
+            .collect();
+        RgbaImage::new(1, 1).save(&images[0]).unwrap();
+        fs::write(&images[1], b"not an image").unwrap();
+        RgbaImage::new(1, 1).save(&images[2]).unwrap();
+
+        let wallpaper_info = WallpaperInfo {
+            path: dir.clone(),
+            sorting: Some(Sorting::Ascending),
+            ..WallpaperInfo::default()
+        };
+        let groups = Rc::new(RefCell::new(WallpaperGroups::new(false)));
+        let (mut picker, qh) = test_picker(&wallpaper_info, &groups);
+        picker.update_current_image(images[0].clone(), 0);
+
+        // The transition to the next image has been requested, it fails to decode
+        picker.action = Some(ImagePickerAction::Next);
+        let (path, _) = picker.get_image_from_path(&dir, &qh).unwrap();
+        assert_eq!(images[1], path);
+        let options = LoadOptions {
+            max_size: None,
+            exif_orientation: false,
+            auto_offset: false,
+            average_color: false,
+            allow_network: false,
+        };
+        let status = loop {
+            match picker.image_loader.borrow_mut().background_load(
+                path.clone(),
+                "test".to_string(),
+                options,
+            ) {
+                ImageLoaderStatus::Waiting => std::thread::sleep(Duration::from_millis(5)),
+                status => break status,
+            }
+        };
+        assert!(matches!(status, ImageLoaderStatus::Error));
+        picker.failed_image(&path);
+
+        // The current wallpaper is kept and the change skips to the image after the broken one
+        assert_eq!(images[0], picker.current_image());
+        let (path, index) = picker.get_image_from_path(&dir, &qh).unwrap();
+        assert_eq!(images[2], path);
+        picker.update_current_image(path, index);
+
+        // The broken image isn't picked again when the list wraps around
+        picker.action = Some(ImagePickerAction::Next);
+        let (path, index) = picker.get_image_from_path(&dir, &qh).unwrap();
+        assert_eq!(images[0], path);
+        picker.update_current_image(path, index);
+        picker.action = Some(ImagePickerAction::Next);
+        assert_eq!(images[2], picker.get_image_from_path(&dir, &qh).unwrap().0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_skip_other_weekdays() {
+        // 1970-01-01 was a thursday
+        assert_eq!(Weekday::Thursday, Weekday::from_day(0));
+        assert_eq!(Weekday::Monday, Weekday::from_day(4));
+        assert_eq!(Weekday::Sunday, Weekday::from_day(-4));
+
+        let files = Arc::new(vec![
+            PathBuf::from("monday/image"),
+            PathBuf::from("monday/friday"),
+            PathBuf::from("other"),
+        ]);
+        let mut monday = WeekdayPin::new(PathBuf::from("monday"));
+        monday.add(Weekday::Monday);
+        // The image pinned inside the pinned directory follows its own days
+        let mut friday = WeekdayPin::new(PathBuf::from("monday/friday"));
+        friday.add(Weekday::Friday);
+        let pins = [monday, friday];
+
+        let mut cache = None;
+        let monday_files = skip_other_weekdays(&mut cache, files.clone(), &pins, Weekday::Monday);
+        assert_eq!(
+            vec![PathBuf::from("monday/image"), PathBuf::from("other")],
+            *monday_files
+        );
+        assert!(Arc::ptr_eq(
+            &monday_files,
+            &skip_other_weekdays(&mut cache, files.clone(), &pins, Weekday::Monday)
+        ));
+        assert_eq!(
+            vec![PathBuf::from("monday/friday"), PathBuf::from("other")],
+            *skip_other_weekdays(&mut None, files.clone(), &pins, Weekday::Friday)
+        );
+        assert_eq!(
+            vec![PathBuf::from("other")],
+            *skip_other_weekdays(&mut None, files.clone(), &pins, Weekday::Sunday)
+        );
+        assert!(Arc::ptr_eq(
+            &files,
+            &skip_other_weekdays(&mut None, files.clone(), &[], Weekday::Sunday)
+        ));
+    }
+
+    #[test]
+    fn test_random_start_index_skips_current() {
+        assert_eq!(0, random_start_index(1, Some(0)));
+        for _ in 0..20 {
+            let index = random_start_index(3, Some(1));
+            assert!(index < 3);
+            assert_ne!(1, index);
+        }
+    }
+
+    #[test]
+    fn test_daily_index_is_stable() {
+        let index = daily_index(20000, 0, 7);
+        assert!(index < 7);
+        assert_eq!(index, daily_index(20000, 0, 7));
+        assert_eq!((index + 3) % 7, daily_index(20000, 3, 7));
+        assert_eq!(index, daily_index(20000, 7, 7));
+        // The picks should not repeat for a whole week
+        let week: HashSet<usize> = (0..7).map(|day| daily_index(day, 0, 1000)).collect();
+        assert_eq!(7, week.len());
+    }
+
+    #[test]
+    fn test_shuffle_shows_every_image() {
+        let mut files: Vec<PathBuf> = (0..10).map(|i| PathBuf::from(format!("{i}.jpg"))).collect();
+        let mut shuffle = Shuffle::starting_from(&files[0]);
+        let mut current = files[0].clone();
+        // The current image counts as shown in the first round
+        let mut round: HashSet<PathBuf> = HashSet::from([current.clone()]);
+        for _ in 1..5 {
+            current = shuffle.next(&current, &files);
+            assert!(round.insert(current.clone()));
+        }
+        // A new image is shown in the same round
+        files.push(PathBuf::from("new.jpg"));
+        for _ in 5..11 {
+            current = shuffle.next(&current, &files);
+            assert!(round.insert(current.clone()));
+        }
+        assert_eq!(files.len(), round.len());
+
+        let last = current.clone();
+        let mut round = HashSet::new();
+        for _ in 0..files.len() {
+            current = shuffle.next(&current, &files);
+            assert!(round.insert(current.clone()));
+        }
+        assert_eq!(files.len(), round.len());
+        // The new round doesn't repeat the last image of the previous one
+        assert_ne!(last, shuffle.shown[0]);
+
+        let previous = shuffle.shown[files.len() - 2].clone();
+        assert_eq!(previous, shuffle.previous(&current));
+        assert_eq!(current, shuffle.next(&previous, &files));
+    }
+
+    #[test]
+    fn test_random_update_groups() {
+        let random = Sorting::Random;
+        let group = |group| Sorting::GroupedRandom { group };
+
+        // An ungrouped display keeps its own sequence until the path changes
+        assert_eq!(
+            Some(RandomUpdate::Keep),
+            random_update(RandomSequence::Own, random, false)
+        );
+        assert_eq!(
+            Some(RandomUpdate::Restart),
+            random_update(RandomSequence::Own, random, true)
+        );
+        // Joining a group replaces the sequence, even for a single display
+        assert_eq!(
+            Some(RandomUpdate::Join(1)),
+            random_update(RandomSequence::Own, group(1), false)
+        );
+        assert_eq!(
+            Some(RandomUpdate::Join(1)),
+            random_update(RandomSequence::None, group(1), true)
+        );
+        assert_eq!(
+            Some(RandomUpdate::Join(2)),
+            random_update(RandomSequence::Group(1), group(2), false)
+        );
+        // A new path moves the display to the pool of the group with the same wallpapers
+        assert_eq!(
+            Some(RandomUpdate::Join(1)),
+            random_update(RandomSequence::Group(1), group(1), true)
+        );
+        // Leaving a group continues from the image of the group
+        assert_eq!(
+            Some(RandomUpdate::Continue),
+            random_update(RandomSequence::Group(1), random, false)
+        );
+        assert_eq!(
+            Some(RandomUpdate::Continue),
+            random_update(RandomSequence::None, random, false)
+        );
+        assert_eq!(
+            None,
+            random_update(RandomSequence::Group(1), Sorting::Ascending, false)
+        );
+    }
+
     #[test]
     fn test_resize2() {
-        let mut queue = Queue::with_capacity(5);
+        let mut queue = Queue::new(5, 5);
         queue.push(PathBuf::from("mypath"));
         queue.push(PathBuf::from("mypath2"));
         queue.push(PathBuf::from("mypath3"));
@@ -635,4 +2365,100 @@ mod tests {
         assert_eq!(Some((Path::new("mypath8"), 1)), queue.next());
         assert_eq!(None, queue.next());
     }
+
+    #[test]
+    fn test_random_start_picked_twice() {
+        let wallpaper_info = WallpaperInfo {
+            sorting: Some(Sorting::RandomStart),
+            ..WallpaperInfo::default()
+        };
+        let groups = Rc::new(RefCell::new(WallpaperGroups::new(false)));
+        let (mut picker, qh) = test_picker(&wallpaper_info, &groups);
+        let files: Vec<PathBuf> = (0..10).map(|i| PathBuf::from(format!("{i}"))).collect();
+
+        // The image is picked when the change is requested and again when it's drawn
+        let (start, path) = picker.get_image_path(&files, &qh);
+        assert_eq!((start, path.clone()), picker.get_image_path(&files, &qh));
+        picker.update_current_image(path, start);
+
+        // The run continues in order from the random start until every image has been shown
+        for step in 1..files.len() {
+            picker.action = Some(ImagePickerAction::Next);
+            let (index, path) = picker.get_image_path(&files, &qh);
+            assert_eq!((start + step) % files.len(), index);
+            assert_eq!((index, path.clone()), picker.get_image_path(&files, &qh));
+            picker.update_current_image(path, index);
+        }
+
+        // Then a new run begins from another random image
+        let last = (start + files.len() - 1) % files.len();
+        picker.action = Some(ImagePickerAction::Next);
+        let (index, path) = picker.get_image_path(&files, &qh);
+        assert_ne!(last, index);
+        assert_eq!((index, path), picker.get_image_path(&files, &qh));
+    }
+
+    #[test]
+    fn test_shuffle_picked_twice() {
+        let wallpaper_info = WallpaperInfo {
+            sorting: Some(Sorting::Shuffle),
+            ..WallpaperInfo::default()
+        };
+        let groups = Rc::new(RefCell::new(WallpaperGroups::new(false)));
+        let (mut picker, qh) = test_picker(&wallpaper_info, &groups);
+        let files: Vec<PathBuf> = (0..5).map(|i| PathBuf::from(format!("{i}"))).collect();
+
+        // Each image is picked when the change is requested and again when it's drawn, the
+        // round still shows all of them once
+        let mut shown = HashSet::new();
+        for _ in 0..files.len() {
+            picker.action = Some(ImagePickerAction::Next);
+            let (index, path) = picker.get_image_path(&files, &qh);
+            assert_eq!((index, path.clone()), picker.get_image_path(&files, &qh));
+            assert!(shown.insert(path.clone()));
+            picker.update_current_image(path, index);
+        }
+        assert_eq!(files.len(), shown.len());
+
+        // Going back is not repeated either
+        let current = picker.current_img.clone();
+        picker.action = Some(ImagePickerAction::Previous);
+        let (index, path) = picker.get_image_path(&files, &qh);
+        assert_ne!(current, path);
+        assert_eq!((index, path), picker.get_image_path(&files, &qh));
+    }
+
+    #[test]
+    fn test_group_changed_once_per_tick() {
+        let groups = Rc::new(RefCell::new(WallpaperGroups::new(false)));
+        let pool = |path: &str| WallpaperInfo {
+            path: PathBuf::from(path),
+            sorting: Some(Sorting::GroupedRandom { group: 0 }),
+            ..WallpaperInfo::default()
+        };
+        let (mut first, qh) = test_picker(&pool("first"), &groups);
+        let (mut second, _) = test_picker(&pool("second"), &groups);
+        let first_files: Vec<PathBuf> = (0..5).map(|i| PathBuf::from(format!("a{i}"))).collect();
+        let second_files: Vec<PathBuf> = (0..5).map(|i| PathBuf::from(format!("b{i}"))).collect();
+        let group = |picker: &ImagePicker| match &picker.sorting {
+            ImagePickerSorting::GroupedRandom(grouped) => grouped.group.clone(),
+            _ => unreachable!(),
+        };
+
+        // The timer of the first display expires, the other pool follows the change
+        first.action = Some(ImagePickerAction::Next);
+        let (index, path) = first.get_image_path(&first_files, &qh);
+        assert_eq!(Some(GroupChange::Next), group(&second).borrow().change);
+
+        // The timer of the second display expires right after, before it has been drawn
+        second.action = Some(ImagePickerAction::Next);
+        let (second_index, second_path) = second.get_image_path(&second_files, &qh);
+        assert!(group(&second).borrow().change.is_none());
+        second.update_current_image(second_path, second_index);
+
+        // The first display doesn't change once more
+        assert!(group(&first).borrow().change.is_none());
+        first.update_current_image(path.clone(), index);
+        assert_eq!(path, group(&first).borrow().current_image);
+    }
 }
@@ -0,0 +1,113 @@
+//! Print how an image would be scaled and cropped on a display, to help choosing its `mode`
+//! and `offset`. The same geometry as the renderer is used, without creating any surface or
+//! OpenGL context. The border, the rotation and the `auto-offset` of the configuration are not
+//! taken into account.
+
+use std::path::Path;
+
+use color_eyre::{
+    eyre::{anyhow, Context, ContextCompat},
+    Result, Section,
+};
+use serde::{de::value::StrDeserializer, Deserialize};
+
+use crate::{
+    config::parse_offset,
+    image_loader::probe,
+    list_outputs::outputs,
+    render::{is_rotated, texture_offset, texture_scale, visible_area},
+    wallpaper_info::{BackgroundMode, DEFAULT_MAX_UPSCALE},
+};
+
+/// Parse an attribute given on the command line like the configuration does
+fn parse_value<'a, T: Deserialize<'a>>(value: &'a str) -> Result<T> {
+    T::deserialize(StrDeserializer::<serde::de::value::Error>::new(value)).map_err(Into::into)
+}
+
+/// Parse a size given as `WIDTHxHEIGHT`
+fn parse_size(size: &str) -> Result<(i32, i32)> {
+    size.split_once('x')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+        .filter(|&(width, height)| width > 0 && height > 0)
+        .with_context(|| format!("invalid size {size:?}"))
+        .suggestion("Pass the width and the height of the display, e.g. 2560x1440")
+}
+
+/// Return the size of the display in pixels, as the wallpaper is drawn on it
+fn output_size(name: &str) -> Result<(i32, i32)> {
+    let outputs = outputs()?;
+    let info = outputs
+        .iter()
+        .find(|info| info.name.as_deref() == Some(name))
+        .with_context(|| format!("display {name:?} not found"))
+        .with_suggestion(|| {
+            let names: Vec<&str> = outputs
+                .iter()
+                .filter_map(|info| info.name.as_deref())
+                .collect();
+            format!("The available displays are: {}", names.join(", "))
+        })?;
+    let (width, height) = info
+        .modes
+        .iter()
+        .find(|mode| mode.current)
+        .map(|mode| mode.dimensions)
+        .with_context(|| format!("display {name:?} has no current mode"))?;
+    Ok(if is_rotated(info.transform) {
+        (height, width)
+    } else {
+        (width, height)
+    })
+}
+
+/// Print the scale and the part of the image that is shown on the display
+pub fn inspect(
+    image: &Path,
+    output: Option<&str>,
+    size: Option<&str>,
+    mode: Option<&str>,
+    offset: Option<&str>,
+) -> Result<()> {
+    let mode = mode.map_or(Ok(BackgroundMode::default()), parse_value)?;
+    let offset = offset.map(parse_offset).transpose()?;
+    let (display_width, display_height) = match (size, output) {
+        (Some(size), _) => parse_size(size)?,
+        (None, Some(output)) => output_size(output)?,
+        (None, None) => {
+            return Err(anyhow!("no display to inspect the image on"))
+                .suggestion("Pass the name of a display with --output or its size with --size")
+        }
+    };
+    // Like the renderer, follow the EXIF orientation
+    let (image_width, image_height) = probe(image)
+        .with_context(|| format!("reading the size of {image:?}"))?
+        .oriented(true);
+
+    let display = (display_width as f32, display_height as f32);
+    let scale = texture_scale(
+        mode,
+        display,
+        (image_width as f32, image_height as f32),
+        DEFAULT_MAX_UPSCALE,
+    );
+    let offset = texture_offset(mode, offset, None, scale);
+    let [x, y, width, height] = visible_area(scale, offset);
+
+    println!("image: {image_width}x{image_height}");
+    println!("display: {display_width}x{display_height}");
+    println!("mode: {mode}, offset {offset}");
+    // Pixels of the display covered by a pixel of the image
+    println!(
+        "scale: {:.3}x{:.3}",
+        display.0 / (scale[0] * image_width as f32),
+        display.1 / (scale[1] * image_height as f32)
+    );
+    println!(
+        "crop: {}x{} at {},{}",
+        (width * image_width as f32).round(),
+        (height * image_height as f32).round(),
+        (x * image_width as f32).round(),
+        (y * image_height as f32).round()
+    );
+    Ok(())
+}
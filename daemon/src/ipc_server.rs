@@ -1,30 +1,253 @@
 //! IPC socket server.
 //! Based on <https://github.com/catacombing/catacomb/blob/master/src/ipc_server.rs>
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io::{BufReader, BufWriter, Read, Write};
-use std::os::unix::net::{UnixListener, UnixStream};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixListener, UnixStream};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-use color_eyre::eyre::{ensure, Context};
+use color_eyre::eyre::{anyhow, bail, ensure, Context};
 use color_eyre::{Result, Section};
-use smithay_client_toolkit::reexports::client::QueueHandle;
-use wpaperd_ipc::{IpcError, IpcMessage, IpcResponse};
+use flexi_logger::LogSpecification;
+use image::RgbaImage;
+use log::{error, warn};
+use smithay_client_toolkit::reexports::{
+    calloop::{
+        channel::{self, Sender},
+        LoopHandle,
+    },
+    client::QueueHandle,
+};
+use wpaperd_ipc::{
+    abstract_name, connect, Candidates, IpcError, IpcMessage, IpcResponse, Status, MAX_PIXELS_SIZE,
+    PROTOCOL_VERSION,
+};
 
+use crate::config::{parse_color, parse_offset, parse_transition};
 use crate::socket::SocketSource;
-use crate::surface::Surface;
+use crate::surface::{write_screenshot, Surface};
+use crate::wallpaper_info::Sorting;
 use crate::Wpaperd;
 
-/// Create an IPC socket.
-pub fn listen_on_ipc_socket(socket_path: &Path) -> Result<SocketSource> {
-    // Try to delete the socket if it exists already.
-    if socket_path.exists() {
-        fs::remove_file(socket_path)?;
+/// Messages handled by the daemon, returned by [`IpcMessage::Capabilities`]
+const SUPPORTED_COMMANDS: &[&str] = &[
+    "CurrentWallpaper",
+    "NextWallpaper",
+    "NextWithTransition",
+    "PreviousWallpaper",
+    "PreviewTransition",
+    "PauseWallpaper",
+    "ResumeWallpaper",
+    "TogglePauseWallpaper",
+    "DisableWallpaper",
+    "EnableWallpaper",
+    "SessionLock",
+    "AllWallpapers",
+    "ReloadWallpaper",
+    "ToggleMode",
+    "Sorting",
+    "Offset",
+    "AddFavorite",
+    "ToggleFavorites",
+    "Metrics",
+    "Status",
+    "Duration",
+    "LogFilter",
+    "Candidates",
+    "FlushCache",
+    "Profile",
+    "SetPixels",
+    "Screenshot",
+    "Capabilities",
+];
+
+/// Maximum time to receive all the pixels of [`IpcMessage::SetPixels`]
+const PIXELS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Size of the reads of the pixels, the buffer grows as they arrive
+const PIXELS_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Return the number of bytes of the pixels of an image of `width` x `height`
+fn pixels_size(width: u32, height: u32) -> Result<u64, IpcError> {
+    if width == 0 || height == 0 || width > MAX_PIXELS_SIZE || height > MAX_PIXELS_SIZE {
+        Err(IpcError::InvalidPixelsSize { width, height })
+    } else {
+        Ok(width as u64 * height as u64 * 4)
+    }
+}
+
+/// Read the pixels following [`IpcMessage::SetPixels`] until the client shuts down its writing
+/// half of the connection, failing once `deadline` has passed. `received` are the bytes read
+/// together with the message; at most one byte more than expected is read, to tell that too many
+/// have been sent.
+fn read_pixels(
+    stream: &mut UnixStream,
+    received: Vec<u8>,
+    expected: u64,
+    deadline: Instant,
+) -> Result<Vec<u8>> {
+    let mut data = received;
+    let mut chunk = vec![0; PIXELS_CHUNK_SIZE];
+    while data.len() as u64 <= expected {
+        let left = deadline.saturating_duration_since(Instant::now());
+        ensure!(
+            !left.is_zero(),
+            "timed out while reading the pixels from IPC"
+        );
+        stream
+            .set_read_timeout(Some(left))
+            .context("unable to set the timeout of the IPC client socket")?;
+        let wanted = (expected + 1 - data.len() as u64).min(chunk.len() as u64) as usize;
+        match stream.read(&mut chunk[..wanted]) {
+            Ok(0) => break,
+            Ok(n) => data.extend_from_slice(&chunk[..n]),
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                bail!("timed out while reading the pixels from IPC")
+            }
+            Err(err) => return Err(err).context("error while reading the pixels from IPC"),
+        }
+    }
+    Ok(data)
+}
+
+/// Pixels of [`IpcMessage::SetPixels`] read by a thread, with the client to answer
+struct ReceivedPixels {
+    stream: UnixStream,
+    monitors: Vec<String>,
+    width: u32,
+    height: u32,
+    data: Result<Vec<u8>>,
+}
+
+/// Read the pixels sent by the clients outside of the event loop, they are drawn once they
+/// have all been received
+#[derive(Clone)]
+pub struct PixelsReader {
+    sender: Sender<ReceivedPixels>,
+}
+
+impl PixelsReader {
+    pub fn new(event_loop_handle: LoopHandle<Wpaperd>, qh: QueueHandle<Wpaperd>) -> Result<Self> {
+        let (sender, received) = channel::channel();
+        event_loop_handle
+            .insert_source(received, move |event, _, wpaperd| {
+                let channel::Event::Msg(ReceivedPixels {
+                    mut stream,
+                    monitors,
+                    width,
+                    height,
+                    data,
+                }) = event
+                else {
+                    return;
+                };
+                // Like the other errors reading a message, the client isn't answered
+                let data = match data {
+                    Ok(data) => data,
+                    Err(err) => {
+                        error!("{err:?}");
+                        return;
+                    }
+                };
+                let resp = draw_pixels(wpaperd, monitors, (width, height), data, &qh);
+                // The client might have stopped waiting, there is nobody to tell
+                if let Err(err) = stream.write_all(&serde_json::to_vec(&resp).unwrap()) {
+                    warn!("unable to write the response to the IPC client: {err}");
+                }
+            })
+            .map_err(|e| anyhow!("inserting the pixels listener in the event loop: {e}"))?;
+
+        Ok(Self { sender })
+    }
+
+    /// Read the `expected` bytes of pixels in a new thread, following the `received` ones
+    fn read(
+        &self,
+        mut stream: UnixStream,
+        received: Vec<u8>,
+        expected: u64,
+        (monitors, width, height): (Vec<String>, u32, u32),
+    ) {
+        let sender = self.sender.clone();
+        std::thread::spawn(move || {
+            let deadline = Instant::now() + PIXELS_TIMEOUT;
+            let data = read_pixels(&mut stream, received, expected, deadline);
+            // The event loop is gone when wpaperd is exiting
+            let _ = sender.send(ReceivedPixels {
+                stream,
+                monitors,
+                width,
+                height,
+                data,
+            });
+        });
+    }
+}
+
+/// Draw the pixels received on the displays requested
+fn draw_pixels(
+    wpaperd: &mut Wpaperd,
+    monitors: Vec<String>,
+    (width, height): (u32, u32),
+    data: Vec<u8>,
+    qh: &QueueHandle<Wpaperd>,
+) -> Result<IpcResponse, IpcError> {
+    let expected = width as u64 * height as u64 * 4;
+    if data.len() as u64 != expected {
+        return Err(IpcError::PixelsMismatch {
+            expected,
+            received: data.len() as u64,
+        });
     }
+    let image = RgbaImage::from_raw(width, height, data).expect("the pixels to fill the image");
+    check_monitors(wpaperd, &monitors)?;
+    let errors: Vec<(String, String)> = collect_surfaces(wpaperd, monitors)
+        .into_iter()
+        .filter_map(|surface| {
+            let res = surface.load_pixels(image.clone(), qh);
+            res.err().map(|err| (surface.name(), format!("{err:?}")))
+        })
+        .collect();
+    if errors.is_empty() {
+        Ok(IpcResponse::Ok)
+    } else {
+        Err(IpcError::DrawErrors(errors))
+    }
+}
+
+/// Create an IPC socket, failing if another instance of wpaperd is listening on it
+pub fn listen_on_ipc_socket(socket_path: &Path) -> Result<SocketSource> {
+    let another_instance = || {
+        Err(anyhow!(
+            "another instance of wpaperd is listening on the socket {socket_path:?}"
+        ))
+        .suggestion(
+            "Stop the other instance, or use --socket or WPAPERD_SOCKET to choose another path",
+        )
+    };
+    let listener = match abstract_name(socket_path) {
+        Some(name) => match UnixListener::bind_addr(&SocketAddr::from_abstract_name(name)?) {
+            Ok(listener) => listener,
+            Err(err) if err.kind() == ErrorKind::AddrInUse => return another_instance(),
+            Err(err) => return Err(err.into()),
+        },
+        None => {
+            if socket_path.exists() {
+                if connect(socket_path).is_ok() {
+                    return another_instance();
+                }
+                // Nobody is listening, the socket has been left by an instance that crashed
+                fs::remove_file(socket_path)?;
+            }
+            UnixListener::bind(socket_path)?
+        }
+    };
 
     // Spawn unix socket event source.
-    let listener = UnixListener::bind(socket_path)?;
     let socket = SocketSource::new(listener)?;
     Ok(socket)
 }
@@ -58,10 +281,163 @@ fn collect_surfaces(wpaperd: &mut Wpaperd, monitors: Vec<String>) -> Vec<&mut Su
         .collect()
 }
 
+/// Parse the sortings that can be set at runtime, using the same names as the configuration
+fn parse_sorting(sorting: String) -> Result<Sorting, IpcError> {
+    match sorting.as_str() {
+        "random" => Ok(Sorting::Random),
+        "ascending" => Ok(Sorting::Ascending),
+        "descending" => Ok(Sorting::Descending),
+        "random-start" => Ok(Sorting::RandomStart),
+        "shuffle" => Ok(Sorting::Shuffle),
+        _ => Err(IpcError::InvalidSorting { sorting }),
+    }
+}
+
+fn sorting_name(sorting: Option<Sorting>) -> String {
+    sorting.map_or_else(|| "none".to_string(), |sorting| sorting.to_string())
+}
+
+fn status_entries(wpaperd: &Wpaperd, monitors: &[String]) -> Vec<(String, Status)> {
+    wpaperd
+        .surfaces
+        .iter()
+        .filter(|surface| monitors.is_empty() || monitors.contains(&surface.name()))
+        .map(|surface| {
+            let status = Status {
+                path: surface.image_picker.current_image(),
+                paused: surface.should_pause(),
+                mode: surface.mode().to_string(),
+                sorting: sorting_name(surface.sorting()),
+                swap_interval: surface.swap_interval(),
+                position: surface.position(),
+                profile: wpaperd.config.profile.clone(),
+            };
+            (surface.name(), status)
+        })
+        .collect()
+}
+
+/// Client that sent [`IpcMessage::Status`] with `watch` set
+pub struct StatusWatcher {
+    stream: UnixStream,
+    monitors: Vec<String>,
+    /// Last status sent for each display, to only send the ones that changed
+    sent: HashMap<String, Status>,
+    /// Data not written yet because the client is not reading fast enough
+    pending: Vec<u8>,
+}
+
+impl StatusWatcher {
+    /// Return true if some data is waiting for the client to read it
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Queue the displays that changed and write as much as possible without blocking.
+    /// Return false when the client has disconnected or the status could not be serialized.
+    fn update(&mut self, wpaperd: &Wpaperd) -> bool {
+        // A slow client doesn't get every update: the changes are coalesced and sent
+        // all together once it has read the previous ones
+        if self.pending.is_empty() {
+            let entries: Vec<(String, Status)> = status_entries(wpaperd, &self.monitors)
+                .into_iter()
+                .filter(|(name, status)| self.sent.get(name) != Some(status))
+                .collect();
+            if !entries.is_empty() {
+                self.sent.extend(entries.iter().cloned());
+                let resp: Result<IpcResponse, IpcError> = Ok(IpcResponse::Status { entries });
+                match serde_json::to_vec(&resp) {
+                    Ok(pending) => self.pending = pending,
+                    Err(err) => {
+                        warn!("unable to serialize the status for the IPC client: {err}");
+                        return false;
+                    }
+                }
+                self.pending.push(b'\n');
+            }
+        }
+
+        while !self.pending.is_empty() {
+            match self.stream.write(&self.pending) {
+                Ok(0) => return false,
+                Ok(n) => {
+                    self.pending.drain(..n);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => return false,
+            }
+        }
+
+        // The client is not expected to send anything else, check if it has closed the socket
+        let mut buffer = [0; 64];
+        match self.stream.read(&mut buffer) {
+            Ok(0) => false,
+            Ok(_) => true,
+            Err(err) => err.kind() == ErrorKind::WouldBlock,
+        }
+    }
+}
+
+/// Send the status changes to the watching clients, dropping the disconnected ones
+pub fn publish_status(wpaperd: &mut Wpaperd) {
+    if wpaperd.status_watchers.is_empty() {
+        return;
+    }
+    let mut watchers = std::mem::take(&mut wpaperd.status_watchers);
+    watchers.retain_mut(|watcher| watcher.update(wpaperd));
+    wpaperd.status_watchers = watchers;
+}
+
+/// Client that sent [`IpcMessage::PreviewTransition`], answered once the previews have ended
+pub struct PreviewClient {
+    stream: UnixStream,
+    monitors: Vec<String>,
+}
+
+/// Answer the clients whose previews have all ended, or whose displays have been removed
+pub fn finish_previews(wpaperd: &mut Wpaperd) {
+    if wpaperd.preview_clients.is_empty() {
+        return;
+    }
+    let mut clients = std::mem::take(&mut wpaperd.preview_clients);
+    clients.retain_mut(|client| {
+        if wpaperd
+            .surfaces
+            .iter_mut()
+            .any(|surface| client.monitors.contains(&surface.name()) && surface.is_previewing())
+        {
+            return true;
+        }
+        let errors: Vec<_> = wpaperd
+            .surfaces
+            .iter_mut()
+            .filter(|surface| client.monitors.contains(&surface.name()))
+            .filter_map(|surface| {
+                surface
+                    .take_preview_error()
+                    .map(|err| (surface.name(), err))
+            })
+            .collect();
+        let resp: Result<IpcResponse, IpcError> = if errors.is_empty() {
+            Ok(IpcResponse::Ok)
+        } else {
+            Err(IpcError::PreviewErrors(errors))
+        };
+        // The client might have stopped waiting, there is nobody to tell
+        if let Err(err) = client.stream.write_all(&serde_json::to_vec(&resp).unwrap()) {
+            warn!("unable to write the end of the preview to the IPC client: {err}");
+        }
+        false
+    });
+    wpaperd.preview_clients = clients;
+}
+
 /// Handle IPC socket messages.
 pub fn handle_message(
     ustream: UnixStream,
     qh: QueueHandle<Wpaperd>,
+    handle: &LoopHandle<Wpaperd>,
+    pixels_reader: &PixelsReader,
     wpaperd: &mut Wpaperd,
 ) -> Result<()> {
     const SIZE: usize = 4096;
@@ -76,11 +452,33 @@ pub fn handle_message(
     if n == 0 {
         return Ok(());
     }
-    ensure!(n != SIZE, "The message received was too big");
 
-    // Read pending events on socket.
-    let message: IpcMessage = serde_json::from_slice(&buffer[..n])
-        .with_context(|| format!("error while deserializing message {:?}", &buffer[..n]))?;
+    // Read pending events on socket. The message can be followed by the pixels of
+    // IpcMessage::SetPixels
+    let mut messages = serde_json::Deserializer::from_slice(&buffer[..n]).into_iter();
+    let message: IpcMessage = match messages.next() {
+        None => return Ok(()),
+        Some(Err(err)) if err.is_eof() && n == SIZE => bail!("The message received was too big"),
+        Some(res) => {
+            res.with_context(|| format!("error while deserializing message {:?}", &buffer[..n]))?
+        }
+    };
+    let trailing = &buffer[messages.byte_offset()..n];
+    ensure!(
+        matches!(message, IpcMessage::SetPixels { .. })
+            || trailing.iter().all(u8::is_ascii_whitespace),
+        "trailing characters after the message {:?}",
+        &buffer[..n]
+    );
+
+    // Set when the client watches the status, the connection is kept open afterwards
+    let mut watcher = None;
+    // Displays previewing their transition, the response is sent once they have finished
+    let mut preview = None;
+    // Pixels to receive, the response is sent once they have been read and drawn
+    let mut pixels = None;
+    // Frame to write, the response is sent once it has been encoded
+    let mut screenshot = None;
 
     // Handle IPC events.
     let resp: Result<IpcResponse, IpcError> = match message {
@@ -108,6 +506,7 @@ pub fn handle_message(
             check_monitors(wpaperd, &monitors).map(|_| {
                 for surface in collect_surfaces(wpaperd, monitors) {
                     surface.image_picker.previous_image();
+                    surface.manual_change();
                     surface.queue_draw(&qh);
                 }
 
@@ -120,12 +519,56 @@ pub fn handle_message(
                 surface
                     .image_picker
                     .next_image(&surface.wallpaper_info.path, &qh);
+                surface.manual_change();
                 surface.queue_draw(&qh);
             }
 
             IpcResponse::Ok
         }),
 
+        IpcMessage::PreviewTransition { monitors, image } => check_monitors(wpaperd, &monitors)
+            .and_then(|_| {
+                let mut names = Vec::new();
+                let mut errors = Vec::new();
+                for surface in collect_surfaces(wpaperd, monitors) {
+                    match surface.preview_transition(image.as_deref(), &qh) {
+                        Ok(()) => names.push(surface.name()),
+                        Err(err) => errors.push((surface.name(), format!("{err:?}"))),
+                    }
+                }
+                if errors.is_empty() {
+                    preview = Some(names);
+                    Ok(IpcResponse::Ok)
+                } else {
+                    Err(IpcError::PreviewErrors(errors))
+                }
+            }),
+
+        IpcMessage::NextWithTransition {
+            monitors,
+            transition,
+            transition_time,
+        } => match parse_transition(&transition) {
+            Ok(parsed) => check_monitors(wpaperd, &monitors).map(|_| {
+                let transition_time =
+                    transition_time.unwrap_or_else(|| parsed.default_transition_time());
+                for surface in collect_surfaces(wpaperd, monitors) {
+                    surface
+                        .image_picker
+                        .next_image(&surface.wallpaper_info.path, &qh);
+                    surface.force_next_transition(parsed.clone(), transition_time);
+                    surface.manual_change();
+                    surface.queue_draw(&qh);
+                }
+
+                IpcResponse::Ok
+            }),
+            Err(err) => Err(IpcError::InvalidTransition {
+                transition,
+                error: format!("{err:#}"),
+            }),
+        },
+
         IpcMessage::ReloadWallpaper { monitors } => check_monitors(wpaperd, &monitors).map(|_| {
             for surface in collect_surfaces(wpaperd, monitors) {
                 surface.image_picker.reload();
@@ -149,6 +592,33 @@ pub fn handle_message(
             IpcResponse::Ok
         }),
 
+        IpcMessage::DisableWallpaper { monitors, color } => color
+            .map(|color| parse_color("color", &color).map_err(|_| IpcError::InvalidColor { color }))
+            .transpose()
+            .and_then(|color| {
+                check_monitors(wpaperd, &monitors).map(|_| {
+                    for surface in collect_surfaces(wpaperd, monitors) {
+                        surface.disable(color);
+                    }
+                    IpcResponse::Ok
+                })
+            }),
+
+        IpcMessage::EnableWallpaper { monitors } => check_monitors(wpaperd, &monitors).map(|_| {
+            for surface in collect_surfaces(wpaperd, monitors) {
+                surface.enable(&qh);
+            }
+            IpcResponse::Ok
+        }),
+
+        IpcMessage::SessionLock { locked } => {
+            wpaperd.session_locked = locked;
+            for surface in &mut wpaperd.surfaces {
+                surface.set_session_locked(locked, &qh);
+            }
+            Ok(IpcResponse::Ok)
+        }
+
         IpcMessage::TogglePauseWallpaper { monitors } => {
             check_monitors(wpaperd, &monitors).map(|_| {
                 for surface in collect_surfaces(wpaperd, monitors) {
@@ -158,13 +628,401 @@ pub fn handle_message(
                 IpcResponse::Ok
             })
         }
+
+        IpcMessage::SetPixels {
+            monitors,
+            width,
+            height,
+        } => {
+            pixels_size(width, height).map(|expected| {
+                // The reader might have buffered some of the pixels after the message
+                let mut received = trailing.to_vec();
+                received.extend_from_slice(stream.buffer());
+                pixels = Some((received, expected, (monitors, width, height)));
+                // Not sent, the client is answered once the pixels have been drawn
+                IpcResponse::Ok
+            })
+        }
+
+        IpcMessage::Screenshot { monitor, path } => {
+            match wpaperd
+                .surfaces
+                .iter_mut()
+                .find(|surface| surface.name() == monitor)
+            {
+                Some(surface) => match surface.screenshot() {
+                    Ok(image) => {
+                        screenshot = Some((image, path, monitor));
+                        // Not sent, the client is answered once the image has been written
+                        Ok(IpcResponse::Ok)
+                    }
+                    Err(err) => Err(IpcError::ScreenshotFailed {
+                        monitor,
+                        error: format!("{err:#}"),
+                    }),
+                },
+                None => Err(IpcError::MonitorNotFound { monitor }),
+            }
+        }
+
+        IpcMessage::Capabilities => Ok(IpcResponse::Capabilities {
+            version: PROTOCOL_VERSION,
+            commands: SUPPORTED_COMMANDS
+                .iter()
+                .map(|command| command.to_string())
+                .collect(),
+        }),
+
+        IpcMessage::ToggleMode { monitors } => {
+            check_monitors(wpaperd, &monitors).map(|_| IpcResponse::BackgroundModes {
+                entries: collect_surfaces(wpaperd, monitors)
+                    .into_iter()
+                    .map(|surface| (surface.name(), surface.toggle_mode(&qh).to_string()))
+                    .collect(),
+            })
+        }
+
+        IpcMessage::Sorting { monitors, sorting } => check_monitors(wpaperd, &monitors)
+            .and_then(|_| sorting.map(parse_sorting).transpose())
+            .and_then(|sorting| {
+                let mut surfaces = collect_surfaces(wpaperd, monitors);
+                if let Some(sorting) = sorting {
+                    // The displays in a group share the same queue, they can't be changed alone
+                    if let Some(surface) = surfaces.iter().find(|surface| {
+                        matches!(surface.sorting(), Some(Sorting::GroupedRandom { .. }))
+                    }) {
+                        return Err(IpcError::GroupedSorting {
+                            monitor: surface.name(),
+                        });
+                    }
+                    for surface in surfaces.iter_mut() {
+                        surface.set_sorting(sorting);
+                    }
+                }
+
+                Ok(IpcResponse::Sortings {
+                    entries: surfaces
+                        .into_iter()
+                        .map(|surface| (surface.name(), sorting_name(surface.sorting())))
+                        .collect(),
+                })
+            }),
+
+        IpcMessage::Offset {
+            monitors,
+            offset,
+            delta,
+        } => check_monitors(wpaperd, &monitors)
+            .and_then(|_| {
+                offset
+                    .map(|offset| {
+                        parse_offset(&offset)
+                            .ok()
+                            .filter(|offset| offset.is_finite())
+                            .ok_or(IpcError::InvalidOffset { offset })
+                    })
+                    .transpose()
+            })
+            .and_then(|offset| match delta {
+                Some(delta) if !delta.is_finite() => Err(IpcError::InvalidOffset {
+                    offset: delta.to_string(),
+                }),
+                _ => Ok(offset),
+            })
+            .map(|offset| IpcResponse::Offsets {
+                entries: collect_surfaces(wpaperd, monitors)
+                    .into_iter()
+                    .map(|surface| {
+                        let offset = if offset.is_some() || delta.is_some() {
+                            surface.set_offset(offset, delta, &qh)
+                        } else {
+                            surface.current_offset()
+                        };
+                        (surface.name(), offset)
+                    })
+                    .collect(),
+            }),
+
+        IpcMessage::AddFavorite { monitors } => check_monitors(wpaperd, &monitors).and_then(|_| {
+            let mut surfaces = collect_surfaces(wpaperd, monitors);
+            if let Some(surface) = surfaces
+                .iter()
+                .find(|surface| !surface.image_picker.has_favorites())
+            {
+                return Err(IpcError::NoFavorites {
+                    monitor: surface.name(),
+                });
+            }
+            let mut entries = Vec::new();
+            let mut errors = Vec::new();
+            for surface in surfaces.iter_mut() {
+                match surface.add_favorite() {
+                    Ok((path, added)) => entries.push((surface.name(), path, added)),
+                    Err(err) => errors.push((surface.name(), format!("{err:?}"))),
+                }
+            }
+            // The files might have just been created
+            wpaperd.rewatch_favorites = true;
+            if errors.is_empty() {
+                Ok(IpcResponse::FavoritesAdded { entries })
+            } else {
+                Err(IpcError::FavoritesErrors(errors))
+            }
+        }),
+
+        IpcMessage::ToggleFavorites { monitors } => {
+            check_monitors(wpaperd, &monitors).and_then(|_| {
+                let mut surfaces = collect_surfaces(wpaperd, monitors);
+                for surface in &surfaces {
+                    if !surface.image_picker.has_favorites() {
+                        return Err(IpcError::NoFavorites {
+                            monitor: surface.name(),
+                        });
+                    }
+                    // The displays in a group share the same queue, they can't be changed alone
+                    if matches!(surface.sorting(), Some(Sorting::GroupedRandom { .. })) {
+                        return Err(IpcError::GroupedFavorites {
+                            monitor: surface.name(),
+                        });
+                    }
+                }
+                Ok(IpcResponse::FavoritesModes {
+                    entries: surfaces
+                        .iter_mut()
+                        .map(|surface| (surface.name(), surface.toggle_favorites(&qh)))
+                        .collect(),
+                })
+            })
+        }
+
+        IpcMessage::Metrics { monitors, reset } => {
+            check_monitors(wpaperd, &monitors).map(|_| IpcResponse::Metrics {
+                entries: collect_surfaces(wpaperd, monitors)
+                    .into_iter()
+                    .map(|surface| {
+                        let metrics = if reset {
+                            std::mem::take(&mut surface.metrics)
+                        } else {
+                            surface.metrics.clone()
+                        };
+                        (surface.name(), metrics)
+                    })
+                    .collect(),
+            })
+        }
+
+        IpcMessage::Duration {
+            monitors,
+            duration,
+            expire,
+            reset,
+        } => check_monitors(wpaperd, &monitors).map(|_| {
+            let mut surfaces = collect_surfaces(wpaperd, monitors);
+            for surface in surfaces.iter_mut() {
+                if reset {
+                    surface.reset_duration(handle, &qh);
+                } else if let Some(duration) = duration {
+                    surface.set_temporary_duration(duration, expire, handle, &qh);
+                }
+            }
+            IpcResponse::Durations {
+                entries: surfaces
+                    .into_iter()
+                    .map(|surface| (surface.name(), surface.duration()))
+                    .collect(),
+            }
+        }),
+
+        IpcMessage::LogFilter { filter, reset } => match filter {
+            Some(filter) => match LogSpecification::parse(&filter) {
+                Ok(spec) => {
+                    // Keep only the startup filter below the new one, so that reset restores it
+                    wpaperd.logger.pop_temp_spec();
+                    wpaperd.logger.push_temp_spec(spec);
+                    Ok(IpcResponse::Ok)
+                }
+                Err(err) => Err(IpcError::InvalidLogFilter {
+                    filter,
+                    error: err.to_string(),
+                }),
+            },
+            None => {
+                if reset {
+                    wpaperd.logger.pop_temp_spec();
+                }
+                Ok(IpcResponse::Ok)
+            }
+        },
+
+        IpcMessage::FlushCache => {
+            let (images, dimensions) = wpaperd.image_loader.borrow_mut().flush();
+            // The filters read the dimensions of the images, apply them again.
+            // The displayed textures are kept until the next wallpaper is loaded
+            for surface in &mut wpaperd.surfaces {
+                surface.image_picker.reset_filters();
+            }
+            Ok(IpcResponse::CacheFlushed { images, dimensions })
+        }
+
+        IpcMessage::Profile { profile, reset } => {
+            if profile.is_none() && !reset {
+                Ok(IpcResponse::Profile {
+                    profile: wpaperd.config.profile.clone(),
+                })
+            } else {
+                match wpaperd.switch_profile(profile.as_deref()) {
+                    Ok(()) => Ok(IpcResponse::Profile { profile }),
+                    Err(err) => Err(IpcError::InvalidProfile {
+                        profile: profile.unwrap_or_else(|| "configuration file".to_string()),
+                        error: format!("{err:#}"),
+                    }),
+                }
+            }
+        }
+
+        IpcMessage::Candidates { monitors, limit } => {
+            check_monitors(wpaperd, &monitors).map(|_| IpcResponse::Candidates {
+                entries: collect_surfaces(wpaperd, monitors)
+                    .into_iter()
+                    .map(|surface| {
+                        let (files, current) = surface
+                            .image_picker
+                            .candidates(&surface.wallpaper_info.path);
+                        let limit = limit.unwrap_or(files.len());
+                        let candidates = Candidates {
+                            paths: files.iter().take(limit).cloned().collect(),
+                            total: files.len(),
+                            current,
+                        };
+                        (surface.name(), candidates)
+                    })
+                    .collect(),
+            })
+        }
+
+        IpcMessage::Status { monitors, watch } => check_monitors(wpaperd, &monitors).map(|_| {
+            let entries = status_entries(wpaperd, &monitors);
+            if watch {
+                watcher = Some((monitors, entries.clone()));
+            }
+            IpcResponse::Status { entries }
+        }),
     };
 
+    if let Some(monitors) = preview {
+        wpaperd.preview_clients.push(PreviewClient {
+            stream: ustream,
+            monitors,
+        });
+        return Ok(());
+    }
+    if let (Ok(_), Some((received, expected, request))) = (&resp, pixels) {
+        pixels_reader.read(ustream, received, expected, request);
+        return Ok(());
+    }
+    if let (Ok(_), Some((image, path, monitor))) = (&resp, screenshot) {
+        std::thread::spawn(move || {
+            let resp: Result<IpcResponse, IpcError> = write_screenshot(&image, &path)
+                .map(|_| IpcResponse::Ok)
+                .map_err(|err| IpcError::ScreenshotFailed {
+                    monitor,
+                    error: format!("{err:#}"),
+                });
+            let mut stream = ustream;
+            // The client might have stopped waiting, there is nobody to tell
+            if let Err(err) = stream.write_all(&serde_json::to_vec(&resp).unwrap()) {
+                warn!("unable to write the response to the IPC client: {err}");
+            }
+        });
+        return Ok(());
+    }
+
     let mut stream = BufWriter::new(ustream);
     stream
         .write_all(&serde_json::to_vec(&resp).unwrap())
         .context("unable to write response to the IPC client")
         .suggestion("Probably the client died, try running it again")?;
 
+    if let Some((monitors, entries)) = watcher {
+        stream
+            .write_all(b"\n")
+            .and_then(|_| stream.flush())
+            .context("unable to write response to the IPC client")?;
+        let stream = stream.into_inner().map_err(|err| err.into_error())?;
+        // Never block the main loop when writing the updates
+        stream
+            .set_nonblocking(true)
+            .context("unable to set the IPC client socket as non-blocking")?;
+        wpaperd.status_watchers.push(StatusWatcher {
+            stream,
+            monitors,
+            sent: entries.into_iter().collect(),
+            pending: Vec::new(),
+        });
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Shutdown, time::Instant};
+
+    use super::*;
+
+    #[test]
+    fn test_pixels_size() {
+        assert_eq!(pixels_size(1, 1).unwrap(), 4);
+        assert_eq!(
+            pixels_size(MAX_PIXELS_SIZE, MAX_PIXELS_SIZE).unwrap(),
+            MAX_PIXELS_SIZE as u64 * MAX_PIXELS_SIZE as u64 * 4
+        );
+        for (width, height) in [
+            (0, 1),
+            (1, 0),
+            (MAX_PIXELS_SIZE + 1, 1),
+            (1, MAX_PIXELS_SIZE + 1),
+        ] {
+            assert!(matches!(
+                pixels_size(width, height),
+                Err(IpcError::InvalidPixelsSize { .. })
+            ));
+        }
+    }
+
+    fn read_sent(received: &[u8], sent: &[u8], expected: u64) -> Result<Vec<u8>> {
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+        client.write_all(sent).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        read_pixels(
+            &mut server,
+            received.to_vec(),
+            expected,
+            Instant::now() + PIXELS_TIMEOUT,
+        )
+    }
+
+    #[test]
+    fn test_read_pixels() {
+        assert_eq!(read_sent(&[1, 2], &[3, 4], 4).unwrap(), [1, 2, 3, 4]);
+        assert_eq!(read_sent(&[1, 2], &[3], 4).unwrap(), [1, 2, 3]);
+        // Only one byte more than expected is read
+        assert_eq!(
+            read_sent(&[], &[1, 2, 3, 4, 5, 6], 4).unwrap(),
+            [1, 2, 3, 4, 5]
+        );
+
+        let data: Vec<u8> = (0..3 * PIXELS_CHUNK_SIZE).map(|i| i as u8).collect();
+        assert_eq!(read_sent(&[], &data, data.len() as u64).unwrap(), data);
+    }
+
+    #[test]
+    fn test_read_pixels_timeout() {
+        // The client never shuts down its writing half
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+        client.write_all(&[1, 2]).unwrap();
+        assert!(read_pixels(&mut server, vec![], 4, Instant::now()).is_err());
+        let deadline = Instant::now() + Duration::from_millis(50);
+        assert!(read_pixels(&mut server, vec![], 4, deadline).is_err());
+    }
+}
@@ -0,0 +1,65 @@
+//! Decode the JPEG XL images with jxl-oxide, as the image crate can't read them. The images are
+//! converted to sRGB before being uploaded, so that the wide gamut and HDR ones are not shown
+//! with washed out colors, and only the first frame of the animated ones is shown. Without the
+//! `jxl` feature, the `.jxl` files are skipped when reading the directories.
+
+use std::path::Path;
+
+#[cfg(feature = "jxl")]
+pub use decode::{decode, dimensions};
+
+/// Return true if the file has the extension of a JPEG XL image
+pub fn has_jxl_extension(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("jxl"))
+}
+
+/// Return true if the data starts with the signature of a JPEG XL codestream or container,
+/// for the images fetched from URLs that don't end with the extension
+#[cfg(feature = "jxl")]
+pub fn is_jxl(data: &[u8]) -> bool {
+    const CODESTREAM: &[u8] = &[0xff, 0x0a];
+    const CONTAINER: &[u8] = b"\0\0\0\x0cJXL \r\n\x87\n";
+    data.starts_with(CODESTREAM) || data.starts_with(CONTAINER)
+}
+
+#[cfg(feature = "jxl")]
+mod decode {
+    use std::{fs::File, io::Read, path::Path};
+
+    use color_eyre::{eyre::anyhow, Result};
+    use image::{DynamicImage, ImageBuffer};
+    use jxl_oxide::{EnumColourEncoding, JxlImage, RenderingIntent};
+
+    /// Decode the first frame of the image in sRGB. The orientation stored in the image is
+    /// applied by jxl-oxide.
+    pub fn decode(reader: impl Read) -> Result<DynamicImage> {
+        let mut image = JxlImage::builder()
+            .read(reader)
+            .map_err(|err| anyhow!("decoding the JPEG XL image: {err}"))?;
+        image.request_color_encoding(EnumColourEncoding::srgb(RenderingIntent::Relative));
+        let frame = image
+            .render_frame(0)
+            .map_err(|err| anyhow!("rendering the JPEG XL image: {err}"))?;
+        let mut stream = frame.stream();
+        let (width, height, channels) = (stream.width(), stream.height(), stream.channels());
+        let mut data = vec![0u8; width as usize * height as usize * channels as usize];
+        stream.write_to_buffer(&mut data);
+        match channels {
+            1 => ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma8),
+            2 => ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLumaA8),
+            3 => ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb8),
+            4 => ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba8),
+            _ => None,
+        }
+        .ok_or_else(|| anyhow!("unsupported JPEG XL image with {channels} channels"))
+    }
+
+    /// Return the width and height of the image once oriented, without rendering it
+    pub fn dimensions(path: &Path) -> Result<(u32, u32)> {
+        let image = JxlImage::builder()
+            .read(File::open(path)?)
+            .map_err(|err| anyhow!("reading the JPEG XL header: {err}"))?;
+        Ok((image.width(), image.height()))
+    }
+}
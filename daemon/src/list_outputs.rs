@@ -0,0 +1,163 @@
+//! Print the displays known to the compositor, to help writing the configuration.
+//! Only the outputs are bound, no surface is created.
+
+use color_eyre::{
+    eyre::{Context, ContextCompat},
+    Result, Section,
+};
+use serde::Serialize;
+use smithay_client_toolkit::{
+    delegate_output, delegate_registry,
+    output::{OutputHandler, OutputInfo, OutputState},
+    reexports::client::{
+        globals::registry_queue_init,
+        protocol::wl_output::{Transform, WlOutput},
+        Connection, QueueHandle,
+    },
+    registry::{ProvidesRegistryState, RegistryState},
+    registry_handlers,
+};
+
+use crate::display_info::serial;
+
+struct OutputLister {
+    registry_state: RegistryState,
+    output_state: OutputState,
+}
+
+#[derive(Serialize)]
+struct Output {
+    name: String,
+    description: String,
+    serial: Option<String>,
+    width: i32,
+    height: i32,
+    /// Logical position, if the compositor supports xdg-output
+    position: Option<(i32, i32)>,
+    scale: i32,
+    transform: &'static str,
+}
+
+impl From<OutputInfo> for Output {
+    fn from(info: OutputInfo) -> Self {
+        let (width, height) = info
+            .modes
+            .iter()
+            .find(|mode| mode.current)
+            .map(|mode| mode.dimensions)
+            .unwrap_or_default();
+        Self {
+            serial: serial(&info),
+            name: info.name.unwrap_or_default(),
+            description: info.description.unwrap_or_default(),
+            width,
+            height,
+            position: info.logical_position,
+            scale: info.scale_factor,
+            transform: transform_name(info.transform),
+        }
+    }
+}
+
+fn transform_name(transform: Transform) -> &'static str {
+    match transform {
+        Transform::Normal => "normal",
+        Transform::_90 => "90",
+        Transform::_180 => "180",
+        Transform::_270 => "270",
+        Transform::Flipped => "flipped",
+        Transform::Flipped90 => "flipped-90",
+        Transform::Flipped180 => "flipped-180",
+        Transform::Flipped270 => "flipped-270",
+        _ => "unknown",
+    }
+}
+
+/// Connect to the compositor and return the information about its displays
+pub fn outputs() -> Result<Vec<OutputInfo>> {
+    let conn = Connection::connect_to_env()
+        .context("connecting to wayland")
+        .suggestion("Are you running a wayland compositor?")?;
+    let (globals, mut event_queue) =
+        registry_queue_init(&conn).context("initializing the wayland registry queue")?;
+    let qh = event_queue.handle();
+
+    let mut lister = OutputLister {
+        registry_state: RegistryState::new(&globals),
+        output_state: OutputState::new(&globals, &qh),
+    };
+    // The first roundtrip receives the outputs, the second one their xdg-output information
+    for _ in 0..2 {
+        event_queue
+            .roundtrip(&mut lister)
+            .context("receiving the outputs from the compositor")?;
+    }
+
+    lister
+        .output_state
+        .outputs()
+        .map(|output| {
+            lister
+                .output_state
+                .info(&output)
+                .context("could not get info about output")
+        })
+        .collect()
+}
+
+/// Connect to the compositor, print the displays and return
+pub fn list_outputs(json: bool) -> Result<()> {
+    let outputs: Vec<Output> = outputs()?.into_iter().map(Output::from).collect();
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string(&outputs).expect("json encoding to work")
+        );
+    } else {
+        for output in outputs {
+            let serial = output
+                .serial
+                .map(|serial| format!(", serial {serial}"))
+                .unwrap_or_default();
+            let position = output
+                .position
+                .map(|(x, y)| format!(", position {x},{y}"))
+                .unwrap_or_default();
+            println!(
+                "{}: {}{serial}, {}x{}{position}, scale {}, transform {}",
+                output.name,
+                output.description,
+                output.width,
+                output.height,
+                output.scale,
+                output.transform
+            );
+        }
+    }
+
+    Ok(())
+}
+
+impl OutputHandler for OutputLister {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.output_state
+    }
+
+    fn new_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {}
+
+    fn update_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {}
+
+    fn output_destroyed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: WlOutput) {
+    }
+}
+
+delegate_output!(OutputLister);
+delegate_registry!(OutputLister);
+
+impl ProvidesRegistryState for OutputLister {
+    fn registry(&mut self) -> &mut RegistryState {
+        &mut self.registry_state
+    }
+    registry_handlers![OutputState];
+}
@@ -1,15 +1,37 @@
+mod battery;
+mod benchmark;
+mod condition;
 mod config;
+mod cursor;
+mod daily;
 mod display_info;
+mod do_not_disturb;
+mod favorites;
 mod filelist_cache;
+mod fractional_scale;
+mod http;
+mod idle;
 mod image_loader;
 mod image_picker;
+mod inspect;
 mod ipc_server;
+mod jxl;
+mod list_outputs;
+mod night_light;
 mod opts;
+mod output_power;
+mod presentation;
+mod ranking;
 mod render;
+mod sidecar;
 mod socket;
+mod state;
 mod surface;
+mod toplevel;
 mod wallpaper_groups;
 mod wallpaper_info;
+mod weekdays;
+mod workspace;
 mod wpaperd;
 
 extern crate khronos_egl as egl;
@@ -19,12 +41,14 @@ use std::{
     fs::File,
     io::Write,
     os::fd::FromRawFd,
+    path::PathBuf,
     process::exit,
     rc::Rc,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
+    time::{Duration, Instant},
 };
 
 use clap::Parser;
@@ -32,33 +56,74 @@ use color_eyre::{
     eyre::{anyhow, ContextCompat, WrapErr},
     Result, Section,
 };
+use condition::ConditionRunner;
 use config::Config;
 use egl::API as egl;
+use favorites::FavoritesWatcher;
 use filelist_cache::FilelistCache;
-use flexi_logger::{Duplicate, FileSpec, Logger};
+use flexi_logger::{Duplicate, FileSpec, Logger, LoggerHandle};
 use hotwatch::Hotwatch;
-use ipc_server::{handle_message, listen_on_ipc_socket};
-use log::error;
+use http::HttpCache;
+use image_loader::ImageLoader;
+use ipc_server::{
+    finish_previews, handle_message, listen_on_ipc_socket, publish_status, PixelsReader,
+};
+use log::{error, warn};
 use nix::unistd::fork;
 use opts::Opts;
+use render::egl_display_on_device;
+#[cfg(feature = "video")]
+use render::VideoFrames;
 use smithay_client_toolkit::reexports::{
     calloop,
     calloop_wayland_source::WaylandSource,
     client::{globals::registry_queue_init, Connection, Proxy},
 };
-use wallpaper_groups::WallpaperGroups;
+use state::State;
 use wallpaper_info::Sorting;
 use wpaperd_ipc::socket_path;
 use xdg::BaseDirectories;
 
-use crate::wpaperd::Wpaperd;
+use crate::wpaperd::{StartupOptions, Wpaperd};
 
 use tikv_jemallocator::Jemalloc;
 
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
 
-fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
+/// How often the displays are checked while waiting to draw them together
+const FIRST_DRAW_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// How often to retry writing the status to the IPC clients that are not reading it
+const STATUS_FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Return the initialized EGL display, on the DRM device if one has been chosen
+fn init_egl_display(
+    native_display: *mut std::ffi::c_void,
+    drm_device: Option<&std::path::Path>,
+) -> Result<egl::Display> {
+    // we use the OpenGL ES API because it's more widely supported
+    // and it's used by wlroots
+    egl.bind_api(egl::OPENGL_ES_API)
+        .context("unable to select OpenGL API")?;
+
+    let egl_display = drm_device.and_then(|device| {
+        unsafe { egl_display_on_device(native_display, device) }
+            .map_err(|err| warn!("{err:?}\nFalling back to the default device"))
+            .ok()
+    });
+    let egl_display = match egl_display {
+        Some(egl_display) => egl_display,
+        None => unsafe {
+            egl.get_display(native_display)
+                .context("getting the display from the WlDisplay")?
+        },
+    };
+    egl.initialize(egl_display)
+        .context("initializing the egl display")?;
+    Ok(egl_display)
+}
+
+fn run(opts: Opts, xdg_dirs: BaseDirectories, logger: LoggerHandle) -> Result<()> {
     // Path passed from the CLI or the wpaperd.toml file has precedence
     let config_file = if let Some(config) = opts.config {
         config
@@ -76,34 +141,39 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
         }
     };
 
+    // The system configurations are read first, from the least important directory of
+    // XDG_CONFIG_DIRS, and the configuration of the user overrides them
+    let system_config_files: Vec<PathBuf> = xdg_dirs
+        .get_config_dirs()
+        .into_iter()
+        .rev()
+        .map(|dir| dir.join("wpaperd").join("config.toml"))
+        .filter(|file| file.exists())
+        .collect();
+
     let reloaded = Arc::new(AtomicBool::new(false));
     // Do not stop when the configuration is invalid, we can always reload it at runtime
-    let mut config = match Config::new_from_path(&config_file) {
+    let mut config = match Config::new_from_paths(&system_config_files, &config_file) {
         Ok(config) => config,
         Err(err) => {
             error!("{err:?}");
             let mut config = Config::default();
-            config.path = config_file;
+            config.path = config_file.clone();
+            config.main_path = config_file;
+            config.system_paths = system_config_files;
             config
         }
     };
     config.reloaded = Some(reloaded);
 
-    // we use the OpenGL ES API because it's more widely supported
-    // and it's used by wlroots
-    egl.bind_api(egl::OPENGL_ES_API)
-        .context("unable to select OpenGL API")?;
-
     let conn = Connection::connect_to_env()
         .context("connecting to wayland")
         .suggestion("Are you running a wayland compositor?")?;
 
-    let egl_display = unsafe {
-        egl.get_display(conn.display().id().as_ptr() as *mut std::ffi::c_void)
-            .context("getting the display from the WlDisplay")?
-    };
-    egl.initialize(egl_display)
-        .context("initializing the egl display")?;
+    let native_display = conn.display().id().as_ptr() as *mut std::ffi::c_void;
+    let egl_display = init_egl_display(native_display, opts.drm_device.as_deref())
+        .map_err(|err| warn!("{err:?}\nDrawing the wallpapers in software"))
+        .ok();
 
     let (globals, event_queue) =
         registry_queue_init(&conn).context("initializing the wayland registry queue")?;
@@ -115,42 +185,115 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
         .insert(event_loop.handle())
         .map_err(|e| anyhow!("insterting the wayland source into the event loop: {e}"))?;
 
-    let (ping, ping_source) =
+    let (config_ping, ping_source) =
         calloop::ping::make_ping().context("Unable to create a calloop::ping::Ping")?;
     event_loop
         .handle()
         .insert_source(ping_source, |_, _, _| {})
         .map_err(|e| anyhow!("inserting the hotwatch event listener in the event loop: {e}"))?;
 
+    let oneshot = opts.oneshot;
     let mut hotwatch = Hotwatch::new().context("hotwatch failed to initialize")?;
-    config.listen_to_changes(&mut hotwatch, ping)?;
+    // The wallpapers are never changed, there is no point in reloading the configuration
+    if !oneshot {
+        config.listen_to_changes(&mut hotwatch, config_ping.clone())?;
+    }
+
+    let (favorites_ping, ping_source) =
+        calloop::ping::make_ping().context("Unable to create a calloop::ping::Ping")?;
+    event_loop
+        .handle()
+        .insert_source(ping_source, |_, _, _| {})
+        .map_err(|e| anyhow!("inserting the favorites event listener in the event loop: {e}"))?;
+    let mut favorites_watcher = FavoritesWatcher::new(favorites_ping);
+    if !oneshot {
+        favorites_watcher.update(config.favorites(), &mut hotwatch);
+    }
 
-    let (ping, filelist_cache) =
-        FilelistCache::new(config.paths(), &mut hotwatch, event_loop.handle())?;
+    let (ping, filelist_cache) = FilelistCache::new(
+        config.paths(),
+        config.commands(),
+        config.pools(),
+        config.fallbacks(),
+        &mut hotwatch,
+        event_loop.handle(),
+        qh.clone(),
+    )?;
     let filelist_cache = Rc::new(RefCell::new(filelist_cache));
 
-    let groups = Rc::new(RefCell::new(WallpaperGroups::new()));
+    let state = if opts.persist_state {
+        let state_file = xdg_dirs
+            .place_state_file("state.json")
+            .context("unable to identify state file state.json")?;
+        Some(Rc::new(RefCell::new(State::load(state_file))))
+    } else {
+        None
+    };
+
+    // The images fetched from the network are kept across restarts
+    let http_cache = HttpCache::new(
+        xdg_dirs
+            .place_cache_file("http")
+            .context("unable to identify the cache directory")?,
+    );
+
+    let image_loader = Rc::new(RefCell::new(ImageLoader::new(
+        http_cache,
+        event_loop.handle(),
+        qh.clone(),
+    )?));
+
+    let condition_runner = ConditionRunner::new(event_loop.handle(), qh.clone())?;
 
     let mut wpaperd = Wpaperd::new(
         &qh,
         &globals,
         config,
-        egl_display,
         filelist_cache.clone(),
-        groups,
+        image_loader,
+        condition_runner,
+        StartupOptions {
+            egl_display,
+            state,
+            output_filter: opts.outputs,
+            unique_wallpapers: opts.unique_wallpapers,
+            suspend_when_off: opts.suspend_when_off,
+            fractional_scale: !opts.no_fractional_scale,
+            logger,
+            #[cfg(feature = "video")]
+            video_frames: VideoFrames::new(event_loop.handle(), qh.clone())?,
+        },
     )?;
 
-    // Start listening on the IPC socket
-    let socket = listen_on_ipc_socket(&socket_path()?).context("spawning the ipc socket")?;
-
-    // Add source to calloop loop.
-    event_loop
-        .handle()
-        .insert_source(socket, |stream, _, wpaperd| {
-            if let Err(err) = handle_message(stream, qh.clone(), wpaperd) {
-                error!("{:?}", err);
-            }
-        })?;
+    // A oneshot run only draws the first wallpapers, it still has to stay connected since the
+    // compositor removes the surfaces of the clients that exit
+    if !oneshot {
+        battery::insert_battery_timer(&event_loop.handle())?;
+        do_not_disturb::insert_do_not_disturb_timer(&event_loop.handle())?;
+        night_light::insert_night_light_timer(&event_loop.handle(), qh.clone())?;
+        weekdays::insert_weekdays_timer(&event_loop.handle(), qh.clone())?;
+
+        // Start listening on the IPC socket
+        let socket_path = match opts.socket {
+            Some(socket_path) => socket_path,
+            None => socket_path()?,
+        };
+        let socket = listen_on_ipc_socket(&socket_path).context("spawning the ipc socket")?;
+
+        // Add source to calloop loop.
+        let ipc_handle = event_loop.handle();
+        let ipc_qh = qh.clone();
+        let pixels_reader = PixelsReader::new(event_loop.handle(), qh.clone())?;
+        event_loop
+            .handle()
+            .insert_source(socket, move |stream, _, wpaperd| {
+                if let Err(err) =
+                    handle_message(stream, ipc_qh.clone(), &ipc_handle, &pixels_reader, wpaperd)
+                {
+                    error!("{:?}", err);
+                }
+            })?;
+    }
 
     if let Some(notify) = opts.notify {
         let mut f = unsafe { File::from_raw_fd(notify as i32) };
@@ -160,15 +303,37 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
         }
     }
 
+    // Hold the first draw of the displays until all of them are ready, or until the deadline
+    let first_draw_deadline = opts
+        .sync_first_draw
+        .then(|| Instant::now() + Duration::from_millis(opts.sync_first_draw_timeout));
+
+    let mut output_filter_checked = false;
     loop {
+        // A profile has been switched to through IPC, it replaces the whole configuration
+        let profile_switched = match wpaperd.pending_config.take() {
+            Some(config) => {
+                wpaperd.config.stop_listening(&mut hotwatch);
+                wpaperd.config = config;
+                if let Err(err) = wpaperd
+                    .config
+                    .listen_to_changes(&mut hotwatch, config_ping.clone())
+                {
+                    error!("{err:?}");
+                }
+                true
+            }
+            None => false,
+        };
         // If the config has been modified, this value will return true
-        if wpaperd
-            .config
-            .reloaded
-            .as_ref()
-            .unwrap()
-            .load(Ordering::Acquire)
-            && wpaperd.config.update()
+        if profile_switched
+            || (wpaperd
+                .config
+                .reloaded
+                .as_ref()
+                .unwrap()
+                .load(Ordering::Acquire)
+                && wpaperd.config.update())
         {
             // Update the filelist cache, keep it up to date
             // We need to call this before because updating the surfaces
@@ -178,11 +343,43 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
                 &mut hotwatch,
                 ping.clone(),
             );
+            filelist_cache
+                .borrow_mut()
+                .update_commands(wpaperd.config.commands(), ping.clone());
+            filelist_cache
+                .borrow_mut()
+                .update_pools(wpaperd.config.pools());
+            filelist_cache
+                .borrow_mut()
+                .update_fallbacks(wpaperd.config.fallbacks());
 
             // Read the config, update the paths in the surfaces
             wpaperd.update_surfaces(event_loop.handle(), &qh);
+            // The displays can use other favorites files
+            wpaperd.rewatch_favorites = true;
+        }
+
+        if !oneshot && std::mem::take(&mut wpaperd.rewatch_favorites) {
+            favorites_watcher.update(wpaperd.config.favorites(), &mut hotwatch);
+        }
+        if favorites_watcher.take_changed() {
+            wpaperd.refresh_favorites(&qh);
+            // The editors that replace the file have to be watched again
+            favorites_watcher.update(wpaperd.config.favorites(), &mut hotwatch);
         }
 
+        // The mirrors pick up the images loaded by the other displays since the last iteration
+        wpaperd.update_mirrors(&qh);
+
+        let hold_first_draw = match first_draw_deadline {
+            Some(deadline) if Instant::now() < deadline => !wpaperd
+                .surfaces
+                .iter_mut()
+                .filter(|surface| surface.is_configured() && !surface.has_been_drawn())
+                .all(|surface| surface.load_first_wallpaper(&qh)),
+            _ => false,
+        };
+
         // Due to how LayerSurface works, we cannot attach the egl window right away.
         // The LayerSurface needs to have received a configure callback first.
         // Afterwards we need to draw for the first time and then add a timer if needed.
@@ -195,15 +392,22 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
 
             // This is only true once per surface at startup (or when a new display gets connected)
             if !surface.has_been_drawn() {
-                surface.add_timer(None, &event_loop.handle(), qh.clone());
+                if hold_first_draw || surface.wait_startup_delay(&event_loop.handle()) {
+                    return;
+                }
+                if !oneshot {
+                    surface.add_timer(None, &event_loop.handle(), qh.clone());
+                }
                 if let Err(err) = surface.draw(&qh, None) {
-                    error!("{err:?}");
+                    error!("{}: {err:?}", surface.name());
                 };
                 surface.drawn();
-            } else {
+            } else if !oneshot {
                 // If the surface has already been drawn for the first time, then handle pausing/resuming
                 // the automatic wallpaper sequence.
                 surface.handle_pause_state(&event_loop.handle(), qh.clone());
+                surface.handle_unavailable_list(&event_loop.handle(), qh.clone());
+                surface.handle_workspace_change(&event_loop.handle(), qh.clone());
                 if matches!(
                     surface.wallpaper_info.sorting,
                     Some(Sorting::GroupedRandom { .. })
@@ -211,14 +415,37 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
                     // surface.image_picker.handle_grouped_sorting();
                 }
             };
+            surface.handle_transition_hold(&event_loop.handle(), qh.clone());
+            surface.handle_lost_context(&event_loop.handle(), qh.clone());
 
             #[cfg(debug_assertions)]
             wpaperd.image_loader.borrow_mut().check_lingering_threads();
         });
 
+        let timeout = if hold_first_draw {
+            // The images are loaded in other threads, check them again shortly
+            Some(FIRST_DRAW_POLL_INTERVAL)
+        } else if wpaperd
+            .status_watchers
+            .iter()
+            .any(|watcher| watcher.is_pending())
+        {
+            Some(STATUS_FLUSH_INTERVAL)
+        } else {
+            None
+        };
         event_loop
-            .dispatch(None, &mut wpaperd)
+            .dispatch(timeout, &mut wpaperd)
             .context("dispatching the event loop")?;
+
+        publish_status(&mut wpaperd);
+        finish_previews(&mut wpaperd);
+
+        // The displays are only known after the first dispatch
+        if !output_filter_checked {
+            wpaperd.check_output_filter();
+            output_filter_checked = true;
+        }
     }
 }
 
@@ -229,6 +456,30 @@ fn main() -> Result<()> {
 
     let opts = Opts::parse();
 
+    if opts.list_outputs {
+        return list_outputs::list_outputs(opts.json);
+    }
+
+    if let Some(image) = &opts.inspect {
+        return inspect::inspect(
+            image,
+            opts.outputs.first().map(String::as_str),
+            opts.size.as_deref(),
+            opts.mode.as_deref(),
+            opts.offset.as_deref(),
+        );
+    }
+
+    if !opts.benchmark.is_empty() {
+        return benchmark::benchmark(
+            &opts.benchmark,
+            opts.outputs.first().map(String::as_str),
+            opts.transition.as_deref(),
+            opts.transition_time,
+            opts.iterations,
+        );
+    }
+
     let mut logger = Logger::try_with_env_or_str(if opts.verbose { "debug" } else { "info" })?;
 
     if opts.daemon {
@@ -243,9 +494,9 @@ fn main() -> Result<()> {
         logger = logger.duplicate_to_stderr(Duplicate::Warn);
     }
 
-    logger.start()?;
+    let logger = logger.start()?;
 
-    if let Err(err) = run(opts, xdg_dirs) {
+    if let Err(err) = run(opts, xdg_dirs, logger) {
         error!("{err:?}");
         Err(err)
     } else {
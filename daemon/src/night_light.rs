@@ -0,0 +1,105 @@
+//! Warm the colors of the wallpaper in the evening, like the night mode of the displays.
+//! The renderer mixes the wallpaper with the color of the configured temperature, using a
+//! factor that follows the time of the day.
+
+use std::time::Duration;
+
+use color_eyre::{eyre::anyhow, Result};
+use smithay_client_toolkit::reexports::{
+    calloop::{
+        timer::{TimeoutAction, Timer},
+        LoopHandle,
+    },
+    client::QueueHandle,
+};
+
+use crate::wpaperd::Wpaperd;
+
+const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+/// How often the factor is recalculated
+const UPDATE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NightLight {
+    /// Color temperature of the night, in Kelvin
+    pub temperature: u32,
+    /// Seconds from midnight when the colors are back to normal
+    pub sunrise: u32,
+    /// Seconds from midnight when the colors start to warm up
+    pub sunset: u32,
+    /// Time needed to go from the normal colors to the night ones and back
+    pub transition: Duration,
+}
+
+impl NightLight {
+    pub const DEFAULT_SUNRISE: u32 = 7 * 60 * 60;
+    pub const DEFAULT_SUNSET: u32 = 19 * 60 * 60;
+    pub const DEFAULT_TRANSITION: Duration = Duration::from_secs(60 * 60);
+
+    /// Return how much the night color should be applied at the given time, from 0.0 during
+    /// the day to 1.0 during the night. The colors start to change at sunset and they are
+    /// back to normal at sunrise.
+    pub fn factor(&self, seconds_of_day: u32) -> f32 {
+        let night_length = (self.sunrise + SECONDS_PER_DAY - self.sunset) % SECONDS_PER_DAY;
+        let since_sunset = (seconds_of_day + SECONDS_PER_DAY - self.sunset) % SECONDS_PER_DAY;
+        if since_sunset >= night_length {
+            return 0.0;
+        }
+        let until_sunrise = night_length - since_sunset;
+        let transition = self.transition.as_secs_f32();
+        if transition == 0.0 {
+            return 1.0;
+        }
+        (since_sunset.min(until_sunrise) as f32 / transition).min(1.0)
+    }
+
+    /// RGB multiplier of the night temperature, white being 6500K
+    pub fn color(&self) -> [f32; 3] {
+        kelvin_to_rgb(self.temperature)
+    }
+}
+
+/// Approximate the color of a black body at the given temperature, from 1000K to 6500K.
+/// Based on the approximation by Tanner Helland.
+fn kelvin_to_rgb(temperature: u32) -> [f32; 3] {
+    let temperature = temperature.clamp(1000, 6500) as f32 / 100.0;
+    let red = 1.0;
+    let green = (99.4708 * temperature.ln() - 161.1196) / 255.0;
+    let blue = if temperature <= 19.0 {
+        0.0
+    } else {
+        (138.5177 * (temperature - 10.0).ln() - 305.0448) / 255.0
+    };
+    [red, green.clamp(0.0, 1.0), blue.clamp(0.0, 1.0)]
+}
+
+/// Return the seconds elapsed since the local midnight
+pub fn local_seconds_of_day() -> u32 {
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&now, &mut tm).is_null() {
+            return 0;
+        }
+        (tm.tm_hour * 60 * 60 + tm.tm_min * 60 + tm.tm_sec) as u32
+    }
+}
+
+/// Recalculate the night light of the surfaces periodically
+pub fn insert_night_light_timer(
+    handle: &LoopHandle<Wpaperd>,
+    qh: QueueHandle<Wpaperd>,
+) -> Result<()> {
+    handle
+        .insert_source(
+            Timer::from_duration(UPDATE_INTERVAL),
+            move |_deadline, _: &mut (), wpaperd: &mut Wpaperd| {
+                for surface in &mut wpaperd.surfaces {
+                    surface.update_night_light(&qh);
+                }
+                TimeoutAction::ToDuration(UPDATE_INTERVAL)
+            },
+        )
+        .map_err(|err| anyhow!("inserting the night light timer in the event loop: {err}"))?;
+    Ok(())
+}
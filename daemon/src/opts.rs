@@ -30,4 +30,123 @@ pub struct Opts {
         help = "Readiness fd used by wpaperd to signal that it has started correctly"
     )]
     pub notify: Option<u8>,
+    #[clap(
+        long,
+        help = "Save the current wallpaper of each display in XDG_STATE_HOME/wpaperd and restore it at startup"
+    )]
+    pub persist_state: bool,
+    #[clap(
+        long = "output",
+        value_name = "OUTPUT",
+        help = "Only draw the wallpaper on this display (can be passed multiple times)"
+    )]
+    pub outputs: Vec<String>,
+    #[clap(
+        long,
+        help = "Stop drawing while a display is turned off (DPMS); other clients won't be able to control its power mode"
+    )]
+    pub suspend_when_off: bool,
+    #[clap(
+        long,
+        help = "Draw the wallpapers at the integer scale of the displays, even when the compositor supports fractional scaling"
+    )]
+    pub no_fractional_scale: bool,
+    #[clap(
+        long,
+        help = "Avoid showing the same random wallpaper on multiple displays at the same time"
+    )]
+    pub unique_wallpapers: bool,
+    #[clap(
+        long,
+        help = "Wait until all the displays have loaded their first wallpaper to show them together"
+    )]
+    pub sync_first_draw: bool,
+    #[clap(
+        long,
+        value_name = "MILLISECONDS",
+        default_value_t = 2000,
+        help = "Maximum time to wait for the displays when --sync-first-draw is passed"
+    )]
+    pub sync_first_draw_timeout: u64,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Render on this DRM device (e.g. /dev/dri/renderD128) instead of the default one"
+    )]
+    pub drm_device: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Listen to wpaperctl on this socket, prefixed by @ for an abstract socket (WPAPERD_SOCKET or XDG_RUNTIME_DIR/wpaperd-$WAYLAND_DISPLAY.sock by default)"
+    )]
+    pub socket: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "Draw a single wallpaper on each display, without changing it, reloading the configuration or listening to wpaperctl"
+    )]
+    pub oneshot: bool,
+    #[clap(
+        long,
+        help = "Print the name, description, resolution, scale and transform of the displays, then exit"
+    )]
+    pub list_outputs: bool,
+    #[clap(
+        long,
+        requires = "list_outputs",
+        help = "Print the displays listed by --list-outputs as JSON"
+    )]
+    pub json: bool,
+    #[clap(
+        long,
+        value_name = "IMAGE",
+        help = "Print how the image is scaled and cropped on the display passed to --output, then exit"
+    )]
+    pub inspect: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "WIDTHxHEIGHT",
+        requires = "inspect",
+        conflicts_with = "outputs",
+        help = "Inspect the image on a display of this size instead of a connected one"
+    )]
+    pub size: Option<String>,
+    #[clap(
+        long,
+        requires = "inspect",
+        help = "Background mode used by --inspect (center by default)"
+    )]
+    pub mode: Option<String>,
+    #[clap(
+        long,
+        requires = "inspect",
+        help = "Offset used by --inspect, as a number or as the side to align the image to"
+    )]
+    pub offset: Option<String>,
+    #[clap(
+        long,
+        num_args = 2,
+        value_names = ["IMAGE", "IMAGE"],
+        conflicts_with_all = ["inspect", "list_outputs"],
+        help = "Play a transition between the two images on the display passed to --output (the first one by default), print the frame times, then exit"
+    )]
+    pub benchmark: Vec<PathBuf>,
+    #[clap(
+        long,
+        requires = "benchmark",
+        help = "Transition played by --benchmark, written like in the configuration (fade by default)"
+    )]
+    pub transition: Option<String>,
+    #[clap(
+        long,
+        value_name = "MILLISECONDS",
+        requires = "benchmark",
+        help = "Duration of the transition played by --benchmark (the default of the transition by default)"
+    )]
+    pub transition_time: Option<u32>,
+    #[clap(
+        long,
+        requires = "benchmark",
+        help = "Number of transitions played by --benchmark (10 by default)"
+    )]
+    pub iterations: Option<u32>,
 }
@@ -0,0 +1,103 @@
+//! Follow the power mode (DPMS) of the displays using the wlr-output-power-management
+//! protocol, to stop drawing while a display is turned off.
+
+use smithay_client_toolkit::reexports::client::{
+    globals::GlobalList, protocol::wl_output::WlOutput, Connection, Dispatch, QueueHandle, WEnum,
+};
+use smithay_client_toolkit::reexports::protocols_wlr::output_power_management::v1::client::{
+    zwlr_output_power_manager_v1::{self, ZwlrOutputPowerManagerV1},
+    zwlr_output_power_v1::{self, ZwlrOutputPowerV1},
+};
+
+use crate::wpaperd::Wpaperd;
+
+pub struct OutputPowerState {
+    manager: Option<ZwlrOutputPowerManagerV1>,
+    powers: Vec<(WlOutput, ZwlrOutputPowerV1)>,
+}
+
+impl OutputPowerState {
+    /// The protocol is optional, when the compositor doesn't support it (or it has not been
+    /// enabled) the displays are always considered turned on.
+    /// Only one client at a time can control the power mode of an output, hence this is opt-in:
+    /// tools like wlopm would fail while wpaperd is running.
+    pub fn bind(globals: &GlobalList, qh: &QueueHandle<Wpaperd>, enabled: bool) -> Self {
+        Self {
+            manager: if enabled {
+                globals.bind(qh, 1..=1, ()).ok()
+            } else {
+                None
+            },
+            powers: Vec::new(),
+        }
+    }
+
+    /// Start receiving the power mode of the output
+    pub fn add(&mut self, output: &WlOutput, qh: &QueueHandle<Wpaperd>) {
+        if let Some(manager) = &self.manager {
+            let power = manager.get_output_power(output, qh, output.clone());
+            self.powers.push((output.clone(), power));
+        }
+    }
+
+    pub fn remove(&mut self, output: &WlOutput) {
+        self.powers.retain(|(o, power)| {
+            if o == output {
+                power.destroy();
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+impl Dispatch<ZwlrOutputPowerManagerV1, ()> for Wpaperd {
+    fn event(
+        _state: &mut Self,
+        _manager: &ZwlrOutputPowerManagerV1,
+        _event: zwlr_output_power_manager_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // The manager doesn't have any event
+    }
+}
+
+impl Dispatch<ZwlrOutputPowerV1, WlOutput> for Wpaperd {
+    fn event(
+        state: &mut Self,
+        _power: &ZwlrOutputPowerV1,
+        event: zwlr_output_power_v1::Event,
+        output: &WlOutput,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_output_power_v1::Event::Mode { mode } => {
+                let powered = !matches!(mode, WEnum::Value(zwlr_output_power_v1::Mode::Off));
+                if let Some(surface) = state
+                    .surfaces
+                    .iter_mut()
+                    .find(|surface| surface.wl_output() == output)
+                {
+                    surface.set_powered(powered, qh);
+                }
+            }
+            // The object is now inert, e.g. the output has been removed or another
+            // client is controlling its power mode
+            zwlr_output_power_v1::Event::Failed => {
+                state.output_power_state.remove(output);
+                if let Some(surface) = state
+                    .surfaces
+                    .iter_mut()
+                    .find(|surface| surface.wl_output() == output)
+                {
+                    surface.set_powered(true, qh);
+                }
+            }
+            _ => {}
+        }
+    }
+}
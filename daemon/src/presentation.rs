@@ -0,0 +1,132 @@
+//! Pace the transitions with the presentation-time protocol. The time of the frame callbacks
+//! tells when the compositor asked for a frame, which can be a few milliseconds away from when
+//! it is shown, and that jitter makes the transitions stutter. When the compositor reports when
+//! each frame is presented and its refresh rate, the progress of a transition is computed from
+//! when the frame being drawn is expected to be shown instead.
+
+use std::{cell::Cell, rc::Rc, time::Duration};
+
+use smithay_client_toolkit::reexports::client::{
+    globals::GlobalList, protocol::wl_surface::WlSurface, Connection, Dispatch, QueueHandle,
+};
+use smithay_client_toolkit::reexports::protocols::wp::presentation_time::client::{
+    wp_presentation::{self, WpPresentation},
+    wp_presentation_feedback::{self, WpPresentationFeedback},
+};
+
+use crate::wpaperd::Wpaperd;
+
+/// Older presentations are not used to predict the next one, the refresh rate could have
+/// drifted in the meantime
+const MAX_PREDICTION: Duration = Duration::from_secs(1);
+
+/// Shared between the surfaces, which request the feedback of their frames
+#[derive(Clone)]
+pub struct Presentation {
+    presentation: WpPresentation,
+    /// Clock used by the compositor for the presentation timestamps, sent right after binding
+    clock_id: Rc<Cell<Option<u32>>>,
+}
+
+impl Presentation {
+    pub fn bind(globals: &GlobalList, qh: &QueueHandle<Wpaperd>) -> Option<Self> {
+        globals.bind(qh, 1..=1, ()).ok().map(|presentation| Self {
+            presentation,
+            clock_id: Rc::new(Cell::new(None)),
+        })
+    }
+
+    /// Ask to be told when the next commit of the surface is presented
+    pub fn feedback(&self, wl_surface: &WlSurface, qh: &QueueHandle<Wpaperd>) {
+        self.presentation
+            .feedback(wl_surface, qh, wl_surface.clone());
+    }
+
+    /// Current time of the presentation clock in nanoseconds, None until the compositor has
+    /// sent which clock it uses
+    pub fn now(&self) -> Option<u64> {
+        let clock_id = self.clock_id.get()?;
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        if unsafe { libc::clock_gettime(clock_id as libc::clockid_t, &mut ts) } != 0 {
+            return None;
+        }
+        Some(ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64)
+    }
+}
+
+/// Last frame presented on a surface
+#[derive(Clone, Copy, Debug)]
+pub struct Presented {
+    /// Time of the presentation in nanoseconds
+    pub time: u64,
+    /// Duration of a refresh cycle of the display in nanoseconds, 0 when unknown
+    pub refresh: u32,
+}
+
+/// Return when the frame drawn at `now` will be presented: the first refresh cycle after
+/// `now`, counted from the last presented frame. All the times are in nanoseconds.
+pub fn next_presentation(now: u64, presented: Option<Presented>) -> u64 {
+    match presented {
+        Some(Presented { time, refresh })
+            if refresh != 0 && now >= time && now - time < MAX_PREDICTION.as_nanos() as u64 =>
+        {
+            let refresh = refresh as u64;
+            time + ((now - time) / refresh + 1) * refresh
+        }
+        _ => now,
+    }
+}
+
+impl Dispatch<WpPresentation, ()> for Wpaperd {
+    fn event(
+        state: &mut Self,
+        _presentation: &WpPresentation,
+        event: wp_presentation::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let wp_presentation::Event::ClockId { clk_id } = event {
+            if let Some(presentation) = &state.presentation {
+                presentation.clock_id.set(Some(clk_id));
+            }
+        }
+    }
+}
+
+impl Dispatch<WpPresentationFeedback, WlSurface> for Wpaperd {
+    fn event(
+        state: &mut Self,
+        _feedback: &WpPresentationFeedback,
+        event: wp_presentation_feedback::Event,
+        wl_surface: &WlSurface,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // The discarded frames have never been shown, keep predicting from the last one that was
+        let wp_presentation_feedback::Event::Presented {
+            tv_sec_hi,
+            tv_sec_lo,
+            tv_nsec,
+            refresh,
+            ..
+        } = event
+        else {
+            return;
+        };
+        let secs = (u64::from(tv_sec_hi) << 32) | u64::from(tv_sec_lo);
+        if let Some(surface) = state
+            .surfaces
+            .iter_mut()
+            .find(|surface| surface.wl_surface() == wl_surface)
+        {
+            surface.set_presented(Presented {
+                time: secs * 1_000_000_000 + u64::from(tv_nsec),
+                refresh,
+            });
+        }
+    }
+}
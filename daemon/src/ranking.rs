@@ -0,0 +1,171 @@
+//! Weights of the wallpapers picked by the `random` sorting, read from a file that another
+//! program can rewrite at any time. Each line has a weight followed by the path of an image,
+//! e.g. `2.5 /home/user/Pictures/sunset.jpg`; empty lines and lines starting with `#` are
+//! ignored. The images that are not listed have a weight of 1.0, and the relative paths are
+//! relative to the directory of the file.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use color_eyre::{
+    eyre::{anyhow, Context},
+    Result,
+};
+use log::{info, warn};
+
+/// Weight of the images missing from the file
+pub const DEFAULT_WEIGHT: f32 = 1.0;
+
+#[derive(Debug)]
+pub struct Ranking {
+    path: PathBuf,
+    /// Modification time of the file when it was last read
+    modified: Option<SystemTime>,
+    weights: HashMap<PathBuf, f32>,
+    /// The file could not be read the last time, so that the error is only logged once
+    failed: bool,
+}
+
+impl Ranking {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            modified: None,
+            weights: HashMap::new(),
+            failed: false,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_weights(weights: HashMap<PathBuf, f32>) -> Self {
+        Self {
+            weights,
+            ..Self::new(PathBuf::new())
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read the file again if it has been modified since the last time. The previous weights
+    /// are kept when it can't be read.
+    pub fn refresh(&mut self, name: &str) {
+        let modified = match fs::metadata(&self.path).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified,
+            Err(err) => {
+                if !std::mem::replace(&mut self.failed, true) {
+                    warn!("{name}: unable to read the ranking {:?}: {err}", self.path);
+                }
+                return;
+            }
+        };
+        if self.modified == Some(modified) {
+            return;
+        }
+        match fs::read_to_string(&self.path)
+            .with_context(|| format!("unable to read the ranking {:?}", self.path))
+            .map(|content| parse(&content, self.path.parent().unwrap_or(Path::new(""))))
+        {
+            Ok((weights, errors)) => {
+                for err in errors {
+                    warn!("{name}: {:?}: {err}", self.path);
+                }
+                info!(
+                    "{name}: read {} weights from the ranking {:?}",
+                    weights.len(),
+                    self.path
+                );
+                self.weights = weights;
+                self.modified = Some(modified);
+                self.failed = false;
+            }
+            Err(err) => {
+                if !std::mem::replace(&mut self.failed, true) {
+                    warn!("{name}: {err:?}");
+                }
+            }
+        }
+    }
+
+    pub fn weight(&self, path: &Path) -> f32 {
+        self.weights.get(path).copied().unwrap_or(DEFAULT_WEIGHT)
+    }
+}
+
+/// Return the weights listed in `content` and the errors of the invalid lines, which are skipped
+fn parse(content: &str, dir: &Path) -> (HashMap<PathBuf, f32>, Vec<color_eyre::Report>) {
+    let mut weights = HashMap::new();
+    let mut errors = Vec::new();
+    let lines = content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+    for (number, line) in lines {
+        match parse_line(line) {
+            Ok((weight, path)) => {
+                weights.insert(dir.join(path), weight);
+            }
+            Err(err) => errors.push(err.wrap_err(format!("line {number} is not valid"))),
+        }
+    }
+    (weights, errors)
+}
+
+fn parse_line(line: &str) -> Result<(f32, &Path)> {
+    let (weight, path) = line
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| anyhow!("expected a weight followed by a path"))?;
+    let weight: f32 = weight
+        .parse()
+        .with_context(|| format!("{weight:?} is not a number"))?;
+    if !weight.is_finite() || weight < 0.0 {
+        return Err(anyhow!("the weight {weight} is not a number of 0 or more"));
+    }
+    Ok((weight, Path::new(path.trim_start())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let content = "# weights\n\
+                       2.5 sunset.jpg\n\
+                       \n\
+                       0 /home/user/Pictures/rain.jpg\n\
+                       1\tsea with spaces.jpg\n";
+        let (weights, errors) = parse(content, Path::new("/ranking"));
+        assert!(errors.is_empty());
+        assert_eq!(
+            weights,
+            HashMap::from([
+                (PathBuf::from("/ranking/sunset.jpg"), 2.5),
+                (PathBuf::from("/home/user/Pictures/rain.jpg"), 0.0),
+                (PathBuf::from("/ranking/sea with spaces.jpg"), 1.0),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_lines() {
+        let content = "sunset.jpg\n\
+                       high sunset.jpg\n\
+                       -1 sunset.jpg\n\
+                       inf sunset.jpg\n\
+                       NaN sunset.jpg\n\
+                       3 rain.jpg\n";
+        let (weights, errors) = parse(content, Path::new(""));
+        // The valid lines are still read
+        assert_eq!(weights, HashMap::from([(PathBuf::from("rain.jpg"), 3.0)]));
+        assert_eq!(errors.len(), 5);
+        assert!(format!("{:?}", errors[0]).contains("line 1 is not valid"));
+        assert!(format!("{:?}", errors[2]).contains("not a number of 0 or more"));
+    }
+}
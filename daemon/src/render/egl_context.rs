@@ -3,7 +3,17 @@ use wayland_egl::WlEglSurface;
 
 use egl::API as egl;
 
-use color_eyre::{eyre::Context, Result};
+use color_eyre::{
+    eyre::{bail, Context, ContextCompat},
+    Report, Result,
+};
+
+type SwapBuffersWithDamage = unsafe extern "system" fn(
+    egl::EGLDisplay,
+    egl::EGLSurface,
+    *const egl::Int,
+    egl::Int,
+) -> egl::Boolean;
 
 pub struct EglContext {
     pub display: egl::Display,
@@ -11,10 +21,14 @@ pub struct EglContext {
     pub config: egl::Config,
     wl_egl_surface: WlEglSurface,
     surface: khronos_egl::Surface,
+    /// eglSwapBuffersWithDamageKHR, if the EGL_KHR_swap_buffers_with_damage extension is available
+    swap_buffers_with_damage: Option<SwapBuffersWithDamage>,
+    /// Swap interval in use, after being clamped to the values supported by the configuration
+    swap_interval: i32,
 }
 
 impl EglContext {
-    pub fn new(egl_display: egl::Display, wl_surface: &WlSurface) -> Self {
+    pub fn new(egl_display: egl::Display, wl_surface: &WlSurface) -> Result<Self> {
         const ATTRIBUTES: [i32; 7] = [
             egl::RED_SIZE,
             8,
@@ -27,8 +41,8 @@ impl EglContext {
 
         let config = egl
             .choose_first_config(egl_display, &ATTRIBUTES)
-            .expect("unable to choose an EGL configuration")
-            .expect("no EGL configuration found");
+            .context("unable to choose an EGL configuration")?
+            .context("no EGL configuration found")?;
 
         const CONTEXT_ATTRIBUTES: [i32; 5] = [
             egl::CONTEXT_MAJOR_VERSION,
@@ -40,10 +54,11 @@ impl EglContext {
 
         let context = egl
             .create_context(egl_display, config, None, &CONTEXT_ATTRIBUTES)
-            .expect("unable to create an EGL context");
+            .context("unable to create an EGL context")?;
 
         // First, create a small surface, we don't know the size of the output yet
-        let wl_egl_surface = WlEglSurface::new(wl_surface.id(), 10, 10).unwrap();
+        let wl_egl_surface = WlEglSurface::new(wl_surface.id(), 10, 10)
+            .context("unable to create a new WlEglSurface")?;
 
         let surface = unsafe {
             egl.create_window_surface(
@@ -52,16 +67,63 @@ impl EglContext {
                 wl_egl_surface.ptr() as egl::NativeWindowType,
                 None,
             )
-            .expect("unable to create an EGL surface")
+            .context("unable to create an EGL surface")?
         };
 
-        Self {
+        let swap_buffers_with_damage = egl
+            .query_string(Some(egl_display), egl::EXTENSIONS)
+            .ok()
+            .filter(|extensions| {
+                extensions
+                    .to_string_lossy()
+                    .split(' ')
+                    .any(|extension| extension == "EGL_KHR_swap_buffers_with_damage")
+            })
+            .and_then(|_| egl.get_proc_address("eglSwapBuffersWithDamageKHR"))
+            .map(|f| unsafe {
+                std::mem::transmute::<extern "system" fn(), SwapBuffersWithDamage>(f)
+            });
+
+        Ok(Self {
             display: egl_display,
             context,
             config,
             surface,
             wl_egl_surface,
-        }
+            swap_buffers_with_damage,
+            // EGL default
+            swap_interval: 1,
+        })
+    }
+
+    /// Destroy the EGL surface and context, e.g. after the context has been lost.
+    /// Errors are ignored, the objects might not be valid anymore.
+    pub fn destroy(&self) {
+        let _ = egl.make_current(self.display, None, None, None);
+        let _ = egl.destroy_surface(self.display, self.surface);
+        let _ = egl.destroy_context(self.display, self.context);
+    }
+
+    /// Set the number of frames to wait for before swapping the buffers, 0 to swap immediately.
+    /// The context must be current, and the interval must be set again after resizing.
+    pub fn set_swap_interval(&mut self, swap_interval: i32) -> Result<()> {
+        let min = egl
+            .get_config_attrib(self.display, self.config, egl::MIN_SWAP_INTERVAL)
+            .unwrap_or(swap_interval);
+        let max = egl
+            .get_config_attrib(self.display, self.config, egl::MAX_SWAP_INTERVAL)
+            .unwrap_or(swap_interval);
+        // EGL clamps the interval on its own, do the same to report the effective value
+        let swap_interval = swap_interval.clamp(min, max.max(min));
+        egl.swap_interval(self.display, swap_interval)
+            .context("unable to set the EGL swap interval")?;
+        self.swap_interval = swap_interval;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn swap_interval(&self) -> i32 {
+        self.swap_interval
     }
 
     #[inline]
@@ -82,6 +144,34 @@ impl EglContext {
             .with_context(|| "unable to post the surface content")
     }
 
+    /// Swap the buffers of the surface, telling the compositor that only the region
+    /// `[x, y, width, height]` (from the top left corner) has changed.
+    /// The whole surface is posted when the EGL implementation doesn't support it.
+    pub fn swap_buffers_with_damage(&self, damage: [i32; 4], surface_height: i32) -> Result<()> {
+        let Some(swap_buffers_with_damage) = self.swap_buffers_with_damage else {
+            return self.swap_buffers();
+        };
+        let [x, y, width, height] = damage;
+        // EGL rectangles start from the bottom left corner
+        let rect = [x, surface_height - y - height, width, height];
+        let res = unsafe {
+            swap_buffers_with_damage(
+                self.display.as_ptr(),
+                self.surface.as_ptr(),
+                rect.as_ptr(),
+                1,
+            )
+        };
+        if res != egl::TRUE {
+            // Keep the EGL error, it tells whether the context has been lost
+            match egl.get_error() {
+                Some(err) => return Err(err).context("unable to post the surface content"),
+                None => bail!("unable to post the surface content"),
+            }
+        }
+        Ok(())
+    }
+
     /// Resize the surface
     /// Resizing the surface means to destroy the previous one and then recreate it
     pub fn resize(&mut self, wl_surface: &WlSurface, width: i32, height: i32) -> Result<()> {
@@ -106,3 +196,10 @@ impl EglContext {
         Ok(())
     }
 }
+
+/// Return true if `err` has been caused by the loss of the EGL context, e.g. after a GPU reset
+/// or a suspend cycle. The context and everything created with it have to be created again.
+pub fn is_context_lost(err: &Report) -> bool {
+    err.chain()
+        .any(|cause| matches!(cause.downcast_ref(), Some(egl::Error::ContextLost)))
+}
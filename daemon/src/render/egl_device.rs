@@ -0,0 +1,106 @@
+//! Create the EGL display on a specific DRM device, e.g. to use the integrated GPU of a laptop.
+//! It requires the EGL_EXT_device_enumeration, EGL_EXT_device_query and
+//! EGL_EXT_explicit_device extensions.
+
+use std::{
+    ffi::{c_char, c_void, CStr},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{
+    eyre::{anyhow, bail, ensure, ContextCompat},
+    Result,
+};
+use egl::API as egl;
+
+type Device = *mut c_void;
+
+type QueryDevices = unsafe extern "system" fn(egl::Int, *mut Device, *mut egl::Int) -> egl::Boolean;
+type QueryDeviceString = unsafe extern "system" fn(Device, egl::Int) -> *const c_char;
+type GetPlatformDisplay =
+    unsafe extern "system" fn(egl::Enum, *mut c_void, *const egl::Attrib) -> egl::EGLDisplay;
+
+const PLATFORM_WAYLAND_KHR: egl::Enum = 0x31D8;
+const DEVICE_EXT: egl::Attrib = 0x322C;
+const DRM_DEVICE_FILE_EXT: egl::Int = 0x3233;
+const DRM_RENDER_NODE_FILE_EXT: egl::Int = 0x3377;
+
+const REQUIRED_EXTENSIONS: [&str; 3] = [
+    "EGL_EXT_device_enumeration",
+    "EGL_EXT_device_query",
+    "EGL_EXT_explicit_device",
+];
+
+fn proc_address<T>(name: &str) -> Result<T> {
+    let f = egl
+        .get_proc_address(name)
+        .with_context(|| format!("{name} is not available"))?;
+    // All the EGL functions are loaded through the same pointer type
+    Ok(unsafe { std::mem::transmute_copy::<extern "system" fn(), T>(&f) })
+}
+
+/// Return the node and the primary device file of an EGL device, resolving any symlink
+fn device_files(query_device_string: QueryDeviceString, device: Device) -> Vec<PathBuf> {
+    [DRM_RENDER_NODE_FILE_EXT, DRM_DEVICE_FILE_EXT]
+        .into_iter()
+        .filter_map(|name| {
+            let file = unsafe { query_device_string(device, name) };
+            (!file.is_null()).then(|| unsafe { CStr::from_ptr(file) })
+        })
+        .filter_map(|file| file.to_str().ok())
+        .map(|file| fs::canonicalize(file).unwrap_or_else(|_| PathBuf::from(file)))
+        .collect()
+}
+
+/// Get the EGL display of the Wayland connection, rendering on the DRM device `path`.
+/// Both the render node (e.g. `/dev/dri/renderD128`) and the primary node are accepted.
+pub unsafe fn egl_display_on_device(
+    native_display: *mut c_void,
+    path: &Path,
+) -> Result<egl::Display> {
+    let client_extensions = egl
+        .query_string(None, egl::EXTENSIONS)
+        .map_err(|err| anyhow!("unable to query the EGL client extensions: {err}"))?
+        .to_string_lossy()
+        .into_owned();
+    if let Some(extension) = REQUIRED_EXTENSIONS
+        .iter()
+        .find(|extension| !client_extensions.split(' ').any(|ext| ext == **extension))
+    {
+        bail!("the EGL implementation does not support {extension}, needed to select the device");
+    }
+
+    let requested = fs::canonicalize(path)
+        .map_err(|err| anyhow!("DRM device {path:?} is not available: {err}"))?;
+
+    let query_devices: QueryDevices = proc_address("eglQueryDevicesEXT")?;
+    let query_device_string: QueryDeviceString = proc_address("eglQueryDeviceStringEXT")?;
+    let get_platform_display: GetPlatformDisplay = proc_address("eglGetPlatformDisplay")?;
+
+    let mut count = 0;
+    ensure!(
+        query_devices(0, std::ptr::null_mut(), &mut count) == egl::TRUE,
+        "unable to count the EGL devices"
+    );
+    let mut devices = vec![std::ptr::null_mut(); count as usize];
+    ensure!(
+        query_devices(count, devices.as_mut_ptr(), &mut count) == egl::TRUE,
+        "unable to list the EGL devices"
+    );
+    devices.truncate(count as usize);
+
+    let device = devices
+        .into_iter()
+        .find(|device| device_files(query_device_string, *device).contains(&requested))
+        .with_context(|| format!("no EGL device matches the DRM device {path:?}"))?;
+
+    let attributes = [DEVICE_EXT, device as egl::Attrib, egl::NONE as egl::Attrib];
+    let display = get_platform_display(PLATFORM_WAYLAND_KHR, native_display, attributes.as_ptr());
+    ensure!(
+        !display.is_null(),
+        "unable to get the EGL display for the DRM device {path:?}"
+    );
+
+    Ok(egl::Display::from_ptr(display))
+}
@@ -0,0 +1,450 @@
+//! Pure geometry of the wallpapers: how the images are scaled, moved and rotated to fill the
+//! display. Nothing here needs an OpenGL context, the renderer passes the results to the shaders.
+
+use smithay_client_toolkit::reexports::client::protocol::wl_output::Transform;
+
+use crate::wallpaper_info::BackgroundMode;
+
+/// Return the scale of the texture coordinates for an image drawn in `display` (width and
+/// height) with `mode`. A scale below 1.0 crops the image on that axis, above 1.0 leaves empty
+/// space, or repeats the image in `tile` mode. `contain` mode doesn't enlarge the image more
+/// than `max_upscale` times.
+pub fn texture_scale(
+    mode: BackgroundMode,
+    display: (f32, f32),
+    image: (f32, f32),
+    max_upscale: f32,
+) -> [f32; 2] {
+    let (display_width, display_height) = display;
+    let (image_width, image_height) = image;
+    let display_ratio = display_width / display_height;
+    let image_ratio: f32 = image_width / image_height;
+    match mode {
+        BackgroundMode::Stretch => [1.0, 1.0],
+        BackgroundMode::Center => [
+            (display_ratio / image_ratio).min(1.0),
+            (image_ratio / display_ratio).min(1.0),
+        ],
+        BackgroundMode::Fit | BackgroundMode::FitBorderColor => {
+            // Portrait mode
+            // In this case we calculate the width relative to the height of the
+            // screen with the ratio of the image
+            let width = display_height * image_ratio;
+            // Same thing as above, just with the width
+            let height = display_width / image_ratio;
+            // Then we calculate the proportions
+            [
+                (display_width / width).max(1.0),
+                (display_height / height).max(1.0),
+            ]
+        }
+        BackgroundMode::Contain => {
+            // The size of the image drawn on the display is scaled like fit, up to max_upscale
+            let scale = (display_width / image_width)
+                .min(display_height / image_height)
+                .min(max_upscale);
+            [
+                (display_width / (image_width * scale)).max(1.0),
+                (display_height / (image_height * scale)).max(1.0),
+            ]
+        }
+        // The image width matches the display width, the visible height depends on
+        // the ratios
+        BackgroundMode::FitWidth => [1.0, image_ratio / display_ratio],
+        // Same as above, just with the height
+        BackgroundMode::FitHeight => [display_ratio / image_ratio, 1.0],
+        BackgroundMode::Tile => {
+            let width_proportion = display_width / image_width * display_ratio;
+            let height_proportion = display_height / image_height * display_ratio;
+            if display_ratio > image_ratio {
+                // Portrait mode
+                if height_proportion.max(1.0) == 1.0 {
+                    // Same as Fit
+                    let width = display_height * image_ratio;
+                    [display_width / width, 1.0]
+                } else {
+                    [width_proportion, height_proportion]
+                }
+            } else {
+                // Landscape mode
+                if width_proportion.max(1.0) == 1.0 {
+                    // Same as Fit
+                    let height = display_width / image_ratio;
+                    [1.0, display_height / height]
+                } else {
+                    [width_proportion, height_proportion]
+                }
+            }
+        }
+    }
+}
+
+/// Return the offset of the texture, the configured one if any. Otherwise the images cropped
+/// by `center` mode are moved towards their `focus`, if it is known, and centered.
+pub fn texture_offset(
+    mode: BackgroundMode,
+    offset: Option<f32>,
+    focus: Option<[f32; 2]>,
+    texture_scale: [f32; 2],
+) -> f32 {
+    match (offset, mode) {
+        // Keep the focus of the image visible in the cropped area
+        (None, BackgroundMode::Center) => {
+            focus.map_or(0.5, |focus| focus_offset(focus, texture_scale))
+        }
+        (
+            None,
+            BackgroundMode::Stretch
+            | BackgroundMode::Fit
+            | BackgroundMode::FitWidth
+            | BackgroundMode::FitHeight
+            | BackgroundMode::FitBorderColor
+            | BackgroundMode::Contain,
+        ) => 0.5,
+        (None, BackgroundMode::Tile) => 0.0,
+        (Some(offset), _) => offset,
+    }
+}
+
+/// Return the offset centering the cropped area on `focus`, along the axis that is cropped.
+/// An offset `o` shows the part of the texture from `o * (1 - scale)` to `o * (1 - scale) + scale`.
+fn focus_offset(focus: [f32; 2], texture_scale: [f32; 2]) -> f32 {
+    let (focus, scale) = if texture_scale[0] < 1.0 {
+        (focus[0], texture_scale[0])
+    } else if texture_scale[1] < 1.0 {
+        (focus[1], texture_scale[1])
+    } else {
+        // The whole image is visible
+        return 0.5;
+    };
+    ((focus - scale / 2.0) / (1.0 - scale)).clamp(0.0, 1.0)
+}
+
+/// Return the part of the image shown with `texture_scale` and `offset`, as `[x, y, width,
+/// height]` in fractions of the image. Outside of the image the display is left empty, or the
+/// image is repeated in `tile` mode, so the area never exceeds the image.
+pub fn visible_area(texture_scale: [f32; 2], offset: f32) -> [f32; 4] {
+    let [x, width] = visible_range(texture_scale[0], offset);
+    let [y, height] = visible_range(texture_scale[1], offset);
+    [x, y, width, height]
+}
+
+/// Return the start and the length of the visible range of the texture along an axis
+fn visible_range(scale: f32, offset: f32) -> [f32; 2] {
+    let start = offset * (1.0 - scale);
+    let end = (start + scale).min(1.0);
+    let start = start.max(0.0);
+    [start, end - start]
+}
+
+/// Return the size of the display in the orientation of the wallpaper, so that a wallpaper
+/// drawn sideways fills the display the other way
+pub fn oriented_size(width: i32, height: i32, wallpaper_transform: Transform) -> (i32, i32) {
+    if is_rotated(wallpaper_transform) {
+        (height, width)
+    } else {
+        (width, height)
+    }
+}
+
+/// Return the size of the area left inside a border of `border` pixels, at least one pixel
+pub fn wallpaper_area((width, height): (i32, i32), border: i32) -> (i32, i32) {
+    ((width - 2 * border).max(1), (height - 2 * border).max(1))
+}
+
+/// Return the width of the border as a fraction of the display, on each axis
+pub fn border_size((width, height): (i32, i32), border: i32) -> [f32; 2] {
+    let border_size = if width > 0 && height > 0 {
+        [border as f32 / width as f32, border as f32 / height as f32]
+    } else {
+        [0.0, 0.0]
+    };
+    // Leave at least a pixel for the wallpaper when the border is wider than the display
+    border_size.map(|size| size.min(0.499))
+}
+
+#[rustfmt::skip]
+pub fn projection_matrix(transform: Transform) -> [f32; 4] {
+    match transform {
+        Transform::Normal => {
+            [
+                1.0, 0.0,
+                0.0, 1.0,
+            ]
+        }
+        Transform::_90 => {
+            [
+                0.0, -1.0,
+                1.0, 0.0,
+            ]
+        }
+        Transform::_180 => {
+            [
+                -1.0, 0.0,
+                0.0, -1.0,
+            ]
+        }
+        Transform::_270 => {
+            [
+                0.0, 1.0,
+                -1.0, 0.0,
+            ]
+        }
+        Transform::Flipped => {
+            [
+                -1.0, 0.0,
+                0.0, 1.0,
+            ]
+        }
+        Transform::Flipped90 => {
+            [
+                0.0, -1.0,
+                -1.0, 0.0,
+            ]
+        }
+        Transform::Flipped180 => {
+            [
+                1.0, 0.0,
+                0.0, -1.0,
+            ]
+        }
+        Transform::Flipped270 => {
+            [
+                0.0, 1.0,
+                1.0, 0.0,
+            ]
+        }
+        _ => unreachable!()
+    }
+}
+
+/// Multiply two column-major 2x2 matrices. Since the vertex shader multiplies the position
+/// on the left, the result applies `a` first and `b` after.
+pub fn multiply_matrices(a: [f32; 4], b: [f32; 4]) -> [f32; 4] {
+    let mut res = [0.0; 4];
+    for col in 0..2 {
+        for row in 0..2 {
+            res[col * 2 + row] = (0..2).map(|k| a[k * 2 + row] * b[col * 2 + k]).sum();
+        }
+    }
+    res
+}
+
+/// Return true if the transform swaps the width and the height
+pub fn is_rotated(transform: Transform) -> bool {
+    matches!(
+        transform,
+        Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FULL_HD: (f32, f32) = (1920.0, 1080.0);
+    const MAX_UPSCALE: f32 = 1.5;
+    const IDENTITY: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+
+    fn assert_close<const N: usize>(expected: [f32; N], actual: [f32; N]) {
+        for (expected, actual) in expected.iter().zip(actual) {
+            assert!(
+                (expected - actual).abs() < 1e-4,
+                "expected {expected:?}, got {actual:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_texture_scale_square_image() {
+        let image = (1000.0, 1000.0);
+        let display_ratio = FULL_HD.0 / FULL_HD.1;
+        assert_close(
+            [1.0, 1.0],
+            texture_scale(BackgroundMode::Stretch, FULL_HD, image, MAX_UPSCALE),
+        );
+        // The top and the bottom of the image are cropped
+        assert_close(
+            [1.0, 1.0 / display_ratio],
+            texture_scale(BackgroundMode::Center, FULL_HD, image, MAX_UPSCALE),
+        );
+        // Black bars are left on the sides
+        assert_close(
+            [display_ratio, 1.0],
+            texture_scale(BackgroundMode::Fit, FULL_HD, image, MAX_UPSCALE),
+        );
+        assert_close(
+            [display_ratio, 1.0],
+            texture_scale(BackgroundMode::FitBorderColor, FULL_HD, image, MAX_UPSCALE),
+        );
+        assert_close(
+            [1.0, 1.0 / display_ratio],
+            texture_scale(BackgroundMode::FitWidth, FULL_HD, image, MAX_UPSCALE),
+        );
+        assert_close(
+            [display_ratio, 1.0],
+            texture_scale(BackgroundMode::FitHeight, FULL_HD, image, MAX_UPSCALE),
+        );
+    }
+
+    #[test]
+    fn test_texture_scale_same_ratio() {
+        // An image with the ratio of the display is shown entirely in every mode but tile
+        for mode in [
+            BackgroundMode::Stretch,
+            BackgroundMode::Center,
+            BackgroundMode::Fit,
+            BackgroundMode::FitBorderColor,
+            BackgroundMode::FitWidth,
+            BackgroundMode::FitHeight,
+            BackgroundMode::Contain,
+        ] {
+            assert_close(
+                [1.0, 1.0],
+                texture_scale(mode, FULL_HD, (3840.0, 2160.0), MAX_UPSCALE),
+            );
+        }
+    }
+
+    #[test]
+    fn test_texture_scale_contain() {
+        // A small image is only enlarged up to 1.5 times, 960x540, and centered
+        assert_close(
+            [2.0, 2.0],
+            texture_scale(
+                BackgroundMode::Contain,
+                FULL_HD,
+                (640.0, 360.0),
+                MAX_UPSCALE,
+            ),
+        );
+        // Enlarging it less than that works like fit
+        let image = (1000.0, 1000.0);
+        assert_close(
+            texture_scale(BackgroundMode::Fit, FULL_HD, image, MAX_UPSCALE),
+            texture_scale(BackgroundMode::Contain, FULL_HD, image, MAX_UPSCALE),
+        );
+        // Big images are scaled down like fit
+        let image = (3840.0, 1000.0);
+        assert_close(
+            texture_scale(BackgroundMode::Fit, FULL_HD, image, MAX_UPSCALE),
+            texture_scale(BackgroundMode::Contain, FULL_HD, image, MAX_UPSCALE),
+        );
+    }
+
+    #[test]
+    fn test_texture_scale_tile_wide_image() {
+        // The image is wider than the display, it is fitted and repeated vertically
+        assert_close(
+            [1.0, 2.0],
+            texture_scale(BackgroundMode::Tile, FULL_HD, (3840.0, 1080.0), MAX_UPSCALE),
+        );
+    }
+
+    #[test]
+    fn test_texture_scale_rotated_wallpaper() {
+        let (width, height) = oriented_size(1920, 1080, Transform::_90);
+        assert_eq!((1080, 1920), (width, height));
+        assert_eq!(
+            (1920, 1080),
+            oriented_size(1920, 1080, Transform::Flipped180)
+        );
+        // A landscape image drawn sideways is cropped on its width
+        let display = (width as f32, height as f32);
+        assert_close(
+            [(1080.0 / 1920.0) / (1920.0 / 1080.0), 1.0],
+            texture_scale(BackgroundMode::Center, display, FULL_HD, MAX_UPSCALE),
+        );
+    }
+
+    #[test]
+    fn test_texture_offset() {
+        let scale = [0.5, 1.0];
+        assert_eq!(
+            0.2,
+            texture_offset(BackgroundMode::Fit, Some(0.2), None, scale)
+        );
+        assert_eq!(0.0, texture_offset(BackgroundMode::Tile, None, None, scale));
+        assert_eq!(
+            0.5,
+            texture_offset(BackgroundMode::Center, None, None, scale)
+        );
+        // The focus is only used to crop the image
+        assert_eq!(
+            0.5,
+            texture_offset(BackgroundMode::Fit, None, Some([0.0, 0.0]), scale)
+        );
+        // The configured offset is preferred to the focus
+        assert_eq!(
+            0.2,
+            texture_offset(BackgroundMode::Center, Some(0.2), Some([0.9, 0.5]), scale)
+        );
+    }
+
+    #[test]
+    fn test_visible_area() {
+        // The left half of the image is shown
+        assert_close([0.0, 0.0, 0.5, 1.0], visible_area([0.5, 1.0], 0.0));
+        // The bottom half of the image is shown
+        assert_close([0.0, 0.5, 1.0, 0.5], visible_area([1.0, 0.5], 1.0));
+        // The whole image is shown, leaving empty space
+        assert_close([0.0, 0.0, 1.0, 1.0], visible_area([2.0, 1.0], 0.5));
+    }
+
+    #[test]
+    fn test_focus_offset() {
+        // Half of the width is visible, the focus is centered in it
+        assert_close([0.3, 0.0], [focus_offset([0.4, 0.5], [0.5, 1.0]), 0.0]);
+        assert_close([0.5, 0.0], [focus_offset([0.5, 0.1], [0.5, 1.0]), 0.0]);
+        // The height is cropped instead
+        assert_close([0.3, 0.0], [focus_offset([0.1, 0.4], [1.0, 0.5]), 0.0]);
+        // The cropped area never leaves the image
+        assert_eq!(1.0, focus_offset([0.9, 0.5], [0.5, 1.0]));
+        assert_eq!(0.0, focus_offset([0.1, 0.5], [0.5, 1.0]));
+        // Nothing is cropped
+        assert_eq!(0.5, focus_offset([0.1, 0.1], [1.0, 2.0]));
+    }
+
+    #[test]
+    fn test_border() {
+        assert_eq!((1900, 1060), wallpaper_area((1920, 1080), 10));
+        assert_eq!((1, 1), wallpaper_area((10, 10), 10));
+        assert_close([0.01, 0.02], border_size((1000, 500), 10));
+        assert_close([0.0, 0.0], border_size((0, 0), 10));
+        assert_close([0.499, 0.499], border_size((10, 10), 10));
+    }
+
+    #[test]
+    fn test_projection_matrix() {
+        assert_eq!(IDENTITY, projection_matrix(Transform::Normal));
+        assert_eq!(
+            IDENTITY,
+            multiply_matrices(
+                projection_matrix(Transform::_90),
+                projection_matrix(Transform::_270)
+            )
+        );
+        assert_eq!(
+            IDENTITY,
+            multiply_matrices(
+                projection_matrix(Transform::Flipped),
+                projection_matrix(Transform::Flipped)
+            )
+        );
+        assert_eq!(
+            projection_matrix(Transform::_180),
+            multiply_matrices(
+                projection_matrix(Transform::_90),
+                projection_matrix(Transform::_90)
+            )
+        );
+        // Flipping then rotating by 90 degrees
+        assert_eq!(
+            projection_matrix(Transform::Flipped90),
+            multiply_matrices(
+                projection_matrix(Transform::Flipped),
+                projection_matrix(Transform::_90)
+            )
+        );
+    }
+}
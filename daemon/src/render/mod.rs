@@ -1,8 +1,14 @@
 mod coordinates;
 mod egl_context;
+mod egl_device;
+mod geometry;
+mod pass;
 mod renderer;
 mod shader;
+mod software;
 mod transition;
+#[cfg(feature = "video")]
+mod video;
 mod wallpaper;
 
 use std::ffi::{c_void, CStr};
@@ -14,9 +20,16 @@ use color_eyre::{
 use coordinates::{get_opengl_point_coordinates, Coordinates};
 use image::DynamicImage;
 
-pub use egl_context::EglContext;
-pub use renderer::Renderer;
+use crate::wallpaper_info::TextureFilter;
+
+pub use egl_context::{is_context_lost, EglContext};
+pub use egl_device::egl_display_on_device;
+pub use geometry::{is_rotated, texture_offset, texture_scale, visible_area};
+pub use renderer::{Backdrop, Border, ColorAdjustment, Renderer};
+pub use software::SoftwareRenderer;
 pub use transition::Transition;
+#[cfg(feature = "video")]
+pub use video::{is_video, VideoFrames, VideoPlayer};
 
 pub mod gl {
     #![allow(clippy::all)]
@@ -123,7 +136,11 @@ fn initialize_objects(gl: &gl::Gl) -> Result<(gl::types::GLuint, gl::types::GLui
     }
 }
 
-fn load_texture(gl: &gl::Gl, image: DynamicImage) -> Result<gl::types::GLuint> {
+fn load_texture(
+    gl: &gl::Gl,
+    image: DynamicImage,
+    filter: TextureFilter,
+) -> Result<gl::types::GLuint> {
     Ok(unsafe {
         let mut texture = 0;
         gl.GenTextures(1, &mut texture);
@@ -144,11 +161,18 @@ fn load_texture(gl: &gl::Gl, image: DynamicImage) -> Result<gl::types::GLuint> {
             image.as_bytes().as_ptr() as *const c_void,
         );
         gl_check!(gl, "defining the texture");
-        gl.GenerateMipmap(gl::TEXTURE_2D);
-        gl_check!(gl, "generating the mipmap");
-        gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+        let (min_filter, mag_filter) = match filter {
+            TextureFilter::Nearest => (gl::NEAREST, gl::NEAREST),
+            TextureFilter::Linear => (gl::LINEAR, gl::LINEAR),
+            TextureFilter::Mipmap => {
+                gl.GenerateMipmap(gl::TEXTURE_2D);
+                gl_check!(gl, "generating the mipmap");
+                (gl::LINEAR_MIPMAP_LINEAR, gl::LINEAR)
+            }
+        };
+        gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, min_filter as i32);
         gl_check!(gl, "defining the texture min filter");
-        gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, mag_filter as i32);
         gl_check!(gl, "defining the texture mag filter");
 
         texture
@@ -0,0 +1,114 @@
+use std::{ffi::CStr, rc::Rc};
+
+use color_eyre::{
+    eyre::{bail, ensure},
+    Result,
+};
+
+use crate::gl_check;
+
+use super::gl;
+
+/// Value of the `render_pass` uniform, see the fragment shader
+pub const SINGLE_PASS: i32 = 0;
+pub const FIRST_PASS: i32 = 1;
+pub const LAST_PASS: i32 = 2;
+
+/// Texture unit the last pass reads the first one from, the wallpapers use the first two
+pub const FIRST_PASS_TEXTURE_UNIT: i32 = 2;
+
+/// Framebuffer the first pass of a multi-pass transition is drawn in, with the size of the
+/// viewport, so that the last pass can read it at the position of its fragments
+pub struct PassTarget {
+    gl: Rc<gl::Gl>,
+    texture: gl::types::GLuint,
+    framebuffer: gl::types::GLuint,
+    width: i32,
+    height: i32,
+}
+
+impl PassTarget {
+    pub fn new(gl: Rc<gl::Gl>, width: i32, height: i32) -> Result<Self> {
+        let mut target = Self {
+            gl,
+            texture: 0,
+            framebuffer: 0,
+            width,
+            height,
+        };
+        unsafe {
+            let gl = &target.gl;
+            // The unit of the first pass, the ones of the wallpapers are left untouched
+            gl.ActiveTexture(gl::TEXTURE0 + FIRST_PASS_TEXTURE_UNIT as u32);
+            gl.GenTextures(1, &mut target.texture);
+            gl.BindTexture(gl::TEXTURE_2D, target.texture);
+            gl.TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                gl::RGBA as i32,
+                width,
+                height,
+                0,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                std::ptr::null(),
+            );
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            gl.ActiveTexture(gl::TEXTURE1);
+            gl_check!(gl, "creating the texture of the first pass");
+
+            let mut previous = 0;
+            gl.GetIntegerv(gl::FRAMEBUFFER_BINDING, &mut previous);
+            gl.GenFramebuffers(1, &mut target.framebuffer);
+            gl.BindFramebuffer(gl::FRAMEBUFFER, target.framebuffer);
+            gl.FramebufferTexture2D(
+                gl::FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::TEXTURE_2D,
+                target.texture,
+                0,
+            );
+            let complete = gl.CheckFramebufferStatus(gl::FRAMEBUFFER) == gl::FRAMEBUFFER_COMPLETE;
+            gl.BindFramebuffer(gl::FRAMEBUFFER, previous as u32);
+            gl_check!(gl, "creating the framebuffer of the first pass");
+            ensure!(
+                complete,
+                "the framebuffer of the first pass is not complete"
+            );
+        }
+        Ok(target)
+    }
+
+    pub fn size(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+
+    pub fn framebuffer(&self) -> gl::types::GLuint {
+        self.framebuffer
+    }
+
+    /// Bind the texture drawn by the first pass to the unit read by the last one
+    pub fn bind(&self) -> Result<()> {
+        unsafe {
+            self.gl
+                .ActiveTexture(gl::TEXTURE0 + FIRST_PASS_TEXTURE_UNIT as u32);
+            self.gl.BindTexture(gl::TEXTURE_2D, self.texture);
+            // The wallpapers are loaded in the unit of the current one
+            self.gl.ActiveTexture(gl::TEXTURE1);
+            gl_check!(self.gl, "binding the texture of the first pass");
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PassTarget {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteFramebuffers(1, &self.framebuffer);
+            self.gl.DeleteTextures(1, &self.texture);
+        }
+    }
+}
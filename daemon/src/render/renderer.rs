@@ -5,21 +5,31 @@ use color_eyre::{
     Result,
 };
 use egl::API as egl;
-use image::{DynamicImage, RgbaImage};
+use image::{imageops, DynamicImage, RgbaImage};
 use log::error;
 use smithay_client_toolkit::reexports::client::protocol::wl_output::Transform;
 
 use crate::{
     display_info::DisplayInfo,
     gl_check,
+    image_loader::ImageStats,
     render::{
+        geometry::{
+            border_size, multiply_matrices, oriented_size, projection_matrix, texture_offset,
+            texture_scale, wallpaper_area,
+        },
         initialize_objects, load_texture,
         shader::{create_shader, FRAGMENT_SHADER_SOURCE, VERTEX_SHADER_SOURCE},
     },
-    wallpaper_info::BackgroundMode,
+    wallpaper_info::{BackgroundMode, TextureFilter, DEFAULT_MAX_UPSCALE},
 };
 
-use super::{gl, wallpaper::Wallpaper, Transition};
+use super::{
+    gl,
+    pass::{PassTarget, FIRST_PASS, FIRST_PASS_TEXTURE_UNIT, LAST_PASS, SINGLE_PASS},
+    wallpaper::Wallpaper,
+    Transition,
+};
 
 fn transparent_image() -> RgbaImage {
     RgbaImage::from_raw(1, 1, vec![0, 0, 0, 0]).unwrap()
@@ -27,11 +37,75 @@ fn transparent_image() -> RgbaImage {
 
 #[derive(Debug)]
 pub enum TransitionStatus {
+    /// The new wallpaper has been loaded, but the previous one is still shown until the
+    /// transition is started
+    Held,
+    /// The texture has been uploaded, the transition starts from the next frame
+    Uploaded,
     Started,
-    Running { started: u32, progress: f32 },
+    Running {
+        started: u32,
+        progress: f32,
+    },
     Ended,
 }
 
+/// Color correction applied to the wallpaper after the transition
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ColorAdjustment {
+    pub brightness: f32,
+    pub contrast: f32,
+    pub gamma: f32,
+    /// How much the corners are darkened, from 0.0 (disabled) to 1.0 (black)
+    pub vignette_strength: f32,
+    /// Distance from the center where the vignette starts, as a fraction of the half diagonal
+    pub vignette_radius: f32,
+}
+
+impl Default for ColorAdjustment {
+    fn default() -> Self {
+        Self {
+            brightness: 1.0,
+            contrast: 1.0,
+            gamma: 1.0,
+            vignette_strength: 0.0,
+            vignette_radius: 0.5,
+        }
+    }
+}
+
+/// Frame drawn around the wallpaper, which is fitted in the area left inside it
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Border {
+    /// Width of each side, in logical pixels
+    pub width: u32,
+    /// RGBA color, each component from 0.0 to 1.0
+    pub color: [f32; 4],
+}
+
+impl Default for Border {
+    fn default() -> Self {
+        Self {
+            width: 0,
+            color: [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+}
+
+/// Shown through the transparent pixels of the images, as a vertical gradient
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Backdrop {
+    /// RGBA colors at the top and at the bottom of the wallpaper, transparent by default
+    pub top: [f32; 4],
+    pub bottom: [f32; 4],
+}
+
+impl Backdrop {
+    pub fn is_transparent(&self) -> bool {
+        self.top[3] == 0.0 && self.bottom[3] == 0.0
+    }
+}
+
 pub struct Renderer {
     gl: Rc<gl::Gl>,
     pub program: gl::types::GLuint,
@@ -45,6 +119,24 @@ pub struct Renderer {
     transparent_texture: gl::types::GLuint,
     /// contains the progress of the current animation
     transition_status: TransitionStatus,
+    color_adjustment: ColorAdjustment,
+    /// Color of the night temperature and how much it is applied
+    night_light: ([f32; 3], f32),
+    /// Rotation and flip requested in the configuration, applied on top of the display one
+    wallpaper_transform: Transform,
+    border: Border,
+    backdrop: Backdrop,
+    /// How many times `contain` mode can enlarge the wallpaper
+    max_upscale: f32,
+    /// Dither the frames drawn while a transition is running
+    dither: bool,
+    /// Mode and offset of the wallpaper put aside by [`Renderer::start_preview`], which is
+    /// drawn again once the preview has ended
+    preview: Option<(BackgroundMode, Option<f32>)>,
+    /// The transition is drawn in two passes, see [`Transition::is_multi_pass`]
+    multi_pass: bool,
+    /// Target of the first pass, kept while the transition is running
+    first_pass: Option<PassTarget>,
 }
 
 impl Renderer {
@@ -60,6 +152,7 @@ impl Renderer {
                 .expect("egl.get_proc_address to work") as *const std::ffi::c_void
         }));
 
+        let multi_pass = transition.is_multi_pass();
         let program = create_program(&gl, transition)
             .context("unable to create program during openGL ES initialization")?;
 
@@ -67,7 +160,8 @@ impl Renderer {
 
         let current_wallpaper = Wallpaper::new(gl.clone());
 
-        let transparent_texture = load_texture(&gl, transparent_image().into())?;
+        let transparent_texture =
+            load_texture(&gl, transparent_image().into(), TextureFilter::Linear)?;
 
         let mut renderer = Self {
             gl,
@@ -80,10 +174,27 @@ impl Renderer {
             display_info,
             transparent_texture,
             transition_status: TransitionStatus::Ended,
+            color_adjustment: ColorAdjustment::default(),
+            night_light: ([1.0; 3], 0.0),
+            wallpaper_transform: Transform::Normal,
+            border: Border::default(),
+            backdrop: Backdrop::default(),
+            max_upscale: DEFAULT_MAX_UPSCALE,
+            dither: false,
+            preview: None,
+            multi_pass,
+            first_pass: None,
         };
 
-        renderer.load_wallpaper(image, BackgroundMode::Stretch, None)?;
+        renderer.load_wallpaper(
+            image,
+            ImageStats::default(),
+            BackgroundMode::Stretch,
+            None,
+            TextureFilter::Linear,
+        )?;
         renderer.set_projection_matrix(transform)?;
+        renderer.set_color_adjustment_uniforms()?;
 
         Ok(renderer)
     }
@@ -97,8 +208,59 @@ impl Renderer {
     }
 
     pub unsafe fn draw(&mut self) -> Result<()> {
+        // The preview has been stopped before its last frame, e.g. by a resize
+        if !self.transition_running() {
+            self.end_preview()?;
+        }
+        let render_pass = if self.multi_pass && self.transition_running() {
+            self.draw_first_pass()?;
+            LAST_PASS
+        } else {
+            SINGLE_PASS
+        };
         self.gl.Clear(gl::COLOR_BUFFER_BIT);
         self.check_error("clearing the screen")?;
+        self.draw_pass(render_pass)
+    }
+
+    /// Draw the first pass of the transition in its own framebuffer, with the size of the bound
+    /// one, and bind it for the last pass
+    unsafe fn draw_first_pass(&mut self) -> Result<()> {
+        let mut framebuffer = 0;
+        self.gl
+            .GetIntegerv(gl::FRAMEBUFFER_BINDING, &mut framebuffer);
+        let mut viewport = [0; 4];
+        self.gl.GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+        self.check_error("getting the framebuffer of the frame")?;
+        let size = (viewport[2], viewport[3]);
+        if self.first_pass.as_ref().map(PassTarget::size) != Some(size) {
+            self.first_pass = None;
+            self.first_pass = Some(PassTarget::new(self.gl.clone(), size.0, size.1)?);
+        }
+        let target = self.first_pass.as_ref().expect("first pass to be created");
+        self.gl
+            .BindFramebuffer(gl::FRAMEBUFFER, target.framebuffer());
+        self.gl.Clear(gl::COLOR_BUFFER_BIT);
+        self.check_error("clearing the first pass")?;
+        let res = self.draw_pass(FIRST_PASS);
+        // Draw the last pass where the frame was going to be drawn
+        self.gl.BindFramebuffer(gl::FRAMEBUFFER, framebuffer as u32);
+        self.check_error("binding the framebuffer of the frame")?;
+        res?;
+        self.first_pass
+            .as_ref()
+            .expect("first pass to be created")
+            .bind()
+    }
+
+    /// Set the uniforms changing on each frame and draw the triangles
+    unsafe fn draw_pass(&self, render_pass: i32) -> Result<()> {
+        let loc = self
+            .gl
+            .GetUniformLocation(self.program, b"render_pass\0".as_ptr() as *const _);
+        self.check_error("getting the uniform location")?;
+        self.gl.Uniform1i(loc, render_pass);
+        self.check_error("calling Uniform1i")?;
 
         let loc = self
             .gl
@@ -107,7 +269,9 @@ impl Renderer {
         self.gl.Uniform1f(
             loc,
             match self.transition_status {
-                TransitionStatus::Started => 0.0,
+                TransitionStatus::Held | TransitionStatus::Uploaded | TransitionStatus::Started => {
+                    0.0
+                }
                 TransitionStatus::Running {
                     started: _,
                     progress,
@@ -117,6 +281,15 @@ impl Renderer {
         );
         self.check_error("calling Uniform1i")?;
 
+        // The static wallpapers are drawn as they are
+        let loc = self
+            .gl
+            .GetUniformLocation(self.program, b"dither\0".as_ptr() as *const _);
+        self.check_error("getting the uniform location")?;
+        let dither = self.dither && self.transition_running();
+        self.gl.Uniform1f(loc, if dither { 1.0 } else { 0.0 });
+        self.check_error("calling Uniform1f")?;
+
         self.gl
             .DrawElements(gl::TRIANGLES, 6, gl::UNSIGNED_INT, std::ptr::null());
         self.check_error("drawing the triangles")?;
@@ -128,12 +301,17 @@ impl Renderer {
     #[inline]
     pub fn update_transition_status(&mut self, time: u32) -> bool {
         let started = match self.transition_status {
+            // The time of this frame was taken before the upload, wait for the next one
+            TransitionStatus::Uploaded => {
+                self.transition_status = TransitionStatus::Started;
+                return true;
+            }
             TransitionStatus::Started => time,
             TransitionStatus::Running {
                 started,
                 progress: _,
             } => started,
-            TransitionStatus::Ended => unreachable!(),
+            TransitionStatus::Held | TransitionStatus::Ended => unreachable!(),
         };
         let progress =
             ((time.saturating_sub(started)) as f32 / self.transition_time as f32).min(1.0);
@@ -146,19 +324,29 @@ impl Renderer {
         }
     }
 
+    /// Upload a new wallpaper, along with the properties computed when decoding it
     pub fn load_wallpaper(
         &mut self,
         image: DynamicImage,
+        stats: ImageStats,
         mode: BackgroundMode,
         offset: Option<f32>,
+        filter: TextureFilter,
     ) -> Result<()> {
-        self.prev_wallpaper = Some(std::mem::replace(
-            &mut self.current_wallpaper,
-            Wallpaper::new(self.gl.clone()),
-        ));
-        self.current_wallpaper.load_image(image)?;
+        self.end_preview()?;
+        if matches!(self.transition_status, TransitionStatus::Held) {
+            // The held wallpaper has never been shown, the transition starts from the same one
+            self.current_wallpaper = Wallpaper::new(self.gl.clone());
+        } else {
+            self.prev_wallpaper = Some(std::mem::replace(
+                &mut self.current_wallpaper,
+                Wallpaper::new(self.gl.clone()),
+            ));
+        }
+        self.current_wallpaper.load_image(image, stats, filter)?;
 
         self.bind_wallpapers(mode, offset)?;
+        self.set_average_color_uniforms()?;
 
         Ok(())
     }
@@ -181,62 +369,45 @@ impl Renderer {
         Ok(())
     }
 
-    pub fn set_mode(&mut self, mode: BackgroundMode, offset: Option<f32>) -> Result<()> {
+    /// Return the size of the display in the orientation of the wallpaper and the width of the
+    /// border, both in pixels
+    fn display_size(&self) -> ((i32, i32), i32) {
         let display_info = (*self.display_info).borrow();
-        let display_width = display_info.scaled_width() as f32;
-        let display_height = display_info.scaled_height() as f32;
+        let size = oriented_size(
+            display_info.scaled_width(),
+            display_info.scaled_height(),
+            self.wallpaper_transform,
+        );
+        (size, display_info.scaled(self.border.width as i32))
+    }
+
+    /// Return the size of the area the wallpaper is drawn in, inside the border
+    fn wallpaper_area(&self) -> (i32, i32) {
+        let (size, border) = self.display_size();
+        wallpaper_area(size, border)
+    }
+
+    pub fn set_mode(&mut self, mode: BackgroundMode, offset: Option<f32>) -> Result<()> {
+        // The wallpaper put aside follows the changes made during the preview
+        if let Some(preview) = &mut self.preview {
+            *preview = (mode, offset);
+        }
+        // The background mode only applies to the area inside the border
+        let (display_width, display_height) = self.wallpaper_area();
+        let display_width = display_width as f32;
+        let display_height = display_height as f32;
         let display_ratio = display_width / display_height;
-        let gen_texture_scale = |image_width: f32, image_height: f32| {
-            let image_ratio: f32 = image_width / image_height;
-            Box::new(match mode {
-                BackgroundMode::Stretch => [1.0, 1.0],
-                BackgroundMode::Center => [
-                    (display_ratio / image_ratio).min(1.0),
-                    (image_ratio / display_ratio).min(1.0),
-                ],
-                BackgroundMode::Fit | BackgroundMode::FitBorderColor => {
-                    // Portrait mode
-                    // In this case we calculate the width relative to the height of the
-                    // screen with the ratio of the image
-                    let width = display_height * image_ratio;
-                    // Same thing as above, just with the width
-                    let height = display_width / image_ratio;
-                    // Then we calculate the proportions
-                    [
-                        (display_width / width).max(1.0),
-                        (display_height / height).max(1.0),
-                    ]
-                }
-                BackgroundMode::Tile => {
-                    let width_proportion = display_width / image_width * display_ratio;
-                    let height_proportion = display_height / image_height * display_ratio;
-                    if display_ratio > image_ratio {
-                        // Portrait mode
-                        if height_proportion.max(1.0) == 1.0 {
-                            // Same as Fit
-                            let width = display_height * image_ratio;
-                            [display_width / width, 1.0]
-                        } else {
-                            [width_proportion, height_proportion]
-                        }
-                    } else {
-                        // Landscape mode
-                        if width_proportion.max(1.0) == 1.0 {
-                            // Same as Fit
-                            let height = display_width / image_ratio;
-                            [1.0, display_height / height]
-                        } else {
-                            [width_proportion, height_proportion]
-                        }
-                    }
-                }
-            })
-        };
-        let texture_scale = gen_texture_scale(
-            self.current_wallpaper.get_image_width() as f32,
-            self.current_wallpaper.get_image_height() as f32,
+        let display_size = (display_width, display_height);
+        let current_texture_scale = texture_scale(
+            mode,
+            display_size,
+            (
+                self.current_wallpaper.get_image_width() as f32,
+                self.current_wallpaper.get_image_height() as f32,
+            ),
+            self.max_upscale,
         );
-        let (prev_image_width, prev_image_height) = if let Some(prev_wp) = &self.prev_wallpaper {
+        let prev_image_size = if let Some(prev_wp) = &self.prev_wallpaper {
             (
                 prev_wp.get_image_width() as f32,
                 prev_wp.get_image_height() as f32,
@@ -245,7 +416,8 @@ impl Renderer {
             (1.0, 1.0)
         };
 
-        let prev_texture_scale = gen_texture_scale(prev_image_width, prev_image_height);
+        let prev_texture_scale =
+            texture_scale(mode, display_size, prev_image_size, self.max_upscale);
 
         unsafe {
             let loc = self
@@ -254,7 +426,7 @@ impl Renderer {
             self.check_error("getting the uniform location")?;
             ensure!(loc > 0, "textureScale not found");
             self.gl
-                .Uniform2fv(loc, 1, texture_scale.as_ptr() as *const _);
+                .Uniform2fv(loc, 1, current_texture_scale.as_ptr() as *const _);
             self.check_error("calling Uniform2fv on textureScale")?;
 
             let loc = self
@@ -273,29 +445,38 @@ impl Renderer {
             self.gl.Uniform1f(loc, display_ratio);
             self.check_error("calling Uniform1f")?;
 
-            let offset = match (offset, mode) {
-                (
-                    None,
-                    BackgroundMode::Stretch
-                    | BackgroundMode::Center
-                    | BackgroundMode::Fit
-                    | BackgroundMode::FitBorderColor,
-                ) => 0.5,
-                (None, BackgroundMode::Tile) => 0.0,
-                (Some(offset), _) => offset,
-            };
+            self.set_border_uniforms()?;
+            // The area around the image in the clamped modes is not part of the image
+            let clamped = !matches!(mode, BackgroundMode::Tile | BackgroundMode::FitBorderColor);
+            self.set_backdrop_uniforms(clamped)?;
 
+            let offset = texture_offset(
+                mode,
+                offset,
+                self.current_wallpaper.stats().focus,
+                current_texture_scale,
+            );
+            self.current_wallpaper.offset = offset;
+            self.set_texture_offset(offset)?;
+            // The previous wallpaper keeps its own offset until the transition ends
+            let prev_offset = self
+                .prev_wallpaper
+                .as_ref()
+                .map_or(offset, |prev_wp| prev_wp.offset);
             let loc = self
                 .gl
-                .GetUniformLocation(self.program, b"texture_offset\0".as_ptr() as *const _);
+                .GetUniformLocation(self.program, b"prev_texture_offset\0".as_ptr() as *const _);
             self.check_error("getting the uniform location")?;
-            self.gl.Uniform1f(loc, offset);
+            self.gl.Uniform1f(loc, prev_offset);
             self.check_error("calling Uniform1f")?;
 
             let texture_wrap = match mode {
-                BackgroundMode::Stretch | BackgroundMode::Center | BackgroundMode::Fit => {
-                    gl::CLAMP_TO_BORDER_EXT
-                }
+                BackgroundMode::Stretch
+                | BackgroundMode::Center
+                | BackgroundMode::Fit
+                | BackgroundMode::FitWidth
+                | BackgroundMode::FitHeight
+                | BackgroundMode::Contain => gl::CLAMP_TO_BORDER_EXT,
                 BackgroundMode::Tile => gl::REPEAT,
                 BackgroundMode::FitBorderColor => gl::CLAMP_TO_EDGE,
             } as i32;
@@ -322,17 +503,425 @@ impl Renderer {
         Ok(())
     }
 
+    /// Draw a border around the wallpaper. The background mode must be set again afterwards.
+    #[inline]
+    pub fn set_border(&mut self, border: Border) {
+        self.border = border;
+    }
+
+    /// Show `backdrop` through the transparent pixels of the images. The background mode must
+    /// be set again afterwards.
+    #[inline]
+    pub fn set_backdrop(&mut self, backdrop: Backdrop) {
+        self.backdrop = backdrop;
+    }
+
+    /// Limit how much `contain` mode enlarges the wallpaper. The background mode must be set
+    /// again afterwards.
+    #[inline]
+    pub fn set_max_upscale(&mut self, max_upscale: f32) {
+        self.max_upscale = max_upscale;
+    }
+
+    fn set_border_uniforms(&self) -> Result<()> {
+        let (size, border) = self.display_size();
+        self.set_border_size(border_size(size, border))?;
+        unsafe {
+            let loc = self
+                .gl
+                .GetUniformLocation(self.program, b"border_color\0".as_ptr() as *const _);
+            self.check_error("getting the uniform location")?;
+            self.gl.Uniform4fv(loc, 1, self.border.color.as_ptr());
+            self.check_error("calling Uniform4fv")?;
+        }
+        Ok(())
+    }
+
+    /// Pass the backdrop to the shader, `clamped` is true when the texture coordinates outside
+    /// of the image show nothing
+    fn set_backdrop_uniforms(&self, clamped: bool) -> Result<()> {
+        unsafe {
+            let loc = self
+                .gl
+                .GetUniformLocation(self.program, b"backdrop_top\0".as_ptr() as *const _);
+            self.check_error("getting the uniform location")?;
+            self.gl.Uniform4fv(loc, 1, self.backdrop.top.as_ptr());
+            self.check_error("calling Uniform4fv")?;
+
+            let loc = self
+                .gl
+                .GetUniformLocation(self.program, b"backdrop_bottom\0".as_ptr() as *const _);
+            self.check_error("getting the uniform location")?;
+            self.gl.Uniform4fv(loc, 1, self.backdrop.bottom.as_ptr());
+            self.check_error("calling Uniform4fv")?;
+
+            let loc = self
+                .gl
+                .GetUniformLocation(self.program, b"backdrop_clamped\0".as_ptr() as *const _);
+            self.check_error("getting the uniform location")?;
+            self.gl.Uniform1f(loc, if clamped { 1.0 } else { 0.0 });
+            self.check_error("calling Uniform1f")?;
+        }
+        Ok(())
+    }
+
+    /// Set the fraction of the display covered by each side of the border
+    fn set_border_size(&self, border_size: [f32; 2]) -> Result<()> {
+        unsafe {
+            let loc = self
+                .gl
+                .GetUniformLocation(self.program, b"border_size\0".as_ptr() as *const _);
+            self.check_error("getting the uniform location")?;
+            self.gl.Uniform2fv(loc, 1, border_size.as_ptr());
+            self.check_error("calling Uniform2fv")?;
+        }
+        Ok(())
+    }
+
+    /// Pass the average colors of the wallpapers to the transitions fading through them.
+    /// The alpha is 0.0 when the color of a wallpaper has not been computed.
+    fn set_average_color_uniforms(&self) -> Result<()> {
+        let average_color = |wallpaper: Option<&Wallpaper>| match wallpaper
+            .and_then(|wallpaper| wallpaper.stats().average_color)
+        {
+            Some([r, g, b]) => [r, g, b, 1.0],
+            None => [0.0; 4],
+        };
+        let set_uniform = |name: &[u8], color: [f32; 4]| unsafe {
+            // The uniforms only exist in the shaders using them
+            let loc = self
+                .gl
+                .GetUniformLocation(self.program, name.as_ptr() as *const _);
+            self.check_error("getting the uniform location")?;
+            self.gl.Uniform4fv(loc, 1, color.as_ptr());
+            self.check_error("calling Uniform4fv")
+        };
+        set_uniform(
+            b"from_average\0",
+            average_color(self.prev_wallpaper.as_ref()),
+        )?;
+        set_uniform(
+            b"to_average\0",
+            average_color(Some(&self.current_wallpaper)),
+        )
+    }
+
+    /// Return the offset of the current wallpaper set by the background mode, before panning
+    pub fn wallpaper_offset(&self) -> f32 {
+        self.current_wallpaper.offset
+    }
+
+    /// Return the part of the current image shown in `mode`, along each axis
+    fn wallpaper_texture_scale(&self, mode: BackgroundMode) -> [f32; 2] {
+        let (width, height) = self.wallpaper_area();
+        texture_scale(
+            mode,
+            (width as f32, height as f32),
+            (
+                self.current_wallpaper.get_image_width() as f32,
+                self.current_wallpaper.get_image_height() as f32,
+            ),
+            self.max_upscale,
+        )
+    }
+
+    /// Return true if the offset moves the wallpaper up and down in `mode`, because the top and
+    /// the bottom of the image are cropped
+    pub fn is_cropped_vertically(&self, mode: BackgroundMode) -> bool {
+        self.wallpaper_texture_scale(mode)[1] < 1.0
+    }
+
+    /// Return true if the offset moves the wallpaper at all in `mode`, because a side of the
+    /// image is cropped
+    pub fn is_cropped(&self, mode: BackgroundMode) -> bool {
+        let [x, y] = self.wallpaper_texture_scale(mode);
+        x < 1.0 || y < 1.0
+    }
+
+    /// Move the wallpaper on the screen without recalculating the background mode
+    pub fn set_texture_offset(&self, offset: f32) -> Result<()> {
+        unsafe {
+            let loc = self
+                .gl
+                .GetUniformLocation(self.program, b"texture_offset\0".as_ptr() as *const _);
+            self.check_error("getting the uniform location")?;
+            self.gl.Uniform1f(loc, offset);
+            self.check_error("calling Uniform1f")?;
+        }
+
+        Ok(())
+    }
+
+    pub fn set_color_adjustment(&mut self, color_adjustment: ColorAdjustment) -> Result<()> {
+        self.color_adjustment = color_adjustment;
+        self.set_color_adjustment_uniforms()
+    }
+
+    /// Mix the wallpaper with `color` by `factor`, from 0.0 (disabled) to 1.0
+    pub fn set_night_light(&mut self, color: [f32; 3], factor: f32) -> Result<()> {
+        self.night_light = (color, factor);
+        self.set_color_adjustment_uniforms()
+    }
+
+    #[inline]
+    pub fn set_dither(&mut self, dither: bool) {
+        self.dither = dither;
+    }
+
+    #[inline]
+    pub fn night_light(&self) -> ([f32; 3], f32) {
+        self.night_light
+    }
+
+    fn set_color_adjustment_uniforms(&self) -> Result<()> {
+        let ColorAdjustment {
+            brightness,
+            contrast,
+            gamma,
+            vignette_strength,
+            vignette_radius,
+        } = self.color_adjustment;
+        let (night_color, night_factor) = self.night_light;
+        for (name, value) in [
+            (b"brightness\0".as_slice(), brightness),
+            (b"contrast\0".as_slice(), contrast),
+            (b"gamma\0".as_slice(), gamma),
+            (b"night_factor\0".as_slice(), night_factor),
+            (b"vignette_strength\0".as_slice(), vignette_strength),
+            (b"vignette_radius\0".as_slice(), vignette_radius),
+        ] {
+            unsafe {
+                let loc = self
+                    .gl
+                    .GetUniformLocation(self.program, name.as_ptr() as *const _);
+                self.check_error("getting the uniform location")?;
+                self.gl.Uniform1f(loc, value);
+                self.check_error("calling Uniform1f")?;
+            }
+        }
+        unsafe {
+            let loc = self
+                .gl
+                .GetUniformLocation(self.program, b"night_color\0".as_ptr() as *const _);
+            self.check_error("getting the uniform location")?;
+            self.gl.Uniform3fv(loc, 1, night_color.as_ptr());
+            self.check_error("calling Uniform3fv")?;
+        }
+
+        Ok(())
+    }
+
     #[inline]
     pub fn start_transition(&mut self, transition_time: u32) {
         match self.transition_status {
-            TransitionStatus::Started | TransitionStatus::Running { .. } => unreachable!(),
-            TransitionStatus::Ended => self.transition_status = TransitionStatus::Started,
+            TransitionStatus::Uploaded
+            | TransitionStatus::Started
+            | TransitionStatus::Running { .. } => unreachable!(),
+            TransitionStatus::Held | TransitionStatus::Ended => {
+                self.transition_status = TransitionStatus::Started
+            }
         }
         // Needed to skip the initial transition depending on the configuration
         self.transition_time = transition_time;
     }
 
+    /// Keep showing the previous wallpaper until [`Renderer::start_transition`] is called.
+    /// The wallpapers loaded in the meantime replace the held one.
+    pub fn hold_transition(&mut self) {
+        self.transition_status = TransitionStatus::Held;
+    }
+
+    #[inline]
+    pub fn transition_held(&self) -> bool {
+        matches!(self.transition_status, TransitionStatus::Held)
+    }
+
+    /// Wait for the GPU to finish uploading the new wallpaper, then start the transition.
+    /// Its time starts from the next frame, so that a slow upload doesn't skip a part of it.
+    pub fn start_transition_after_upload(&mut self, transition_time: u32) -> Result<()> {
+        self.start_transition(transition_time);
+        unsafe {
+            self.gl.Finish();
+            self.check_error("waiting for the texture upload")?;
+        }
+        if transition_time != 0 {
+            self.transition_status = TransitionStatus::Uploaded;
+        }
+        Ok(())
+    }
+
+    /// Play the transition from the current wallpaper to `image`, or to itself, then draw the
+    /// current wallpaper again, as if nothing had changed. A running transition is ended first.
+    pub fn start_preview(
+        &mut self,
+        image: Option<(DynamicImage, ImageStats)>,
+        mode: BackgroundMode,
+        offset: Option<f32>,
+        filter: TextureFilter,
+        transition_time: u32,
+    ) -> Result<()> {
+        if self.transition_running() {
+            self.transition_finished();
+        }
+        self.end_preview()?;
+        match image {
+            Some((image, stats)) => {
+                // The current wallpaper becomes the previous one until the preview ends
+                self.load_wallpaper(image, stats, mode, offset, filter)?;
+                self.preview = Some((mode, offset));
+            }
+            None => {
+                // Start from a copy of the wallpaper, the transition ends on the wallpaper itself
+                self.prev_wallpaper = Some(self.draw_to_texture()?);
+                self.bind_wallpapers(mode, offset)?;
+                self.set_average_color_uniforms()?;
+            }
+        }
+        self.start_transition(transition_time);
+        Ok(())
+    }
+
+    /// Draw the wallpaper put aside by [`Renderer::start_preview`] again, dropping the preview
+    fn end_preview(&mut self) -> Result<()> {
+        let Some((mode, offset)) = self.preview.take() else {
+            return Ok(());
+        };
+        if let Some(wallpaper) = self.prev_wallpaper.take() {
+            self.current_wallpaper = wallpaper;
+            unsafe {
+                self.gl.ActiveTexture(gl::TEXTURE1);
+                self.check_error("activating gl::TEXTURE1")?;
+            }
+            self.current_wallpaper.bind()?;
+        }
+        self.set_mode(mode, offset)?;
+        self.set_average_color_uniforms()
+    }
+
+    /// Draw what is currently on the display into a texture and use it in place of the current
+    /// wallpaper, so that the next transition starts from it
+    pub fn capture(&mut self) -> Result<()> {
+        self.current_wallpaper = self.draw_to_texture()?;
+        Ok(())
+    }
+
+    /// Draw what is currently on the display into a new texture. The colors are captured
+    /// without any adjustment, since they are applied again while drawing, and the border is
+    /// left out.
+    fn draw_to_texture(&mut self) -> Result<Wallpaper> {
+        // The capture is drawn in the orientation of the wallpaper, like the images
+        let (width, height) = self.wallpaper_area();
+        let display_transform = (*self.display_info).borrow().transform;
+        ensure!(
+            width > 0 && height > 0,
+            "the display has not been configured yet"
+        );
+
+        let mut texture = 0;
+        let mut framebuffer = 0;
+        let res = unsafe {
+            self.gl.GenTextures(1, &mut texture);
+            self.gl.ActiveTexture(gl::TEXTURE2);
+            self.gl.BindTexture(gl::TEXTURE_2D, texture);
+            self.gl.TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                gl::RGBA as i32,
+                width,
+                height,
+                0,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                std::ptr::null(),
+            );
+            self.gl
+                .TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+            self.gl
+                .TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+            self.gl.GenFramebuffers(1, &mut framebuffer);
+            self.gl.BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+            self.gl.FramebufferTexture2D(
+                gl::FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::TEXTURE_2D,
+                texture,
+                0,
+            );
+            self.check_error("creating the capture framebuffer")
+                .and_then(|_| {
+                    ensure!(
+                        self.gl.CheckFramebufferStatus(gl::FRAMEBUFFER) == gl::FRAMEBUFFER_COMPLETE,
+                        "the capture framebuffer is not complete"
+                    );
+                    self.draw_capture(width, height)
+                })
+        };
+
+        // Restore the state used to draw on the display, even if the capture failed
+        let restored = unsafe {
+            self.gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+            self.gl.DeleteFramebuffers(1, &framebuffer);
+            self.gl.ActiveTexture(gl::TEXTURE1);
+            self.check_error("deleting the capture framebuffer")
+                .and_then(|_| self.resize())
+                .and_then(|_| self.set_projection_matrix(display_transform))
+                .and_then(|_| self.set_color_adjustment_uniforms())
+                .and_then(|_| self.set_border_uniforms())
+        };
+
+        match res.and(restored) {
+            Ok(()) => Ok(Wallpaper::from_texture(
+                self.gl.clone(),
+                texture,
+                width as u32,
+                height as u32,
+            )),
+            Err(err) => {
+                unsafe { self.gl.DeleteTextures(1, &texture) };
+                Err(err)
+            }
+        }
+    }
+
+    /// Draw the wallpaper in the bound framebuffer, without the display transform, the color
+    /// adjustment and the border
+    unsafe fn draw_capture(&mut self, width: i32, height: i32) -> Result<()> {
+        self.gl.Viewport(0, 0, width, height);
+        self.check_error("setting the capture viewport")?;
+        self.set_border_size([0.0, 0.0])?;
+        // The texture coordinates are upside down compared to the framebuffer ones
+        let loc = self
+            .gl
+            .GetUniformLocation(self.program, b"projection_matrix\0".as_ptr() as *const _);
+        self.check_error("getting the uniform location for projection_matrix")?;
+        self.gl
+            .UniformMatrix2fv(loc, 1, 0, [1.0, 0.0, 0.0, -1.0].as_ptr());
+        self.check_error("calling UniformMatrix2fv")?;
+
+        let color_adjustment = std::mem::take(&mut self.color_adjustment);
+        let night_light = std::mem::replace(&mut self.night_light, ([1.0; 3], 0.0));
+        let res = self
+            .set_color_adjustment_uniforms()
+            .and_then(|_| self.draw());
+        self.color_adjustment = color_adjustment;
+        self.night_light = night_light;
+        res
+    }
+
     #[inline]
+    /// Fill the whole surface with `color`, leaving the wallpaper textures untouched
+    pub fn clear_to_color(&self, color: [f32; 4]) -> Result<()> {
+        let [red, green, blue, alpha] = color;
+        unsafe {
+            self.gl.ClearColor(red, green, blue, alpha);
+            self.gl.Clear(gl::COLOR_BUFFER_BIT);
+            self.check_error("clearing the screen")?;
+            // The wallpaper is drawn over the default clear color
+            self.gl.ClearColor(0.0, 0.0, 0.0, 0.0);
+        }
+        Ok(())
+    }
+
     pub fn clear_after_draw(&self) -> Result<()> {
         unsafe {
             // Unbind the framebuffer and renderbuffer before deleting.
@@ -347,6 +936,50 @@ impl Renderer {
         Ok(())
     }
 
+    /// Read back the frame drawn last, before its buffer is swapped. The image has the size of
+    /// the display in physical pixels, with the scale factor applied, and is in its orientation.
+    pub fn read_pixels(&self) -> Result<RgbaImage> {
+        let info = (*self.display_info).borrow();
+        let (width, height) = (info.adjusted_width(), info.adjusted_height());
+        let transform = info.transform;
+        drop(info);
+        ensure!(
+            width > 0 && height > 0,
+            "the display has not been configured yet"
+        );
+
+        let mut image = RgbaImage::new(width as u32, height as u32);
+        unsafe {
+            self.gl.PixelStorei(gl::PACK_ALIGNMENT, 1);
+            self.check_error("setting the pack alignment")?;
+            self.gl.ReadPixels(
+                0,
+                0,
+                width,
+                height,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                image.as_mut_ptr() as *mut _,
+            );
+            self.check_error("reading the pixels of the frame")?;
+        }
+        // OpenGL returns the rows from the bottom
+        imageops::flip_vertical_in_place(&mut image);
+
+        // The buffer is in the orientation of the output, the compositor applies the inverse of
+        // its transform to show it. The flipped transforms are their own inverse.
+        Ok(match transform {
+            Transform::_90 => imageops::rotate90(&image),
+            Transform::_180 => imageops::rotate180(&image),
+            Transform::_270 => imageops::rotate270(&image),
+            Transform::Flipped => imageops::flip_horizontal(&image),
+            Transform::Flipped90 => imageops::rotate270(&imageops::flip_horizontal(&image)),
+            Transform::Flipped180 => imageops::flip_vertical(&image),
+            Transform::Flipped270 => imageops::rotate90(&imageops::flip_horizontal(&image)),
+            _ => image,
+        })
+    }
+
     pub fn resize(&mut self) -> Result<()> {
         let info = (*self.display_info).borrow();
         unsafe {
@@ -363,6 +996,9 @@ impl Renderer {
 
     #[inline]
     pub fn transition_finished(&mut self) {
+        if let Err(err) = self.end_preview() {
+            error!("{err:?}");
+        }
         // By binding transparent pixel into the old wallpaper, we can delete the texture,
         // freeing space from the GPU
         unsafe {
@@ -372,16 +1008,21 @@ impl Renderer {
                 .BindTexture(gl::TEXTURE_2D, self.transparent_texture);
             self.prev_wallpaper.take();
         }
+        // The held transition, if any, is not needed anymore
+        self.transition_status = TransitionStatus::Ended;
+        self.first_pass = None;
     }
 
     #[inline]
     pub fn force_transition_end(&mut self) {
         // Force the transition to end
         self.transition_status = TransitionStatus::Ended;
+        self.first_pass = None;
     }
 
     #[inline]
     pub fn update_transition(&mut self, transition: Transition, transform: Transform) {
+        let multi_pass = transition.is_multi_pass();
         match create_program(&self.gl, transition) {
             Ok(program) => {
                 unsafe {
@@ -392,11 +1033,20 @@ impl Renderer {
                     self.force_transition_end();
                 }
                 self.program = program;
+                self.multi_pass = multi_pass;
                 unsafe {
                     if let Err(err) = self.set_projection_matrix(transform) {
                         error!("{err:?}");
                     }
                 }
+                // The uniforms are bound to the program, set them again
+                if let Err(err) = self
+                    .set_color_adjustment_uniforms()
+                    .and_then(|_| self.set_border_uniforms())
+                    .and_then(|_| self.set_average_color_uniforms())
+                {
+                    error!("{err:?}");
+                }
             }
             Err(err) => error!("{err:?}"),
         }
@@ -405,13 +1055,26 @@ impl Renderer {
     #[inline]
     pub fn transition_running(&self) -> bool {
         match self.transition_status {
-            TransitionStatus::Started | TransitionStatus::Running { .. } => true,
-            TransitionStatus::Ended => false,
+            TransitionStatus::Uploaded
+            | TransitionStatus::Started
+            | TransitionStatus::Running { .. } => true,
+            TransitionStatus::Held | TransitionStatus::Ended => false,
         }
     }
 
+    /// Rotate or flip the wallpaper independently of the display transform.
+    /// Both the projection matrix and the background mode must be set again afterwards.
+    #[inline]
+    pub fn set_wallpaper_transform(&mut self, transform: Transform) {
+        self.wallpaper_transform = transform;
+    }
+
     pub unsafe fn set_projection_matrix(&self, transform: Transform) -> Result<()> {
-        let projection_matrix = projection_matrix(transform);
+        // The wallpaper transform is applied first, then the one of the display
+        let projection_matrix = multiply_matrices(
+            projection_matrix(self.wallpaper_transform),
+            projection_matrix(transform),
+        );
         let loc = self
             .gl
             .GetUniformLocation(self.program, b"projection_matrix\0".as_ptr() as *const _);
@@ -476,6 +1139,13 @@ fn create_program(gl: &gl::Gl, transition: Transition) -> Result<gl::types::GLui
         ensure!(loc > 0, "u_texture not found");
         gl.Uniform1i(loc, 1);
         gl_check!(gl, "calling Uniform1i");
+        // Only used by the multi-pass transitions
+        let loc = gl.GetUniformLocation(program, b"u_first_pass\0".as_ptr() as *const _);
+        gl_check!(gl, "getting the uniform location for u_first_pass");
+        if loc >= 0 {
+            gl.Uniform1i(loc, FIRST_PASS_TEXTURE_UNIT);
+            gl_check!(gl, "calling Uniform1i");
+        }
 
         uniform_callback(gl, program)?;
 
@@ -483,61 +1153,6 @@ fn create_program(gl: &gl::Gl, transition: Transition) -> Result<gl::types::GLui
     }
 }
 
-#[rustfmt::skip]
-fn projection_matrix(transform: Transform) -> [f32; 4] {
-    match transform {
-        Transform::Normal => {
-            [
-                1.0, 0.0,
-                0.0, 1.0,
-            ]
-        }
-        Transform::_90 => {
-            [
-                0.0, -1.0,
-                1.0, 0.0,
-            ]
-        }
-        Transform::_180 => {
-            [
-                -1.0, 0.0,
-                0.0, -1.0,
-            ]
-        }
-        Transform::_270 => {
-            [
-                0.0, 1.0,
-                -1.0, 0.0,
-            ]
-        }
-        Transform::Flipped => {
-            [
-                -1.0, 0.0,
-                0.0, 1.0,
-            ]
-        }
-        Transform::Flipped90 => {
-            [
-                0.0, -1.0,
-                -1.0, 0.0,
-            ]
-        }
-        Transform::Flipped180 => {
-            [
-                1.0, 0.0,
-                0.0, -1.0,
-            ]
-        }
-        Transform::Flipped270 => {
-            [
-                0.0, 1.0,
-                1.0, 0.0,
-            ]
-        }
-        _ => unreachable!()
-    }
-}
-
 impl Deref for Renderer {
     type Target = gl::Gl;
 
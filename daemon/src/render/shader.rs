@@ -83,19 +83,111 @@ layout (location = 5) uniform sampler2D u_texture;
 uniform float progress;
 uniform float ratio;
 uniform float texture_offset;
+uniform float prev_texture_offset;
+uniform float brightness;
+uniform float contrast;
+uniform float gamma;
+uniform vec3 night_color;
+uniform float night_factor;
+uniform vec2 border_size;
+uniform vec4 border_color;
+uniform float vignette_strength;
+uniform float vignette_radius;
+uniform float dither;
+uniform vec4 backdrop_top;
+uniform vec4 backdrop_bottom;
+uniform float backdrop_clamped;
+// 0 when the transition is drawn at once. The first pass of a multi-pass transition (1) is drawn
+// in u_first_pass without any adjustment, the last one (2) reads it with getFirstPassColor.
+uniform int render_pass;
+uniform sampler2D u_first_pass;
+
+// Moves a point of the wallpaper to the matching point of the framebuffer, set in main()
+highp mat2 frag_per_uv;
 
 vec4 transition(vec2);
 
+vec4 adjustColor(vec4 color) {
+    vec3 rgb = pow(color.rgb, vec3(1.0 / gamma));
+    rgb = (rgb - 0.5) * contrast + 0.5;
+    rgb *= mix(vec3(1.0), night_color, night_factor);
+    return vec4(clamp(rgb * brightness, 0.0, 1.0), color.a);
+}
+
+// Darken the wallpaper towards its corners, the distance is measured in the proportions of
+// the area inside the border so that the vignette stays round
+vec4 vignette(vec4 color, vec2 uv) {
+    if (vignette_strength == 0.0) {
+        return color;
+    }
+    vec2 size = vec2(ratio, 1.0);
+    float dist = length((uv - 0.5) * size) / length(0.5 * size);
+    float shade = smoothstep(vignette_radius, 1.0, dist);
+    return vec4(color.rgb * (1.0 - vignette_strength * shade), color.a);
+}
+
+// Offset the color by a threshold of a 4x4 Bayer matrix, smaller than a step of 8 bits, so
+// that the blended colors of the transitions are spread between the two closest steps
+vec4 ditherColor(vec4 color) {
+    if (dither == 0.0) {
+        return color;
+    }
+    const mat4 bayer = mat4(
+        0.0, 12.0, 3.0, 15.0,
+        8.0, 4.0, 11.0, 7.0,
+        2.0, 14.0, 1.0, 13.0,
+        10.0, 6.0, 9.0, 5.0
+    );
+    ivec2 pos = ivec2(mod(gl_FragCoord.xy, 4.0));
+    float threshold = (bayer[pos.x][pos.y] + 0.5) / 16.0 - 0.5;
+    return vec4(clamp(color.rgb + threshold / 255.0, 0.0, 1.0), color.a);
+}
+
+// Composite the color of the image sampled at texture_uv over the backdrop, which goes from the
+// top of the wallpaper to its bottom. The empty area around the image in the clamped modes is
+// left transparent.
+vec4 overBackdrop(vec4 color, vec2 uv, vec2 texture_uv) {
+    if (backdrop_top.a == 0.0 && backdrop_bottom.a == 0.0) {
+        return color;
+    }
+    if (backdrop_clamped == 1.0
+        && (any(lessThan(texture_uv, vec2(0.0))) || any(greaterThan(texture_uv, vec2(1.0))))) {
+        return color;
+    }
+    vec4 backdrop = mix(backdrop_top, backdrop_bottom, clamp(uv.y, 0.0, 1.0));
+    return vec4(mix(backdrop.rgb, color.rgb, color.a), color.a + backdrop.a * (1.0 - color.a));
+}
+
 vec4 getFromColor(vec2 uv) {
-    uv = (uv - texture_offset) * prevTextureScale + (texture_offset);
-    return texture(u_prev_texture, uv);
+    vec2 texture_uv = (uv - prev_texture_offset) * prevTextureScale + (prev_texture_offset);
+    return overBackdrop(texture(u_prev_texture, texture_uv), uv, texture_uv);
 }
 
 vec4 getToColor(vec2 uv) {
-    uv = (uv - texture_offset) * textureScale + (texture_offset);
-    return texture(u_texture, uv);
+    vec2 texture_uv = (uv - texture_offset) * textureScale + (texture_offset);
+    return overBackdrop(texture(u_texture, texture_uv), uv, texture_uv);
+}
+
+// Color drawn by the first pass at the point of the wallpaper moved by `offset` from `uv`, the
+// point of the current fragment. The passes draw the same triangles, whatever the transforms.
+vec4 getFirstPassColor(highp vec2 uv, highp vec2 offset) {
+    highp vec2 target = clamp(uv + offset, 0.0, 1.0);
+    highp vec2 frag = gl_FragCoord.xy + frag_per_uv * (target - uv);
+    return texture(u_first_pass, frag / vec2(textureSize(u_first_pass, 0)));
 }
 
 void main() {
-    FragColor = transition(v_texcoord);
+    // The wallpaper is drawn in the area inside the border
+    highp vec2 uv = (v_texcoord - border_size) / (1.0 - 2.0 * border_size);
+    // The derivatives are only defined outside of the branches
+    frag_per_uv = inverse(mat2(dFdx(uv), dFdy(uv)));
+    if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0)))) {
+        FragColor = adjustColor(border_color);
+        return;
+    }
+    if (render_pass == 1) {
+        FragColor = transition(uv);
+        return;
+    }
+    FragColor = ditherColor(vignette(adjustColor(transition(uv)), uv));
 }";
@@ -0,0 +1,50 @@
+// Blur the old image, swap to the new one when the blur is at its peak, then sharpen the new
+// one. The gaussian is separable: the first pass blurs the images horizontally, the last one
+// blurs the result of the first vertically.
+
+uniform float radius; // = 0.04
+uniform float crossover; // = 0.5
+
+// Samples along each axis
+const int taps = 25;
+// Part of the transition around the crossover where the two blurred images are mixed, so that
+// the swap doesn't pop
+const float swap_window = 0.1;
+
+vec4 swapped(vec2 uv, float swap) {
+  if (swap == 0.0) {
+    return getFromColor(uv);
+  }
+  if (swap == 1.0) {
+    return getToColor(uv);
+  }
+  return mix(getFromColor(uv), getToColor(uv), swap);
+}
+
+vec4 transition(vec2 uv) {
+  float peak = clamp(crossover, 0.01, 0.99);
+  // Grow the blur up to the crossover, then shrink it
+  float blur = progress < peak ? progress / peak : (1.0 - progress) / (1.0 - peak);
+  blur = smoothstep(0.0, 1.0, blur);
+  float swap = smoothstep(peak - swap_window / 2.0, peak + swap_window / 2.0, progress);
+  if (blur == 0.0) {
+    return swapped(uv, step(0.5, swap));
+  }
+  // The radius is a fraction of the height, scale the horizontal axis so that the blur is not
+  // stretched by the display ratio
+  bool horizontal = render_pass != 2;
+  vec2 axis = horizontal ? vec2(1.0 / ratio, 0.0) : vec2(0.0, 1.0);
+  vec2 spacing = axis * radius * blur / float(taps / 2);
+  vec4 color = vec4(0.0);
+  float total = 0.0;
+  for (int i = -taps / 2; i <= taps / 2; i++) {
+    float pos = float(i) / float(taps / 2);
+    float weight = exp(-2.0 * pos * pos);
+    vec2 offset = float(i) * spacing;
+    // Repeat the pixels at the edges of the display instead of blurring with the outside
+    color += weight * (horizontal ? swapped(clamp(uv + offset, 0.0, 1.0), swap)
+                                  : getFirstPassColor(uv, offset));
+    total += weight;
+  }
+  return color / total;
+}
@@ -1,3 +1,16 @@
+// Crossfade the wallpapers. With through_average, pass through the mix of their average colors
+// at the middle of the transition, unless one of them is not known (its alpha is 0.0).
+
+uniform bool through_average; // = false
+uniform vec4 from_average;
+uniform vec4 to_average;
+
 vec4 transition(vec2 uv) {
+  if (!through_average || from_average.a == 0.0 || to_average.a == 0.0) {
     return mix(getFromColor(uv), getToColor(uv), progress);
+  }
+  vec4 midpoint = vec4(mix(from_average.rgb, to_average.rgb, 0.5), 1.0);
+  return progress < 0.5
+    ? mix(getFromColor(uv), midpoint, progress * 2.0)
+    : mix(midpoint, getToColor(uv), progress * 2.0 - 1.0);
 }
@@ -0,0 +1,23 @@
+// Reveal the new image through a circle growing from the center, or hide the old one
+// through a shrinking circle when contracting.
+
+uniform vec2 center; // = vec2(0.5, 0.5)
+uniform float smoothness; // = 0.02
+uniform bool contract; // = false
+
+vec4 transition(vec2 uv) {
+  // Scale the horizontal axis so that the circle is not stretched by the display ratio
+  vec2 aspect = vec2(ratio, 1.0);
+  float dist = distance(uv * aspect, center * aspect);
+  // Distance from the center to the farthest corner, so that the circle covers the display
+  float max_dist = max(
+    max(length(center * aspect), length((vec2(1.0, 0.0) - center) * aspect)),
+    max(length((vec2(0.0, 1.0) - center) * aspect), length((vec2(1.0) - center) * aspect))
+  );
+  float radius = (contract ? 1.0 - progress : progress) * (max_dist + smoothness);
+  float inside = 1.0 - smoothstep(radius - smoothness, radius, dist);
+  // When contracting, the old image is inside the circle
+  return contract
+    ? mix(getToColor(uv), getFromColor(uv), inside)
+    : mix(getFromColor(uv), getToColor(uv), inside);
+}
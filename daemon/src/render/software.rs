@@ -0,0 +1,283 @@
+//! Draw the wallpapers in shared memory buffers, used when EGL is not available, e.g. in some
+//! virtual machines. The image is scaled on the CPU when it or the display changes, so only the
+//! static wallpapers are shown: there are no transitions, color adjustments or videos, and the
+//! wallpaper is not rotated. The buffers are in the orientation of the display, the compositor
+//! rotates them.
+
+use std::{cell::RefCell, rc::Rc};
+
+use color_eyre::{eyre::Context, Result};
+use image::{
+    imageops::{self, FilterType},
+    RgbaImage,
+};
+use smithay_client_toolkit::{
+    reexports::client::protocol::{wl_shm, wl_surface::WlSurface},
+    shm::{slot::SlotPool, Shm},
+};
+
+use crate::{
+    display_info::DisplayInfo,
+    wallpaper_info::{BackgroundMode, DEFAULT_MAX_UPSCALE},
+};
+
+use super::{
+    geometry::{texture_offset, texture_scale, visible_area, wallpaper_area},
+    Backdrop, Border,
+};
+
+/// Shown where the image doesn't cover the display, like the transparent border of the
+/// textures drawn with OpenGL
+const BACKGROUND: [u8; 4] = [0, 0, 0, 255];
+
+pub struct SoftwareRenderer {
+    pool: SlotPool,
+    display_info: Rc<RefCell<DisplayInfo>>,
+    image: RgbaImage,
+    /// Point of the image kept visible in `center` mode, see [`crate::image_loader::ImageStats`]
+    focus: Option<[f32; 2]>,
+    mode: BackgroundMode,
+    offset: Option<f32>,
+    border: Border,
+    backdrop: Backdrop,
+    max_upscale: f32,
+    /// Last frame drawn, copied to the buffers until the wallpaper or the display change
+    frame: Option<Frame>,
+}
+
+/// Pixels of a frame in the format of the buffers, for the size and border width of the display
+struct Frame {
+    size: (i32, i32),
+    border: i32,
+    pixels: Vec<u8>,
+}
+
+impl SoftwareRenderer {
+    pub fn new(shm: &Shm, display_info: Rc<RefCell<DisplayInfo>>) -> Result<Self> {
+        Ok(Self {
+            // The pool grows to fit the buffers of the display
+            pool: SlotPool::new(1, shm).context("creating the shared memory pool")?,
+            display_info,
+            image: RgbaImage::from_raw(1, 1, BACKGROUND.to_vec()).unwrap(),
+            focus: None,
+            mode: BackgroundMode::default(),
+            offset: None,
+            border: Border::default(),
+            backdrop: Backdrop::default(),
+            max_upscale: DEFAULT_MAX_UPSCALE,
+            frame: None,
+        })
+    }
+
+    /// Show `image` from the next draw, replacing the previous one right away
+    pub fn load_wallpaper(
+        &mut self,
+        image: RgbaImage,
+        focus: Option<[f32; 2]>,
+        mode: BackgroundMode,
+        offset: Option<f32>,
+    ) {
+        self.image = image;
+        self.focus = focus;
+        self.set_mode(mode, offset);
+        self.frame = None;
+    }
+
+    pub fn set_mode(&mut self, mode: BackgroundMode, offset: Option<f32>) {
+        if self.mode != mode || self.offset != offset {
+            self.mode = mode;
+            self.offset = offset;
+            self.frame = None;
+        }
+    }
+
+    pub fn set_border(&mut self, border: Border) {
+        self.border = border;
+        self.frame = None;
+    }
+
+    pub fn set_backdrop(&mut self, backdrop: Backdrop) {
+        self.backdrop = backdrop;
+        self.frame = None;
+    }
+
+    pub fn set_max_upscale(&mut self, max_upscale: f32) {
+        self.max_upscale = max_upscale;
+        self.frame = None;
+    }
+
+    /// Attach a buffer with the wallpaper to the surface, the caller damages and commits it
+    pub fn draw(&mut self, wl_surface: &WlSurface) -> Result<()> {
+        let info = self.display_info.borrow();
+        let size = (info.scaled_width(), info.scaled_height());
+        let border = info.scaled(self.border.width as i32);
+        drop(info);
+
+        let frame = match self.frame.take() {
+            Some(frame) if frame.size == size && frame.border == border => frame,
+            _ => self.draw_frame(size, border),
+        };
+        let res = self.attach(wl_surface, size, |canvas| {
+            canvas.copy_from_slice(&frame.pixels)
+        });
+        self.frame = Some(frame);
+        res
+    }
+
+    /// Scale the image and fill a frame of `size` with it, inside the border
+    fn draw_frame(&self, size: (i32, i32), border: i32) -> Frame {
+        let (width, height) = wallpaper_area(size, border);
+        let scaled = self.scaled_image((width, height));
+        let border_color = bgrx(self.border.color.map(|c| (c * 255.0).round() as u8));
+        let mut pixels = vec![0; (size.0.max(0) * size.1.max(0) * 4) as usize];
+        fill(&mut pixels, size.0, |x, y| {
+            let (x, y) = (x - border, y - border);
+            if x < 0 || y < 0 || x >= width || y >= height {
+                border_color
+            } else {
+                scaled.sample(x, y)
+            }
+        });
+        Frame {
+            size,
+            border,
+            pixels,
+        }
+    }
+
+    /// Attach a buffer filled with `color` to the surface, the caller damages and commits it
+    pub fn clear_to_color(&mut self, wl_surface: &WlSurface, color: [f32; 4]) -> Result<()> {
+        let info = self.display_info.borrow();
+        let size = (info.scaled_width(), info.scaled_height());
+        drop(info);
+
+        let color = bgrx(color.map(|c| (c * 255.0).round() as u8));
+        self.attach(wl_surface, size, |canvas| {
+            fill(canvas, size.0, |_, _| color);
+        })
+    }
+
+    /// Scale the visible part of the image to the pixels it covers in `area`
+    fn scaled_image(&self, (width, height): (i32, i32)) -> ScaledImage {
+        let (image_width, image_height) = self.image.dimensions();
+        let scale = texture_scale(
+            self.mode,
+            (width as f32, height as f32),
+            (image_width as f32, image_height as f32),
+            self.max_upscale,
+        );
+        let offset = texture_offset(self.mode, self.offset, self.focus, scale);
+        let [x, y, visible_width, visible_height] = visible_area(scale, offset);
+        // Size of the whole image on the display, and where its visible part starts
+        let full_width = width as f32 / scale[0];
+        let full_height = height as f32 / scale[1];
+        let origin = [
+            ((offset - offset / scale[0]) * width as f32 + x * full_width).round() as i32,
+            ((offset - offset / scale[1]) * height as f32 + y * full_height).round() as i32,
+        ];
+
+        let crop = imageops::crop_imm(
+            &self.image,
+            (x * image_width as f32) as u32,
+            (y * image_height as f32) as u32,
+            ((visible_width * image_width as f32).round() as u32).max(1),
+            ((visible_height * image_height as f32).round() as u32).max(1),
+        )
+        .to_image();
+        let image = imageops::resize(
+            &crop,
+            ((visible_width * full_width).round() as u32).max(1),
+            ((visible_height * full_height).round() as u32).max(1),
+            FilterType::Triangle,
+        );
+        ScaledImage {
+            image,
+            origin,
+            mode: self.mode,
+            backdrop: self.backdrop,
+            height,
+        }
+    }
+
+    fn attach(
+        &mut self,
+        wl_surface: &WlSurface,
+        (width, height): (i32, i32),
+        draw: impl FnOnce(&mut [u8]),
+    ) -> Result<()> {
+        if width <= 0 || height <= 0 {
+            // The display has not been configured yet
+            return Ok(());
+        }
+        let (buffer, canvas) = self
+            .pool
+            .create_buffer(width, height, width * 4, wl_shm::Format::Xrgb8888)
+            .context("creating the shared memory buffer")?;
+        draw(&mut canvas[..(width * height * 4) as usize]);
+        // The buffer is destroyed once the compositor releases it
+        buffer
+            .attach_to(wl_surface)
+            .context("attaching the shared memory buffer")
+    }
+}
+
+/// Fill the rows of `width` pixels of `canvas` with the color returned for each of them
+fn fill(canvas: &mut [u8], width: i32, pixel: impl Fn(i32, i32) -> [u8; 4]) {
+    if width <= 0 {
+        return;
+    }
+    for (y, row) in canvas.chunks_exact_mut(width as usize * 4).enumerate() {
+        for (x, color) in row.chunks_exact_mut(4).enumerate() {
+            color.copy_from_slice(&pixel(x as i32, y as i32));
+        }
+    }
+}
+
+/// Image scaled to the display, placed at `origin` in the area inside the border
+struct ScaledImage {
+    image: RgbaImage,
+    origin: [i32; 2],
+    mode: BackgroundMode,
+    backdrop: Backdrop,
+    /// Height of the area inside the border, which the backdrop goes through
+    height: i32,
+}
+
+impl ScaledImage {
+    /// Return the pixel shown at `x` and `y` in the format of the buffer. Like the textures,
+    /// the image is repeated in `tile` mode and its edges are stretched in `fit-border-color`.
+    fn sample(&self, x: i32, y: i32) -> [u8; 4] {
+        let (width, height) = (self.image.width() as i32, self.image.height() as i32);
+        let row = y;
+        let (x, y) = (x - self.origin[0], y - self.origin[1]);
+        let (x, y) = match self.mode {
+            BackgroundMode::Tile => (x.rem_euclid(width), y.rem_euclid(height)),
+            BackgroundMode::FitBorderColor => (x.clamp(0, width - 1), y.clamp(0, height - 1)),
+            _ if x < 0 || y < 0 || x >= width || y >= height => return bgrx(BACKGROUND),
+            _ => (x, y),
+        };
+        bgrx(self.over_backdrop(self.image.get_pixel(x as u32, y as u32).0, row))
+    }
+
+    /// Composite `color` over the backdrop at `row` of the area inside the border. The buffers
+    /// are opaque, the backdrop is composited over black first.
+    fn over_backdrop(&self, color: [u8; 4], row: i32) -> [u8; 4] {
+        if self.backdrop.is_transparent() {
+            return color;
+        }
+        let progress = (row as f32 / (self.height - 1).max(1) as f32).clamp(0.0, 1.0);
+        let alpha = color[3] as f32 / 255.0;
+        let Backdrop { top, bottom } = self.backdrop;
+        let component = |i: usize| {
+            let (top, bottom) = (top[i] * top[3], bottom[i] * bottom[3]);
+            let backdrop = (top + (bottom - top) * progress) * 255.0;
+            (backdrop * (1.0 - alpha) + color[i] as f32 * alpha).round() as u8
+        };
+        [component(0), component(1), component(2), color[3]]
+    }
+}
+
+/// Convert an RGBA color to the little endian XRGB8888 format of the buffers
+fn bgrx([r, g, b, _]: [u8; 4]) -> [u8; 4] {
+    [b, g, r, 255]
+}
@@ -139,6 +139,11 @@ macro_rules! transition_shader {
 
 transition_shader! {
     Transition {
+        // Blur the old wallpaper, swap at the peak of the blur and sharpen the new one
+        BlurSwitch {
+            radius: f32 = ("radius", 0.04),
+            crossover: f32 = ("crossover", 0.5)
+        } => 1500,
         BookFlip{} => 2000,
         Bounce {
             shadow_colour: [f32; 4] = ("shadow_colour", [0.0, 0.0, 0.0, 0.6]),
@@ -200,7 +205,9 @@ transition_shader! {
             thickness: f32 = ("edge_thickness", 0.001),
             brightness: f32 = ("edge_brightness", 8.0)
         } => 1500,
-        Fade{} => 300,
+        Fade {
+            through_average: bool = ("through_average", false)
+        } => 300,
         FilmBurn { seed: f32 = ("Seed", 2.31) } => 2000,
         GlitchDisplace{} => 1500,
         GlitchMemories{} => 1500,
@@ -218,12 +225,19 @@ transition_shader! {
         HorizontalClose{} => 2000,
         HorizontalOpen{} => 2000,
         InvertedPageCurl{} => 2000,
+        Iris {
+            center: [f32; 2] = ("center", [0.5, 0.5]),
+            smoothness: f32 = ("smoothness", 0.02),
+            contract: bool = ("contract", false)
+        } => 1500,
         LeftRight{} => 2000,
         LinearBlur { intensity: f32 = ("intensity", 0.1) } => 800,
         Mosaic{
             endx: i32 = ("endx", 2),
             endy: i32 = ("endy", -1)
         } => 2000,
+        // Swap the wallpapers instantly
+        None{} => 0,
         Overexposure{} => 2000,
         Pixelize {
             squares_min: [i32; 2] = ("squaresMin", [20, 20]),
@@ -280,3 +294,20 @@ transition_shader! {
         WindowBlinds {} => 1500
     }
 }
+
+impl Transition {
+    /// Return true if the transition is drawn in two passes, the last one reading the first
+    pub fn is_multi_pass(&self) -> bool {
+        matches!(self, Transition::BlurSwitch { .. })
+    }
+
+    /// Return true if the transition needs the average color of the wallpapers
+    pub fn uses_average_color(&self) -> bool {
+        matches!(
+            self,
+            Transition::Fade {
+                through_average: Some(true)
+            }
+        )
+    }
+}
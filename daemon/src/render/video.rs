@@ -0,0 +1,171 @@
+//! Play videos as wallpaper using libmpv, rendering directly into the EGL surface. mpv tells
+//! when a new frame is ready from its own thread, the event loop is woken up through a channel
+//! and the display only draws the video then.
+
+use std::{ffi::c_void, path::Path};
+
+use color_eyre::{
+    eyre::{anyhow, ContextCompat},
+    Result,
+};
+use egl::API as egl;
+use libmpv2::{
+    render::{MpvRenderUpdate, OpenGLInitParams, RenderContext, RenderParam, RenderParamApiType},
+    Mpv,
+};
+use smithay_client_toolkit::reexports::{
+    calloop::{
+        channel::{self, Sender},
+        LoopHandle,
+    },
+    client::QueueHandle,
+};
+
+use crate::{wallpaper_info::BackgroundMode, wpaperd::Wpaperd};
+
+/// Flag returned by `mpv_render_context_update` when there is a new frame to render
+const MPV_RENDER_UPDATE_FRAME: MpvRenderUpdate = 1;
+
+/// Return true if the file should be played with libmpv instead of being decoded as an image
+pub fn is_video(path: &Path) -> bool {
+    new_mime_guess::from_path(path)
+        .first()
+        .is_some_and(|guess| guess.type_() == "video")
+}
+
+fn get_proc_address(_ctx: &(), name: &str) -> *mut c_void {
+    egl.get_proc_address(name)
+        .map_or(std::ptr::null_mut(), |f| f as *mut c_void)
+}
+
+/// Forward the update callbacks of mpv to the event loop, with the name of the display
+#[derive(Clone)]
+pub struct VideoFrames {
+    sender: Sender<String>,
+}
+
+impl VideoFrames {
+    pub fn new(event_loop_handle: LoopHandle<Wpaperd>, qh: QueueHandle<Wpaperd>) -> Result<Self> {
+        let (sender, updates) = channel::channel();
+        event_loop_handle
+            .insert_source(updates, move |event, _, wpaperd| {
+                let channel::Event::Msg(name) = event else {
+                    return;
+                };
+                if let Some(surface) = wpaperd
+                    .surfaces
+                    .iter_mut()
+                    .find(|surface| surface.name() == name)
+                {
+                    surface.video_updated(&qh);
+                }
+            })
+            .map_err(|e| anyhow!("inserting the video listener in the event loop: {e}"))?;
+
+        Ok(Self { sender })
+    }
+
+    /// Return the update callback of the video played on the display `name`
+    fn callback(&self, name: String) -> impl Fn() + Send + 'static {
+        let sender = self.sender.clone();
+        move || {
+            // The event loop has stopped
+            let _ = sender.send(name.clone());
+        }
+    }
+}
+
+pub struct VideoPlayer {
+    // The render context must be dropped before the mpv handle
+    render_context: RenderContext,
+    mpv: Mpv,
+}
+
+impl VideoPlayer {
+    /// Start playing a video in a loop, without audio, on the display `name`.
+    /// The EGL context of the surface must be current.
+    pub fn new(
+        path: &Path,
+        mode: BackgroundMode,
+        paused: bool,
+        frames: &VideoFrames,
+        name: String,
+    ) -> Result<Self> {
+        let path = path
+            .to_str()
+            .with_context(|| format!("video path {path:?} is not valid UTF-8"))?;
+        let mut mpv = Mpv::with_initializer(|init| {
+            init.set_property("vo", "libmpv")?;
+            init.set_property("loop-file", "inf")?;
+            init.set_property("mute", "yes")?;
+            init.set_property("audio", "no")?;
+            Ok(())
+        })
+        .map_err(|err| anyhow!("unable to initialize mpv: {err}"))?;
+        let mut render_context = RenderContext::new(
+            unsafe { mpv.ctx.as_mut() },
+            vec![
+                RenderParam::ApiType(RenderParamApiType::OpenGl),
+                RenderParam::InitParams(OpenGLInitParams {
+                    get_proc_address,
+                    ctx: (),
+                }),
+            ],
+        )
+        .map_err(|err| anyhow!("unable to create the mpv render context: {err}"))?;
+        render_context.set_update_callback(frames.callback(name));
+
+        let player = Self {
+            render_context,
+            mpv,
+        };
+        player.set_mode(mode)?;
+        player.set_paused(paused)?;
+        player
+            .mpv
+            .command("loadfile", &[path])
+            .map_err(|err| anyhow!("unable to load video {path}: {err}"))?;
+
+        Ok(player)
+    }
+
+    /// mpv handles the scaling of the video by itself, map the background mode to its options
+    pub fn set_mode(&self, mode: BackgroundMode) -> Result<()> {
+        let (keepaspect, panscan) = match mode {
+            BackgroundMode::Stretch => ("no", 0.0),
+            BackgroundMode::Center => ("yes", 1.0),
+            BackgroundMode::Fit
+            | BackgroundMode::FitWidth
+            | BackgroundMode::FitHeight
+            | BackgroundMode::Tile
+            | BackgroundMode::FitBorderColor
+            | BackgroundMode::Contain => ("yes", 0.0),
+        };
+        self.mpv
+            .set_property("keepaspect", keepaspect)
+            .and_then(|_| self.mpv.set_property("panscan", panscan))
+            .map_err(|err| anyhow!("unable to set the video mode: {err}"))
+    }
+
+    /// Pause the playback, e.g. while the display is turned off or paused
+    pub fn set_paused(&self, paused: bool) -> Result<()> {
+        self.mpv
+            .set_property("pause", paused)
+            .map_err(|err| anyhow!("unable to pause the video: {err}"))
+    }
+
+    /// Return true when mpv has a new frame to draw, after its update callback has been called
+    pub fn frame_ready(&self) -> Result<bool> {
+        self.render_context
+            .update()
+            .map(|flags| flags & MPV_RENDER_UPDATE_FRAME != 0)
+            .map_err(|err| anyhow!("unable to update the video: {err}"))
+    }
+
+    /// Draw the current frame into the default framebuffer
+    pub fn render(&self, width: i32, height: i32) -> Result<()> {
+        self.render_context
+            .render::<()>(0, width, height, true)
+            .map_err(|err| anyhow!("unable to render the video frame: {err}"))
+    }
+}
@@ -6,7 +6,7 @@ use color_eyre::{
 };
 use image::DynamicImage;
 
-use crate::{gl_check, render::gl};
+use crate::{gl_check, image_loader::ImageStats, render::gl, wallpaper_info::TextureFilter};
 
 use super::load_texture;
 
@@ -15,6 +15,9 @@ pub struct Wallpaper {
     texture: gl::types::GLuint,
     image_width: u32,
     image_height: u32,
+    stats: ImageStats,
+    /// Offset the image is drawn with, as set by the background mode
+    pub offset: f32,
 }
 
 impl Wallpaper {
@@ -24,6 +27,31 @@ impl Wallpaper {
             texture: 0,
             image_width: 10,
             image_height: 10,
+            stats: ImageStats {
+                focus: None,
+                average_color: None,
+            },
+            offset: 0.5,
+        }
+    }
+
+    /// Take ownership of a texture that has already been filled
+    pub const fn from_texture(
+        gl: Rc<gl::Gl>,
+        texture: gl::types::GLuint,
+        image_width: u32,
+        image_height: u32,
+    ) -> Self {
+        Self {
+            gl,
+            texture,
+            image_width,
+            image_height,
+            stats: ImageStats {
+                focus: None,
+                average_color: None,
+            },
+            offset: 0.5,
         }
     }
 
@@ -36,11 +64,17 @@ impl Wallpaper {
         Ok(())
     }
 
-    pub fn load_image(&mut self, image: DynamicImage) -> Result<()> {
+    pub fn load_image(
+        &mut self,
+        image: DynamicImage,
+        stats: ImageStats,
+        filter: TextureFilter,
+    ) -> Result<()> {
         self.image_width = image.width();
         self.image_height = image.height();
+        self.stats = stats;
 
-        let texture = load_texture(&self.gl, image)?;
+        let texture = load_texture(&self.gl, image, filter)?;
 
         unsafe {
             // Delete from memory the previous texture
@@ -58,6 +92,10 @@ impl Wallpaper {
     pub fn get_image_width(&self) -> u32 {
         self.image_width
     }
+
+    pub fn stats(&self) -> ImageStats {
+        self.stats
+    }
 }
 
 impl Drop for Wallpaper {
@@ -0,0 +1,55 @@
+//! Presentation of a single image, read from a `.toml` file next to it, e.g. `forest.jpg.toml`
+//! for `forest.jpg`. The attributes it sets replace the ones of the display while the image
+//! is shown.
+
+use std::{fs, io::ErrorKind, path::Path};
+
+use color_eyre::{eyre::Context, Result};
+use serde::Deserialize;
+
+use crate::{
+    config::{transition_deserialize, SerializedOffset},
+    render::Transition,
+    wallpaper_info::BackgroundMode,
+};
+
+#[derive(Deserialize, Default, PartialEq, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Sidecar {
+    pub mode: Option<BackgroundMode>,
+    pub offset: Option<SerializedOffset>,
+    #[serde(default, deserialize_with = "transition_deserialize")]
+    pub transition: Option<Transition>,
+    #[serde(rename = "transition-time")]
+    transition_time: Option<u32>,
+}
+
+impl Sidecar {
+    /// Read the sidecar of `image`, None if it has none
+    pub fn load(image: &Path) -> Result<Option<Self>> {
+        let mut path = image.as_os_str().to_owned();
+        path.push(".toml");
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).with_context(|| format!("reading the sidecar {path:?}")),
+        };
+        toml::from_str(&content)
+            .map(Some)
+            .with_context(|| format!("parsing the sidecar {path:?}"))
+    }
+
+    pub fn offset(&self) -> Option<f32> {
+        self.offset.map(SerializedOffset::value)
+    }
+
+    /// Return the duration of the transition, the default one of the transition set here
+    /// if only the latter is given
+    pub fn transition_time(&self) -> Option<u32> {
+        self.transition_time.or_else(|| {
+            self.transition
+                .as_ref()
+                .map(Transition::default_transition_time)
+        })
+    }
+}
@@ -0,0 +1,86 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{eyre::Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SavedOutput {
+    name: String,
+    description: String,
+    image: PathBuf,
+    index: usize,
+}
+
+/// Keep track of the image drawn on each display, so that wpaperd can resume
+/// from it after a restart
+pub struct State {
+    path: PathBuf,
+    outputs: Vec<SavedOutput>,
+}
+
+impl State {
+    pub fn load(path: PathBuf) -> Self {
+        let outputs = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|err| {
+                warn!("State file {path:?} is not valid, ignoring it: {err:?}");
+                Vec::new()
+            }),
+            // wpaperd has never been run with this option before
+            Err(err) if err.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(err) => {
+                warn!("Could not read state file {path:?}: {err:?}");
+                Vec::new()
+            }
+        };
+
+        Self { path, outputs }
+    }
+
+    /// Return the image saved for a display, matching first by name and then by description
+    pub fn get(&self, name: &str, description: &str) -> Option<(PathBuf, usize)> {
+        self.outputs
+            .iter()
+            .find(|output| output.name == name)
+            .or_else(|| {
+                self.outputs
+                    .iter()
+                    .find(|output| !description.is_empty() && output.description == description)
+            })
+            .map(|output| (output.image.clone(), output.index))
+    }
+
+    /// Save the image currently drawn on a display
+    pub fn update(&mut self, name: &str, description: &str, image: &Path, index: usize) {
+        match self.outputs.iter_mut().find(|output| output.name == name) {
+            Some(output) => {
+                if output.image == image && output.index == index {
+                    return;
+                }
+                output.description = description.to_string();
+                output.image = image.to_path_buf();
+                output.index = index;
+            }
+            None => self.outputs.push(SavedOutput {
+                name: name.to_string(),
+                description: description.to_string(),
+                image: image.to_path_buf(),
+                index,
+            }),
+        }
+
+        if let Err(err) = self.save() {
+            warn!("{err:?}");
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let content = serde_json::to_vec(&self.outputs).context("serializing the state")?;
+        fs::write(&self.path, content)
+            .with_context(|| format!("writing state file {:?}", self.path))
+    }
+}
@@ -1,28 +1,63 @@
 use std::{
-    cell::RefCell,
-    path::PathBuf,
+    cell::{Ref, RefCell},
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
     rc::Rc,
     time::{Duration, Instant},
 };
 
-use color_eyre::eyre::{Context, ContextCompat};
+use color_eyre::eyre::{anyhow, bail, ensure, Context, ContextCompat};
 use color_eyre::Result;
-use image::RgbaImage;
-use log::{error, warn};
+use image::{codecs::png::PngEncoder, DynamicImage, ExtendedColorType, ImageEncoder, RgbaImage};
+use log::{debug, error, info, warn};
 use smithay_client_toolkit::reexports::calloop::{LoopHandle, RegistrationToken};
 use smithay_client_toolkit::reexports::client::protocol::wl_output::{Transform, WlOutput};
 use smithay_client_toolkit::reexports::client::protocol::wl_surface;
-use smithay_client_toolkit::reexports::client::QueueHandle;
-use smithay_client_toolkit::shell::wlr_layer::{LayerSurface, LayerSurfaceConfigure};
+use smithay_client_toolkit::reexports::client::{Proxy, QueueHandle};
+use smithay_client_toolkit::shell::wlr_layer::{
+    Layer, LayerSurface, LayerSurfaceConfigure, SurfaceKind,
+};
 use smithay_client_toolkit::{
     reexports::calloop::timer::{TimeoutAction, Timer},
     shell::WaylandSurface,
 };
+use wpaperd_ipc::Metrics;
 
-use crate::render::{EglContext, Renderer};
+use crate::condition::{apply_condition, ConditionRunner};
+use crate::daily::until_midnight;
+use crate::filelist_cache::ListCommand;
+use crate::fractional_scale::FractionalScale;
+use crate::http::is_url;
+use crate::night_light::local_seconds_of_day;
+use crate::presentation::{next_presentation, Presentation, Presented};
+use crate::render::{
+    is_context_lost, is_rotated, texture_offset, ColorAdjustment, EglContext, Renderer,
+    SoftwareRenderer, Transition,
+};
+#[cfg(feature = "video")]
+use crate::render::{is_video, VideoFrames, VideoPlayer};
+use crate::state::State;
+use crate::weekdays::{is_shown_on, Weekday};
+use crate::workspace::apply_workspace;
 use crate::wpaperd::Wpaperd;
-use crate::{display_info::DisplayInfo, wallpaper_info::WallpaperInfo};
-use crate::{image_loader::ImageLoader, image_picker::ImagePicker};
+use crate::{
+    display_info::DisplayInfo,
+    wallpaper_info::{BackgroundMode, LayerPlacement, Sorting, WallpaperInfo},
+};
+use crate::{
+    image_loader::{average_color, Downscale, ImageLoader, ImageStats, LoadOptions},
+    image_picker::ImagePicker,
+    sidecar::Sidecar,
+};
+
+/// What a display shows, copied by the displays mirroring it
+#[derive(Clone, PartialEq)]
+pub struct Mirrored {
+    pub image: PathBuf,
+    pub transition: Transition,
+    pub transition_time: u32,
+}
 
 #[derive(Debug)]
 pub enum EventSource {
@@ -32,29 +67,213 @@ pub enum EventSource {
     Paused(Duration),
 }
 
+impl EventSource {
+    /// Remove the running timer before replacing it. While the automatic changes are paused the
+    /// timer stays removed and `remaining` is kept for when they resume; return true if the new
+    /// timer can be added.
+    fn stop(&mut self, handle: &LoopHandle<Wpaperd>, paused: bool, remaining: Duration) -> bool {
+        if let EventSource::Running(registration_token) = *self {
+            handle.remove(registration_token);
+        }
+        if paused {
+            *self = EventSource::Paused(remaining);
+        } else {
+            *self = EventSource::NotSet;
+        }
+        !paused
+    }
+}
+
+/// Frame callbacks of a surface, so that the GPU is only kept awake while something is animating
+#[derive(Debug, Default)]
+struct FrameRequests {
+    /// A frame callback has been requested and not received yet
+    pending: bool,
+    /// The last frame didn't request another one and nothing has changed since then
+    idle: bool,
+}
+
+impl FrameRequests {
+    /// Something has changed, the next frames are expected
+    fn wake(&mut self) {
+        self.idle = false;
+    }
+
+    /// Return true if a frame callback has to be requested, only one is requested at a time.
+    /// The requests done while idle are counted in `metrics`, there should never be any.
+    fn request(&mut self, metrics: &mut Metrics) -> bool {
+        if self.pending {
+            return false;
+        }
+        metrics.frame_requests += 1;
+        if self.idle {
+            metrics.idle_frame_requests += 1;
+        }
+        self.pending = true;
+        true
+    }
+
+    fn received(&mut self) {
+        self.pending = false;
+    }
+
+    /// A frame has been drawn, nothing is animating unless it has requested the next one. The
+    /// frames drawn while idle are counted in `metrics`, there should never be any.
+    fn drawn(&mut self, metrics: &mut Metrics) {
+        if self.idle {
+            metrics.idle_draws += 1;
+        }
+        self.idle = !self.pending;
+    }
+}
+
+/// How the wallpaper is drawn on the surface
+enum Backend {
+    Gl {
+        egl_context: EglContext,
+        renderer: Renderer,
+    },
+    /// Used when the EGL context can't be created, see [`SoftwareRenderer`]
+    Software(SoftwareRenderer),
+}
+
 pub struct Surface {
     wl_surface: wl_surface::WlSurface,
     wl_output: WlOutput,
     layer: LayerSurface,
-    egl_context: EglContext,
-    renderer: Renderer,
+    backend: Backend,
     pub image_picker: ImagePicker,
     event_source: EventSource,
     pub wallpaper_info: WallpaperInfo,
     info: Rc<RefCell<DisplayInfo>>,
     image_loader: Rc<RefCell<ImageLoader>>,
+    state: Option<Rc<RefCell<State>>>,
     window_drawn: bool,
     loading_image: Option<(PathBuf, usize)>,
     loading_image_tries: u8,
     /// Determines whether we should skip the next transition. Used to skip
-    /// the first transition when starting up.
+    /// the first transition when starting up and after a configuration reload.
     ///
-    /// See [crate::wallpaper_info::WallpaperInfo]'s `initial_transition` field
+    /// See [crate::wallpaper_info::WallpaperInfo]'s `initial_transition` and
+    /// `reload_transition` fields
     skip_next_transition: bool,
+    /// Start the next transition from a capture of the display, see
+    /// [crate::wallpaper_info::WallpaperInfo]'s `transition_capture` field
+    capture_next_transition: bool,
+    /// The next change has been requested with wpaperctl
+    manual_change: bool,
+    /// The current wallpaper has been changed with wpaperctl and uses `manual-transition`
+    manual_transition: bool,
+    /// Transition and time requested for the next change only
+    next_transition: Option<(Transition, u32)>,
+    /// Transition requested for the change to the current wallpaper, the configured ones are
+    /// used again once it has finished
+    forced_transition: Option<(Transition, u32)>,
     /// Pause state of the automatic wallpaper sequence.
     /// Setting this to true will mean only an explicit next/previous wallpaper command will change
     /// the wallpaper.
     should_pause: bool,
+    /// True when a fullscreen window covers this display
+    fullscreen: bool,
+    /// True when the display has been turned off, nothing will be drawn until it's turned on
+    /// again. The automatic wallpaper sequence is paused in the meantime.
+    powered_off: bool,
+    /// True while the session is locked, the surface is hidden behind the lock screen and it's
+    /// handled like a display turned off
+    session_locked: bool,
+    /// True while disabled with wpaperctl, nothing is drawn and the timer is paused
+    disabled: bool,
+    /// True when the user has been idle for longer than `idle-timeout`, the automatic wallpaper
+    /// sequence is paused in the meantime
+    idle: bool,
+    /// True when the system is running on battery, the automatic wallpaper sequence is paused
+    /// in the meantime if `pause-on-battery` is set
+    on_battery: bool,
+    /// True when the local time is inside one of the `do-not-disturb` windows, the automatic
+    /// wallpaper sequence is paused in the meantime
+    do_not_disturb: bool,
+    /// Time of the last frame drawn during a transition, used to honor `max-fps`
+    last_frame_time: Option<u32>,
+    /// None when the compositor doesn't support presentation-time
+    presentation: Option<Presentation>,
+    /// Last frame presented during a transition
+    presented: Option<Presented>,
+    /// None when the buffers are drawn at the integer scale of the display
+    fractional_scale: Option<FractionalScale>,
+    /// Reference point of the panning animation
+    pan_start: Instant,
+    /// How long the panning had been running for when it has been stopped, because the display
+    /// has been paused or the user is idle
+    pan_stopped: Option<Duration>,
+    /// Position of the pointer on the display in logical coordinates, None when it's not on it
+    pointer_position: Option<(f64, f64)>,
+    /// Shift of the offset following the pointer, from -0.5 to 0.5 times `cursor-parallax`
+    parallax_shift: f32,
+    /// Last time the parallax shift moved, so that it moves at the same speed at any frame rate
+    parallax_updated: Instant,
+    /// Frame callbacks requested by [`Surface::request_frame`]
+    frames: FrameRequests,
+    /// Video being played instead of a static image
+    #[cfg(feature = "video")]
+    video: Option<VideoPlayer>,
+    /// Timings of the last decode and draw, returned by the metrics IPC command
+    pub metrics: Metrics,
+    /// Last wallpaper loaded, kept to be compared with the next one when `partial-transition`
+    /// is enabled
+    last_image: Option<RgbaImage>,
+    /// Region changed by the current transition (x, y, width, height), None for the whole surface
+    transition_damage: Option<[i32; 4]>,
+    /// True when a timer will check again the list of wallpapers, because it was empty
+    retry_scheduled: bool,
+    /// Time to wait before checking again the list of wallpapers, doubled after each attempt
+    retry_delay: Duration,
+    /// Configured duration while it is replaced by the one set with IPC, along with the timer
+    /// restoring it
+    duration_override: Option<(Option<Duration>, Option<RegistrationToken>)>,
+    /// Presentation of the current image, replacing the one of the display
+    sidecar: Sidecar,
+    /// Time of the transition waiting for `transition-delay` to pass, see
+    /// [`Surface::handle_transition_hold`]
+    held_transition: Option<u32>,
+    /// True when a wallpaper has been loaded while holding, the delay starts over
+    restart_hold: bool,
+    /// True while the transition started by [`Surface::preview_transition`] is running, or
+    /// while its image is being decoded
+    previewing: bool,
+    /// Image passed to [`Surface::preview_transition`], the preview starts once it's decoded
+    preview_image: Option<PathBuf>,
+    /// Why the last preview could not be played, sent to the client waiting for it
+    preview_error: Option<String>,
+    hold_timer: Option<RegistrationToken>,
+    /// Fires once `startup-delay` has passed, see [`Surface::wait_startup_delay`]
+    startup_timer: Option<RegistrationToken>,
+    startup_delay_elapsed: bool,
+    /// Index of the transition picked from the `transitions` of the configuration
+    picked_transition: Option<usize>,
+    /// Last wallpaper shown by the display set in `mirror`, kept after it has been disconnected
+    mirrored: Option<Mirrored>,
+    /// True while the display set in `mirror` is connected
+    mirror_connected: bool,
+    /// True when the EGL context has been lost, nothing is drawn until it has been created
+    /// again, see [`Surface::handle_lost_context`]
+    context_lost: bool,
+    context_recovery_scheduled: bool,
+    /// Time to wait before creating the context again, doubled after each failed attempt
+    context_recovery_delay: Duration,
+    /// Name of the active workspace on the display, it selects the wallpapers in `workspaces`
+    workspace: Option<String>,
+    /// True when the active workspace has changed, see [`Surface::handle_workspace_change`]
+    workspace_changed: bool,
+    workspace_timer: Option<RegistrationToken>,
+    condition_runner: ConditionRunner,
+    #[cfg(feature = "video")]
+    video_frames: VideoFrames,
+    /// Last output of the `condition` command, it selects the wallpapers in `conditions`
+    condition: Option<String>,
+    /// True while the wallpapers of a new condition are set, see [`Surface::update_condition`]
+    condition_changed: bool,
+    /// Sorting to restore when leaving the favorites mode, which picks them randomly
+    sorting_before_favorites: Option<Sorting>,
 }
 
 impl Surface {
@@ -64,70 +283,267 @@ impl Surface {
         wl_output: WlOutput,
         info: DisplayInfo,
         wallpaper_info: WallpaperInfo,
-        egl_display: egl::Display,
+        egl_display: Option<egl::Display>,
         qh: &QueueHandle<Wpaperd>,
-    ) -> Self {
+    ) -> Result<Self> {
+        let name = info.name.clone();
         let wl_surface = wl_layer.wl_surface().clone();
-        let egl_context = EglContext::new(egl_display, &wl_surface);
-        // Make the egl context as current to make the renderer creation work
-        egl_context
-            .make_current()
-            .expect("EGL context switching to work");
+        let egl_context = egl_display
+            .context("EGL is not available")
+            .and_then(|egl_display| EglContext::new(egl_display, &wl_surface))
+            .and_then(|mut egl_context| {
+                // Make the egl context as current to make the renderer creation work
+                egl_context.make_current()?;
+                if let Err(err) = egl_context.set_swap_interval(wallpaper_info.swap_interval) {
+                    error!("{name}: {err:?}");
+                }
+                Ok(egl_context)
+            });
 
+        set_layer_placement(&wl_layer, wallpaper_info.layer_placement);
         // Commit the surface
         wl_surface.commit();
 
-        let image_picker = ImagePicker::new(
+        let mut image_picker = ImagePicker::new(
+            name.clone(),
             &wallpaper_info,
             &wl_surface,
             wpaperd.filelist_cache.clone(),
             wpaperd.wallpaper_groups.clone(),
+            wpaperd.image_loader.clone(),
         );
 
-        let image = black_image();
+        let state = wpaperd.state.clone();
+        if let Some((image, index)) = state
+            .as_ref()
+            .and_then(|state| state.borrow().get(&info.name, &info.description))
+        {
+            // Only restore images that are still part of the configured directory, a command
+            // can list any file
+            if wallpaper_info.command.is_some()
+                || (wallpaper_info.path.is_dir() && image.starts_with(&wallpaper_info.path))
+                || wallpaper_info.pool.iter().any(|path| {
+                    // The images in a pool are stored with their canonical path
+                    path.canonicalize()
+                        .is_ok_and(|path| image.starts_with(path))
+                })
+                || wallpaper_info
+                    .fallback
+                    .iter()
+                    .any(|path| image.starts_with(path))
+            {
+                image_picker.restore(image, index);
+            }
+        }
+
         let info = Rc::new(RefCell::new(info));
 
-        let renderer = unsafe {
-            Renderer::new(
-                image.into(),
+        let night_light = wallpaper_info
+            .night_light
+            .map_or(([1.0; 3], 0.0), |night_light| {
+                (
+                    night_light.color(),
+                    night_light.factor(local_seconds_of_day()),
+                )
+            });
+        let backend = egl_context.and_then(|egl_context| {
+            let renderer = create_renderer(
+                &name,
                 info.clone(),
-                0,
+                &wallpaper_info,
                 wallpaper_info.transition.clone(),
-                info.borrow().transform,
+                wallpaper_info.color_adjustment,
+                night_light,
             )
-            .expect("unable to create the renderer")
+            .map_err(|err| {
+                egl_context.destroy();
+                err.wrap_err("creating the renderer")
+            })?;
+            Ok(Backend::Gl {
+                egl_context,
+                renderer,
+            })
+        });
+        let backend = match backend {
+            Ok(backend) => {
+                info!("{name}: drawing with OpenGL ES");
+                backend
+            }
+            Err(err) => {
+                warn!("{name}: {err:?}");
+                warn!("{name}: drawing in software, without transitions, color adjustments, rotation or videos");
+                // The compositor rotates the buffers, they are drawn in the orientation of
+                // the display
+                wl_surface.set_buffer_transform(Transform::Normal);
+                let mut renderer = SoftwareRenderer::new(&wpaperd.shm_state, info.clone())
+                    .context("unable to create the software renderer")?;
+                renderer.set_border(wallpaper_info.border);
+                renderer.set_backdrop(wallpaper_info.backdrop);
+                renderer.set_max_upscale(wallpaper_info.max_upscale);
+                Backend::Software(renderer)
+            }
         };
 
+        let fractional_scale = wpaperd
+            .fractional_scale
+            .as_ref()
+            .map(|manager| manager.create(&wl_surface, qh));
+
         let first_transition = !wallpaper_info.initial_transition;
         let mut surface = Self {
             wl_output,
             layer: wl_layer,
             info,
             wl_surface,
-            egl_context,
-            renderer,
+            backend,
             image_picker,
             event_source: EventSource::NotSet,
             wallpaper_info,
             window_drawn: false,
             should_pause: false,
+            fullscreen: false,
+            powered_off: false,
+            session_locked: false,
+            disabled: false,
+            idle: false,
+            on_battery: false,
+            do_not_disturb: false,
+            last_frame_time: None,
+            presentation: wpaperd.presentation.clone(),
+            presented: None,
+            fractional_scale,
+            pan_start: Instant::now(),
+            pan_stopped: None,
+            pointer_position: None,
+            parallax_shift: 0.0,
+            parallax_updated: Instant::now(),
+            frames: FrameRequests::default(),
+            #[cfg(feature = "video")]
+            video: None,
+            metrics: Metrics::default(),
+            last_image: None,
+            transition_damage: None,
+            retry_scheduled: false,
+            retry_delay: RETRY_INITIAL_DELAY,
+            duration_override: None,
+            sidecar: Sidecar::default(),
+            held_transition: None,
+            previewing: false,
+            preview_image: None,
+            preview_error: None,
+            restart_hold: false,
+            hold_timer: None,
+            startup_timer: None,
+            startup_delay_elapsed: false,
+            picked_transition: None,
+            mirrored: None,
+            mirror_connected: false,
+            context_lost: false,
+            context_recovery_scheduled: false,
+            context_recovery_delay: CONTEXT_RECOVERY_INITIAL_DELAY,
+            workspace: None,
+            workspace_changed: false,
+            workspace_timer: None,
+            condition_runner: wpaperd.condition_runner.clone(),
+            #[cfg(feature = "video")]
+            video_frames: wpaperd.video_frames.clone(),
+            condition: None,
+            condition_changed: false,
+            sorting_before_favorites: None,
             image_loader: wpaperd.image_loader.clone(),
+            state,
             loading_image: None,
             loading_image_tries: 0,
             skip_next_transition: first_transition,
+            capture_next_transition: false,
+            manual_change: false,
+            manual_transition: false,
+            next_transition: None,
+            forced_transition: None,
         };
 
         // Start loading the wallpaper as soon as possible (i.e. surface creation)
         // It will still be loaded as a texture when we have an openGL context
         if let Err(err) = surface.load_wallpaper(qh) {
-            warn!("{err:?}");
+            warn!("{name}: {err:?}");
         }
+        // The first wallpaper comes from `path`, the condition applies from the next change
+        surface.run_condition();
 
-        surface
+        Ok(surface)
     }
 
     /// Returns true if something has been drawn to the surface
     pub fn draw(&mut self, qh: &QueueHandle<Wpaperd>, time: Option<u32>) -> Result<()> {
+        if time.is_some() {
+            // We have been called by a frame callback
+            self.frames.received();
+        } else {
+            // Drawn because the wallpaper or the display have changed
+            self.frames.wake();
+        }
+
+        // Nothing can be drawn until the context has been created again
+        if self.context_lost {
+            return Ok(());
+        }
+        let res = self.draw_frame(qh, time);
+        self.frames.drawn(&mut self.metrics);
+        if let Err(err) = &res {
+            if is_context_lost(err) {
+                warn!(
+                    "{}: the EGL context has been lost, creating it again",
+                    self.name()
+                );
+                self.context_lost = true;
+                return Ok(());
+            }
+        }
+        res
+    }
+
+    /// Time in milliseconds used to compute the progress of the transitions: when the frame
+    /// being drawn is expected to be presented when the compositor supports presentation-time,
+    /// the time of the frame callback otherwise
+    fn transition_clock(&self, time: Option<u32>) -> u32 {
+        match self.presentation.as_ref().and_then(Presentation::now) {
+            // Wrap around like the time of the frame callbacks
+            Some(now) => (next_presentation(now, self.presented) / 1_000_000) as u32,
+            None => time.unwrap_or(0),
+        }
+    }
+
+    pub fn set_presented(&mut self, presented: Presented) {
+        self.presented = Some(presented);
+    }
+
+    fn draw_frame(&mut self, qh: &QueueHandle<Wpaperd>, time: Option<u32>) -> Result<()> {
+        // Do not request any frame while the display is off, we will draw again when it's on
+        if self.hidden() || self.disabled {
+            return Ok(());
+        }
+        if let Backend::Software(_) = self.backend {
+            return self.draw_software(qh);
+        }
+
+        let animating = self.transition_running() || self.is_playing_video();
+        let max_fps = match self.wallpaper_info.max_fps {
+            // Panning is slow enough that there is no need to follow the refresh rate
+            0 if !animating && self.pan_running() => PAN_MAX_FPS,
+            max_fps => max_fps,
+        };
+        // Skip this frame if the previous one has been drawn too recently
+        if let (true, Some(time), Some(last_frame_time)) =
+            (animating || self.pan_running(), time, self.last_frame_time)
+        {
+            if max_fps != 0 && time.wrapping_sub(last_frame_time) < 1000 / max_fps {
+                self.request_frame(qh);
+                self.wl_surface.commit();
+                return Ok(());
+            }
+        }
+        self.last_frame_time = time;
+
         let info = self.info.borrow();
         let width = info.adjusted_width();
         let height = info.adjusted_height();
@@ -135,41 +551,82 @@ impl Surface {
         drop(info);
 
         // Use the correct context before loading the texture and drawing
-        self.egl_context.make_current()?;
+        self.make_current()?;
 
         let wallpaper_loaded = self.load_wallpaper(qh)?;
 
-        if self.renderer.transition_running() {
+        #[cfg(feature = "video")]
+        if let Some(video) = &self.video {
+            video.render(width, height)?;
+            self.swap_buffers(None, height)?;
+            self.release_current()?;
+            self.wl_surface.damage_buffer(0, 0, width, height);
+            // The next frame is drawn when mpv has it, see Surface::video_updated
+            self.wl_surface.commit();
+            return Ok(());
+        }
+
+        // Only the changed region needs to be posted while transitioning
+        let damage = self.transition_damage.filter(|_| self.transition_running());
+        if self.transition_running() {
+            self.metrics.transition_frames += 1;
             // Recalculate the current progress, the transition might end now
-            let transition_running = self.renderer.update_transition_status(time.unwrap_or(0));
+            let clock = self.transition_clock(time);
+            let transition_running = self
+                .renderer_mut()
+                .is_some_and(|renderer| renderer.update_transition_status(clock));
             // If we don't have any time passed, just consider the transition to be ended
             if transition_running {
                 // Don't call queue_draw as it calls load_wallpaper again
-                self.wl_surface.frame(qh, self.wl_surface.clone());
+                self.request_frame(qh);
             } else {
-                self.renderer.transition_finished();
-            }
-        } else if !wallpaper_loaded {
-            self.wl_surface.frame(qh, self.wl_surface.clone());
-            if self.window_drawn {
-                // We need to call commit, otherwise the call to frame above doesn't work
-                self.wl_surface().commit();
-                return Ok(());
+                if let Some(renderer) = self.renderer_mut() {
+                    renderer.transition_finished();
+                }
+                self.end_forced_transition();
             }
+        } else if !wallpaper_loaded && self.window_drawn {
+            // Keep showing the previous frame, the image loader draws the surface again once
+            // the image has been decoded
+            return Ok(());
         }
 
-        unsafe { self.renderer.draw()? }
+        let pan_offset = self.pan_offset();
+        let parallax_shift = self.update_parallax_shift();
+        if pan_offset.is_some() || parallax_shift.is_some() {
+            if let Some(renderer) = self.renderer_mut() {
+                let offset = pan_offset.unwrap_or_else(|| renderer.wallpaper_offset())
+                    + parallax_shift.unwrap_or_default();
+                renderer.set_texture_offset(offset.clamp(0.0, 1.0))?;
+            }
+        }
+        if self.pan_running() || self.is_parallax_moving() {
+            self.request_frame(qh);
+        }
 
-        self.renderer.clear_after_draw()?;
-        self.egl_context.swap_buffers()?;
+        if let Some(renderer) = self.renderer_mut() {
+            let draw_start = Instant::now();
+            unsafe { renderer.draw()? }
+            let draw_time = draw_start.elapsed();
+            renderer.clear_after_draw()?;
+            self.metrics.draw_time = Some(draw_time);
+        }
+        // Follow when the frames of the transition are shown, the feedback applies to the
+        // commit done when swapping the buffers
+        if self.transition_running() {
+            if let Some(presentation) = &self.presentation {
+                presentation.feedback(&self.wl_surface, qh);
+            }
+        }
+        self.swap_buffers(damage, height)?;
 
         // Reset the context
-        egl::API
-            .make_current(self.egl_context.display, None, None, None)
-            .context("Resetting the GL context")?;
+        self.release_current()?;
 
-        // Mark the entire surface as damaged
-        self.wl_surface.damage_buffer(0, 0, width, height);
+        // Mark the changed region, or the entire surface, as damaged
+        let [x, y, damage_width, damage_height] = damage.unwrap_or([0, 0, width, height]);
+        self.wl_surface
+            .damage_buffer(x, y, damage_width, damage_height);
 
         // Finally, commit the surface
         self.wl_surface.commit();
@@ -177,25 +634,134 @@ impl Surface {
         Ok(())
     }
 
-    // Call surface::frame when this return false
+    /// Draw the current wallpaper in a shared memory buffer. There are no transitions nor
+    /// animations, nothing is drawn until the wallpaper or the display change.
+    fn draw_software(&mut self, qh: &QueueHandle<Wpaperd>) -> Result<()> {
+        let wallpaper_loaded = self.load_wallpaper(qh)?;
+        if !wallpaper_loaded && self.window_drawn {
+            return Ok(());
+        }
+        let Backend::Software(renderer) = &mut self.backend else {
+            return Ok(());
+        };
+        let draw_start = Instant::now();
+        renderer.draw(&self.wl_surface)?;
+        self.metrics.draw_time = Some(draw_start.elapsed());
+        // The buffer is in the orientation of the display
+        let info = self.info.borrow();
+        self.wl_surface
+            .damage_buffer(0, 0, info.scaled_width(), info.scaled_height());
+        drop(info);
+        self.wl_surface.commit();
+        Ok(())
+    }
+
+    /// Return the renderer, None when drawing in software
+    fn renderer(&self) -> Option<&Renderer> {
+        match &self.backend {
+            Backend::Gl { renderer, .. } => Some(renderer),
+            Backend::Software(_) => None,
+        }
+    }
+
+    fn renderer_mut(&mut self) -> Option<&mut Renderer> {
+        match &mut self.backend {
+            Backend::Gl { renderer, .. } => Some(renderer),
+            Backend::Software(_) => None,
+        }
+    }
+
+    /// Switch the renderer to the transition of the current wallpaper
+    fn update_transition(&mut self) {
+        let transition = self.transition().clone();
+        let transform = self.info.borrow().transform;
+        if let Some(renderer) = self.renderer_mut() {
+            renderer.update_transition(transition, transform);
+        }
+    }
+
+    /// Go back to the configured transitions once the one requested with wpaperctl for the
+    /// change to the current wallpaper has ended
+    fn end_forced_transition(&mut self) {
+        if self.forced_transition.is_none() {
+            return;
+        }
+        let previous_transition = self.transition().clone();
+        self.forced_transition = None;
+        if *self.transition() != previous_transition {
+            self.update_transition();
+        }
+    }
+
+    /// Return true while a transition is running, there are none in software
+    fn transition_running(&self) -> bool {
+        match &self.backend {
+            Backend::Gl { renderer, .. } => renderer.transition_running(),
+            Backend::Software(_) => false,
+        }
+    }
+
+    /// Make the EGL context of the surface the current one, if any
+    fn make_current(&self) -> Result<()> {
+        match &self.backend {
+            Backend::Gl { egl_context, .. } => egl_context.make_current(),
+            Backend::Software(_) => Ok(()),
+        }
+    }
+
+    /// Reset the current EGL context after drawing
+    fn release_current(&self) -> Result<()> {
+        if let Backend::Gl { egl_context, .. } = &self.backend {
+            egl::API
+                .make_current(egl_context.display, None, None, None)
+                .context("Resetting the GL context")?;
+        }
+        Ok(())
+    }
+
+    /// Post what has been drawn with OpenGL, only the `damage` region if given
+    fn swap_buffers(&self, damage: Option<[i32; 4]>, height: i32) -> Result<()> {
+        match (&self.backend, damage) {
+            (Backend::Gl { egl_context, .. }, Some(damage)) => {
+                egl_context.swap_buffers_with_damage(damage, height)
+            }
+            (Backend::Gl { egl_context, .. }, None) => egl_context.swap_buffers(),
+            (Backend::Software(_), _) => Ok(()),
+        }
+    }
+
+    /// Return false while the image is being decoded, [`ImageLoader`] calls
+    /// [`Surface::queue_draw`] once it's ready
     pub fn load_wallpaper(&mut self, qh: &QueueHandle<Wpaperd>) -> Result<bool> {
         Ok(loop {
             // If we were not already trying to load an image
             if self.loading_image.is_none() {
-                if let Some(item) = self
-                    .image_picker
-                    .get_image_from_path(&self.wallpaper_info.path, qh)
-                {
+                let item = match &self.mirrored {
+                    // Show the same image as the mirrored display
+                    Some(mirrored) => Some((mirrored.image.clone(), 0)),
+                    // Wait for the mirrored display to show something
+                    None if !self.wallpaper_info.has_wallpapers() => None,
+                    None => self
+                        .image_picker
+                        .get_image_from_path(&self.wallpaper_info.path, qh),
+                };
+                if let Some(item) = item {
+                    // The picker only returns the current URL when it's time to fetch it again
                     if self.image_picker.current_image() == item.0
                         && !self.image_picker.is_reloading()
+                        && (!is_url(&item.0) || self.mirrored.is_some())
                     {
+                        self.manual_change = false;
+                        self.next_transition = None;
                         break true;
                     } else {
                         // We are trying to load a new image
                         self.loading_image = Some(item);
                     }
                 } else {
-                    // we don't need to load any image
+                    // we don't need to load any image, not even for a manual change
+                    self.manual_change = false;
+                    self.next_transition = None;
                     break true;
                 }
             }
@@ -205,29 +771,144 @@ impl Surface {
                 .expect("loading image to be set")
                 .clone();
 
-            if self.renderer.transition_running() {
+            if self.transition_running() {
                 break true;
             }
 
-            let res = self
-                .image_loader
-                .borrow_mut()
-                .background_load(image_path.to_owned(), self.name());
+            // The videos are rendered with OpenGL, the image loader skips them in software
+            #[cfg(feature = "video")]
+            if is_video(&image_path) && matches!(self.backend, Backend::Gl { .. }) {
+                self.make_current()?;
+                self.load_sidecar(&image_path);
+                let res = VideoPlayer::new(
+                    &image_path,
+                    self.mode(),
+                    self.video_paused(),
+                    &self.video_frames,
+                    self.name(),
+                );
+                match res {
+                    Ok(video) => {
+                        self.video = Some(video);
+                        self.image_picker.update_current_image(image_path, index);
+                        self.save_state(index);
+                        self.loading_image_tries = 0;
+                        self.loading_image = None;
+                        break true;
+                    }
+                    Err(err) => {
+                        warn!("{}: {err:?}", self.name());
+                        self.loading_image_tries += 1;
+                        // Skip it like an image that can't be decoded, instead of creating the
+                        // player again at each draw
+                        self.image_picker.failed_image(&image_path);
+                        self.loading_image = None;
+                        if self.loading_image_tries == 5 {
+                            self.loading_image_tries = 0;
+                            break true;
+                        }
+                        continue;
+                    }
+                }
+            }
+
+            let options = LoadOptions {
+                max_size: self.max_image_size(),
+                exif_orientation: self.wallpaper_info.exif_orientation,
+                auto_offset: self.wallpaper_info.auto_offset,
+                // The transition of the new image is not picked yet
+                average_color: self.transition().uses_average_color()
+                    || self
+                        .wallpaper_info
+                        .transitions
+                        .iter()
+                        .chain(&self.wallpaper_info.manual_transition)
+                        .any(|(transition, _)| transition.uses_average_color()),
+                allow_network: self.wallpaper_info.allow_network,
+            };
+            let res = self.image_loader.borrow_mut().background_load(
+                image_path.to_owned(),
+                self.name(),
+                options,
+            );
             match res {
-                crate::image_loader::ImageLoaderStatus::Loaded(data) => {
+                crate::image_loader::ImageLoaderStatus::Loaded(image)
+                    if matches!(self.backend, Backend::Software(_)) =>
+                {
+                    self.metrics.decode_time = Some(image.decode_time);
+                    self.load_sidecar(&image_path);
+                    let (mode, offset) = (self.mode(), self.offset());
+                    if let Backend::Software(renderer) = &mut self.backend {
+                        renderer.load_wallpaper(image.data, image.stats.focus, mode, offset);
+                    }
+                    self.skip_next_transition = false;
+                    self.manual_change = false;
+                    self.next_transition = None;
+                    if self.image_picker.is_reloading() {
+                        self.image_picker.reloaded();
+                    } else {
+                        self.image_picker.update_current_image(image_path, index);
+                        self.save_state(index);
+                    }
+                    self.loading_image_tries = 0;
+                    self.loading_image = None;
+                    break true;
+                }
+                crate::image_loader::ImageLoaderStatus::Loaded(image) => {
+                    self.metrics.decode_time = Some(image.decode_time);
+                    self.transition_damage = self.partial_transition_damage(&image.data);
                     // Renderer::load_wallpaper load the wallpaper in a openGL texture
                     // Set the correct opengl context
-                    self.egl_context.make_current()?;
-                    self.renderer.load_wallpaper(
-                        data.into(),
-                        self.wallpaper_info.mode,
-                        self.wallpaper_info.offset,
-                    )?;
+                    self.make_current()?;
+                    // Stop playing the previous video, if any
+                    #[cfg(feature = "video")]
+                    {
+                        self.video = None;
+                    }
+                    if std::mem::take(&mut self.capture_next_transition)
+                        && !self.skip_next_transition
+                        && !self.image_picker.is_reloading()
+                    {
+                        if let Some(Err(err)) =
+                            self.renderer_mut().map(|renderer| renderer.capture())
+                        {
+                            warn!(
+                                "{}: transitioning from the previous wallpaper instead of the display: {err:?}",
+                                self.name()
+                            );
+                        }
+                    }
+                    // The transition to the new image is the manual one, the one of its sidecar
+                    // or the next one of the configured set
+                    let previous_transition = self.transition().clone();
+                    if !self.image_picker.is_reloading() {
+                        self.forced_transition = self.next_transition.take();
+                        self.manual_transition = std::mem::take(&mut self.manual_change)
+                            && self.wallpaper_info.manual_transition.is_some();
+                        if !self.manual_transition && self.forced_transition.is_none() {
+                            self.pick_transition();
+                        }
+                    }
+                    self.load_sidecar(&image_path);
+                    if *self.transition() != previous_transition {
+                        self.update_transition();
+                    }
+                    let (mode, offset) = (self.mode(), self.offset());
+                    let texture_filter = self.wallpaper_info.texture_filter;
+                    if let Some(renderer) = self.renderer_mut() {
+                        renderer.load_wallpaper(
+                            image.data.into(),
+                            image.stats,
+                            mode,
+                            offset,
+                            texture_filter,
+                        )?;
+                    }
 
                     let transition_time = if self.skip_next_transition {
                         0
                     } else {
-                        self.wallpaper_info.transition_time
+                        self.transition_time()
                     };
                     self.skip_next_transition = false;
 
@@ -235,7 +916,33 @@ impl Surface {
                         self.image_picker.reloaded();
                     } else {
                         self.image_picker.update_current_image(image_path, index);
-                        self.renderer.start_transition(transition_time);
+                        self.save_state(index);
+                        let hold = self.wallpaper_info.transition_delay != 0
+                            && transition_time != 0
+                            && self.forced_transition.is_none();
+                        let wait_upload = self.wallpaper_info.transition_wait_upload;
+                        if *self.transition() == (Transition::None {}) {
+                            // Show the new wallpaper right away and free the previous one
+                            if let Some(renderer) = self.renderer_mut() {
+                                renderer.transition_finished();
+                            }
+                            self.end_forced_transition();
+                            self.held_transition = None;
+                        } else if hold {
+                            // Let quick successive changes settle before starting the transition
+                            if let Some(renderer) = self.renderer_mut() {
+                                renderer.hold_transition();
+                            }
+                            self.held_transition = Some(transition_time);
+                            self.restart_hold = true;
+                        } else if let Some(renderer) = self.renderer_mut() {
+                            if wait_upload {
+                                renderer.start_transition_after_upload(transition_time)?;
+                            } else {
+                                renderer.start_transition(transition_time);
+                            }
+                        }
+                        self.metrics.transition_frames = 0;
                     }
                     // Restart the counter
                     self.loading_image_tries = 0;
@@ -249,43 +956,317 @@ impl Surface {
                 crate::image_loader::ImageLoaderStatus::Error => {
                     // We don't want to try too many times
                     self.loading_image_tries += 1;
-                    // The image we were trying to load failed
+                    // The image we were trying to load failed, skip it and pick the next one.
+                    // The current wallpaper is kept, along with its transition if it is still
+                    // running
+                    self.image_picker.failed_image(&image_path);
                     self.loading_image = None;
                 }
             }
-            // If we have tried too many times, stop
+            // If we have tried too many times, stop until the display is drawn again
             if self.loading_image_tries == 5 {
+                self.loading_image_tries = 0;
                 break true;
             }
         })
     }
 
+    /// Return the size the decoded images are downscaled to, if `max-image-scale` is set
+    fn max_image_size(&self) -> Option<Downscale> {
+        let max_image_scale = self.wallpaper_info.max_image_scale?;
+        let info = self.info.borrow();
+        let (width, height) = (info.adjusted_width(), info.adjusted_height());
+        if width <= 0 || height <= 0 {
+            // The display has not been configured yet
+            return None;
+        }
+        // The wallpaper is drawn rotated, it has to cover the display the other way around
+        let (width, height) = if is_rotated(self.wallpaper_info.transform) {
+            (height, width)
+        } else {
+            (width, height)
+        };
+        Some(Downscale {
+            width: (width as f32 * max_image_scale).ceil() as u32,
+            height: (height as f32 * max_image_scale).ceil() as u32,
+            filter: self.wallpaper_info.downscale_filter,
+        })
+    }
+
+    /// Compare the new wallpaper with the previous one and return the region that changed.
+    /// Only used when the images have the same size of the display, so that they are drawn
+    /// pixel by pixel, and when the changes are small enough.
+    fn partial_transition_damage(&mut self, image: &RgbaImage) -> Option<[i32; 4]> {
+        if !self.wallpaper_info.partial_transition {
+            self.last_image = None;
+            return None;
+        }
+        let prev_image = self.last_image.replace(image.clone())?;
+        let info = self.info.borrow();
+        if info.transform != Transform::Normal
+            || self.wallpaper_info.transform != Transform::Normal
+            || self.wallpaper_info.border.width != 0
+            || image.dimensions() != (info.adjusted_width() as u32, info.adjusted_height() as u32)
+        {
+            return None;
+        }
+        let damage = changed_region(&prev_image, image)?;
+        let [_, _, width, height] = damage;
+        let changed_area = width as f32 * height as f32;
+        let area = image.width() as f32 * image.height() as f32;
+        (changed_area <= area * PARTIAL_TRANSITION_MAX_AREA).then_some(damage)
+    }
+
+    /// Save the current image, if wpaperd has been asked to persist its state
+    fn save_state(&self, index: usize) {
+        if let Some(state) = &self.state {
+            let info = self.info.borrow();
+            state.borrow_mut().update(
+                &info.name,
+                &info.description,
+                &self.image_picker.current_image(),
+                index,
+            );
+        }
+    }
+
+    /// Request a frame callback to keep drawing an animation. Only a single callback is requested
+    /// at a time, otherwise the surface would be drawn multiple times each frame.
+    fn request_frame(&mut self, qh: &QueueHandle<Wpaperd>) {
+        if self.frames.request(&mut self.metrics) {
+            self.wl_surface.frame(qh, self.wl_surface.clone());
+        }
+    }
+
+    /// Start drawing an animation from the next frame, the surface isn't idle anymore
+    fn animate(&mut self, qh: &QueueHandle<Wpaperd>) {
+        self.frames.wake();
+        self.request_frame(qh);
+        self.wl_surface.commit();
+    }
+
+    /// Return true if the wallpaper should be panned, i.e. the offset moves the image
+    fn is_panning(&self) -> bool {
+        self.wallpaper_info.pan_range > 0.0
+            && matches!(
+                self.mode(),
+                BackgroundMode::Center | BackgroundMode::FitWidth | BackgroundMode::FitHeight
+            )
+            && !self.is_playing_video()
+            && self
+                .renderer()
+                .is_some_and(|renderer| renderer.is_cropped(self.mode()))
+    }
+
+    /// Return true if the panning has to be drawn continuously, it stays still while stopped
+    fn pan_running(&self) -> bool {
+        self.is_panning() && self.pan_stopped.is_none()
+    }
+
+    /// Stop the panning while the display is paused or the user is idle, then start it again
+    /// from the same position
+    fn update_pan(&mut self, qh: &QueueHandle<Wpaperd>) {
+        match (self.should_pause || self.idle, self.pan_stopped) {
+            (true, None) => self.pan_stopped = Some(self.pan_start.elapsed()),
+            (false, Some(elapsed)) => {
+                self.pan_stopped = None;
+                self.pan_start = Instant::now()
+                    .checked_sub(elapsed)
+                    .unwrap_or_else(Instant::now);
+                if self.is_panning() && self.window_drawn {
+                    self.animate(qh);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Calculate the offset of the panning animation for the current time.
+    /// The offset moves back and forth around the configured one, without ever leaving the
+    /// image, because an offset between 0.0 and 1.0 only moves the cropped area.
+    fn pan_offset(&self) -> Option<f32> {
+        if !self.is_panning() {
+            return None;
+        }
+        let range = self.wallpaper_info.pan_range;
+        let duration = self.wallpaper_info.pan_duration.as_secs_f32();
+        let elapsed = self.pan_stopped.unwrap_or_else(|| self.pan_start.elapsed());
+        let phase = elapsed.as_secs_f32() % duration / duration;
+        // Triangle wave going from 0.0 to 1.0 and back
+        let progress = 1.0 - (2.0 * phase - 1.0).abs();
+        let center = self
+            .renderer()?
+            .wallpaper_offset()
+            .clamp(range / 2.0, 1.0 - range / 2.0);
+        Some((center - range / 2.0 + progress * range).clamp(0.0, 1.0))
+    }
+
+    /// Return true if the pointer input is needed, to follow it or to pause while it's on the
+    /// display
+    pub fn wants_pointer(&self) -> bool {
+        self.wallpaper_info.cursor_parallax > 0.0 || self.wallpaper_info.pause_on_hover
+    }
+
+    /// Return true if the wallpaper should follow the pointer
+    fn is_following_cursor(&self) -> bool {
+        self.wallpaper_info.cursor_parallax > 0.0
+            && matches!(
+                self.mode(),
+                BackgroundMode::Center | BackgroundMode::FitWidth | BackgroundMode::FitHeight
+            )
+            && !self.is_playing_video()
+    }
+
+    /// Return the shift of the offset the parallax is moving to, from the position of the
+    /// pointer along the axis that is cropped. The wallpaper goes back to the center when the
+    /// pointer leaves the display.
+    fn parallax_target(&self) -> f32 {
+        let Some((x, y)) = self.pointer_position else {
+            return 0.0;
+        };
+        let info = self.info.borrow();
+        // The wallpaper is drawn rotated, the cropped axis is the other one on the display
+        let vertical = self
+            .renderer()
+            .is_some_and(|renderer| renderer.is_cropped_vertically(self.mode()))
+            != is_rotated(self.wallpaper_info.transform);
+        let fraction = if vertical {
+            y / info.height.max(1) as f64
+        } else {
+            x / info.width.max(1) as f64
+        };
+        (fraction.clamp(0.0, 1.0) as f32 - 0.5) * self.wallpaper_info.cursor_parallax
+    }
+
+    /// Return true if the parallax shift has not reached the pointer yet
+    fn is_parallax_moving(&self) -> bool {
+        self.is_following_cursor() && self.parallax_shift != self.parallax_target()
+    }
+
+    /// Move the parallax shift towards the pointer and return it, None if the wallpaper doesn't
+    /// follow the pointer. It eases out so that the wallpaper doesn't jump when the pointer
+    /// enters or leaves the display.
+    fn update_parallax_shift(&mut self) -> Option<f32> {
+        if !self.is_following_cursor() {
+            self.parallax_shift = 0.0;
+            return None;
+        }
+        let elapsed = self.parallax_updated.elapsed().as_secs_f32();
+        self.parallax_updated = Instant::now();
+        let target = self.parallax_target();
+        let progress = 1.0 - (-elapsed / PARALLAX_SMOOTHING.as_secs_f32()).exp();
+        self.parallax_shift += (target - self.parallax_shift) * progress;
+        if (target - self.parallax_shift).abs() < PARALLAX_MIN_SHIFT {
+            self.parallax_shift = target;
+        }
+        Some(self.parallax_shift)
+    }
+
+    /// Update the position of the pointer, None when it has left the display, and draw the
+    /// wallpaper at the new offset
+    pub fn set_pointer_position(
+        &mut self,
+        position: Option<(f64, f64)>,
+        qh: &QueueHandle<Wpaperd>,
+    ) {
+        let moving = self.is_parallax_moving();
+        self.pointer_position = position;
+        if !self.is_following_cursor() || !self.window_drawn {
+            return;
+        }
+        if !moving {
+            // The wallpaper was still, don't count the time since it stopped
+            self.parallax_updated = Instant::now();
+        }
+        // The parallax moves until it reaches the pointer
+        self.frames.wake();
+        if !self.frames.pending {
+            self.request_frame(qh);
+            self.wl_surface.commit();
+        }
+    }
+
+    /// Return true if a video is being played on this surface
+    #[cfg(feature = "video")]
+    fn is_playing_video(&self) -> bool {
+        self.video.is_some()
+    }
+
+    #[cfg(not(feature = "video"))]
+    fn is_playing_video(&self) -> bool {
+        false
+    }
+
     pub fn name(&self) -> String {
         self.info.borrow().name.to_string()
     }
 
+    pub fn info(&self) -> Ref<'_, DisplayInfo> {
+        self.info.borrow()
+    }
+
     /// Resize the surface
     pub fn resize(&mut self, qh: &QueueHandle<Wpaperd>) -> Result<()> {
         let info = self.info.borrow();
         let width = info.adjusted_width();
         let height = info.adjusted_height();
+        let transform = info.transform;
+        let (logical_width, logical_height) = (info.width, info.height);
         // Drop the borrow to self
         drop(info);
         // self.layer.set_size(width as u32, height as u32);
+        if let Some(fractional_scale) = &self.fractional_scale {
+            // The destination can't be empty, it is set again by the first configure
+            if logical_width > 0 && logical_height > 0 {
+                fractional_scale.set_size(logical_width, logical_height);
+            }
+        }
         let display_name = self.name();
-        self.egl_context
-            .resize(&self.wl_surface, width, height)
-            .with_context(|| {
-                format!("unable to switch resize EGL context for display {display_name}",)
+        let (mode, offset) = (self.mode(), self.offset());
+        let swap_interval = self.wallpaper_info.swap_interval;
+        // The software renderer scales the wallpaper to the new size when drawing it
+        if let Backend::Gl {
+            egl_context,
+            renderer,
+        } = &mut self.backend
+        {
+            egl_context
+                .resize(&self.wl_surface, width, height)
+                .with_context(|| {
+                    format!("unable to switch resize EGL context for display {display_name}",)
+                })?;
+            egl_context.make_current().with_context(|| {
+                format!("unable to switch the openGL context for display {display_name}")
+            })?;
+            // The swap interval belongs to the EGL surface that has just been recreated
+            egl_context
+                .set_swap_interval(swap_interval)
+                .with_context(|| {
+                    format!("unable to set the swap interval for display {display_name}")
+                })?;
+            renderer.resize().with_context(|| {
+                format!("unable to resize the GL window for display {display_name}")
+            })?;
+            // The wallpaper transform is composed with the display one
+            unsafe { renderer.set_projection_matrix(transform) }.with_context(|| {
+                format!("unable to update the projection matrix for display {display_name}")
             })?;
-        self.egl_context.make_current().with_context(|| {
-            format!("unable to switch the openGL context for display {display_name}")
-        })?;
-        self.renderer.resize().with_context(|| {
-            format!("unable to resize the GL window for display {display_name}")
-        })?;
-        // If we resize, stop immediately any lingering transition
-        self.renderer.force_transition_end();
+            // The texture scale depends on the display ratio, recalculate it
+            renderer.set_mode(mode, offset).with_context(|| {
+                format!("unable to update the background mode for display {display_name}")
+            })?;
+            // If we resize, stop immediately any lingering transition
+            renderer.force_transition_end();
+        }
+        self.end_forced_transition();
+
+        let info = self.info.borrow();
+        let (width, height) = (info.width, info.height);
+        drop(info);
+        if height != 0 {
+            self.image_picker
+                .set_display_ratio(width as f32 / height as f32);
+        }
 
         // Queue drawing for the next frame. We can directly draw here, but we would still
         // need to queue the draw for the next frame, otherwise wpaperd doesn't work at startup
@@ -299,7 +1280,7 @@ impl Surface {
         if info.change_size(configure) {
             drop(info);
             if let Err(err) = self.resize(qh) {
-                error!("{err:?}");
+                error!("{}: {err:?}", self.name());
             }
         }
     }
@@ -308,16 +1289,13 @@ impl Surface {
         let mut info = self.info.borrow_mut();
         if info.change_transform(transform) {
             drop(info);
-            self.wl_surface.set_buffer_transform(transform);
-            if let Err(err) = self
-                .resize(qh)
-                .and_then(|_| {
-                    self.renderer
-                        .set_mode(self.wallpaper_info.mode, self.wallpaper_info.offset)
-                })
-                .and_then(|_| unsafe { self.renderer.set_projection_matrix(transform) })
-            {
-                error!("{err:?}");
+            // The compositor rotates the buffers drawn in software
+            if let Backend::Gl { .. } = self.backend {
+                self.wl_surface.set_buffer_transform(transform);
+            }
+            // resize updates both the background mode and the projection matrix
+            if let Err(err) = self.resize(qh) {
+                error!("{}: {err:?}", self.name());
             }
         }
     }
@@ -325,127 +1303,1189 @@ impl Surface {
     pub fn change_scale_factor(&mut self, scale_factor: i32, qh: &QueueHandle<Wpaperd>) {
         let mut info = self.info.borrow_mut();
         if info.change_scale_factor(scale_factor) {
+            // The integer scale is not used anymore once the fractional one has been received
+            let fractional = info.fractional_scale.is_some();
             drop(info);
-            self.wl_surface.set_buffer_scale(scale_factor);
+            if self.fractional_scale.is_none() {
+                self.wl_surface.set_buffer_scale(scale_factor);
+            }
             // Resize the gl viewport
-            if let Err(err) = self.resize(qh) {
-                error!("{err:?}");
+            if !fractional {
+                if let Err(err) = self.resize(qh) {
+                    error!("{}: {err:?}", self.name());
+                }
             }
         }
     }
 
-    /// Check that the dimensions are valid
-    pub fn is_configured(&self) -> bool {
-        let info = self.info.borrow();
-        info.width != 0 && info.height != 0
+    /// Draw at the scale preferred by the compositor, in 120ths
+    pub fn change_fractional_scale(&mut self, scale: u32, qh: &QueueHandle<Wpaperd>) {
+        let mut info = self.info.borrow_mut();
+        if info.change_fractional_scale(scale) {
+            // The first configure resizes the surface anyway
+            let configured = info.width > 0 && info.height > 0;
+            drop(info);
+            debug!(
+                "{}: fractional scale {:.3}",
+                self.name(),
+                scale as f32 / 120.0
+            );
+            if configured {
+                if let Err(err) = self.resize(qh) {
+                    error!("{}: {err:?}", self.name());
+                }
+            }
+        }
     }
 
-    pub fn has_been_drawn(&self) -> bool {
-        self.window_drawn
+    /// Update the logical position of the display, e.g. after the outputs have been arranged
+    pub fn change_position(&mut self, position: Option<(i32, i32)>) {
+        if self.info.borrow_mut().change_position(position) {
+            debug!("Display {} moved to {position:?}", self.name());
+        }
     }
 
-    pub fn drawn(&mut self) {
-        self.window_drawn = true;
+    /// Update the logical size of the display, the margins are checked against it
+    pub fn change_logical_size(&mut self, logical_size: Option<(i32, i32)>) {
+        if self.info.borrow_mut().change_logical_size(logical_size) {
+            debug!("Display {} resized to {logical_size:?}", self.name());
+        }
     }
 
-    /// Update the wallpaper_info of this Surface
-    /// return true if the duration has changed
-    pub fn update_wallpaper_info(
-        &mut self,
-        handle: &LoopHandle<Wpaperd>,
+    /// Return the logical position of the display, if the compositor has sent it
+    #[inline]
+    pub fn position(&self) -> Option<(i32, i32)> {
+        self.info.borrow().position
+    }
+
+    /// Check that the dimensions are valid
+    pub fn is_configured(&self) -> bool {
+        let info = self.info.borrow();
+        info.width != 0 && info.height != 0
+    }
+
+    /// Start loading the first wallpaper and return true when it can be drawn
+    pub fn load_first_wallpaper(&mut self, qh: &QueueHandle<Wpaperd>) -> bool {
+        match self.load_wallpaper(qh) {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                // Do not wait for a wallpaper that cannot be loaded
+                warn!("{}: {err:?}", self.name());
+                true
+            }
+        }
+    }
+
+    /// Return true if the image at `path` is being decoded for this display
+    pub fn is_loading(&self, path: &Path) -> bool {
+        self.loading_image
+            .as_ref()
+            .is_some_and(|(loading, _)| loading == path)
+    }
+
+    pub fn has_been_drawn(&self) -> bool {
+        self.window_drawn
+    }
+
+    pub fn drawn(&mut self) {
+        self.window_drawn = true;
+    }
+
+    /// Update the wallpaper_info of this Surface after the configuration has been reloaded,
+    /// which replaces the changes made with IPC
+    pub fn update_wallpaper_info(
+        &mut self,
+        handle: &LoopHandle<Wpaperd>,
+        qh: &QueueHandle<Wpaperd>,
+        wallpaper_info: WallpaperInfo,
+    ) {
+        if let Some((_, Some(expiry))) = self.duration_override.take() {
+            handle.remove(expiry);
+        }
+        // The sorting of the configuration is applied below
+        if self.image_picker.favorites_only() {
+            self.image_picker.set_favorites_only(false);
+            self.sorting_before_favorites = None;
+        }
+        self.apply_wallpaper_info(handle, qh, wallpaper_info);
+    }
+
+    /// Update the wallpaper_info of this Surface, keeping the duration set with IPC and the
+    /// favorites mode
+    fn apply_wallpaper_info(
+        &mut self,
+        handle: &LoopHandle<Wpaperd>,
         qh: &QueueHandle<Wpaperd>,
         mut wallpaper_info: WallpaperInfo,
     ) {
+        // The wallpapers of the active workspace have precedence over the condition
+        apply_condition(&mut wallpaper_info, self.condition.as_deref());
+        apply_workspace(&mut wallpaper_info, self.workspace.as_deref());
+        // The new values are restored once the changes made with IPC have ended
+        if let Some((configured, _)) = &mut self.duration_override {
+            *configured = wallpaper_info.duration;
+            wallpaper_info.duration = self.wallpaper_info.duration;
+        }
+        if self.image_picker.favorites_only() {
+            self.sorting_before_favorites = wallpaper_info.sorting;
+            wallpaper_info.sorting = Some(Sorting::Random);
+        }
         if self.wallpaper_info == wallpaper_info {
             return;
         }
 
         // Put the new value in place
         std::mem::swap(&mut self.wallpaper_info, &mut wallpaper_info);
-        let path_changed = self.wallpaper_info.path != wallpaper_info.path;
+        let path_changed = self.wallpaper_info.path != wallpaper_info.path
+            || self.wallpaper_info.pool != wallpaper_info.pool
+            || self.wallpaper_info.fallback != wallpaper_info.fallback
+            || self.wallpaper_info.list_command() != wallpaper_info.list_command();
+        self.image_picker
+            .update_command(self.wallpaper_info.list_command());
+        self.image_picker
+            .update_pool(self.wallpaper_info.pool.clone());
+        self.image_picker
+            .update_fallback(self.wallpaper_info.fallback.clone());
+        self.image_picker
+            .update_aspect_ratio_tolerance(self.wallpaper_info.aspect_ratio_tolerance);
+        self.image_picker
+            .update_min_resolution(self.wallpaper_info.min_resolution);
+        self.image_picker
+            .update_exif_orientation(self.wallpaper_info.exif_orientation);
+        self.image_picker
+            .update_daily_offset(self.wallpaper_info.daily_offset);
+        self.image_picker
+            .update_weekdays(self.wallpaper_info.weekdays.clone());
+        self.image_picker
+            .update_ranking(self.wallpaper_info.ranking.clone());
+        self.image_picker
+            .update_favorites(self.wallpaper_info.favorites.clone());
         self.image_picker.update_sorting(
             self.wallpaper_info.sorting,
             &self.wallpaper_info.path,
             path_changed,
             wallpaper_info.drawn_images_queue_size,
+            wallpaper_info.no_repeat_size,
         );
-        if path_changed {
+        if !path_changed
+            && self.wallpaper_info.sorting != wallpaper_info.sorting
+            && matches!(
+                self.wallpaper_info.sorting,
+                Some(Sorting::GroupedRandom { .. })
+            )
+        {
+            // Show the image of the group that has just been joined
+            self.queue_draw(qh);
+        }
+        // The wallpapers of a new condition are picked from the next change
+        if path_changed && !self.condition_changed {
+            if !self.wallpaper_info.reload_transition {
+                self.skip_next_transition = true;
+            }
             // ask the image_picker to pick a new a image
             self.image_picker.next_image(&self.wallpaper_info.path, qh);
             self.queue_draw(qh);
         }
-        if self.wallpaper_info.duration != wallpaper_info.duration {
+        if self.wallpaper_info.mirror != wallpaper_info.mirror {
+            self.mirrored = None;
+            self.mirror_connected = false;
+            // The displays that stop mirroring go back to their own wallpapers, the other ones
+            // are updated by Wpaperd::update_mirrors
+            self.restart_timer(None, handle, qh);
+            if self.wallpaper_info.mirror.is_none() && !path_changed {
+                self.image_picker.next_image(&self.wallpaper_info.path, qh);
+                self.queue_draw(qh);
+            }
+        } else if self.wallpaper_info.is_daily() != wallpaper_info.is_daily() {
+            // The wallpaper of the day changes at midnight instead of after the duration
+            self.restart_timer(None, handle, qh);
+            if !path_changed {
+                self.queue_draw(qh);
+            }
+        } else if self.wallpaper_info.is_daily() {
+            if self.wallpaper_info.daily_offset != wallpaper_info.daily_offset {
+                self.queue_draw(qh);
+            }
+        } else if self.wallpaper_info.duration != wallpaper_info.duration {
             match (self.wallpaper_info.duration, wallpaper_info.duration) {
                 (None, None) => {
                     unreachable!()
                 }
                 // There was a duration before but now it has been removed
                 (None, Some(_)) => {
-                    if let EventSource::Running(registration_token) = self.event_source {
-                        handle.remove(registration_token);
-                    }
+                    self.event_source.stop(handle, false, Duration::ZERO);
                 }
                 // There wasn't a duration before but now it has been added or it has changed
                 (Some(new_duration), None) | (Some(new_duration), Some(_)) => {
-                    if let EventSource::Running(registration_token) = self.event_source {
-                        handle.remove(registration_token);
-                    }
-
                     // if the path has not changed or the duration has changed
                     // and the remaining time is great than 0
-                    let timer = if let (false, Some(remaining_time)) = (
+                    let delay = if let (false, Some(remaining_time)) = (
                         path_changed,
                         remaining_duration(new_duration, self.image_picker.image_changed_instant),
                     ) {
-                        Some(Timer::from_duration(remaining_time))
+                        remaining_time
                     } else {
                         // otherwise draw the image immediately, the next timer
                         // will be set to the new duration
-                        Some(Timer::immediate())
+                        Duration::ZERO
                     };
-
-                    self.event_source = EventSource::NotSet;
-                    self.add_timer(timer, handle, qh.clone());
+                    self.restart_timer(Some(delay), handle, qh);
                 }
             }
         }
 
         if self.wallpaper_info.mode != wallpaper_info.mode
             || self.wallpaper_info.offset != wallpaper_info.offset
+            || self.wallpaper_info.border != wallpaper_info.border
+            || self.wallpaper_info.backdrop != wallpaper_info.backdrop
+            || self.wallpaper_info.max_upscale != wallpaper_info.max_upscale
         {
-            if let Err(err) = self.egl_context.make_current().and_then(|_| {
-                self.renderer
-                    .set_mode(self.wallpaper_info.mode, self.wallpaper_info.offset)
-            }) {
-                error!("{err:?}");
+            let (border, backdrop, max_upscale) = (
+                self.wallpaper_info.border,
+                self.wallpaper_info.backdrop,
+                self.wallpaper_info.max_upscale,
+            );
+            match &mut self.backend {
+                Backend::Gl { renderer, .. } => {
+                    renderer.set_border(border);
+                    renderer.set_backdrop(backdrop);
+                    renderer.set_max_upscale(max_upscale);
+                }
+                Backend::Software(renderer) => {
+                    renderer.set_border(border);
+                    renderer.set_backdrop(backdrop);
+                    renderer.set_max_upscale(max_upscale);
+                }
             }
+            self.apply_mode();
             if !path_changed {
                 // We should draw immediately
                 if let Err(err) = self.draw(qh, None) {
-                    warn!("{err:?}");
+                    warn!("{}: {err:?}", self.name());
+                }
+            }
+        }
+        if self.wallpaper_info.layer_placement != wallpaper_info.layer_placement {
+            // The compositor configures the surface again with its new size
+            set_layer_placement(&self.layer, self.wallpaper_info.layer_placement);
+            self.wl_surface.commit();
+        }
+        if self.wallpaper_info.layer != wallpaper_info.layer {
+            // Wpaperd creates the surface again when the compositor can't move it
+            self.layer.set_layer(self.wallpaper_info.layer);
+            self.wl_surface.commit();
+        }
+        if self.wallpaper_info.transition_dither != wallpaper_info.transition_dither {
+            let dither = self.wallpaper_info.transition_dither;
+            if let Some(renderer) = self.renderer_mut() {
+                renderer.set_dither(dither);
+            }
+        }
+        if self.wallpaper_info.color_adjustment != wallpaper_info.color_adjustment
+            || self.wallpaper_info.fullscreen_brightness != wallpaper_info.fullscreen_brightness
+        {
+            self.apply_color_adjustment();
+            if !path_changed {
+                if let Err(err) = self.draw(qh, None) {
+                    warn!("{}: {err:?}", self.name());
+                }
+            }
+        }
+        if self.wallpaper_info.night_light != wallpaper_info.night_light {
+            self.update_night_light(qh);
+        }
+        if self.wallpaper_info.swap_interval != wallpaper_info.swap_interval {
+            let swap_interval = self.wallpaper_info.swap_interval;
+            if let Backend::Gl { egl_context, .. } = &mut self.backend {
+                if let Err(err) = egl_context
+                    .make_current()
+                    .and_then(|_| egl_context.set_swap_interval(swap_interval))
+                {
+                    error!("{}: {err:?}", self.name());
                 }
             }
         }
-        if self.wallpaper_info.transition != wallpaper_info.transition {
-            match self.egl_context.make_current() {
+        if self.wallpaper_info.transform != wallpaper_info.transform {
+            let wallpaper_transform = self.wallpaper_info.transform;
+            let transform = self.info.borrow().transform;
+            if let Backend::Gl {
+                egl_context,
+                renderer,
+            } = &mut self.backend
+            {
+                renderer.set_wallpaper_transform(wallpaper_transform);
+                if let Err(err) = egl_context
+                    .make_current()
+                    .and_then(|_| unsafe { renderer.set_projection_matrix(transform) })
+                {
+                    error!("{}: {err:?}", self.name());
+                }
+            }
+            // The texture scale depends on whether the wallpaper is rotated
+            self.apply_mode();
+            if !path_changed {
+                if let Err(err) = self.draw(qh, None) {
+                    warn!("{}: {err:?}", self.name());
+                }
+            }
+        }
+        if self.wallpaper_info.transitions != wallpaper_info.transitions
+            || self.wallpaper_info.random_transition != wallpaper_info.random_transition
+        {
+            self.picked_transition = None;
+            self.pick_transition();
+        }
+        if self.wallpaper_info.transition != wallpaper_info.transition
+            || self.wallpaper_info.transitions != wallpaper_info.transitions
+            || self.wallpaper_info.manual_transition != wallpaper_info.manual_transition
+        {
+            match self.make_current() {
                 Ok(_) => {
-                    let transform = self.renderer.display_info.borrow().transform;
-                    self.renderer
-                        .update_transition(self.wallpaper_info.transition.clone(), transform);
+                    self.update_transition();
+                    // The new program has none of the uniforms set by the background mode
+                    self.apply_mode();
                 }
                 Err(err) => {
-                    error!("{err:?}");
+                    error!("{}: {err:?}", self.name());
                 }
             }
         }
-        if self.wallpaper_info.drawn_images_queue_size != wallpaper_info.drawn_images_queue_size {
-            self.image_picker
-                .update_queue_size(self.wallpaper_info.drawn_images_queue_size);
+        if self.wallpaper_info.pan_range != wallpaper_info.pan_range
+            || self.wallpaper_info.pan_duration != wallpaper_info.pan_duration
+            || self.wallpaper_info.cursor_parallax != wallpaper_info.cursor_parallax
+        {
+            // Restore the configured offset in case the panning or the parallax have been
+            // disabled
+            self.apply_mode();
+            if !path_changed {
+                if let Err(err) = self.draw(qh, None) {
+                    warn!("{}: {err:?}", self.name());
+                }
+            }
+        }
+        // The filters are applied when loading the texture, load the same image again
+        if (self.wallpaper_info.texture_filter != wallpaper_info.texture_filter
+            || self.wallpaper_info.max_image_scale != wallpaper_info.max_image_scale
+            || self.wallpaper_info.downscale_filter != wallpaper_info.downscale_filter
+            || self.wallpaper_info.exif_orientation != wallpaper_info.exif_orientation
+            || self.wallpaper_info.auto_offset != wallpaper_info.auto_offset)
+            && !path_changed
+        {
+            self.image_picker.reload();
+            self.queue_draw(qh);
+        }
+        if self.wallpaper_info.condition != wallpaper_info.condition
+            || self.wallpaper_info.condition_timeout != wallpaper_info.condition_timeout
+        {
+            if self.wallpaper_info.condition.is_none() {
+                self.condition = None;
+            }
+            self.run_condition();
+        }
+        if self.wallpaper_info.weekdays != wallpaper_info.weekdays && !path_changed {
+            self.check_weekdays(qh);
+        }
+        if self.wallpaper_info.drawn_images_queue_size != wallpaper_info.drawn_images_queue_size
+            || self.wallpaper_info.no_repeat_size != wallpaper_info.no_repeat_size
+        {
+            self.image_picker.update_queue_size(
+                self.wallpaper_info.drawn_images_queue_size,
+                self.wallpaper_info.no_repeat_size,
+            );
+        }
+        if self.wallpaper_info.transition_time != wallpaper_info.transition_time
+            || self.wallpaper_info.transitions != wallpaper_info.transitions
+            || self.wallpaper_info.manual_transition != wallpaper_info.manual_transition
+        {
+            let transition_time = self.transition_time();
+            if let Some(renderer) = self.renderer_mut() {
+                renderer.update_transition_time(transition_time);
+            }
+        }
+    }
+
+    /// Switch to the next background mode and draw the wallpaper again.
+    /// The new mode is kept until the configuration is reloaded.
+    pub fn toggle_mode(&mut self, qh: &QueueHandle<Wpaperd>) -> BackgroundMode {
+        self.wallpaper_info.mode = self.mode().next();
+        // The mode of the sidecar would hide the new one
+        self.sidecar.mode = None;
+        self.apply_mode();
+        if let Err(err) = self.draw(qh, None) {
+            warn!("{}: {err:?}", self.name());
+        }
+        self.wallpaper_info.mode
+    }
+
+    /// Set the offset to `offset`, or to the current one, and move it by `delta` until the
+    /// configuration is reloaded. The wallpaper is drawn again and the new offset, kept
+    /// between 0 and 1, is returned.
+    pub fn set_offset(
+        &mut self,
+        offset: Option<f32>,
+        delta: Option<f32>,
+        qh: &QueueHandle<Wpaperd>,
+    ) -> f32 {
+        let offset = offset.unwrap_or_else(|| self.current_offset()) + delta.unwrap_or(0.0);
+        self.wallpaper_info.offset = Some(offset.clamp(0.0, 1.0));
+        // The offset of the sidecar would hide the new one
+        self.sidecar.offset = None;
+        self.apply_mode();
+        if let Err(err) = self.draw(qh, None) {
+            warn!("{}: {err:?}", self.name());
+        }
+        self.current_offset()
+    }
+
+    /// Return the offset of the wallpaper, the one computed by the background mode when it's
+    /// not set, before panning
+    pub fn current_offset(&self) -> f32 {
+        match &self.backend {
+            Backend::Gl { renderer, .. } => renderer.wallpaper_offset(),
+            // The focus of the images is only followed when drawing
+            Backend::Software(_) => texture_offset(self.mode(), self.offset(), None, [1.0; 2]),
+        }
+    }
+
+    /// Change the sorting until the configuration is reloaded and return it.
+    /// The sorting of a single image can't be changed, so it is left as it is.
+    pub fn set_sorting(&mut self, sorting: Sorting) -> Option<Sorting> {
+        if self.wallpaper_info.is_list() {
+            self.image_picker.set_sorting(
+                sorting,
+                &self.wallpaper_info.path,
+                self.wallpaper_info.drawn_images_queue_size,
+                self.wallpaper_info.no_repeat_size,
+            );
+            self.wallpaper_info.sorting = Some(sorting);
+        }
+        self.sorting()
+    }
+
+    /// Pick the wallpapers randomly from the favorites only, showing one of them right away,
+    /// or go back to the configured wallpapers and sorting. Return whether the favorites mode
+    /// is on, it lasts until the configuration is reloaded.
+    pub fn toggle_favorites(&mut self, qh: &QueueHandle<Wpaperd>) -> bool {
+        if self.image_picker.favorites_only() {
+            self.image_picker.set_favorites_only(false);
+            let sorting = self.sorting_before_favorites.take();
+            self.image_picker.set_sorting(
+                sorting.unwrap_or_default(),
+                &self.wallpaper_info.path,
+                self.wallpaper_info.drawn_images_queue_size,
+                self.wallpaper_info.no_repeat_size,
+            );
+            self.wallpaper_info.sorting = sorting;
+        } else if self.image_picker.has_favorites() {
+            self.image_picker.set_favorites_only(true);
+            self.sorting_before_favorites = self.wallpaper_info.sorting;
+            self.image_picker.set_sorting(
+                Sorting::Random,
+                &self.wallpaper_info.path,
+                self.wallpaper_info.drawn_images_queue_size,
+                self.wallpaper_info.no_repeat_size,
+            );
+            self.wallpaper_info.sorting = Some(Sorting::Random);
+            self.image_picker.next_image(&self.wallpaper_info.path, qh);
+            self.manual_change();
+            self.queue_draw(qh);
+        }
+        self.image_picker.favorites_only()
+    }
+
+    /// Append the current wallpaper to the favorites file, return it and whether it was not
+    /// already there
+    pub fn add_favorite(&mut self) -> Result<(PathBuf, bool)> {
+        let added = self.image_picker.add_favorite()?;
+        Ok((self.image_picker.current_image(), added))
+    }
+
+    /// Show another favorite if the current wallpaper has been removed from the favorites
+    pub fn refresh_favorites(&mut self, qh: &QueueHandle<Wpaperd>) {
+        if self.image_picker.refresh_favorites() {
+            info!("{}: the wallpaper is not a favorite anymore", self.name());
+            self.image_picker.next_image(&self.wallpaper_info.path, qh);
+            self.queue_draw(qh);
+        }
+    }
+
+    /// Return the swap interval in use, which might differ from the configured one
+    #[inline]
+    pub fn swap_interval(&self) -> i32 {
+        match &self.backend {
+            Backend::Gl { egl_context, .. } => egl_context.swap_interval(),
+            // The buffers are posted as soon as they are drawn
+            Backend::Software(_) => 0,
+        }
+    }
+
+    /// Return the sorting used to pick the wallpapers, None for a single image
+    pub fn sorting(&self) -> Option<Sorting> {
+        if self.wallpaper_info.is_list() {
+            // The image picker uses random when no sorting has been set
+            Some(self.wallpaper_info.sorting.unwrap_or_default())
+        } else {
+            None
+        }
+    }
+
+    /// Read the sidecar of the image being loaded. Without one, or when it is not valid, the
+    /// configuration of the display is used.
+    fn load_sidecar(&mut self, image_path: &Path) {
+        self.sidecar = match Sidecar::load(image_path) {
+            Ok(sidecar) => sidecar.unwrap_or_default(),
+            Err(err) => {
+                warn!("{}: {err:?}", self.name());
+                Sidecar::default()
+            }
+        };
+    }
+
+    /// Return the background mode of the current image
+    pub fn mode(&self) -> BackgroundMode {
+        self.sidecar.mode.unwrap_or(self.wallpaper_info.mode)
+    }
+
+    fn offset(&self) -> Option<f32> {
+        self.sidecar.offset().or(self.wallpaper_info.offset)
+    }
+
+    /// Return the transition requested with wpaperctl for the change to the current image, or
+    /// its `manual-transition`
+    fn manual_transition(&self) -> Option<&(Transition, u32)> {
+        self.forced_transition.as_ref().or_else(|| {
+            self.wallpaper_info
+                .manual_transition
+                .as_ref()
+                .filter(|_| self.manual_transition)
+        })
+    }
+
+    /// Return the transition to the current image
+    fn transition(&self) -> &Transition {
+        if let Some(mirrored) = &self.mirrored {
+            return &mirrored.transition;
+        }
+        if let Some((transition, _)) = self.manual_transition() {
+            return transition;
+        }
+        self.sidecar.transition.as_ref().unwrap_or_else(|| {
+            self.picked_transition()
+                .map_or(&self.wallpaper_info.transition, |(transition, _)| {
+                    transition
+                })
+        })
+    }
+
+    fn transition_time(&self) -> u32 {
+        if let Some(mirrored) = &self.mirrored {
+            return mirrored.transition_time;
+        }
+        if let Some((_, time)) = self.manual_transition() {
+            return *time;
+        }
+        self.sidecar.transition_time().unwrap_or_else(|| {
+            self.picked_transition()
+                .map_or(self.wallpaper_info.transition_time, |(_, time)| *time)
+        })
+    }
+
+    /// Return what this display shows, for the displays mirroring it
+    pub fn mirrored(&self) -> Option<Mirrored> {
+        let image = self.image_picker.current_image();
+        (!image.as_os_str().is_empty()).then(|| Mirrored {
+            image,
+            transition: self.transition().clone(),
+            transition_time: self.transition_time(),
+        })
+    }
+
+    /// Copy what the display set in `mirror` shows, `None` when it is not connected.
+    /// The last wallpaper of the mirrored display is kept after it has been disconnected.
+    pub fn mirror(&mut self, source: Option<Mirrored>, qh: &QueueHandle<Wpaperd>) {
+        let Some(source_name) = self.wallpaper_info.mirror.clone() else {
+            return;
+        };
+        let Some(source) = source else {
+            if std::mem::take(&mut self.mirror_connected) {
+                warn!(
+                    "{}: display {source_name} is not connected anymore, keeping its last wallpaper",
+                    self.name()
+                );
+            }
+            return;
+        };
+        if !self.mirror_connected {
+            info!("{}: mirroring display {source_name}", self.name());
+            self.mirror_connected = true;
+        }
+        if self.mirrored.as_ref() == Some(&source) {
+            return;
+        }
+        let image_changed = self
+            .mirrored
+            .as_ref()
+            .map_or(true, |mirrored| mirrored.image != source.image);
+        let transition_changed = *self.transition() != source.transition;
+        self.mirrored = Some(source);
+        if transition_changed {
+            match self.make_current() {
+                Ok(_) => {
+                    self.update_transition();
+                    self.apply_mode();
+                }
+                Err(err) => error!("{}: {err:?}", self.name()),
+            }
+        }
+        // The first wallpaper is loaded when the surface is drawn for the first time
+        if image_changed && self.has_been_drawn() {
+            self.queue_draw(qh);
+        }
+    }
+
+    fn picked_transition(&self) -> Option<&(Transition, u32)> {
+        self.picked_transition
+            .and_then(|index| self.wallpaper_info.transitions.get(index))
+    }
+
+    /// Pick the next transition of the configured set, at random or in order.
+    /// The renderer must be updated if [`Surface::transition`] has changed.
+    fn pick_transition(&mut self) {
+        let len = self.wallpaper_info.transitions.len();
+        if len == 0 {
+            self.picked_transition = None;
+            return;
+        }
+        let index = match (
+            self.picked_transition,
+            self.wallpaper_info.random_transition,
+        ) {
+            (Some(current), true) if len > 1 => {
+                // Don't pick the same transition twice in a row
+                let index = rand::random::<usize>() % (len - 1);
+                if index >= current {
+                    index + 1
+                } else {
+                    index
+                }
+            }
+            (Some(current), false) => (current + 1) % len,
+            (None, true) => rand::random::<usize>() % len,
+            _ => 0,
+        };
+        self.picked_transition = Some(index);
+    }
+
+    /// Update the renderer after the background mode or the offset have changed
+    fn apply_mode(&mut self) {
+        let (mode, offset) = (self.mode(), self.offset());
+        let res = match &mut self.backend {
+            Backend::Gl {
+                egl_context,
+                renderer,
+            } => egl_context
+                .make_current()
+                .and_then(|_| renderer.set_mode(mode, offset)),
+            Backend::Software(renderer) => {
+                renderer.set_mode(mode, offset);
+                Ok(())
+            }
+        };
+        if let Err(err) = res {
+            error!("{}: {err:?}", self.name());
+        }
+        #[cfg(feature = "video")]
+        if let Some(Err(err)) = self.video.as_ref().map(|video| video.set_mode(self.mode())) {
+            error!("{}: {err:?}", self.name());
         }
-        if self.wallpaper_info.transition_time != wallpaper_info.transition_time {
-            self.renderer
-                .update_transition_time(self.wallpaper_info.transition_time);
+    }
+
+    /// Update the renderer after the color adjustment or the fullscreen state have changed
+    fn apply_color_adjustment(&mut self) {
+        let color_adjustment = self.color_adjustment();
+        if let Err(err) = self.make_current().and_then(|_| {
+            self.renderer_mut().map_or(Ok(()), |renderer| {
+                renderer.set_color_adjustment(color_adjustment)
+            })
+        }) {
+            error!("{}: {err:?}", self.name());
+        }
+    }
+
+    /// Return the color adjustment to draw with, dimming the wallpaper when needed
+    fn color_adjustment(&self) -> ColorAdjustment {
+        let mut color_adjustment = self.wallpaper_info.color_adjustment;
+        if let (true, Some(brightness)) =
+            (self.fullscreen, self.wallpaper_info.fullscreen_brightness)
+        {
+            color_adjustment.brightness *= brightness;
+        }
+        color_adjustment
+    }
+
+    /// Update whether a fullscreen window covers this display
+    pub fn set_fullscreen(&mut self, fullscreen: bool, qh: &QueueHandle<Wpaperd>) {
+        if self.fullscreen == fullscreen {
+            return;
+        }
+        self.fullscreen = fullscreen;
+        // Dimming is opt-in
+        if self.wallpaper_info.fullscreen_brightness.is_none() {
+            return;
+        }
+
+        self.apply_color_adjustment();
+        if self.window_drawn {
+            if let Err(err) = self.draw(qh, None) {
+                warn!("{}: {err:?}", self.name());
+            }
+        }
+    }
+
+    /// Recalculate the night light for the current time, and draw again if it has changed
+    pub fn update_night_light(&mut self, qh: &QueueHandle<Wpaperd>) {
+        let (color, factor) = match self.wallpaper_info.night_light {
+            Some(night_light) => (
+                night_light.color(),
+                night_light.factor(local_seconds_of_day()),
+            ),
+            None => ([1.0; 3], 0.0),
+        };
+        let Backend::Gl {
+            egl_context,
+            renderer,
+        } = &mut self.backend
+        else {
+            return;
+        };
+        if renderer.night_light() == (color, factor) {
+            return;
         }
+        if let Err(err) = egl_context
+            .make_current()
+            .and_then(|_| renderer.set_night_light(color, factor))
+        {
+            error!("{}: {err:?}", self.name());
+        }
+        if self.window_drawn {
+            if let Err(err) = self.draw(qh, None) {
+                warn!("{}: {err:?}", self.name());
+            }
+        }
+    }
+
+    /// Return true when nobody can see the surface: its display is turned off or the session is
+    /// locked
+    #[inline]
+    fn hidden(&self) -> bool {
+        self.powered_off || self.session_locked
+    }
+
+    /// Update the power mode of the display. While it's turned off, the surface won't be drawn
+    /// and the timer will be paused.
+    pub fn set_powered(&mut self, powered: bool, qh: &QueueHandle<Wpaperd>) {
+        let was_hidden = self.hidden();
+        self.powered_off = !powered;
+        self.hidden_changed(was_hidden, qh);
+    }
+
+    /// Update the lock state of the session, the surface is handled like a display turned off
+    /// while it's locked
+    pub fn set_session_locked(&mut self, locked: bool, qh: &QueueHandle<Wpaperd>) {
+        let was_hidden = self.hidden();
+        self.session_locked = locked;
+        self.hidden_changed(was_hidden, qh);
+    }
+
+    /// Stop drawing when the surface has just been hidden, draw it again when it's visible
+    fn hidden_changed(&mut self, was_hidden: bool, qh: &QueueHandle<Wpaperd>) {
+        let hidden = self.hidden();
+        if hidden == was_hidden {
+            return;
+        }
+
+        self.update_video_pause();
+
+        if hidden {
+            // There is no point in finishing a transition that nobody can see
+            if let Some(renderer) = self.renderer_mut() {
+                renderer.force_transition_end();
+            }
+            self.end_forced_transition();
+        } else if self.window_drawn {
+            // Draw immediately, the wallpaper might have been changed in the meantime
+            if let Err(err) = self.draw(qh, None) {
+                warn!("{}: {err:?}", self.name());
+            }
+        }
+    }
+
+    /// Stop drawing and pause the timer until [`Surface::enable`] is called. The display is
+    /// filled with `color` if given, otherwise the last frame is left on it.
+    pub fn disable(&mut self, color: Option<[f32; 4]>) {
+        self.disabled = true;
+        // There is no point in finishing a transition that nobody can see
+        if let Some(renderer) = self.renderer_mut() {
+            renderer.force_transition_end();
+        }
+        self.end_forced_transition();
+
+        self.update_video_pause();
+
+        if let Some(color) = color.filter(|_| self.window_drawn) {
+            if let Err(err) = self.fill(color) {
+                error!("{}: {err:?}", self.name());
+            }
+        }
+    }
+
+    /// Draw the current wallpaper again after [`Surface::disable`]
+    pub fn enable(&mut self, qh: &QueueHandle<Wpaperd>) {
+        if !std::mem::take(&mut self.disabled) {
+            return;
+        }
+
+        self.update_video_pause();
+
+        if self.window_drawn {
+            if let Err(err) = self.draw(qh, None) {
+                warn!("{}: {err:?}", self.name());
+            }
+        }
+    }
+
+    /// Return true when the video must not play: nobody can see it, the display is disabled or
+    /// paused, or the user is idle
+    #[cfg(feature = "video")]
+    fn video_paused(&self) -> bool {
+        self.hidden() || self.disabled || self.should_pause || self.idle
+    }
+
+    /// Pause or resume the video, if one is playing, after its state has changed
+    fn update_video_pause(&self) {
+        #[cfg(feature = "video")]
+        if let Some(Err(err)) = self
+            .video
+            .as_ref()
+            .map(|video| video.set_paused(self.video_paused()))
+        {
+            error!("{}: {err:?}", self.name());
+        }
+    }
+
+    /// mpv has a new frame of the video, draw it on the next frame callback
+    #[cfg(feature = "video")]
+    pub fn video_updated(&mut self, qh: &QueueHandle<Wpaperd>) {
+        let ready = match self.video.as_ref().map(VideoPlayer::frame_ready) {
+            Some(Ok(ready)) => ready,
+            Some(Err(err)) => {
+                warn!("{}: {err:?}", self.name());
+                false
+            }
+            None => false,
+        };
+        // Each frame decoded by mpv is drawn like the frames of an animation
+        if ready && !self.hidden() && !self.disabled && self.window_drawn {
+            self.animate(qh);
+        }
+    }
+
+    /// Fill the whole surface with `color`
+    fn fill(&mut self, color: [f32; 4]) -> Result<()> {
+        let info = self.info.borrow();
+        let (width, height) = match self.backend {
+            Backend::Gl { .. } => (info.adjusted_width(), info.adjusted_height()),
+            // The buffer is in the orientation of the display
+            Backend::Software(_) => (info.scaled_width(), info.scaled_height()),
+        };
+        drop(info);
+
+        match &mut self.backend {
+            Backend::Gl {
+                egl_context,
+                renderer,
+            } => {
+                egl_context.make_current()?;
+                renderer.clear_to_color(color)?;
+                egl_context.swap_buffers()?;
+            }
+            Backend::Software(renderer) => renderer.clear_to_color(&self.wl_surface, color)?,
+        }
+        self.release_current()?;
+        self.wl_surface.damage_buffer(0, 0, width, height);
+        self.wl_surface.commit();
+        Ok(())
+    }
+
+    /// Show the pixels sent by [`wpaperd_ipc::IpcMessage::SetPixels`] with the transition of the
+    /// display. They are replaced by the next wallpaper, or when the current one is reloaded.
+    pub fn load_pixels(&mut self, image: RgbaImage, qh: &QueueHandle<Wpaperd>) -> Result<()> {
+        // Drop the image being decoded, it would replace the pixels once ready
+        self.loading_image = None;
+        self.loading_image_tries = 0;
+        self.transition_damage = self.partial_transition_damage(&image);
+
+        self.make_current()?;
+        // Start from what is being shown if the previous transition is still running
+        if let Some(renderer) = self.renderer_mut() {
+            renderer.force_transition_end();
+        }
+        #[cfg(feature = "video")]
+        {
+            self.video = None;
+        }
+        // The pixels don't have a sidecar
+        let previous_transition = self.transition().clone();
+        self.sidecar = Sidecar::default();
+        self.manual_transition = false;
+        self.forced_transition = None;
+        self.pick_transition();
+        if *self.transition() != previous_transition {
+            self.update_transition();
+        }
+        let (mode, offset) = (self.mode(), self.offset());
+        let texture_filter = self.wallpaper_info.texture_filter;
+        let no_transition = *self.transition() == (Transition::None {});
+        let transition_time = self.transition_time();
+        let average_color = self
+            .transition()
+            .uses_average_color()
+            .then(|| average_color(&image));
+        match &mut self.backend {
+            Backend::Gl { renderer, .. } => {
+                let stats = ImageStats {
+                    focus: None,
+                    average_color,
+                };
+                renderer.load_wallpaper(image.into(), stats, mode, offset, texture_filter)?;
+                if no_transition {
+                    renderer.transition_finished();
+                } else {
+                    renderer.start_transition(transition_time);
+                }
+            }
+            // Shown right away, there are no transitions in software
+            Backend::Software(renderer) => renderer.load_wallpaper(image, None, mode, offset),
+        }
+        self.held_transition = None;
+        self.metrics.transition_frames = 0;
+        self.release_current()?;
+
+        self.queue_draw(qh);
+        Ok(())
+    }
+
+    /// Draw the current frame again and return it as it is shown on the display, see
+    /// [`write_screenshot`]
+    pub fn screenshot(&mut self) -> Result<RgbaImage> {
+        #[cfg(feature = "video")]
+        ensure!(self.video.is_none(), "the videos can't be captured");
+        let Backend::Gl {
+            egl_context,
+            renderer,
+        } = &mut self.backend
+        else {
+            bail!("the display is drawn in software, there is no framebuffer to read");
+        };
+        egl_context.make_current()?;
+        // The back buffer is undefined after being swapped, draw it again without posting it
+        let image = unsafe { renderer.draw() }
+            .and_then(|_| renderer.read_pixels())
+            .and_then(|image| renderer.clear_after_draw().map(|_| image));
+        self.release_current()?;
+        image
+    }
+
+    /// Play the transition from the current wallpaper to `image`, or to itself, and then show
+    /// the current wallpaper again. The image picker and the timer are left untouched, so that
+    /// the transitions can be tuned without disturbing the sequence of wallpapers.
+    pub fn preview_transition(
+        &mut self,
+        image: Option<&Path>,
+        qh: &QueueHandle<Wpaperd>,
+    ) -> Result<()> {
+        ensure!(
+            matches!(self.backend, Backend::Gl { .. }),
+            "the display is drawn in software, there are no transitions"
+        );
+        #[cfg(feature = "video")]
+        ensure!(
+            self.video.is_none(),
+            "the transitions from a video can't be previewed"
+        );
+        ensure!(self.window_drawn, "no wallpaper has been drawn yet");
+        ensure!(
+            self.held_transition.is_none(),
+            "the next wallpaper is waiting for its transition"
+        );
+        ensure!(
+            *self.transition() != (Transition::None {}),
+            "the transition is none, there is nothing to preview"
+        );
+        // Same as the transition-capture, which copies the wallpaper
+        ensure!(
+            image.is_some() || self.mode() != BackgroundMode::Tile,
+            "the tiled wallpapers can only be previewed to another image"
+        );
+        self.preview_error = None;
+        match image {
+            Some(path) => {
+                // Decoded in the background, the current wallpaper is kept until then
+                self.preview_image = Some(path.to_path_buf());
+                self.previewing = true;
+                self.load_preview(qh);
+                Ok(())
+            }
+            None => self.start_preview(None, qh),
+        }
+    }
+
+    /// Return true if `path` is the image of the preview waiting to be decoded
+    pub fn is_loading_preview(&self, path: &Path) -> bool {
+        self.preview_image.as_deref() == Some(path)
+    }
+
+    /// Start the preview once its image has been decoded by the image loader
+    pub fn load_preview(&mut self, qh: &QueueHandle<Wpaperd>) {
+        let Some(path) = self.preview_image.clone() else {
+            return;
+        };
+        let options = LoadOptions {
+            max_size: self.max_image_size(),
+            exif_orientation: self.wallpaper_info.exif_orientation,
+            auto_offset: false,
+            average_color: self.transition().uses_average_color(),
+            allow_network: self.wallpaper_info.allow_network,
+        };
+        let res =
+            self.image_loader
+                .borrow_mut()
+                .background_load(path.clone(), self.name(), options);
+        let res = match res {
+            crate::image_loader::ImageLoaderStatus::Loaded(image) => {
+                let image = (DynamicImage::ImageRgba8(image.data), image.stats);
+                self.start_preview(Some(image), qh)
+            }
+            crate::image_loader::ImageLoaderStatus::Waiting => return,
+            crate::image_loader::ImageLoaderStatus::Error => {
+                Err(anyhow!("unable to decode the image {path:?}"))
+            }
+        };
+        self.preview_image = None;
+        if let Err(err) = res {
+            warn!("{}: {err:?}", self.name());
+            self.preview_error = Some(format!("{err:?}"));
+            self.previewing = false;
+        }
+    }
+
+    /// Play the transition to `image`, or to the current wallpaper
+    fn start_preview(
+        &mut self,
+        image: Option<(DynamicImage, ImageStats)>,
+        qh: &QueueHandle<Wpaperd>,
+    ) -> Result<()> {
+        let (mode, offset) = (self.mode(), self.offset());
+        let texture_filter = self.wallpaper_info.texture_filter;
+        let transition_time = self.transition_time();
+        self.make_current()?;
+        let res = self.renderer_mut().map_or(Ok(()), |renderer| {
+            renderer.start_preview(image, mode, offset, texture_filter, transition_time)
+        });
+        self.release_current()?;
+        res?;
+        // The whole display changes
+        self.transition_damage = None;
+        self.metrics.transition_frames = 0;
+        self.previewing = true;
+        self.queue_draw(qh);
+        Ok(())
+    }
+
+    /// Return why the last preview could not be played, if it failed
+    pub fn take_preview_error(&mut self) -> Option<String> {
+        self.preview_error.take()
+    }
+
+    /// Return true until the transition started by [`Surface::preview_transition`] has ended
+    pub fn is_previewing(&mut self) -> bool {
+        self.previewing &= self.preview_image.is_some() || self.transition_running();
+        self.previewing
+    }
+
+    /// Use `transition` for the next change only, without waiting for `transition-delay`
+    pub fn force_next_transition(&mut self, transition: Transition, transition_time: u32) {
+        self.next_transition = Some((transition, transition_time));
+    }
+
+    /// Mark the next change as requested with wpaperctl: it uses `manual-transition` if set,
+    /// and starts from what is currently on the display if `transition-capture` is set
+    pub fn manual_change(&mut self) {
+        self.manual_change = true;
+        let transition = match (
+            &self.next_transition,
+            &self.wallpaper_info.manual_transition,
+        ) {
+            (Some((transition, _)), _) | (None, Some((transition, _))) => transition,
+            (None, None) => self.transition(),
+        };
+        // Tiled wallpapers are scaled, the capture would not cover the display in the same way
+        self.capture_next_transition = self.wallpaper_info.transition_capture
+            && self.mode() != BackgroundMode::Tile
+            && *transition != (Transition::None {});
+    }
+
+    /// Change the wallpaper if it is pinned to other days of the week with `weekdays`, e.g.
+    /// at midnight. Paused displays keep their wallpaper.
+    pub fn check_weekdays(&mut self, qh: &QueueHandle<Wpaperd>) {
+        if self.wallpaper_info.weekdays.is_empty() || self.should_pause || self.disabled {
+            return;
+        }
+        let current_image = self.image_picker.current_image();
+        if !is_shown_on(
+            &current_image,
+            &self.wallpaper_info.weekdays,
+            Weekday::today(),
+        ) {
+            self.image_picker.next_image(&self.wallpaper_info.path, qh);
+            self.queue_draw(qh);
+        }
+    }
+
+    /// Update the idle state of the user. When the user comes back, show a new wallpaper right
+    /// away.
+    pub fn set_idle(&mut self, idle: bool, qh: &QueueHandle<Wpaperd>) {
+        if self.idle == idle {
+            return;
+        }
+        self.idle = idle;
+        self.update_video_pause();
+
+        // Only change the wallpaper on resume when it would be changing otherwise: not when
+        // paused with wpaperctl, disabled, showing a single image or copying another display
+        if !idle
+            && !self.should_pause
+            && !self.disabled
+            && self.wallpaper_info.is_list()
+            && self.wallpaper_info.mirror.is_none()
+        {
+            self.image_picker.next_image(&self.wallpaper_info.path, qh);
+            self.queue_draw(qh);
+        }
+    }
+
+    /// Update whether the system is running on battery.
+    /// The actual pausing/resuming is handled in [`Surface::handle_pause_state`]
+    #[inline]
+    pub fn set_on_battery(&mut self, on_battery: bool) {
+        self.on_battery = on_battery;
+    }
+
+    /// Update whether the local time is inside a `do-not-disturb` window.
+    /// The actual pausing/resuming is handled in [`Surface::handle_pause_state`]
+    #[inline]
+    pub fn set_do_not_disturb(&mut self, do_not_disturb: bool) {
+        self.do_not_disturb = do_not_disturb;
     }
 
     /// Add a new timer in the event_loop for the current duration
@@ -459,7 +2499,15 @@ impl Surface {
         if matches!(self.event_source, EventSource::Running(_)) {
             return;
         }
-        let Some(duration) = self.wallpaper_info.duration else {
+        // The wallpaper changes with the one of the mirrored display
+        if self.wallpaper_info.mirror.is_some() {
+            return;
+        }
+        let duration = if self.wallpaper_info.is_daily() {
+            until_midnight()
+        } else if let Some(duration) = self.wallpaper_info.duration {
+            duration
+        } else {
             return;
         };
 
@@ -481,7 +2529,12 @@ impl Surface {
                         }
                     };
 
-                    if let Some(duration) = surface.wallpaper_info.duration {
+                    if surface.wallpaper_info.is_daily() {
+                        // The image picker notices that the day has changed by itself
+                        surface.queue_draw(&qh);
+                        surface.run_condition();
+                        TimeoutAction::ToDuration(until_midnight())
+                    } else if let Some(duration) = surface.wallpaper_info.duration {
                         // Check that the timer has expired
                         // if the daemon received a next or previous image command
                         // the timer will be reset and we need to account that here
@@ -498,6 +2551,7 @@ impl Surface {
                                 .image_picker
                                 .next_image(&surface.wallpaper_info.path, &qh);
                             surface.queue_draw(&qh);
+                            surface.run_condition();
                             TimeoutAction::ToDuration(duration)
                         }
                     } else {
@@ -507,14 +2561,140 @@ impl Surface {
             )
             .expect("Failed to insert event source!");
 
-        self.event_source = EventSource::Running(registration_token);
+        self.event_source = EventSource::Running(registration_token);
+    }
+
+    /// Replace the duration until the configuration is reloaded, or until `expire` has passed.
+    /// The time already elapsed since the wallpaper was changed is kept.
+    pub fn set_temporary_duration(
+        &mut self,
+        duration: Duration,
+        expire: Option<Duration>,
+        handle: &LoopHandle<Wpaperd>,
+        qh: &QueueHandle<Wpaperd>,
+    ) {
+        if !self.wallpaper_info.is_list() {
+            return;
+        }
+        let configured = match self.duration_override.take() {
+            Some((configured, expiry)) => {
+                if let Some(expiry) = expiry {
+                    handle.remove(expiry);
+                }
+                configured
+            }
+            None => self.wallpaper_info.duration,
+        };
+        let expiry = expire.map(|expire| {
+            let name = self.name();
+            let loop_handle = handle.clone();
+            let qh = qh.clone();
+            handle
+                .insert_source(
+                    Timer::from_duration(expire),
+                    move |_deadline, _: &mut (), wpaperd: &mut Wpaperd| {
+                        if let Some(surface) = wpaperd.surface_from_name(&name) {
+                            // This timer is dropped right after, it doesn't need to be removed
+                            if let Some((_, expiry)) = &mut surface.duration_override {
+                                *expiry = None;
+                            }
+                            surface.reset_duration(&loop_handle, &qh);
+                        }
+                        TimeoutAction::Drop
+                    },
+                )
+                .expect("Failed to insert event source!")
+        });
+        self.duration_override = Some((configured, expiry));
+        self.change_duration(Some(duration), handle, qh);
+    }
+
+    /// Restore the configured duration, if it was replaced with IPC
+    pub fn reset_duration(&mut self, handle: &LoopHandle<Wpaperd>, qh: &QueueHandle<Wpaperd>) {
+        if let Some((configured, expiry)) = self.duration_override.take() {
+            if let Some(expiry) = expiry {
+                handle.remove(expiry);
+            }
+            self.change_duration(configured, handle, qh);
+        }
+    }
+
+    /// Use a new duration for the current wallpaper, keeping the time already elapsed
+    fn change_duration(
+        &mut self,
+        duration: Option<Duration>,
+        handle: &LoopHandle<Wpaperd>,
+        qh: &QueueHandle<Wpaperd>,
+    ) {
+        if self.wallpaper_info.duration == duration {
+            return;
+        }
+        self.wallpaper_info.duration = duration;
+        let remaining = duration.map(|duration| {
+            remaining_duration(duration, self.image_picker.image_changed_instant)
+                .unwrap_or_default()
+        });
+        match remaining {
+            Some(remaining) => self.restart_timer(Some(remaining), handle, qh),
+            None => {
+                self.event_source.stop(handle, false, Duration::ZERO);
+            }
+        }
+    }
+
+    /// Replace the timer of the automatic changes with one firing after `delay`, or after the
+    /// whole duration if None. While paused the timer is only added when resuming.
+    fn restart_timer(
+        &mut self,
+        delay: Option<Duration>,
+        handle: &LoopHandle<Wpaperd>,
+        qh: &QueueHandle<Wpaperd>,
+    ) {
+        let remaining = delay.unwrap_or_else(|| {
+            if self.wallpaper_info.is_daily() {
+                until_midnight()
+            } else {
+                self.wallpaper_info.duration.unwrap_or_default()
+            }
+        });
+        if self
+            .event_source
+            .stop(handle, self.timer_paused(), remaining)
+        {
+            self.add_timer(delay.map(Timer::from_duration), handle, qh.clone());
+        }
+    }
+
+    /// Return true when the timer must not run: the automatic changes have been paused, the
+    /// display is turned off or disabled, the session is locked, the user is idle, the system is running on battery,
+    /// it's during a do not disturb window or the pointer is on the display
+    fn timer_paused(&self) -> bool {
+        let on_battery = self.on_battery && self.wallpaper_info.pause_on_battery;
+        let hovered = self.pointer_position.is_some() && self.wallpaper_info.pause_on_hover;
+        self.should_pause
+            || self.hidden()
+            || self.disabled
+            || self.idle
+            || on_battery
+            || self.do_not_disturb
+            || hovered
+    }
+
+    /// Return the duration in use, which might have been replaced with IPC
+    #[inline]
+    pub fn duration(&self) -> Option<Duration> {
+        self.wallpaper_info.duration
     }
 
     /// Handle updating the timer based on the pause state of the automatic wallpaper sequence.
     /// Remove the timer if pausing, and add a new timer with the remaining duration of the old
-    /// timer when resuming.
+    /// timer when resuming. The timer is also paused while the display is turned off or the
+    /// session is locked, the user is idle, the system is running on battery, during a do not
+    /// disturb window or while the pointer is on the display with `pause-on-hover`. The panning
+    /// stops along with the automatic changes while paused and while the user is idle.
     pub fn handle_pause_state(&mut self, handle: &LoopHandle<Wpaperd>, qh: QueueHandle<Wpaperd>) {
-        match (self.should_pause, &self.event_source) {
+        self.update_pan(&qh);
+        match (self.timer_paused(), &self.event_source) {
             // Should pause, but timer is still currently running
             (true, EventSource::Running(registration_token)) => {
                 let remaining_duration = self.get_remaining_duration().unwrap_or_default();
@@ -535,14 +2715,16 @@ impl Surface {
     pub fn queue_draw(&mut self, qh: &QueueHandle<Wpaperd>) {
         // Start loading the next image immediately
         if let Err(err) = self.load_wallpaper(qh) {
-            warn!("{err:?}");
+            warn!("{}: {err:?}", self.name());
         }
-        self.wl_surface.frame(qh, self.wl_surface.clone());
-        self.wl_surface.commit();
+        self.animate(qh);
     }
 
     #[inline]
     fn get_remaining_duration(&self) -> Option<Duration> {
+        if self.wallpaper_info.is_daily() {
+            return Some(until_midnight());
+        }
         let duration = self.wallpaper_info.duration?;
         remaining_duration(duration, self.image_picker.image_changed_instant)
     }
@@ -553,6 +2735,7 @@ impl Surface {
     #[inline]
     pub fn pause(&mut self) {
         self.should_pause = true;
+        self.update_video_pause();
     }
     /// Indicate to the main event loop that the automatic wallpaper sequence for this [`Surface`]
     /// should be resumed.
@@ -560,6 +2743,7 @@ impl Surface {
     #[inline]
     pub fn resume(&mut self) {
         self.should_pause = false;
+        self.update_video_pause();
     }
 
     /// Toggle the pause state for this [`Surface`], which is responsible for indicating to the main
@@ -574,6 +2758,330 @@ impl Surface {
         };
     }
 
+    /// A command printing the wallpapers has finished running. Show one of them when the display
+    /// was waiting for its first list, or when the previous one was empty.
+    pub fn command_listed(&mut self, commands: &[ListCommand], qh: &QueueHandle<Wpaperd>) {
+        let listed = self
+            .wallpaper_info
+            .list_command()
+            .is_some_and(|command| commands.contains(&command));
+        if listed
+            && self.has_been_drawn()
+            && (self.image_picker.is_unavailable()
+                || self.image_picker.current_image().as_os_str().is_empty())
+        {
+            self.image_picker.next_image(&self.wallpaper_info.path, qh);
+            self.queue_draw(qh);
+        }
+    }
+
+    /// The dimensions of some images have been read, pick an image if the filters were
+    /// waiting for them
+    pub fn images_probed(&mut self, qh: &QueueHandle<Wpaperd>) {
+        if !self.image_picker.is_probing() {
+            return;
+        }
+        self.image_picker.images_probed();
+        if self.has_been_drawn() && self.image_picker.current_image().as_os_str().is_empty() {
+            self.image_picker.next_image(&self.wallpaper_info.path, qh);
+            self.queue_draw(qh);
+        }
+    }
+
+    /// When there are no wallpapers to pick from, e.g. because the directory is on a network
+    /// share that has been unmounted, keep the current wallpaper and check the list again after
+    /// a delay, which doubles each time the list is still empty. The paths before the fallback
+    /// in use are checked again the same way.
+    pub fn handle_unavailable_list(
+        &mut self,
+        handle: &LoopHandle<Wpaperd>,
+        qh: QueueHandle<Wpaperd>,
+    ) {
+        if !self.image_picker.is_unavailable() && !self.image_picker.is_using_fallback() {
+            self.retry_delay = RETRY_INITIAL_DELAY;
+            return;
+        }
+        if self.retry_scheduled {
+            return;
+        }
+
+        let delay = self.retry_delay;
+        self.retry_delay = (delay * 2).min(RETRY_MAX_DELAY);
+        self.retry_scheduled = true;
+        let name = self.name();
+        handle
+            .insert_source(
+                Timer::from_duration(delay),
+                move |_deadline, _: &mut (), wpaperd: &mut Wpaperd| {
+                    if let Some(surface) = wpaperd.surface_from_name(&name) {
+                        surface.retry_scheduled = false;
+                        let path = &surface.wallpaper_info.path;
+                        let unavailable = surface.image_picker.is_unavailable();
+                        let fallback_level = surface.image_picker.fallback_level();
+                        surface.image_picker.refresh_filelist(path);
+                        // Only change the wallpaper when a better path is available again
+                        if unavailable || surface.image_picker.fallback_level() != fallback_level {
+                            surface.image_picker.next_image(path, &qh);
+                            surface.queue_draw(&qh);
+                        }
+                    }
+                    TimeoutAction::Drop
+                },
+            )
+            .expect("Failed to insert event source!");
+    }
+
+    /// Create the EGL context and the renderer again after the context has been lost, waiting
+    /// longer after each failed attempt
+    pub fn handle_lost_context(&mut self, handle: &LoopHandle<Wpaperd>, qh: QueueHandle<Wpaperd>) {
+        if !self.context_lost {
+            self.context_recovery_delay = CONTEXT_RECOVERY_INITIAL_DELAY;
+            return;
+        }
+        if self.context_recovery_scheduled {
+            return;
+        }
+
+        let delay = self.context_recovery_delay;
+        self.context_recovery_delay = (delay * 2).min(CONTEXT_RECOVERY_MAX_DELAY);
+        self.context_recovery_scheduled = true;
+        let name = self.name();
+        handle
+            .insert_source(
+                Timer::from_duration(delay),
+                move |_deadline, _: &mut (), wpaperd: &mut Wpaperd| {
+                    if let Some(surface) = wpaperd.surface_from_name(&name) {
+                        surface.context_recovery_scheduled = false;
+                        match surface.recover_context(&qh) {
+                            Ok(()) => info!("{name}: the EGL context has been created again"),
+                            Err(err) => warn!(
+                                "{name}: {err:?}\nTrying again in {}s",
+                                surface.context_recovery_delay.as_secs()
+                            ),
+                        }
+                    }
+                    TimeoutAction::Drop
+                },
+            )
+            .expect("Failed to insert event source!");
+    }
+
+    fn recover_context(&mut self, qh: &QueueHandle<Wpaperd>) -> Result<()> {
+        // The video is rendered with the lost context too
+        #[cfg(feature = "video")]
+        {
+            self.video = None;
+        }
+        let name = self.name();
+        let transition = self.transition().clone();
+        let color_adjustment = self.color_adjustment();
+        // There is no context to lose when drawing in software
+        let Backend::Gl {
+            egl_context,
+            renderer,
+        } = &mut self.backend
+        else {
+            return Ok(());
+        };
+        // The surface of the window must be destroyed before creating a new one
+        egl_context.destroy();
+        *egl_context = EglContext::new(egl_context.display, &self.wl_surface)
+            .context("creating the EGL context again")?;
+        egl_context.make_current()?;
+        let new_renderer = create_renderer(
+            &name,
+            self.info.clone(),
+            &self.wallpaper_info,
+            transition,
+            color_adjustment,
+            renderer.night_light(),
+        )
+        .context("creating the renderer again")?;
+        // The objects of the previous renderer belonged to the lost context and have been
+        // freed with it, deleting them now could delete the ones of the new context
+        std::mem::forget(std::mem::replace(renderer, new_renderer));
+        self.context_lost = false;
+
+        // Load the current wallpaper in the new context, without any transition
+        self.image_picker.reload();
+        self.loading_image = None;
+        self.resize(qh)
+    }
+
+    /// Run the `condition` command, its output selects the wallpapers of the next change
+    fn run_condition(&self) {
+        if let Some(command) = &self.wallpaper_info.condition {
+            self.condition_runner.run(
+                self.name(),
+                command.clone(),
+                self.wallpaper_info.condition_timeout,
+            );
+        }
+    }
+
+    /// Called with the output of the `condition` command, return true if it selects other
+    /// wallpapers. The names missing from `conditions` select `path`.
+    pub fn set_condition(&mut self, condition: Option<String>) -> bool {
+        let condition = condition.filter(|condition| {
+            let known = self.wallpaper_info.conditions.contains_key(condition);
+            if !known {
+                warn!(
+                    "{}: condition {condition:?} is not in conditions, using path",
+                    self.name()
+                );
+            }
+            known
+        });
+        if self.condition == condition {
+            return false;
+        }
+        self.condition = condition;
+        true
+    }
+
+    /// Use the wallpapers of the new condition, without changing the current one
+    pub fn update_condition(
+        &mut self,
+        handle: &LoopHandle<Wpaperd>,
+        qh: &QueueHandle<Wpaperd>,
+        wallpaper_info: WallpaperInfo,
+    ) {
+        self.condition_changed = true;
+        self.apply_wallpaper_info(handle, qh, wallpaper_info);
+        self.condition_changed = false;
+    }
+
+    /// Called when the compositor has sent the active workspace of the display
+    pub fn set_workspace(&mut self, workspace: Option<String>) {
+        if self.workspace != workspace {
+            self.workspace = workspace;
+            self.workspace_changed = !self.wallpaper_info.workspaces.is_empty();
+        }
+    }
+
+    /// Show the wallpapers of the active workspace once it has been active for
+    /// [`WORKSPACE_DELAY`], replacing the running timer so that switching quickly through
+    /// multiple workspaces doesn't load the wallpapers of each of them
+    pub fn handle_workspace_change(
+        &mut self,
+        handle: &LoopHandle<Wpaperd>,
+        qh: QueueHandle<Wpaperd>,
+    ) {
+        if !std::mem::take(&mut self.workspace_changed) {
+            return;
+        }
+        if let Some(workspace_timer) = self.workspace_timer.take() {
+            handle.remove(workspace_timer);
+        }
+        let name = self.name();
+        let loop_handle = handle.clone();
+        self.workspace_timer = Some(
+            handle
+                .insert_source(
+                    Timer::from_duration(WORKSPACE_DELAY),
+                    move |_deadline, _: &mut (), wpaperd: &mut Wpaperd| {
+                        let Some(surface) = wpaperd
+                            .surfaces
+                            .iter_mut()
+                            .find(|surface| surface.name() == name)
+                        else {
+                            return TimeoutAction::Drop;
+                        };
+                        // This timer is dropped right after, it doesn't need to be removed
+                        surface.workspace_timer = None;
+                        // Go through the configuration again, for the workspaces without
+                        // wallpapers of their own
+                        let res = wpaperd.config.get_output(&surface.info());
+                        match res {
+                            Ok(wallpaper_info) => {
+                                surface.apply_wallpaper_info(&loop_handle, &qh, wallpaper_info)
+                            }
+                            Err(err) => warn!("Configuration error for display {name}: {err:?}"),
+                        }
+                        TimeoutAction::Drop
+                    },
+                )
+                .expect("Failed to insert event source!"),
+        );
+    }
+
+    /// Start the timer of `transition-delay` after a wallpaper has been loaded, replacing the
+    /// running one so that the delay starts over at each change. The previous wallpaper is
+    /// shown until it fires.
+    pub fn handle_transition_hold(
+        &mut self,
+        handle: &LoopHandle<Wpaperd>,
+        qh: QueueHandle<Wpaperd>,
+    ) {
+        if !std::mem::take(&mut self.restart_hold) {
+            return;
+        }
+        if let Some(hold_timer) = self.hold_timer.take() {
+            handle.remove(hold_timer);
+        }
+        let name = self.name();
+        let delay = Duration::from_millis(self.wallpaper_info.transition_delay.into());
+        self.hold_timer = Some(
+            handle
+                .insert_source(
+                    Timer::from_duration(delay),
+                    move |_deadline, _: &mut (), wpaperd: &mut Wpaperd| {
+                        if let Some(surface) = wpaperd.surface_from_name(&name) {
+                            // This timer is dropped right after, it doesn't need to be removed
+                            surface.hold_timer = None;
+                            surface.start_held_transition(&qh);
+                        }
+                        TimeoutAction::Drop
+                    },
+                )
+                .expect("Failed to insert event source!"),
+        );
+    }
+
+    /// Return true until `startup-delay` has passed since the first call, which starts its
+    /// timer. The main loop doesn't draw the display nor start the timer of `duration` before.
+    pub fn wait_startup_delay(&mut self, handle: &LoopHandle<Wpaperd>) -> bool {
+        if self.startup_delay_elapsed || self.wallpaper_info.startup_delay == 0 {
+            return false;
+        }
+        if self.startup_timer.is_none() {
+            let name = self.name();
+            let delay = Duration::from_millis(self.wallpaper_info.startup_delay.into());
+            debug!("{name}: waiting {}ms before drawing", delay.as_millis());
+            self.startup_timer = Some(
+                handle
+                    .insert_source(
+                        Timer::from_duration(delay),
+                        move |_deadline, _: &mut (), wpaperd: &mut Wpaperd| {
+                            if let Some(surface) = wpaperd.surface_from_name(&name) {
+                                // This timer is dropped right after, it doesn't need to be removed
+                                surface.startup_timer = None;
+                                surface.startup_delay_elapsed = true;
+                            }
+                            TimeoutAction::Drop
+                        },
+                    )
+                    .expect("Failed to insert event source!"),
+            );
+        }
+        true
+    }
+
+    fn start_held_transition(&mut self, qh: &QueueHandle<Wpaperd>) {
+        let Some(transition_time) = self.held_transition.take() else {
+            return;
+        };
+        // The wallpaper might have been swapped instantly in the meantime
+        let Some(renderer) = self.renderer_mut() else {
+            return;
+        };
+        if renderer.transition_held() {
+            renderer.start_transition(transition_time);
+            debug!("{}: starting the held transition", self.name());
+            self.animate(qh);
+        }
+    }
+
     /// Returns a boolean representing whether this [`Surface`] is set to indicate to the main event
     /// loop that its automatic wallpaper sequence should be paused.
     #[inline]
@@ -589,15 +3097,181 @@ impl Surface {
         &self.wl_output
     }
 
+    /// Whether the compositor can move the surface to another layer, which requires version 2
+    /// of the layer shell protocol
+    pub fn can_set_layer(&self) -> bool {
+        match self.layer.kind() {
+            SurfaceKind::Wlr(layer) => layer.version() >= 2,
+            _ => false,
+        }
+    }
+
+    /// Remove the timers of this surface, they would find the one created again in its place by
+    /// name
+    pub fn remove_timers(&mut self, handle: &LoopHandle<Wpaperd>) {
+        if let EventSource::Running(_) = self.event_source {
+            let remaining = self.get_remaining_duration().unwrap_or_default();
+            self.event_source.stop(handle, true, remaining);
+        }
+        for token in [
+            self.hold_timer.take(),
+            self.startup_timer.take(),
+            self.workspace_timer.take(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            handle.remove(token);
+        }
+    }
+
+    /// Continue from `previous`, the surface of the same display before it was created again on
+    /// another layer: the history of the wallpapers, the pause and the changes made with IPC are
+    /// kept. Its timers must have been removed with [`Surface::remove_timers`].
+    pub fn take_over(
+        &mut self,
+        mut previous: Surface,
+        handle: &LoopHandle<Wpaperd>,
+        qh: &QueueHandle<Wpaperd>,
+    ) {
+        self.image_picker.take_history(&mut previous.image_picker);
+        self.loading_image = None;
+        self.should_pause = previous.should_pause;
+        self.startup_delay_elapsed = previous.startup_delay_elapsed;
+        self.next_transition = previous.next_transition.take();
+        // The timer restoring the duration finds this surface by name
+        self.duration_override = previous.duration_override.take();
+        if self.duration_override.is_some() {
+            self.wallpaper_info.duration = previous.wallpaper_info.duration;
+        }
+        if self.image_picker.favorites_only() {
+            self.sorting_before_favorites = previous.sorting_before_favorites;
+            self.wallpaper_info.sorting = Some(Sorting::Random);
+        }
+        // Keep the time left before the next change
+        if let EventSource::Paused(remaining) = previous.event_source {
+            if self.should_pause {
+                self.event_source = EventSource::Paused(remaining);
+            } else {
+                self.add_timer(Some(Timer::from_duration(remaining)), handle, qh.clone());
+            }
+        }
+        self.update_video_pause();
+        if let Err(err) = self.load_wallpaper(qh) {
+            warn!("{}: {err:?}", self.name());
+        }
+    }
+
+    /// Layer the surface has been created on, from the configuration
+    pub fn configured_layer(&self) -> Layer {
+        self.wallpaper_info.layer
+    }
+
     pub fn layer(&self) -> &LayerSurface {
         &self.layer
     }
 }
 
+/// Frame rate used for panning when `max-fps` is not set
+const PAN_MAX_FPS: u32 = 30;
+
+/// Time needed by the parallax to cover about two thirds of the distance to the pointer
+const PARALLAX_SMOOTHING: Duration = Duration::from_millis(150);
+/// Below this difference from the pointer, the parallax reaches it and stops drawing
+const PARALLAX_MIN_SHIFT: f32 = 0.0005;
+
+/// Delays used to check again an empty list of wallpapers
+const RETRY_INITIAL_DELAY: Duration = Duration::from_secs(5);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(300);
+/// The GPU might need some time after a reset or a resume before accepting new contexts
+const CONTEXT_RECOVERY_INITIAL_DELAY: Duration = Duration::from_secs(1);
+const CONTEXT_RECOVERY_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// How long a workspace has to stay active before its wallpapers are shown
+const WORKSPACE_DELAY: Duration = Duration::from_millis(300);
+
+/// Fraction of the display that can change for `partial-transition` to only damage that region
+const PARTIAL_TRANSITION_MAX_AREA: f32 = 0.5;
+
+/// Return the bounding box (x, y, width, height) of the pixels that differ between the two
+/// images, None if they have different sizes or if they are identical
+fn changed_region(prev: &RgbaImage, next: &RgbaImage) -> Option<[i32; 4]> {
+    if prev.dimensions() != next.dimensions() || prev.width() == 0 {
+        return None;
+    }
+    let row_len = prev.width() as usize * 4;
+    // Left, top, right and bottom sides of the region, inclusive
+    let mut region: Option<(usize, usize, usize, usize)> = None;
+    let rows = prev.chunks_exact(row_len).zip(next.chunks_exact(row_len));
+    for (y, (prev_row, next_row)) in rows.enumerate() {
+        if prev_row == next_row {
+            continue;
+        }
+        let pixels = || prev_row.chunks_exact(4).zip(next_row.chunks_exact(4));
+        let left = pixels().position(|(a, b)| a != b).unwrap_or(0);
+        let right = pixels().rposition(|(a, b)| a != b).unwrap_or(0);
+        region = Some(match region {
+            Some((l, t, r, _)) => (l.min(left), t, r.max(right), y),
+            None => (left, y, right, y),
+        });
+    }
+
+    region.map(|(left, top, right, bottom)| {
+        [
+            left as i32,
+            top as i32,
+            (right - left + 1) as i32,
+            (bottom - top + 1) as i32,
+        ]
+    })
+}
+
 fn black_image() -> RgbaImage {
     RgbaImage::from_raw(1, 1, vec![0, 0, 0, 255]).unwrap()
 }
 
+/// Create a renderer showing a black image, with the color adjustment, the night light, the
+/// wallpaper transform, the border and the backdrop of the display. The EGL context must be current.
+fn create_renderer(
+    name: &str,
+    info: Rc<RefCell<DisplayInfo>>,
+    wallpaper_info: &WallpaperInfo,
+    transition: Transition,
+    color_adjustment: ColorAdjustment,
+    (night_color, night_factor): ([f32; 3], f32),
+) -> Result<Renderer> {
+    let transform = info.borrow().transform;
+    let mut renderer =
+        unsafe { Renderer::new(black_image().into(), info, 0, transition, transform)? };
+    if let Err(err) = renderer.set_color_adjustment(color_adjustment) {
+        error!("{name}: {err:?}");
+    }
+    if let Err(err) = renderer.set_night_light(night_color, night_factor) {
+        error!("{name}: {err:?}");
+    }
+    if wallpaper_info.transform != Transform::Normal {
+        renderer.set_wallpaper_transform(wallpaper_info.transform);
+        if let Err(err) = unsafe { renderer.set_projection_matrix(transform) } {
+            error!("{name}: {err:?}");
+        }
+    }
+
+    // The background mode is set when the display is configured
+    renderer.set_border(wallpaper_info.border);
+    renderer.set_backdrop(wallpaper_info.backdrop);
+    renderer.set_max_upscale(wallpaper_info.max_upscale);
+    renderer.set_dither(wallpaper_info.transition_dither);
+    Ok(renderer)
+}
+
+/// Set the anchors, the margins and the size of the layer surface, applied at the next commit
+fn set_layer_placement(layer: &LayerSurface, placement: LayerPlacement) {
+    let [top, right, bottom, left] = placement.margin;
+    layer.set_anchor(placement.anchor);
+    layer.set_margin(top, right, bottom, left);
+    layer.set_size(placement.size.0, placement.size.1);
+}
+
 fn remaining_duration(duration: Duration, image_changed: Instant) -> Option<Duration> {
     // The timer has already expired
     let diff = image_changed.elapsed();
@@ -607,3 +3281,470 @@ fn remaining_duration(duration: Duration, image_changed: Instant) -> Option<Dura
         Some(duration - diff)
     }
 }
+
+/// Compress the frame returned by [`Surface::screenshot`] to a PNG image written to `path`. It
+/// takes a while for the big displays, it's called outside of the event loop.
+pub fn write_screenshot(image: &RgbaImage, path: &Path) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("unable to create the file {path:?}"))?;
+    PngEncoder::new(BufWriter::new(file))
+        .write_image(
+            image.as_raw(),
+            image.width(),
+            image.height(),
+            ExtendedColorType::Rgba8,
+        )
+        .with_context(|| format!("unable to write the screenshot to {path:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        os::unix::net::UnixStream,
+    };
+
+    use hotwatch::Hotwatch;
+    use smithay_client_toolkit::reexports::{
+        calloop::EventLoop,
+        client::{globals::registry_queue_init, Connection, EventQueue},
+    };
+
+    use super::*;
+    use crate::{
+        config::Config, filelist_cache::FilelistCache, http::HttpCache, wpaperd::StartupOptions,
+    };
+
+    /// Globals advertised by [`fake_compositor`], the ones [`Wpaperd::new`] requires
+    const GLOBALS: [(&str, u32); 3] = [
+        ("wl_compositor", 6),
+        ("wl_shm", 1),
+        ("zwlr_layer_shell_v1", 4),
+    ];
+
+    /// Serialize an event of the Wayland protocol
+    fn event(object: u32, opcode: u32, args: &[u8]) -> Vec<u8> {
+        let size = 8 + args.len() as u32;
+        let mut event = object.to_ne_bytes().to_vec();
+        event.extend_from_slice(&(size << 16 | opcode).to_ne_bytes());
+        event.extend_from_slice(args);
+        event
+    }
+
+    /// Answer the roundtrip of [`registry_queue_init`] with [`GLOBALS`] in a new thread. The
+    /// requests sent afterwards are read and ignored, no event is ever sent back for them.
+    fn fake_compositor(mut stream: UnixStream) {
+        std::thread::spawn(move || {
+            let mut registry = None;
+            let mut received = Vec::new();
+            let mut chunk = [0; 4096];
+            while let Ok(n @ 1..) = stream.read(&mut chunk) {
+                received.extend_from_slice(&chunk[..n]);
+                while received.len() >= 8 {
+                    let word =
+                        |at: usize| u32::from_ne_bytes(received[at..at + 4].try_into().unwrap());
+                    let (object, opcode, size) =
+                        (word(0), word(4) & 0xffff, (word(4) >> 16) as usize);
+                    if received.len() < size {
+                        break;
+                    }
+                    match (object, opcode) {
+                        // wl_display.get_registry
+                        (1, 1) => registry = Some(word(8)),
+                        // wl_display.sync
+                        (1, 0) => {
+                            let callback = word(8);
+                            let mut events = Vec::new();
+                            for (name, (interface, version)) in (1u32..).zip(GLOBALS) {
+                                let Some(registry) = registry else { break };
+                                let mut args = name.to_ne_bytes().to_vec();
+                                let padded = (interface.len() + 4) / 4 * 4;
+                                args.extend_from_slice(&(interface.len() as u32 + 1).to_ne_bytes());
+                                args.extend_from_slice(interface.as_bytes());
+                                args.resize(args.len() + padded - interface.len(), 0);
+                                args.extend_from_slice(&version.to_ne_bytes());
+                                // wl_registry.global
+                                events.extend(event(registry, 0, &args));
+                            }
+                            registry = None;
+                            // wl_callback.done, then wl_display.delete_id
+                            events.extend(event(callback, 0, &0u32.to_ne_bytes()));
+                            events.extend(event(1, 1, &callback.to_ne_bytes()));
+                            if stream.write_all(&events).is_err() {
+                                return;
+                            }
+                        }
+                        _ => {}
+                    }
+                    received.drain(..size);
+                }
+            }
+        });
+    }
+
+    /// Daemon connected to [`fake_compositor`], drawing in software
+    struct TestDaemon {
+        wpaperd: Wpaperd,
+        event_loop: EventLoop<'static, Wpaperd>,
+        qh: QueueHandle<Wpaperd>,
+        _queue: EventQueue<Wpaperd>,
+    }
+
+    impl TestDaemon {
+        /// Daemon listing the wallpapers of `paths`
+        fn new(paths: Vec<PathBuf>) -> Self {
+            let (client, server) = UnixStream::pair().unwrap();
+            fake_compositor(server);
+            let connection = Connection::from_socket(client).unwrap();
+            let (globals, queue) = registry_queue_init::<Wpaperd>(&connection).unwrap();
+            let qh = queue.handle();
+            let event_loop = EventLoop::<Wpaperd>::try_new().unwrap();
+            let handle = event_loop.handle();
+            let (_, filelist_cache) = FilelistCache::new(
+                paths,
+                vec![],
+                vec![],
+                vec![],
+                &mut Hotwatch::new().unwrap(),
+                handle.clone(),
+                qh.clone(),
+            )
+            .unwrap();
+            let image_loader = ImageLoader::new(
+                HttpCache::new(std::env::temp_dir()),
+                handle.clone(),
+                qh.clone(),
+            )
+            .unwrap();
+            let (_, logger) = flexi_logger::Logger::try_with_str("off")
+                .unwrap()
+                .build()
+                .unwrap();
+            let wpaperd = Wpaperd::new(
+                &qh,
+                &globals,
+                Config::default(),
+                Rc::new(RefCell::new(filelist_cache)),
+                Rc::new(RefCell::new(image_loader)),
+                ConditionRunner::new(handle.clone(), qh.clone()).unwrap(),
+                StartupOptions {
+                    egl_display: None,
+                    state: None,
+                    output_filter: Vec::new(),
+                    unique_wallpapers: false,
+                    suspend_when_off: false,
+                    fractional_scale: false,
+                    logger,
+                    #[cfg(feature = "video")]
+                    video_frames: VideoFrames::new(handle, qh.clone()).unwrap(),
+                },
+            )
+            .unwrap();
+            Self {
+                wpaperd,
+                event_loop,
+                qh,
+                _queue: queue,
+            }
+        }
+
+        /// Create the surface of a display that is never configured by the compositor
+        fn surface(&self, wallpaper_info: WallpaperInfo) -> Surface {
+            let layer = self.wpaperd.layer_state.create_layer_surface(
+                &self.qh,
+                self.wpaperd.compositor_state.create_surface(&self.qh),
+                Layer::Background,
+                Some("wpaperd-test"),
+                None,
+            );
+            let info = DisplayInfo {
+                name: "test".to_string(),
+                description: String::new(),
+                serial: None,
+                width: 0,
+                height: 0,
+                scale: 1,
+                fractional_scale: None,
+                transform: Transform::Normal,
+                position: None,
+                logical_size: None,
+            };
+            Surface::new(
+                &self.wpaperd,
+                layer,
+                WlOutput::inert(
+                    self.wpaperd
+                        .compositor_state
+                        .wl_compositor()
+                        .backend()
+                        .clone(),
+                ),
+                info,
+                wallpaper_info,
+                None,
+                &self.qh,
+            )
+            .unwrap()
+        }
+    }
+
+    #[test]
+    fn test_update_wallpaper_info_while_paused() {
+        let daemon = TestDaemon::new(Vec::new());
+        let handle = daemon.event_loop.handle();
+        let wallpaper_info = |duration| WallpaperInfo {
+            duration: Some(Duration::from_secs(duration)),
+            ..WallpaperInfo::default()
+        };
+        let mut surface = daemon.surface(wallpaper_info(60));
+        surface.add_timer(None, &handle, daemon.qh.clone());
+        assert!(matches!(surface.event_source, EventSource::Running(_)));
+
+        surface.pause();
+        surface.handle_pause_state(&handle, daemon.qh.clone());
+        assert!(matches!(surface.event_source, EventSource::Paused(_)));
+
+        // Reloading the same configuration, or one with another duration, keeps the timer
+        // removed until the changes are resumed
+        surface.update_wallpaper_info(&handle, &daemon.qh, wallpaper_info(60));
+        assert!(matches!(surface.event_source, EventSource::Paused(_)));
+        surface.update_wallpaper_info(&handle, &daemon.qh, wallpaper_info(10));
+        assert!(surface.should_pause());
+        assert!(
+            matches!(surface.event_source, EventSource::Paused(remaining) if remaining <= Duration::from_secs(10))
+        );
+        surface.handle_pause_state(&handle, daemon.qh.clone());
+        assert!(matches!(surface.event_source, EventSource::Paused(_)));
+
+        surface.resume();
+        surface.handle_pause_state(&handle, daemon.qh.clone());
+        assert!(matches!(surface.event_source, EventSource::Running(_)));
+    }
+
+    #[test]
+    fn test_update_condition_keeps_ipc_duration() {
+        // The duration can only be replaced for a list of wallpapers
+        let dir = std::env::temp_dir().join(format!("wpaperd-condition-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let daemon = TestDaemon::new(vec![dir.clone()]);
+        let handle = daemon.event_loop.handle();
+        let wallpaper_info = |duration| WallpaperInfo {
+            path: dir.clone(),
+            duration: Some(Duration::from_secs(duration)),
+            ..WallpaperInfo::default()
+        };
+        let mut surface = daemon.surface(wallpaper_info(60));
+        surface.set_temporary_duration(Duration::from_secs(5), None, &handle, &daemon.qh);
+        assert_eq!(
+            surface.wallpaper_info.duration,
+            Some(Duration::from_secs(5))
+        );
+
+        // A new condition keeps the duration set with IPC, until it's restored
+        surface.update_condition(&handle, &daemon.qh, wallpaper_info(30));
+        assert_eq!(
+            surface.wallpaper_info.duration,
+            Some(Duration::from_secs(5))
+        );
+        surface.reset_duration(&handle, &daemon.qh);
+        assert_eq!(
+            surface.wallpaper_info.duration,
+            Some(Duration::from_secs(30))
+        );
+
+        // Reloading the configuration replaces it
+        surface.set_temporary_duration(Duration::from_secs(5), None, &handle, &daemon.qh);
+        surface.update_wallpaper_info(&handle, &daemon.qh, wallpaper_info(20));
+        assert_eq!(
+            surface.wallpaper_info.duration,
+            Some(Duration::from_secs(20))
+        );
+        assert!(surface.duration_override.is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_update_condition_keeps_favorites() {
+        let dir =
+            std::env::temp_dir().join(format!("wpaperd-favorites-mode-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("favorites"), "a.png\n").unwrap();
+        let daemon = TestDaemon::new(vec![dir.clone()]);
+        let handle = daemon.event_loop.handle();
+        let wallpaper_info = |sorting| WallpaperInfo {
+            path: dir.clone(),
+            sorting: Some(sorting),
+            favorites: Some(dir.join("favorites")),
+            ..WallpaperInfo::default()
+        };
+        let mut surface = daemon.surface(wallpaper_info(Sorting::Ascending));
+        assert!(surface.toggle_favorites(&daemon.qh));
+        assert_eq!(surface.wallpaper_info.sorting, Some(Sorting::Random));
+
+        // The favorites mode is kept by a new condition, which changes the sorting to restore
+        surface.update_condition(&handle, &daemon.qh, wallpaper_info(Sorting::Descending));
+        assert!(surface.image_picker.favorites_only());
+        assert_eq!(surface.wallpaper_info.sorting, Some(Sorting::Random));
+        assert!(!surface.toggle_favorites(&daemon.qh));
+        assert_eq!(surface.wallpaper_info.sorting, Some(Sorting::Descending));
+
+        // Reloading the configuration leaves it
+        assert!(surface.toggle_favorites(&daemon.qh));
+        surface.update_wallpaper_info(&handle, &daemon.qh, wallpaper_info(Sorting::Ascending));
+        assert!(!surface.image_picker.favorites_only());
+        assert_eq!(surface.wallpaper_info.sorting, Some(Sorting::Ascending));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_preview_decode_error() {
+        let daemon = TestDaemon::new(Vec::new());
+        let mut surface = daemon.surface(WallpaperInfo::default());
+        let image =
+            std::env::temp_dir().join(format!("wpaperd-preview-{}.png", std::process::id()));
+        std::fs::write(&image, "not an image").unwrap();
+
+        // The client waits until the image loader has given up on the image
+        surface.preview_image = Some(image.clone());
+        surface.previewing = true;
+        while surface.is_loading_preview(&image) {
+            assert!(surface.is_previewing());
+            surface.load_preview(&daemon.qh);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!surface.is_previewing());
+        assert!(surface.take_preview_error().is_some());
+        assert!(surface.take_preview_error().is_none());
+        std::fs::remove_file(&image).unwrap();
+    }
+
+    #[test]
+    fn test_take_over() {
+        let dir = std::env::temp_dir().join(format!("wpaperd-take-over-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let daemon = TestDaemon::new(vec![dir.clone()]);
+        let handle = daemon.event_loop.handle();
+        let wallpaper_info = || WallpaperInfo {
+            path: dir.clone(),
+            duration: Some(Duration::from_secs(60)),
+            sorting: Some(Sorting::Ascending),
+            ..WallpaperInfo::default()
+        };
+        let mut previous = daemon.surface(wallpaper_info());
+        previous.image_picker.restore(dir.join("b.png"), 1);
+        previous.set_temporary_duration(Duration::from_secs(5), None, &handle, &daemon.qh);
+        previous.add_timer(None, &handle, daemon.qh.clone());
+        previous.pause();
+
+        previous.remove_timers(&handle);
+        assert!(matches!(previous.event_source, EventSource::Paused(_)));
+        let mut surface = daemon.surface(wallpaper_info());
+        surface.take_over(previous, &handle, &daemon.qh);
+        assert_eq!(surface.image_picker.current_image(), dir.join("b.png"));
+        assert!(surface.should_pause());
+        assert!(matches!(surface.event_source, EventSource::Paused(_)));
+        assert_eq!(
+            surface.wallpaper_info.duration,
+            Some(Duration::from_secs(5))
+        );
+        // The configured duration is restored on the new surface
+        surface.reset_duration(&handle, &daemon.qh);
+        assert_eq!(
+            surface.wallpaper_info.duration,
+            Some(Duration::from_secs(60))
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reload_while_paused() {
+        let event_loop = EventLoop::<Wpaperd>::try_new().unwrap();
+        let handle = event_loop.handle();
+        let registration_token = handle
+            .insert_source(Timer::from_duration(Duration::from_secs(60)), |_, _, _| {
+                TimeoutAction::Drop
+            })
+            .unwrap();
+
+        // Pausing removes the timer and keeps the time left
+        let mut event_source = EventSource::Running(registration_token);
+        assert!(!event_source.stop(&handle, true, Duration::from_secs(30)));
+        assert!(
+            matches!(event_source, EventSource::Paused(remaining) if remaining.as_secs() == 30)
+        );
+
+        // A reload changing the duration while paused doesn't add the timer back
+        assert!(!event_source.stop(&handle, true, Duration::from_secs(10)));
+        assert!(
+            matches!(event_source, EventSource::Paused(remaining) if remaining.as_secs() == 10)
+        );
+
+        // Once resumed, the new timer can be added
+        assert!(event_source.stop(&handle, false, Duration::ZERO));
+        assert!(matches!(event_source, EventSource::NotSet));
+    }
+
+    #[test]
+    fn test_no_frame_requested_while_idle() {
+        let mut metrics = Metrics::default();
+        let mut frames = FrameRequests::default();
+
+        // A change draws a transition, each frame requests the next one
+        frames.wake();
+        assert!(frames.request(&mut metrics));
+        // Only one callback is pending at a time
+        assert!(!frames.request(&mut metrics));
+        frames.drawn(&mut metrics);
+        frames.received();
+        assert!(frames.request(&mut metrics));
+        frames.drawn(&mut metrics);
+
+        // The last frame of the transition doesn't request any other one
+        frames.received();
+        frames.drawn(&mut metrics);
+        assert!(frames.idle);
+        assert_eq!(metrics.frame_requests, 2);
+        assert_eq!(metrics.idle_frame_requests, 0);
+        assert_eq!(metrics.idle_draws, 0);
+
+        // A request without any change keeps the GPU awake for nothing
+        assert!(frames.request(&mut metrics));
+        assert_eq!(metrics.idle_frame_requests, 1);
+
+        // And so does the frame drawn for it
+        frames.received();
+        frames.drawn(&mut metrics);
+        assert_eq!(metrics.idle_draws, 1);
+
+        // The next change wakes the surface up again
+        frames.wake();
+        assert!(frames.request(&mut metrics));
+        assert_eq!(metrics.idle_frame_requests, 1);
+    }
+
+    #[test]
+    fn test_no_frame_drawn_while_idle() {
+        let daemon = TestDaemon::new(Vec::new());
+        let mut surface = daemon.surface(WallpaperInfo::default());
+        surface.drawn();
+
+        // A change draws the surface once, nothing is animating afterwards
+        surface.queue_draw(&daemon.qh);
+        let _ = surface.draw(&daemon.qh, Some(16));
+        assert!(surface.frames.idle);
+        let _ = surface.draw(&daemon.qh, None);
+        assert!(surface.frames.idle);
+        assert_eq!(surface.metrics.frame_requests, 1);
+        assert_eq!(surface.metrics.idle_frame_requests, 0);
+        assert_eq!(surface.metrics.idle_draws, 0);
+
+        // Animations, like the frames of mpv or the panning, wake the surface up
+        surface.animate(&daemon.qh);
+        let _ = surface.draw(&daemon.qh, Some(32));
+        assert_eq!(surface.metrics.idle_frame_requests, 0);
+        assert_eq!(surface.metrics.idle_draws, 0);
+
+        // A frame drawn without any change is counted
+        let _ = surface.draw(&daemon.qh, Some(48));
+        assert_eq!(surface.metrics.idle_draws, 1);
+    }
+}
@@ -0,0 +1,136 @@
+//! Track the toplevel windows using the wlr-foreign-toplevel-management protocol,
+//! to know which displays are covered by a fullscreen window.
+
+use smithay_client_toolkit::reexports::client::{
+    event_created_child, globals::GlobalList, protocol::wl_output::WlOutput, Connection, Dispatch,
+    QueueHandle,
+};
+use smithay_client_toolkit::reexports::protocols_wlr::foreign_toplevel::v1::client::{
+    zwlr_foreign_toplevel_handle_v1::{self, ZwlrForeignToplevelHandleV1},
+    zwlr_foreign_toplevel_manager_v1::{self, ZwlrForeignToplevelManagerV1},
+};
+
+use crate::wpaperd::Wpaperd;
+
+struct Toplevel {
+    handle: ZwlrForeignToplevelHandleV1,
+    outputs: Vec<WlOutput>,
+    fullscreen: bool,
+    /// State received but not applied yet, we need to wait for the done event
+    pending_fullscreen: bool,
+}
+
+pub struct ToplevelState {
+    // Keep the manager alive, otherwise we won't receive any event
+    _manager: Option<ZwlrForeignToplevelManagerV1>,
+    toplevels: Vec<Toplevel>,
+}
+
+impl ToplevelState {
+    /// The protocol is optional, when the compositor doesn't support it no display will
+    /// ever be considered fullscreen
+    pub fn bind(globals: &GlobalList, qh: &QueueHandle<Wpaperd>) -> Self {
+        Self {
+            _manager: globals.bind(qh, 1..=3, ()).ok(),
+            toplevels: Vec::new(),
+        }
+    }
+
+    /// Return true if a fullscreen window is shown on the output
+    pub fn is_fullscreen(&self, output: &WlOutput) -> bool {
+        self.toplevels
+            .iter()
+            .any(|toplevel| toplevel.fullscreen && toplevel.outputs.contains(output))
+    }
+
+    fn get_mut(&mut self, handle: &ZwlrForeignToplevelHandleV1) -> Option<&mut Toplevel> {
+        self.toplevels
+            .iter_mut()
+            .find(|toplevel| &toplevel.handle == handle)
+    }
+}
+
+impl Wpaperd {
+    fn update_fullscreen_state(&mut self, qh: &QueueHandle<Self>) {
+        for surface in &mut self.surfaces {
+            let fullscreen = self.toplevel_state.is_fullscreen(surface.wl_output());
+            surface.set_fullscreen(fullscreen, qh);
+        }
+    }
+}
+
+impl Dispatch<ZwlrForeignToplevelManagerV1, ()> for Wpaperd {
+    fn event(
+        state: &mut Self,
+        _manager: &ZwlrForeignToplevelManagerV1,
+        event: zwlr_foreign_toplevel_manager_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let zwlr_foreign_toplevel_manager_v1::Event::Toplevel { toplevel } = event {
+            state.toplevel_state.toplevels.push(Toplevel {
+                handle: toplevel,
+                outputs: Vec::new(),
+                fullscreen: false,
+                pending_fullscreen: false,
+            });
+        }
+    }
+
+    event_created_child!(Wpaperd, ZwlrForeignToplevelManagerV1, [
+        zwlr_foreign_toplevel_manager_v1::EVT_TOPLEVEL_OPCODE => (ZwlrForeignToplevelHandleV1, ()),
+    ]);
+}
+
+impl Dispatch<ZwlrForeignToplevelHandleV1, ()> for Wpaperd {
+    fn event(
+        state: &mut Self,
+        handle: &ZwlrForeignToplevelHandleV1,
+        event: zwlr_foreign_toplevel_handle_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_foreign_toplevel_handle_v1::Event::State {
+                state: toplevel_state,
+            } => {
+                if let Some(toplevel) = state.toplevel_state.get_mut(handle) {
+                    // The state is an array of u32 in native endianness
+                    toplevel.pending_fullscreen = toplevel_state
+                        .chunks_exact(4)
+                        .map(|chunk| u32::from_ne_bytes(chunk.try_into().unwrap()))
+                        .any(|value| {
+                            value == zwlr_foreign_toplevel_handle_v1::State::Fullscreen as u32
+                        });
+                }
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::OutputEnter { output } => {
+                if let Some(toplevel) = state.toplevel_state.get_mut(handle) {
+                    toplevel.outputs.push(output);
+                }
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::OutputLeave { output } => {
+                if let Some(toplevel) = state.toplevel_state.get_mut(handle) {
+                    toplevel.outputs.retain(|o| o != &output);
+                }
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::Done => {
+                if let Some(toplevel) = state.toplevel_state.get_mut(handle) {
+                    toplevel.fullscreen = toplevel.pending_fullscreen;
+                }
+                state.update_fullscreen_state(qh);
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::Closed => {
+                state
+                    .toplevel_state
+                    .toplevels
+                    .retain(|toplevel| &toplevel.handle != handle);
+                handle.destroy();
+                state.update_fullscreen_state(qh);
+            }
+            _ => {}
+        }
+    }
+}
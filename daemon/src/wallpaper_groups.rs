@@ -1,13 +1,30 @@
 use std::{
     cell::RefCell,
     collections::{HashMap, HashSet},
-    path::PathBuf,
+    path::{Path, PathBuf},
     rc::Rc,
 };
 
 use smithay_client_toolkit::reexports::client::{protocol::wl_surface::WlSurface, QueueHandle};
 
-use crate::{image_picker::Queue, wpaperd::Wpaperd};
+use crate::{filelist_cache::ListCommand, image_picker::Queue, wpaperd::Wpaperd};
+
+/// Wallpapers the displays of a group pick from. The displays of a group with the same ones
+/// share the same image, the others only change their own image at the same time.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GroupPool {
+    pub path: PathBuf,
+    pub pool: Vec<PathBuf>,
+    pub fallback: Vec<PathBuf>,
+    pub command: Option<ListCommand>,
+}
+
+/// Change of wallpaper made by another pool of the same group, to be followed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupChange {
+    Next,
+    Previous,
+}
 
 pub struct WallpaperGroup {
     pub index: usize,
@@ -15,16 +32,19 @@ pub struct WallpaperGroup {
     pub loading_image: Option<(usize, PathBuf)>,
     pub surfaces: HashSet<WlSurface>,
     pub queue: Queue,
+    /// Set when another pool of the group has changed its wallpaper
+    pub change: Option<GroupChange>,
 }
 
 impl WallpaperGroup {
-    pub fn new(queue_size: usize) -> Self {
+    pub fn new(queue_size: usize, no_repeat_size: usize) -> Self {
         Self {
             index: 0,
             current_image: PathBuf::from(""),
             loading_image: None,
             surfaces: HashSet::new(),
-            queue: Queue::with_capacity(queue_size),
+            queue: Queue::new(queue_size, no_repeat_size),
+            change: None,
         }
     }
 
@@ -37,27 +57,93 @@ impl WallpaperGroup {
 }
 
 pub struct WallpaperGroups {
-    groups: HashMap<u8, Rc<RefCell<WallpaperGroup>>>,
+    /// Each group is split by the wallpapers its displays pick from
+    groups: HashMap<(u8, GroupPool), Rc<RefCell<WallpaperGroup>>>,
+    /// Avoid picking a random image that is already shown on another display
+    unique: bool,
+    /// The image shown, or being loaded, on each display, only tracked when `unique` is set
+    shown: HashMap<WlSurface, PathBuf>,
 }
 
 impl WallpaperGroups {
-    pub fn new() -> Self {
+    pub fn new(unique: bool) -> Self {
         Self {
             groups: HashMap::new(),
+            unique,
+            shown: HashMap::new(),
         }
     }
 
+    pub fn set_shown(&mut self, wl_surface: &WlSurface, image: &Path) {
+        if self.unique {
+            self.shown.insert(wl_surface.clone(), image.to_path_buf());
+        }
+    }
+
+    pub fn remove_shown(&mut self, wl_surface: &WlSurface) {
+        self.shown.remove(wl_surface);
+    }
+
+    /// Return the images shown on the other displays. The displays in the same group as
+    /// `wl_surface` are skipped, since they share the same image on purpose.
+    pub fn shown_elsewhere(&self, wl_surface: &WlSurface) -> HashSet<PathBuf> {
+        let group = self
+            .groups
+            .values()
+            .find(|group| group.borrow().surfaces.contains(wl_surface));
+        self.shown
+            .iter()
+            .filter(|(surface, _)| {
+                *surface != wl_surface
+                    && group.map_or(true, |group| !group.borrow().surfaces.contains(*surface))
+            })
+            .map(|(_, image)| image.to_path_buf())
+            .collect()
+    }
+
     pub fn get_or_insert(
         &mut self,
         group: u8,
+        pool: GroupPool,
         wl_surface: &WlSurface,
         queue_size: usize,
+        no_repeat_size: usize,
     ) -> Rc<RefCell<WallpaperGroup>> {
-        self.groups
-            .entry(group)
-            .or_insert_with(|| Rc::new(RefCell::new(WallpaperGroup::new(queue_size))));
-        let wp_group = self.groups.get_mut(&group).unwrap();
-        wp_group.borrow_mut().surfaces.insert(wl_surface.clone());
+        let wp_group = self.groups.entry((group, pool)).or_insert_with(|| {
+            Rc::new(RefCell::new(WallpaperGroup::new(
+                queue_size,
+                no_repeat_size,
+            )))
+        });
+        let mut wp_group_mut = wp_group.borrow_mut();
+        // A change made while nobody was picking from this pool is stale
+        if wp_group_mut.surfaces.is_empty() {
+            wp_group_mut.change = None;
+        }
+        wp_group_mut.surfaces.insert(wl_surface.clone());
+        drop(wp_group_mut);
         wp_group.clone()
     }
+
+    /// Make the other pools of the group follow the change of `wp_group`, so that all the
+    /// displays of the group change their wallpaper at the same time
+    pub fn change_other_pools(
+        &self,
+        group: u8,
+        wp_group: &Rc<RefCell<WallpaperGroup>>,
+        change: GroupChange,
+        qh: &QueueHandle<Wpaperd>,
+    ) {
+        for ((id, _), other) in &self.groups {
+            if *id != group || Rc::ptr_eq(other, wp_group) {
+                continue;
+            }
+            let mut other = other.borrow_mut();
+            if other.surfaces.is_empty() {
+                continue;
+            }
+            other.change = Some(change);
+            other.queue_all_surfaces(qh);
+        }
+    }
 }
@@ -1,43 +1,300 @@
-use std::{path::PathBuf, time::Duration};
+use std::{collections::BTreeMap, fmt, path::PathBuf, time::Duration};
 
 use serde::Deserialize;
+use smithay_client_toolkit::{
+    reexports::client::protocol::wl_output::Transform,
+    shell::wlr_layer::{Anchor, Layer},
+};
 
-use crate::{image_picker::ImagePicker, render::Transition};
+use crate::{
+    condition::DEFAULT_CONDITION_TIMEOUT,
+    do_not_disturb::TimeWindow,
+    filelist_cache::ListCommand,
+    image_picker::ImagePicker,
+    night_light::NightLight,
+    render::{Backdrop, Border, ColorAdjustment, Transition},
+    weekdays::WeekdayPin,
+};
 
 #[derive(PartialEq, Debug)]
 pub struct WallpaperInfo {
     pub path: PathBuf,
+    /// Directories and files combined in a single list, `path` is empty when this is set
+    pub pool: Vec<PathBuf>,
+    /// Directories and files tried in order until one has any wallpaper, `path` is empty when
+    /// this is set
+    pub fallback: Vec<PathBuf>,
     pub duration: Option<Duration>,
     pub apply_shadow: bool,
     pub sorting: Option<Sorting>,
+    /// Move the wallpaper of the day by this amount of images, so that the displays differ
+    pub daily_offset: usize,
+    /// Directories and images only shown on some days of the week
+    pub weekdays: Vec<WeekdayPin>,
+    /// Directory or image shown in place of `path` on each workspace, by name
+    pub workspaces: BTreeMap<String, PathBuf>,
+    /// Command selecting one of `conditions`, see [`crate::condition`]
+    pub condition: Option<String>,
+    pub condition_timeout: Duration,
+    /// Directory or image shown in place of `path` for each output of `condition`
+    pub conditions: BTreeMap<String, PathBuf>,
     pub mode: BackgroundMode,
     pub drawn_images_queue_size: usize,
+    /// Number of the most recent random images that are not picked again
+    pub no_repeat_size: usize,
     pub transition_time: u32,
+    /// Milliseconds to wait after loading a wallpaper before starting the transition, so that
+    /// quick successive changes settle first
+    pub transition_delay: u32,
 
     /// Determines if we should show the transition between black and first
     /// wallpaper. `false` means we instantly cut to the first wallpaper,
     /// `true` means we fade from black to the first wallpaper.
     pub initial_transition: bool,
+    /// Milliseconds to wait after the display has been configured before drawing it and
+    /// starting the timer of `duration`, while the compositor settles
+    pub startup_delay: u32,
+    /// Determines if we should show the transition to the wallpaper picked after a
+    /// configuration reload changed the path, `false` means we instantly cut to it.
+    pub reload_transition: bool,
     pub transition: Transition,
+    /// Transitions to pick from each time the wallpaper changes, along with their duration.
+    /// `transition` is used when empty.
+    pub transitions: Vec<(Transition, u32)>,
+    /// Pick from `transitions` at random, otherwise in order
+    pub random_transition: bool,
+    /// Transition and its time for the changes requested with wpaperctl, None to use the same
+    /// transitions as the automatic changes
+    pub manual_transition: Option<(Transition, u32)>,
 
     /// Determine the offset for the wallpaper to be drawn into the screen
     /// Must be from 0.0 to 1.0, by default is 0.0 in tile mode and 0.5 in all the others
     pub offset: Option<f32>,
+
+    /// Rotation and flip of the wallpaper, composed with the transform of the display
+    pub transform: Transform,
+
+    /// Brightness, contrast and gamma applied to the wallpaper when drawing it
+    pub color_adjustment: ColorAdjustment,
+
+    /// Warm the colors of the wallpaper during the night, `None` to disable
+    pub night_light: Option<NightLight>,
+
+    /// Multiply the brightness when a fullscreen window covers the display, `None` to disable
+    pub fullscreen_brightness: Option<f32>,
+
+    /// Pause after the user has been idle for this long, then change the wallpaper on resume
+    pub idle_timeout: Option<Duration>,
+
+    /// Pause the automatic wallpaper sequence while the system is running on battery
+    pub pause_on_battery: bool,
+    /// Pause the automatic wallpaper sequence while the pointer is on the display
+    pub pause_on_hover: bool,
+
+    /// Times of the day when the wallpaper is not changed automatically
+    pub do_not_disturb: Vec<TimeWindow>,
+
+    /// Maximum number of frames drawn each second during transitions, 0 means uncapped
+    pub max_fps: u32,
+
+    /// Frames to wait for before swapping the EGL buffers, 0 disables the vertical sync
+    pub swap_interval: i32,
+
+    /// Only damage the region that differs from the previous wallpaper during transitions
+    pub partial_transition: bool,
+
+    /// Wait for the texture upload to complete and start the transition on the next frame,
+    /// so that big images don't skip the beginning of the transition
+    pub transition_wait_upload: bool,
+
+    /// Add an ordered dither to the frames of the transitions, so that the slow fades don't
+    /// band on the displays with 8 bits per channel
+    pub transition_dither: bool,
+
+    /// Start the transitions requested with wpaperctl from a capture of the display instead
+    /// of the previous wallpaper, so that they continue from the panned image
+    pub transition_capture: bool,
+
+    /// Filter used when the wallpaper is scaled to the display
+    pub texture_filter: TextureFilter,
+
+    /// Downscale the decoded images bigger than this factor of the display size, `None` to
+    /// keep their full resolution
+    pub max_image_scale: Option<f32>,
+    /// How many times `contain` mode can enlarge the wallpaper
+    pub max_upscale: f32,
+    /// Filter used to downscale the images on load
+    pub downscale_filter: DownscaleFilter,
+    /// Rotate the images as stored in their EXIF metadata
+    pub exif_orientation: bool,
+    /// Move the images cropped by the `center` mode towards their most detailed region when
+    /// no offset is set
+    pub auto_offset: bool,
+
+    /// Slowly move the offset back and forth within this range, 0.0 to disable
+    pub pan_range: f32,
+    /// Time needed to pan from one end of the range to the other and back
+    pub pan_duration: Duration,
+
+    /// Move the offset towards the pointer within this range, 0.0 to disable
+    pub cursor_parallax: f32,
+
+    /// Command listing the wallpapers, `path` is empty when this is set
+    pub command: Option<String>,
+    pub command_interval: Option<Duration>,
+
+    /// File with the weights of the images picked by the random sorting
+    pub ranking: Option<PathBuf>,
+
+    /// File with the images marked as favorites
+    pub favorites: Option<PathBuf>,
+
+    /// Fetch the wallpapers given as HTTP(S) URLs
+    pub allow_network: bool,
+
+    /// Name of the display whose wallpaper and transitions are copied, `path` is only shown
+    /// until the display is connected
+    pub mirror: Option<String>,
+
+    /// Skip the images whose aspect ratio differs from the display one by more than
+    /// this fraction, `None` to disable
+    pub aspect_ratio_tolerance: Option<f32>,
+    /// Skip the images smaller than this width and height
+    pub min_resolution: Option<(u32, u32)>,
+
+    /// Frame drawn around the wallpaper, the background mode applies to the area inside it
+    pub border: Border,
+    /// Shown through the transparent pixels of the images
+    pub backdrop: Backdrop,
+
+    /// Anchors, margins and size of the layer surface
+    pub layer_placement: LayerPlacement,
+    /// Layer of the compositor the surface is on
+    pub layer: Layer,
+}
+
+/// Where the layer surface of the wallpaper is placed on the display
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LayerPlacement {
+    pub anchor: Anchor,
+    /// Distance from the top, right, bottom and left edges, in logical pixels
+    pub margin: [i32; 4],
+    /// Width and height in logical pixels, 0 to stretch the surface between the anchors
+    pub size: (u32, u32),
+}
+
+impl Default for LayerPlacement {
+    /// Cover the whole display
+    fn default() -> Self {
+        Self {
+            anchor: Anchor::TOP | Anchor::LEFT | Anchor::RIGHT | Anchor::BOTTOM,
+            margin: [0; 4],
+            size: (0, 0),
+        }
+    }
+}
+
+impl WallpaperInfo {
+    pub const DEFAULT_PAN_DURATION: Duration = Duration::from_secs(300);
+    pub const DEFAULT_ASPECT_RATIO_TOLERANCE: f32 = 0.1;
+
+    /// Return true if the wallpapers are picked from a list instead of being a single image
+    pub fn is_list(&self) -> bool {
+        self.path.is_dir()
+            || self.command.is_some()
+            || !self.pool.is_empty()
+            || !self.fallback.is_empty()
+    }
+
+    /// Return the command listing the wallpapers along with its interval, if set
+    pub fn list_command(&self) -> Option<ListCommand> {
+        self.command
+            .clone()
+            .map(|command| (command, self.command_interval))
+    }
+
+    /// Return true if any wallpaper has been configured, a mirror can have none
+    pub fn has_wallpapers(&self) -> bool {
+        !self.path.as_os_str().is_empty()
+            || self.command.is_some()
+            || !self.pool.is_empty()
+            || !self.fallback.is_empty()
+    }
+
+    /// Return true if the wallpaper of the day is shown, changing at midnight
+    pub fn is_daily(&self) -> bool {
+        self.sorting == Some(Sorting::Daily)
+    }
 }
 
 impl Default for WallpaperInfo {
     fn default() -> Self {
         Self {
             path: PathBuf::new(),
+            pool: Vec::new(),
+            fallback: Vec::new(),
             duration: None,
             apply_shadow: false,
             sorting: None,
+            daily_offset: 0,
+            weekdays: Vec::new(),
+            workspaces: BTreeMap::new(),
+            condition: None,
+            condition_timeout: DEFAULT_CONDITION_TIMEOUT,
+            conditions: BTreeMap::new(),
             mode: BackgroundMode::default(),
             drawn_images_queue_size: ImagePicker::DEFAULT_DRAWN_IMAGES_QUEUE_SIZE,
-            transition_time: Transition::Fade {}.default_transition_time(),
+            no_repeat_size: ImagePicker::DEFAULT_DRAWN_IMAGES_QUEUE_SIZE,
+            transition_time: Transition::Fade {
+                through_average: None,
+            }
+            .default_transition_time(),
+            transition_delay: 0,
             initial_transition: true,
-            transition: Transition::Fade {},
+            startup_delay: 0,
+            reload_transition: true,
+            transition: Transition::Fade {
+                through_average: None,
+            },
+            transitions: Vec::new(),
+            random_transition: true,
+            manual_transition: None,
             offset: None,
+            transform: Transform::Normal,
+            color_adjustment: ColorAdjustment::default(),
+            fullscreen_brightness: None,
+            night_light: None,
+            idle_timeout: None,
+            pause_on_battery: false,
+            pause_on_hover: false,
+            do_not_disturb: Vec::new(),
+            max_fps: 0,
+            swap_interval: 1,
+            partial_transition: false,
+            transition_wait_upload: false,
+            transition_dither: false,
+            transition_capture: false,
+            texture_filter: TextureFilter::default(),
+            max_image_scale: None,
+            max_upscale: DEFAULT_MAX_UPSCALE,
+            downscale_filter: DownscaleFilter::default(),
+            exif_orientation: true,
+            auto_offset: false,
+            pan_range: 0.0,
+            pan_duration: Self::DEFAULT_PAN_DURATION,
+            cursor_parallax: 0.0,
+            command: None,
+            command_interval: None,
+            ranking: None,
+            favorites: None,
+            allow_network: false,
+            mirror: None,
+            aspect_ratio_tolerance: None,
+            min_resolution: None,
+            border: Border::default(),
+            backdrop: Backdrop::default(),
+            layer_placement: LayerPlacement::default(),
+            layer: Layer::Background,
         }
     }
 }
@@ -46,11 +303,22 @@ impl Default for WallpaperInfo {
 pub enum Sorting {
     #[default]
     Random,
-    GroupedRandom { group: u8 },
+    GroupedRandom {
+        group: u8,
+    },
     Ascending,
     Descending,
+    /// Start from a random image, then continue in ascending order
+    RandomStart,
+    /// Pick the same image for the whole day, based on the date
+    Daily,
+    /// Show every image once in a random order, then shuffle them again
+    Shuffle,
 }
 
+/// How many times `contain` mode can enlarge the wallpaper, unless `max-upscale` is set
+pub const DEFAULT_MAX_UPSCALE: f32 = 1.5;
+
 #[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum BackgroundMode {
@@ -58,6 +326,84 @@ pub enum BackgroundMode {
     #[default]
     Center,
     Fit,
+    /// Scale the image to the width of the display, cropping or leaving empty space vertically
+    FitWidth,
+    /// Scale the image to the height of the display, cropping or leaving empty space horizontally
+    FitHeight,
     Tile,
     FitBorderColor,
+    /// Work like fit, but don't enlarge the image more than `max-upscale` times, centering it
+    /// in the empty space instead
+    Contain,
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TextureFilter {
+    /// Use the nearest pixel, best suited for pixel art
+    Nearest,
+    #[default]
+    Linear,
+    /// Generate the mipmaps of the wallpaper, improving the quality of scaled down images
+    /// at the cost of more GPU memory
+    Mipmap,
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DownscaleFilter {
+    Nearest,
+    /// Linear filter, fast and good enough for most photos
+    #[default]
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    /// Best quality, but the slowest one
+    Lanczos3,
+}
+
+impl BackgroundMode {
+    /// Return the mode that follows this one, used to cycle through all the modes
+    pub fn next(self) -> Self {
+        match self {
+            BackgroundMode::Stretch => BackgroundMode::Center,
+            BackgroundMode::Center => BackgroundMode::Fit,
+            BackgroundMode::Fit => BackgroundMode::FitWidth,
+            BackgroundMode::FitWidth => BackgroundMode::FitHeight,
+            BackgroundMode::FitHeight => BackgroundMode::Tile,
+            BackgroundMode::Tile => BackgroundMode::FitBorderColor,
+            BackgroundMode::FitBorderColor => BackgroundMode::Contain,
+            BackgroundMode::Contain => BackgroundMode::Stretch,
+        }
+    }
+}
+
+impl fmt::Display for Sorting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sorting::Random => f.write_str("random"),
+            Sorting::GroupedRandom { group } => write!(f, "random (group {group})"),
+            Sorting::Ascending => f.write_str("ascending"),
+            Sorting::Descending => f.write_str("descending"),
+            Sorting::RandomStart => f.write_str("random-start"),
+            Sorting::Daily => f.write_str("daily"),
+            Sorting::Shuffle => f.write_str("shuffle"),
+        }
+    }
+}
+
+impl fmt::Display for BackgroundMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Use the same names as the configuration
+        f.write_str(match self {
+            BackgroundMode::Stretch => "stretch",
+            BackgroundMode::Center => "center",
+            BackgroundMode::Fit => "fit",
+            BackgroundMode::FitWidth => "fit-width",
+            BackgroundMode::FitHeight => "fit-height",
+            BackgroundMode::Tile => "tile",
+            BackgroundMode::FitBorderColor => "fit-border-color",
+            BackgroundMode::Contain => "contain",
+        })
+    }
 }
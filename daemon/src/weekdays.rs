@@ -0,0 +1,102 @@
+//! Pin images and directories to some days of the week with `weekdays`, e.g. to show a
+//! directory only on mondays. The pinned images are removed from the wallpapers of the other
+//! days, and the displays showing one of them change their wallpaper at midnight.
+
+use std::path::{Path, PathBuf};
+
+use color_eyre::{eyre::anyhow, Result};
+use serde::Deserialize;
+use smithay_client_toolkit::reexports::{
+    calloop::{
+        timer::{TimeoutAction, Timer},
+        LoopHandle,
+    },
+    client::QueueHandle,
+};
+
+use crate::{
+    daily::{local_day, until_midnight},
+    wpaperd::Wpaperd,
+};
+
+#[derive(Deserialize, PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Weekday {
+    const ALL: [Weekday; 7] = [
+        Weekday::Monday,
+        Weekday::Tuesday,
+        Weekday::Wednesday,
+        Weekday::Thursday,
+        Weekday::Friday,
+        Weekday::Saturday,
+        Weekday::Sunday,
+    ];
+
+    /// Return the day of the week of `day`, counted in days since the epoch
+    pub fn from_day(day: i64) -> Self {
+        // The epoch was a thursday
+        Self::ALL[(day + 3).rem_euclid(7) as usize]
+    }
+
+    /// Return the local day of the week
+    pub fn today() -> Self {
+        Self::from_day(local_day())
+    }
+}
+
+/// Days of the week an image or a directory is shown on
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeekdayPin {
+    pub path: PathBuf,
+    /// One bit for each day, starting from monday
+    days: u8,
+}
+
+impl WeekdayPin {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, days: 0 }
+    }
+
+    pub fn add(&mut self, day: Weekday) {
+        self.days |= 1 << day as u8;
+    }
+
+    pub fn contains(&self, day: Weekday) -> bool {
+        self.days & (1 << day as u8) != 0
+    }
+}
+
+/// Return false if the file is pinned to other days of the week. When it's inside multiple
+/// pinned directories, the most specific pin applies.
+pub fn is_shown_on(file: &Path, pins: &[WeekdayPin], day: Weekday) -> bool {
+    pins.iter()
+        .filter(|pin| file.starts_with(&pin.path))
+        .max_by_key(|pin| pin.path.components().count())
+        .map_or(true, |pin| pin.contains(day))
+}
+
+/// Change the wallpapers pinned to the day that has just ended, at each local midnight
+pub fn insert_weekdays_timer(handle: &LoopHandle<Wpaperd>, qh: QueueHandle<Wpaperd>) -> Result<()> {
+    handle
+        .insert_source(
+            Timer::from_duration(until_midnight()),
+            move |_deadline, _: &mut (), wpaperd: &mut Wpaperd| {
+                for surface in &mut wpaperd.surfaces {
+                    surface.check_weekdays(&qh);
+                }
+                TimeoutAction::ToDuration(until_midnight())
+            },
+        )
+        .map_err(|err| anyhow!("inserting the weekdays timer in the event loop: {err}"))?;
+    Ok(())
+}
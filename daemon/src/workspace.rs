@@ -0,0 +1,231 @@
+//! Follow the active workspace of each display with the ext-workspace protocol, to show the
+//! wallpapers set in `workspaces` for it. Switching quickly between workspaces only changes
+//! the wallpaper once the active one has been kept for a moment, see
+//! [`crate::surface::Surface::handle_workspace_change`]. `workspaces` is ignored when the
+//! compositor doesn't support the protocol.
+
+use log::warn;
+use smithay_client_toolkit::reexports::client::{
+    event_created_child, globals::GlobalList, protocol::wl_output::WlOutput, Connection, Dispatch,
+    QueueHandle, WEnum,
+};
+use wayland_protocols::ext::workspace::v1::client::{
+    ext_workspace_group_handle_v1::{self, ExtWorkspaceGroupHandleV1},
+    ext_workspace_handle_v1::{self, ExtWorkspaceHandleV1},
+    ext_workspace_manager_v1::{self, ExtWorkspaceManagerV1},
+};
+
+use crate::{surface::Surface, wallpaper_info::WallpaperInfo, wpaperd::Wpaperd};
+
+/// The workspaces of a group are shown on its outputs
+struct WorkspaceGroup {
+    handle: ExtWorkspaceGroupHandleV1,
+    outputs: Vec<WlOutput>,
+    workspaces: Vec<ExtWorkspaceHandleV1>,
+}
+
+struct Workspace {
+    handle: ExtWorkspaceHandleV1,
+    name: Option<String>,
+    active: bool,
+}
+
+pub struct WorkspaceState {
+    // Keep the manager alive, otherwise we won't receive any event
+    manager: Option<ExtWorkspaceManagerV1>,
+    groups: Vec<WorkspaceGroup>,
+    workspaces: Vec<Workspace>,
+    /// The lack of support has already been reported
+    warned: bool,
+}
+
+impl WorkspaceState {
+    pub fn bind(globals: &GlobalList, qh: &QueueHandle<Wpaperd>) -> Self {
+        Self {
+            manager: globals.bind(qh, 1..=1, ()).ok(),
+            groups: Vec::new(),
+            workspaces: Vec::new(),
+            warned: false,
+        }
+    }
+
+    /// Return the name of the active workspace shown on the output, if any
+    pub fn active_workspace(&self, output: &WlOutput) -> Option<String> {
+        self.groups
+            .iter()
+            .filter(|group| group.outputs.contains(output))
+            .flat_map(|group| &group.workspaces)
+            .filter_map(|handle| {
+                self.workspaces
+                    .iter()
+                    .find(|workspace| workspace.handle == *handle)
+            })
+            .find(|workspace| workspace.active)
+            .and_then(|workspace| workspace.name.clone())
+    }
+
+    /// Tell each display which workspace is active on it
+    pub fn update(&mut self, surfaces: &mut [Surface]) {
+        if self.manager.is_none() {
+            if !self.warned {
+                if let Some(surface) = surfaces
+                    .iter()
+                    .find(|surface| !surface.wallpaper_info.workspaces.is_empty())
+                {
+                    warn!(
+                        "{}: workspaces is not supported by the compositor, ignoring it",
+                        surface.name()
+                    );
+                    self.warned = true;
+                }
+            }
+            return;
+        }
+        for surface in surfaces {
+            let workspace = self.active_workspace(surface.wl_output());
+            surface.set_workspace(workspace);
+        }
+    }
+
+    fn group_mut(&mut self, handle: &ExtWorkspaceGroupHandleV1) -> Option<&mut WorkspaceGroup> {
+        self.groups.iter_mut().find(|group| group.handle == *handle)
+    }
+
+    fn workspace_mut(&mut self, handle: &ExtWorkspaceHandleV1) -> Option<&mut Workspace> {
+        self.workspaces
+            .iter_mut()
+            .find(|workspace| workspace.handle == *handle)
+    }
+}
+
+/// Replace the wallpapers with the ones set for the workspace in `workspaces`, if any
+pub fn apply_workspace(wallpaper_info: &mut WallpaperInfo, workspace: Option<&str>) {
+    let Some(path) = workspace.and_then(|workspace| wallpaper_info.workspaces.get(workspace))
+    else {
+        return;
+    };
+    wallpaper_info.path = path.clone();
+    wallpaper_info.pool = Vec::new();
+    wallpaper_info.fallback = Vec::new();
+    wallpaper_info.command = None;
+}
+
+impl Dispatch<ExtWorkspaceManagerV1, ()> for Wpaperd {
+    fn event(
+        state: &mut Self,
+        _manager: &ExtWorkspaceManagerV1,
+        event: ext_workspace_manager_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let workspace_state = &mut state.workspace_state;
+        match event {
+            ext_workspace_manager_v1::Event::WorkspaceGroup { workspace_group } => {
+                workspace_state.groups.push(WorkspaceGroup {
+                    handle: workspace_group,
+                    outputs: Vec::new(),
+                    workspaces: Vec::new(),
+                });
+            }
+            ext_workspace_manager_v1::Event::Workspace { workspace } => {
+                workspace_state.workspaces.push(Workspace {
+                    handle: workspace,
+                    name: None,
+                    active: false,
+                });
+            }
+            // All the changes since the last done event have been sent
+            ext_workspace_manager_v1::Event::Done => {
+                workspace_state.update(&mut state.surfaces);
+            }
+            ext_workspace_manager_v1::Event::Finished => {
+                workspace_state.manager = None;
+                workspace_state.groups.clear();
+                workspace_state.workspaces.clear();
+            }
+            _ => {}
+        }
+    }
+
+    event_created_child!(Wpaperd, ExtWorkspaceManagerV1, [
+        ext_workspace_manager_v1::EVT_WORKSPACE_GROUP_OPCODE => (ExtWorkspaceGroupHandleV1, ()),
+        ext_workspace_manager_v1::EVT_WORKSPACE_OPCODE => (ExtWorkspaceHandleV1, ()),
+    ]);
+}
+
+impl Dispatch<ExtWorkspaceGroupHandleV1, ()> for Wpaperd {
+    fn event(
+        state: &mut Self,
+        handle: &ExtWorkspaceGroupHandleV1,
+        event: ext_workspace_group_handle_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let workspace_state = &mut state.workspace_state;
+        match event {
+            ext_workspace_group_handle_v1::Event::OutputEnter { output } => {
+                if let Some(group) = workspace_state.group_mut(handle) {
+                    group.outputs.push(output);
+                }
+            }
+            ext_workspace_group_handle_v1::Event::OutputLeave { output } => {
+                if let Some(group) = workspace_state.group_mut(handle) {
+                    group.outputs.retain(|o| *o != output);
+                }
+            }
+            ext_workspace_group_handle_v1::Event::WorkspaceEnter { workspace } => {
+                if let Some(group) = workspace_state.group_mut(handle) {
+                    group.workspaces.push(workspace);
+                }
+            }
+            ext_workspace_group_handle_v1::Event::WorkspaceLeave { workspace } => {
+                if let Some(group) = workspace_state.group_mut(handle) {
+                    group.workspaces.retain(|w| *w != workspace);
+                }
+            }
+            ext_workspace_group_handle_v1::Event::Removed => {
+                workspace_state
+                    .groups
+                    .retain(|group| group.handle != *handle);
+                handle.destroy();
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ExtWorkspaceHandleV1, ()> for Wpaperd {
+    fn event(
+        state: &mut Self,
+        handle: &ExtWorkspaceHandleV1,
+        event: ext_workspace_handle_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let workspace_state = &mut state.workspace_state;
+        match event {
+            ext_workspace_handle_v1::Event::Name { name } => {
+                if let Some(workspace) = workspace_state.workspace_mut(handle) {
+                    workspace.name = Some(name);
+                }
+            }
+            ext_workspace_handle_v1::Event::State {
+                state: WEnum::Value(flags),
+            } => {
+                if let Some(workspace) = workspace_state.workspace_mut(handle) {
+                    workspace.active = flags.contains(ext_workspace_handle_v1::State::Active);
+                }
+            }
+            ext_workspace_handle_v1::Event::Removed => {
+                workspace_state
+                    .workspaces
+                    .retain(|workspace| workspace.handle != *handle);
+                handle.destroy();
+            }
+            _ => {}
+        }
+    }
+}
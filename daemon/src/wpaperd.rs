@@ -1,9 +1,11 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use color_eyre::eyre::Context;
 use color_eyre::owo_colors::OwoColorize;
 use color_eyre::Result;
-use log::{error, warn};
+use flexi_logger::LoggerHandle;
+use log::{error, info, warn};
 use smithay_client_toolkit::compositor::{CompositorHandler, CompositorState, Region};
 use smithay_client_toolkit::output::{OutputHandler, OutputState};
 use smithay_client_toolkit::reexports::calloop::LoopHandle;
@@ -12,7 +14,7 @@ use smithay_client_toolkit::reexports::client::protocol::{wl_output, wl_surface}
 use smithay_client_toolkit::reexports::client::{Connection, QueueHandle};
 use smithay_client_toolkit::registry::{ProvidesRegistryState, RegistryState};
 use smithay_client_toolkit::shell::wlr_layer::{
-    Anchor, Layer, LayerShell, LayerShellHandler, LayerSurface, LayerSurfaceConfigure,
+    LayerShell, LayerShellHandler, LayerSurface, LayerSurfaceConfigure,
 };
 use smithay_client_toolkit::shm::{Shm, ShmHandler};
 use smithay_client_toolkit::{
@@ -20,13 +22,25 @@ use smithay_client_toolkit::{
     registry_handlers,
 };
 
+use crate::condition::ConditionRunner;
 use crate::config::Config;
+use crate::cursor::CursorState;
 use crate::display_info::DisplayInfo;
 use crate::filelist_cache::FilelistCache;
+use crate::fractional_scale::FractionalScaleManager;
+use crate::idle::IdleState;
 use crate::image_loader::ImageLoader;
+use crate::ipc_server::{PreviewClient, StatusWatcher};
+use crate::output_power::OutputPowerState;
+use crate::presentation::Presentation;
+#[cfg(feature = "video")]
+use crate::render::VideoFrames;
+use crate::state::State;
 use crate::surface::Surface;
+use crate::toplevel::ToplevelState;
 use crate::wallpaper_groups::WallpaperGroups;
 use crate::wallpaper_info::WallpaperInfo;
+use crate::workspace::{apply_workspace, WorkspaceState};
 
 pub struct Wpaperd {
     pub compositor_state: CompositorState,
@@ -36,10 +50,57 @@ pub struct Wpaperd {
     pub registry_state: RegistryState,
     pub surfaces: Vec<Surface>,
     pub config: Config,
-    egl_display: egl::Display,
+    /// Configuration of the profile switched to, applied by the main loop
+    pub pending_config: Option<Config>,
+    /// None when EGL is not available, the wallpapers are drawn in software
+    egl_display: Option<egl::Display>,
     pub filelist_cache: Rc<RefCell<FilelistCache>>,
     pub image_loader: Rc<RefCell<ImageLoader>>,
+    pub condition_runner: ConditionRunner,
+    #[cfg(feature = "video")]
+    pub video_frames: VideoFrames,
     pub wallpaper_groups: Rc<RefCell<WallpaperGroups>>,
+    pub state: Option<Rc<RefCell<State>>>,
+    /// Only create surfaces for these displays; all of them when empty
+    output_filter: Vec<String>,
+    pub toplevel_state: ToplevelState,
+    pub output_power_state: OutputPowerState,
+    /// Set with `wpaperctl session-locked`, the surfaces created in the meantime start hidden
+    pub session_locked: bool,
+    pub idle_state: IdleState,
+    pub cursor_state: CursorState,
+    /// None when the compositor doesn't support presentation-time
+    pub presentation: Option<Presentation>,
+    /// None when the compositor doesn't support fractional-scale and viewporter, or when
+    /// `--no-fractional-scale` has been passed
+    pub fractional_scale: Option<FractionalScaleManager>,
+    pub workspace_state: WorkspaceState,
+    /// IPC clients receiving the status of the displays when it changes
+    pub status_watchers: Vec<StatusWatcher>,
+    /// IPC clients waiting for the end of the transitions they are previewing
+    pub preview_clients: Vec<PreviewClient>,
+    /// The favorites files have to be watched again, e.g. after one has been created by IPC
+    pub rewatch_favorites: bool,
+    /// Used to change the log filter at runtime
+    pub logger: LoggerHandle,
+}
+
+/// What wpaperd has been started with, from the command line
+pub struct StartupOptions {
+    /// None when EGL is not available, the wallpapers are drawn in software
+    pub egl_display: Option<egl::Display>,
+    /// Set with `--persist-state`
+    pub state: Option<Rc<RefCell<State>>>,
+    /// Only create surfaces for these displays; all of them when empty
+    pub output_filter: Vec<String>,
+    pub unique_wallpapers: bool,
+    pub suspend_when_off: bool,
+    /// Draw at the fractional scale of the displays when the compositor supports it
+    pub fractional_scale: bool,
+    pub logger: LoggerHandle,
+    /// Wakes up the event loop when a video has a new frame
+    #[cfg(feature = "video")]
+    pub video_frames: VideoFrames,
 }
 
 impl Wpaperd {
@@ -47,14 +108,13 @@ impl Wpaperd {
         qh: &QueueHandle<Self>,
         globals: &GlobalList,
         config: Config,
-        egl_display: egl::Display,
         filelist_cache: Rc<RefCell<FilelistCache>>,
-        wallpaper_groups: Rc<RefCell<WallpaperGroups>>,
+        image_loader: Rc<RefCell<ImageLoader>>,
+        condition_runner: ConditionRunner,
+        options: StartupOptions,
     ) -> Result<Self> {
         let shm_state = Shm::bind(globals, qh)?;
 
-        let image_loader = Rc::new(RefCell::new(ImageLoader::new()));
-
         Ok(Self {
             compositor_state: CompositorState::bind(globals, qh)?,
             output_state: OutputState::new(globals, qh),
@@ -63,17 +123,66 @@ impl Wpaperd {
             registry_state: RegistryState::new(globals),
             surfaces: Vec::new(),
             config,
-            egl_display,
+            pending_config: None,
+            egl_display: options.egl_display,
             filelist_cache,
             image_loader,
-            wallpaper_groups,
+            condition_runner,
+            #[cfg(feature = "video")]
+            video_frames: options.video_frames,
+            wallpaper_groups: Rc::new(RefCell::new(WallpaperGroups::new(
+                options.unique_wallpapers,
+            ))),
+            state: options.state,
+            output_filter: options.output_filter,
+            toplevel_state: ToplevelState::bind(globals, qh),
+            output_power_state: OutputPowerState::bind(globals, qh, options.suspend_when_off),
+            session_locked: false,
+            idle_state: IdleState::bind(globals, qh),
+            cursor_state: CursorState::bind(globals, qh),
+            presentation: Presentation::bind(globals, qh),
+            fractional_scale: options
+                .fractional_scale
+                .then(|| FractionalScaleManager::bind(globals, qh))
+                .flatten(),
+            workspace_state: WorkspaceState::bind(globals, qh),
+            status_watchers: Vec::new(),
+            preview_clients: Vec::new(),
+            rewatch_favorites: false,
+            logger: options.logger,
         })
     }
 
+    /// Warn about the displays passed to `--output` that are not connected
+    pub fn check_output_filter(&self) {
+        let names: Vec<String> = self
+            .output_state
+            .outputs()
+            .filter_map(|output| self.output_state.info(&output))
+            .filter_map(|info| info.name)
+            .collect();
+        for name in &self.output_filter {
+            if !names.contains(name) {
+                warn!(
+                    "Display {} passed to --output could not be found",
+                    name.bold().magenta()
+                );
+            }
+        }
+    }
+
     pub fn update_surfaces(&mut self, ev_handle: LoopHandle<Wpaperd>, qh: &QueueHandle<Wpaperd>) {
-        for surface in &mut self.surfaces {
-            let res = self.config.get_output_by_name(&surface.name());
+        // Displays whose surface has to be created again on its new layer
+        let mut recreate = Vec::new();
+        for (index, surface) in self.surfaces.iter_mut().enumerate() {
+            let res = self.config.get_output(&surface.info());
             match res {
+                Ok(wallpaper_info)
+                    if wallpaper_info.layer != surface.configured_layer()
+                        && !surface.can_set_layer() =>
+                {
+                    recreate.push(index);
+                }
                 Ok(wallpaper_info) => {
                     surface.update_wallpaper_info(&ev_handle, qh, wallpaper_info);
                 }
@@ -83,6 +192,175 @@ impl Wpaperd {
                 ),
             }
         }
+        // From the last one, so that swap_remove doesn't move the next displays to remove
+        for index in recreate.into_iter().rev() {
+            let mut surface = self.surfaces.swap_remove(index);
+            let output = surface.wl_output().clone();
+            info!(
+                "{}: creating the surface again on its new layer",
+                surface.name()
+            );
+            surface.remove_timers(&ev_handle);
+            self.output_power_state.remove(&output);
+            self.add_surface(qh, output.clone());
+            match self
+                .surfaces
+                .last_mut()
+                .filter(|new_surface| *new_surface.wl_output() == output)
+            {
+                Some(new_surface) => new_surface.take_over(surface, &ev_handle, qh),
+                None => drop(surface),
+            }
+        }
+        self.idle_state.update(&self.surfaces, qh);
+        self.cursor_state
+            .update(&self.surfaces, &self.compositor_state);
+        self.workspace_state.update(&mut self.surfaces);
+    }
+
+    /// Read the favorites files again and replace the wallpapers removed from them
+    pub fn refresh_favorites(&mut self, qh: &QueueHandle<Wpaperd>) {
+        for surface in &mut self.surfaces {
+            surface.refresh_favorites(qh);
+        }
+    }
+
+    /// Create the surface drawing the wallpaper on `output`
+    fn add_surface(&mut self, qh: &QueueHandle<Self>, output: wl_output::WlOutput) {
+        let info = match self.output_state.info(&output) {
+            Some(info) => info,
+            None => {
+                error!("could not get info about output");
+                return;
+            }
+        };
+
+        let name = info
+            .name
+            .as_ref()
+            .map(|name| name.to_string())
+            .unwrap_or_else(|| "unnamed".to_string());
+
+        if !self.output_filter.is_empty() && !self.output_filter.contains(&name) {
+            info!("Ignoring display {name}, it has not been passed to --output");
+            return;
+        }
+
+        let surface = self.compositor_state.create_surface(qh);
+        // With a viewport the buffers are drawn at the exact scale, the compositor doesn't scale
+        // them
+        surface.set_buffer_scale(if self.fractional_scale.is_some() {
+            1
+        } else {
+            info.scale_factor
+        });
+        surface.set_buffer_transform(info.transform);
+        let display_info = DisplayInfo::new(info);
+
+        let mut wallpaper_info = match self.config.get_output(&display_info) {
+            Ok(wallpaper_info) => wallpaper_info,
+            Err(err) => {
+                warn!(
+                    "Configuration error on display {}: {err:?}",
+                    name.bold().magenta()
+                );
+                WallpaperInfo::default()
+            }
+        };
+
+        let layer = self.layer_state.create_layer_surface(
+            qh,
+            surface.clone(),
+            wallpaper_info.layer,
+            Some(format!("wpaperd-{}", name)),
+            Some(&output),
+        );
+        // The anchors, the margins and the size are set by the surface, from its configuration
+        layer.set_exclusive_zone(-1);
+
+        match Region::new(&self.compositor_state) {
+            Ok(region) => {
+                // Wayland clients are expected to render the cursor on their input region. By setting the
+                // input region to an empty region, the compositor renders the default cursor. Without
+                // this, and empty desktop won't render a cursor.
+                surface.set_input_region(Some(region.wl_region()));
+
+                // From `wl_surface::set_opaque_region`:
+                // > Setting the pending opaque region has copy semantics, and the
+                // > wl_region object can be destroyed immediately.
+                region.wl_region().destroy();
+            }
+
+            Err(_) => {
+                warn!("could not create region, cursor won't be shown for display {name}");
+                return;
+            }
+        };
+
+        // Start from the wallpapers of the active workspace, if it is already known
+        apply_workspace(
+            &mut wallpaper_info,
+            self.workspace_state.active_workspace(&output).as_deref(),
+        );
+
+        let surface = Surface::new(
+            self,
+            layer,
+            output.clone(),
+            display_info,
+            wallpaper_info,
+            self.egl_display,
+            qh,
+        );
+        // Neither OpenGL nor the shared memory can draw on the display
+        let mut surface = match surface {
+            Ok(surface) => surface,
+            Err(err) => {
+                error!("{name}: {err:?}");
+                return;
+            }
+        };
+        self.output_power_state.add(&output, qh);
+        if self.session_locked {
+            surface.set_session_locked(true, qh);
+        }
+        self.surfaces.push(surface);
+        self.idle_state.update(&self.surfaces, qh);
+        self.cursor_state
+            .update(&self.surfaces, &self.compositor_state);
+        self.workspace_state.update(&mut self.surfaces);
+    }
+
+    /// Load the profile `profile`, or the configuration of the user when it's None, for the
+    /// main loop to apply it. Nothing changes if the configuration of any display is invalid.
+    pub fn switch_profile(&mut self, profile: Option<&str>) -> Result<()> {
+        let config = self.config.load_profile(profile)?;
+        for surface in &self.surfaces {
+            config.get_output(&surface.info()).with_context(|| {
+                format!(
+                    "while validating display {}",
+                    surface.name().bold().magenta()
+                )
+            })?;
+        }
+        self.pending_config = Some(config);
+        Ok(())
+    }
+
+    /// Copy what each display set in `mirror` shows to the displays mirroring it
+    pub fn update_mirrors(&mut self, qh: &QueueHandle<Wpaperd>) {
+        for index in 0..self.surfaces.len() {
+            let Some(source_name) = &self.surfaces[index].wallpaper_info.mirror else {
+                continue;
+            };
+            let source = self
+                .surfaces
+                .iter()
+                .enumerate()
+                .find(|(i, surface)| *i != index && surface.name() == *source_name)
+                .and_then(|(_, surface)| surface.mirrored());
+            self.surfaces[index].mirror(source, qh);
+        }
     }
 
     pub fn surface_from_name(&mut self, name: &str) -> Option<&mut Surface> {
@@ -123,7 +401,7 @@ impl CompositorHandler for Wpaperd {
         match surface.draw(qh, Some(time)) {
             Ok(_) => {}
             Err(err) => {
-                error!("Error drawing surface: {err:?}");
+                error!("{}: error drawing surface: {err:?}", surface.name());
             }
         }
     }
@@ -169,93 +447,35 @@ impl OutputHandler for Wpaperd {
         qh: &QueueHandle<Self>,
         output: wl_output::WlOutput,
     ) {
-        let surface = self.compositor_state.create_surface(qh);
-
-        let info = match self.output_state.info(&output) {
-            Some(info) => info,
-            None => {
-                error!("could not get info about output");
-                return;
-            }
-        };
-        surface.set_buffer_scale(info.scale_factor);
-        surface.set_buffer_transform(info.transform);
-
-        let name = info
-            .name
-            .as_ref()
-            .map(|name| name.to_string())
-            .unwrap_or_else(|| "unnamed".to_string());
-        let display_info = DisplayInfo::new(info);
-
-        let layer = self.layer_state.create_layer_surface(
-            qh,
-            surface.clone(),
-            Layer::Background,
-            Some(format!("wpaperd-{}", name)),
-            Some(&output),
-        );
-        layer.set_anchor(Anchor::TOP | Anchor::LEFT | Anchor::RIGHT | Anchor::BOTTOM);
-        layer.set_exclusive_zone(-1);
-        layer.set_size(
-            display_info.adjusted_width() as u32,
-            display_info.adjusted_height() as u32,
-        );
-
-        match Region::new(&self.compositor_state) {
-            Ok(region) => {
-                // Wayland clients are expected to render the cursor on their input region. By setting the
-                // input region to an empty region, the compositor renders the default cursor. Without
-                // this, and empty desktop won't render a cursor.
-                surface.set_input_region(Some(region.wl_region()));
-
-                // From `wl_surface::set_opaque_region`:
-                // > Setting the pending opaque region has copy semantics, and the
-                // > wl_region object can be destroyed immediately.
-                region.wl_region().destroy();
-            }
-
-            Err(_) => {
-                warn!("could not create region, cursor won't be shown for display {name}");
-                return;
-            }
-        };
-
-        let wallpaper_info = match self.config.get_output_by_name(&name) {
-            Ok(wallpaper_info) => wallpaper_info,
-            Err(err) => {
-                warn!(
-                    "Configuration error on display {}: {err:?}",
-                    name.bold().magenta()
-                );
-                WallpaperInfo::default()
-            }
-        };
-
-        self.surfaces.push(Surface::new(
-            self,
-            layer,
-            output,
-            display_info,
-            wallpaper_info,
-            self.egl_display,
-            qh,
-        ));
+        self.add_surface(qh, output);
     }
 
     fn update_output(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _output: wl_output::WlOutput,
+        output: wl_output::WlOutput,
     ) {
-        // TODO: Do we need to do something here?
+        // The size of the surface, the scale and the transform are received through the
+        // surface events, only the position and the size of the display are tracked here
+        let Some(info) = self.output_state.info(&output) else {
+            error!("could not get info about output");
+            return;
+        };
+        if let Some(surface) = self
+            .surfaces
+            .iter_mut()
+            .find(|surface| *surface.wl_output() == output)
+        {
+            surface.change_position(info.logical_position);
+            surface.change_logical_size(info.logical_size);
+        }
     }
 
     fn output_destroyed(
         &mut self,
         _conn: &Connection,
-        _qh: &QueueHandle<Self>,
+        qh: &QueueHandle<Self>,
         output: wl_output::WlOutput,
     ) {
         // Find the destroyed output and remove it
@@ -268,6 +488,10 @@ impl OutputHandler for Wpaperd {
         {
             Some((index, _)) => {
                 self.surfaces.swap_remove(index);
+                self.output_power_state.remove(&output);
+                self.idle_state.update(&self.surfaces, qh);
+                self.cursor_state
+                    .update(&self.surfaces, &self.compositor_state);
             }
             None => error!("could not find display while handling output_destroyed"),
         }
@@ -1,34 +1,361 @@
-use std::path::PathBuf;
+use std::{
+    env,
+    ffi::OsString,
+    io,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::{
+            ffi::OsStrExt,
+            net::{SocketAddr, UnixStream},
+        },
+    },
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use serde::{Deserialize, Serialize};
 use xdg::{BaseDirectories, BaseDirectoriesError};
 
+/// Increased each time the messages change in a way that is not backward compatible
+pub const PROTOCOL_VERSION: u32 = 1;
+
 #[derive(Serialize, Deserialize)]
 pub enum IpcMessage {
-    CurrentWallpaper { monitor: String },
-    NextWallpaper { monitors: Vec<String> },
-    PreviousWallpaper { monitors: Vec<String> },
-    PauseWallpaper { monitors: Vec<String> },
-    ResumeWallpaper { monitors: Vec<String> },
-    TogglePauseWallpaper { monitors: Vec<String> },
+    CurrentWallpaper {
+        monitor: String,
+    },
+    NextWallpaper {
+        monitors: Vec<String>,
+    },
+    /// Change to the next wallpaper with `transition`, written like the `transition`
+    /// attribute of the configuration or as a bare name, e.g. `iris`, running for
+    /// `transition_time` milliseconds. The displays go back to their configured transitions
+    /// at the following change
+    NextWithTransition {
+        monitors: Vec<String>,
+        transition: String,
+        transition_time: Option<u32>,
+    },
+    PreviousWallpaper {
+        monitors: Vec<String>,
+    },
+    /// Play the transition of the displays from their current wallpaper to `image`, or to
+    /// itself, then show the current wallpaper again without changing it. The response is
+    /// sent once all the transitions have ended
+    PreviewTransition {
+        monitors: Vec<String>,
+        image: Option<PathBuf>,
+    },
+    PauseWallpaper {
+        monitors: Vec<String>,
+    },
+    ResumeWallpaper {
+        monitors: Vec<String>,
+    },
+    TogglePauseWallpaper {
+        monitors: Vec<String>,
+    },
+    /// Stop drawing on the displays and pause their timers, leaving what is on them or filling
+    /// them with `color`, in the #RRGGBB or #RRGGBBAA format
+    DisableWallpaper {
+        monitors: Vec<String>,
+        color: Option<String>,
+    },
+    /// Draw the current wallpaper again on the displays stopped by
+    /// [`IpcMessage::DisableWallpaper`]
+    EnableWallpaper {
+        monitors: Vec<String>,
+    },
+    /// Tell the daemon that the session has been locked or unlocked, e.g. by the `lock` and
+    /// `unlock` events of swayidle. While it's locked, nothing is drawn and the timers are
+    /// paused, like for the displays turned off
+    SessionLock {
+        locked: bool,
+    },
     AllWallpapers,
-    ReloadWallpaper { monitors: Vec<String> },
+    ReloadWallpaper {
+        monitors: Vec<String>,
+    },
+    ToggleMode {
+        monitors: Vec<String>,
+    },
+    /// Get the sorting of the displays, or set it when `sorting` is given
+    Sorting {
+        monitors: Vec<String>,
+        sorting: Option<String>,
+    },
+    /// Get the offset of the displays, or set it to `offset`, a number from 0 to 1 or the side
+    /// to align the wallpaper to, and move it by `delta`, until the configuration is reloaded
+    Offset {
+        monitors: Vec<String>,
+        offset: Option<String>,
+        delta: Option<f32>,
+    },
+    /// Append the current wallpaper of the displays to their `favorites` file
+    AddFavorite {
+        monitors: Vec<String>,
+    },
+    /// Pick the wallpapers of the displays randomly from their favorites only, or go back to
+    /// the configured ones, until the configuration is reloaded
+    ToggleFavorites {
+        monitors: Vec<String>,
+    },
+    /// Get the performance metrics of the displays, then reset them if `reset` is set
+    Metrics {
+        monitors: Vec<String>,
+        reset: bool,
+    },
+    /// Get the status of the displays. When `watch` is set, the connection is kept open and
+    /// the daemon sends only the displays that changed each time, one response per line
+    Status {
+        monitors: Vec<String>,
+        watch: bool,
+    },
+    /// Get the duration of the displays, or replace it until `expire` has passed or the
+    /// configuration is reloaded. `reset` restores the configured duration right away
+    Duration {
+        monitors: Vec<String>,
+        duration: Option<Duration>,
+        expire: Option<Duration>,
+        reset: bool,
+    },
+    /// Replace the log filter, e.g. `debug` or `wpaperd::surface=debug`, until `reset` is sent
+    LogFilter {
+        filter: Option<String>,
+        reset: bool,
+    },
+    /// Get the wallpapers each display picks from, at most `limit` of them per display
+    Candidates {
+        monitors: Vec<String>,
+        limit: Option<usize>,
+    },
+    /// Drop the images decoded in advance and the cached dimensions, so that the images
+    /// edited in place are read again from disk
+    FlushCache,
+    /// Get the active profile, or switch all the displays to `profile`, read from
+    /// `profiles/<profile>.toml` next to the configuration file; `reset` goes back to the
+    /// configuration file
+    Profile {
+        profile: Option<String>,
+        reset: bool,
+    },
+    /// Show `width`x`height` pixels on the displays, without reading any file. The message is
+    /// directly followed by the pixels, 4 bytes each in the RGBA order, row by row from the top
+    /// left; the client then shuts down the writing half of the connection
+    SetPixels {
+        monitors: Vec<String>,
+        width: u32,
+        height: u32,
+    },
+    /// Write what is shown on the display to `path` as a PNG image. The file is written by the
+    /// daemon, `path` should be absolute
+    Screenshot {
+        monitor: String,
+        path: PathBuf,
+    },
+    Capabilities,
+}
+
+/// Largest width and height accepted by [`IpcMessage::SetPixels`]
+pub const MAX_PIXELS_SIZE: u32 = 16384;
+
+/// Performance metrics of a display, to debug stutters
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct Metrics {
+    /// Time spent decoding the last image
+    pub decode_time: Option<Duration>,
+    /// Time spent drawing the last frame
+    pub draw_time: Option<Duration>,
+    /// Frames drawn during the last transition
+    pub transition_frames: u32,
+    /// Frame callbacks requested to draw the animations
+    pub frame_requests: u32,
+    /// Frame callbacks requested while nothing was animating, which keep the GPU awake for
+    /// nothing; it should always be 0
+    pub idle_frame_requests: u32,
+    /// Frames drawn while nothing was animating and nothing had changed, it should always be 0
+    pub idle_draws: u32,
+}
+
+/// State of a display, returned by [`IpcMessage::Status`]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Status {
+    pub path: PathBuf,
+    /// The automatic wallpaper sequence has been paused by the user
+    pub paused: bool,
+    pub mode: String,
+    pub sorting: String,
+    /// Effective EGL swap interval, 0 when the vertical sync is disabled
+    pub swap_interval: i32,
+    /// Logical position of the display, None if the compositor has not sent it
+    pub position: Option<(i32, i32)>,
+    /// Profile switched to with [`IpcMessage::Profile`], None for the configuration file
+    pub profile: Option<String>,
+}
+
+/// Wallpapers a display picks from, returned by [`IpcMessage::Candidates`]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Candidates {
+    /// The first wallpapers of the list, after the filters have been applied
+    pub paths: Vec<PathBuf>,
+    /// Number of wallpapers in the whole list
+    pub total: usize,
+    /// Index of the current wallpaper in the list, None if it is not part of it
+    pub current: Option<usize>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum IpcResponse {
-    CurrentWallpaper { path: PathBuf },
-    AllWallpapers { entries: Vec<(String, PathBuf)> },
+    CurrentWallpaper {
+        path: PathBuf,
+    },
+    AllWallpapers {
+        entries: Vec<(String, PathBuf)>,
+    },
+    BackgroundModes {
+        entries: Vec<(String, String)>,
+    },
+    Sortings {
+        entries: Vec<(String, String)>,
+    },
+    /// Offset in use by each display, between 0 and 1
+    Offsets {
+        entries: Vec<(String, f32)>,
+    },
+    /// Wallpaper added to the favorites of each display, and false if it was already there
+    FavoritesAdded {
+        entries: Vec<(String, PathBuf, bool)>,
+    },
+    /// Whether each display picks its wallpapers only from its favorites
+    FavoritesModes {
+        entries: Vec<(String, bool)>,
+    },
+    Metrics {
+        entries: Vec<(String, Metrics)>,
+    },
+    Status {
+        entries: Vec<(String, Status)>,
+    },
+    /// Duration in use by each display, None when the wallpaper is never changed automatically
+    Durations {
+        entries: Vec<(String, Option<Duration>)>,
+    },
+    Candidates {
+        entries: Vec<(String, Candidates)>,
+    },
+    /// Number of decoded images and of image dimensions evicted by [`IpcMessage::FlushCache`]
+    CacheFlushed {
+        images: usize,
+        dimensions: usize,
+    },
+    /// Active profile, None for the configuration file
+    Profile {
+        profile: Option<String>,
+    },
+    Capabilities {
+        version: u32,
+        commands: Vec<String>,
+    },
     Ok,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum IpcError {
-    MonitorNotFound { monitor: String },
+    MonitorNotFound {
+        monitor: String,
+    },
+    InvalidSorting {
+        sorting: String,
+    },
+    InvalidLogFilter {
+        filter: String,
+        error: String,
+    },
+    GroupedSorting {
+        monitor: String,
+    },
+    InvalidColor {
+        color: String,
+    },
+    InvalidOffset {
+        offset: String,
+    },
+    /// The display has no `favorites` file configured
+    NoFavorites {
+        monitor: String,
+    },
+    GroupedFavorites {
+        monitor: String,
+    },
+    /// The favorites files that could not be written, by display
+    FavoritesErrors(Vec<(String, String)>),
+    InvalidProfile {
+        profile: String,
+        error: String,
+    },
+    InvalidTransition {
+        transition: String,
+        error: String,
+    },
+    /// The size given to [`IpcMessage::SetPixels`] is 0 or bigger than [`MAX_PIXELS_SIZE`]
+    InvalidPixelsSize {
+        width: u32,
+        height: u32,
+    },
+    /// The client has not sent the `width * height * 4` bytes of [`IpcMessage::SetPixels`].
+    /// At most one byte more than expected is read, `received` is `expected + 1` when there
+    /// are too many
+    PixelsMismatch {
+        expected: u64,
+        received: u64,
+    },
     DrawErrors(Vec<(String, String)>),
+    /// The displays whose transition could not be previewed, with the reason
+    PreviewErrors(Vec<(String, String)>),
+    /// The frame of the display could not be read or written by [`IpcMessage::Screenshot`]
+    ScreenshotFailed {
+        monitor: String,
+        error: String,
+    },
 }
 
+/// Environment variable replacing the default path of the IPC socket
+pub const SOCKET_ENV: &str = "WPAPERD_SOCKET";
+
+/// Return the path of the IPC socket, `WPAPERD_SOCKET` if it is set. Otherwise the socket is
+/// named after `WAYLAND_DISPLAY` in the runtime directory, e.g. `wpaperd-wayland-1.sock`, so
+/// that the instances running on different compositors don't collide.
 pub fn socket_path() -> Result<PathBuf, BaseDirectoriesError> {
+    if let Some(path) = env::var_os(SOCKET_ENV).filter(|path| !path.is_empty()) {
+        return Ok(PathBuf::from(path));
+    }
+    let name = match env::var_os("WAYLAND_DISPLAY").filter(|display| !display.is_empty()) {
+        Some(display) => {
+            // WAYLAND_DISPLAY can also be the absolute path of the compositor socket
+            let display = Path::new(&display)
+                .file_name()
+                .unwrap_or(&display)
+                .to_owned();
+            let mut name = OsString::from("wpaperd-");
+            name.push(display);
+            name.push(".sock");
+            name
+        }
+        None => OsString::from("wpaperd.sock"),
+    };
     let xdg_dirs = BaseDirectories::with_prefix("wpaperd")?;
-    Ok(xdg_dirs.get_runtime_directory()?.join("wpaperd.sock"))
+    Ok(xdg_dirs.get_runtime_directory()?.join(name))
+}
+
+/// Return the name of the Linux abstract socket when `path` starts with `@`
+pub fn abstract_name(path: &Path) -> Option<&[u8]> {
+    path.as_os_str().as_bytes().strip_prefix(b"@")
+}
+
+/// Connect to the IPC socket, which can be an abstract socket
+pub fn connect(path: &Path) -> io::Result<UnixStream> {
+    match abstract_name(path) {
+        Some(name) => UnixStream::connect_addr(&SocketAddr::from_abstract_name(name)?),
+        None => UnixStream::connect(path),
+    }
 }